@@ -0,0 +1,309 @@
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// 自动备份目录（位于数据目录下，不参与资料包导出）
+pub const BACKUP_DIR: &str = "backups";
+const CONFIG_FILE: &str = "auto_backup.json";
+const STATE_FILE: &str = "auto_backup_state.json";
+const FILE_PREFIX: &str = "auto_";
+const FILE_TIME_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// 计数器与上次备份时间的读写锁（命令与调度线程共用）
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AutoBackupConfig {
+    pub enabled: bool,
+    /// 定时备份间隔（分钟），0 表示不按时间触发
+    pub interval_minutes: u64,
+    /// 新增消息达到该数量后备份，0 表示不按消息数触发
+    pub every_messages: u64,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+impl Default for AutoBackupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_minutes: 24 * 60,
+            every_messages: 0,
+            keep_daily: 7,
+            keep_weekly: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AutoBackupState {
+    pub last_backup_at: Option<i64>,
+    pub messages_since: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoBackupEntry {
+    pub name: String,
+    pub path: String,
+    pub bytes: u64,
+    pub created_at: String,
+}
+
+pub fn backup_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(BACKUP_DIR)
+}
+
+fn read_json<T: for<'de> Deserialize<'de> + Default>(path: &Path) -> T {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| e.to_string())
+}
+
+pub fn load_config(data_dir: &Path) -> AutoBackupConfig {
+    read_json(&backup_dir(data_dir).join(CONFIG_FILE))
+}
+
+pub fn save_config(data_dir: &Path, config: &AutoBackupConfig) -> Result<(), String> {
+    write_json(&backup_dir(data_dir).join(CONFIG_FILE), config)
+}
+
+pub fn load_state(data_dir: &Path) -> AutoBackupState {
+    read_json(&backup_dir(data_dir).join(STATE_FILE))
+}
+
+/// 在锁内读取、修改并写回备份状态
+pub fn update_state<T>(
+    data_dir: &Path,
+    update: impl FnOnce(&mut AutoBackupState) -> T,
+) -> Result<T, String> {
+    let _guard = STATE_LOCK
+        .lock()
+        .map_err(|_| "auto backup state lock poisoned".to_string())?;
+    let mut state = load_state(data_dir);
+    let out = update(&mut state);
+    write_json(&backup_dir(data_dir).join(STATE_FILE), &state)?;
+    Ok(out)
+}
+
+/// 判断是否需要备份，返回触发原因
+pub fn due_reason(
+    config: &AutoBackupConfig,
+    state: &AutoBackupState,
+    now_ms: i64,
+) -> Option<&'static str> {
+    if !config.enabled {
+        return None;
+    }
+    if config.every_messages > 0 && state.messages_since >= config.every_messages {
+        return Some("messages");
+    }
+    if config.interval_minutes == 0 {
+        return None;
+    }
+    let interval_ms = i64::try_from(config.interval_minutes.saturating_mul(60_000)).ok()?;
+    match state.last_backup_at {
+        Some(last) if now_ms.saturating_sub(last) < interval_ms => None,
+        _ => Some("interval"),
+    }
+}
+
+/// 新备份文件名（同一秒内重复时追加序号）
+pub fn next_backup_path(data_dir: &Path, now: DateTime<Utc>) -> PathBuf {
+    let dir = backup_dir(data_dir);
+    let stamp = now.format(FILE_TIME_FORMAT);
+    let mut path = dir.join(format!("{FILE_PREFIX}{stamp}.zip"));
+    let mut seq = 1;
+    while path.exists() {
+        path = dir.join(format!("{FILE_PREFIX}{stamp}_{seq}.zip"));
+        seq += 1;
+    }
+    path
+}
+
+fn parse_backup_time(name: &str) -> Option<DateTime<Utc>> {
+    let stem = name.strip_prefix(FILE_PREFIX)?.strip_suffix(".zip")?;
+    let stamp = stem.get(..15)?;
+    NaiveDateTime::parse_from_str(stamp, FILE_TIME_FORMAT)
+        .ok()
+        .map(|dt| Utc.from_utc_datetime(&dt))
+}
+
+/// 列出自动备份（最新在前）
+pub fn list_backups(data_dir: &Path) -> Result<Vec<(AutoBackupEntry, DateTime<Utc>)>, String> {
+    let dir = backup_dir(data_dir);
+    let mut out = Vec::new();
+    if !dir.is_dir() {
+        return Ok(out);
+    }
+    for entry in fs::read_dir(&dir).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some(created) = parse_backup_time(name) else {
+            continue;
+        };
+        let bytes = entry.metadata().map_or(0, |m| m.len());
+        out.push((
+            AutoBackupEntry {
+                name: name.to_string(),
+                path: path.to_string_lossy().to_string(),
+                bytes,
+                created_at: created.to_rfc3339(),
+            },
+            created,
+        ));
+    }
+    out.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.name.cmp(&a.0.name)));
+    Ok(out)
+}
+
+/// 保留策略：每天保留最新一份（最近 N 天），每周保留最新一份（最近 M 周），最新备份始终保留
+pub fn retained_indices(
+    created: &[DateTime<Utc>],
+    keep_daily: usize,
+    keep_weekly: usize,
+) -> HashSet<usize> {
+    let mut keep = HashSet::new();
+    if !created.is_empty() {
+        keep.insert(0);
+    }
+    let mut days = Vec::new();
+    let mut weeks = Vec::new();
+    for (idx, time) in created.iter().enumerate() {
+        let day = time.date_naive();
+        if days.len() < keep_daily && !days.contains(&day) {
+            days.push(day);
+            keep.insert(idx);
+        }
+        let week = (time.iso_week().year(), time.iso_week().week());
+        if weeks.len() < keep_weekly && !weeks.contains(&week) {
+            weeks.push(week);
+            keep.insert(idx);
+        }
+    }
+    keep
+}
+
+/// 按保留策略删除多余备份，返回被删除的文件名
+pub fn apply_retention(data_dir: &Path, config: &AutoBackupConfig) -> Result<Vec<String>, String> {
+    let backups = list_backups(data_dir)?;
+    let created: Vec<DateTime<Utc>> = backups.iter().map(|(_, time)| *time).collect();
+    let keep = retained_indices(&created, config.keep_daily, config.keep_weekly);
+    let mut removed = Vec::new();
+    for (idx, (entry, _)) in backups.into_iter().enumerate() {
+        if keep.contains(&idx) {
+            continue;
+        }
+        fs::remove_file(&entry.path).map_err(|e| e.to_string())?;
+        removed.push(entry.name);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::make_temp_dir;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn due_by_interval_or_messages() {
+        let mut config = AutoBackupConfig {
+            enabled: true,
+            interval_minutes: 60,
+            every_messages: 10,
+            ..AutoBackupConfig::default()
+        };
+        let mut state = AutoBackupState::default();
+        assert_eq!(due_reason(&config, &state, 0), Some("interval"));
+        state.last_backup_at = Some(1_000);
+        assert_eq!(due_reason(&config, &state, 1_000 + 59 * 60_000), None);
+        assert_eq!(
+            due_reason(&config, &state, 1_000 + 60 * 60_000),
+            Some("interval")
+        );
+        state.messages_since = 10;
+        assert_eq!(due_reason(&config, &state, 1_000), Some("messages"));
+        config.enabled = false;
+        assert_eq!(due_reason(&config, &state, 1_000), None);
+    }
+
+    #[test]
+    fn retention_keeps_daily_and_weekly() {
+        // 2026-03-02 为周一；newest first
+        let created = vec![
+            at(16, 20),
+            at(16, 8),
+            at(15, 9),
+            at(14, 9),
+            at(9, 9),
+            at(3, 9),
+            at(2, 9),
+        ];
+        let keep = retained_indices(&created, 2, 3);
+        let mut kept: Vec<usize> = keep.into_iter().collect();
+        kept.sort_unstable();
+        // 日：16 日最新、15 日；周：第 12 周(16)、第 11 周(15)、第 10 周(3)
+        assert_eq!(kept, vec![0, 2, 5]);
+        assert_eq!(
+            retained_indices(&created, 0, 0)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn list_and_prune_backup_files() {
+        let data = make_temp_dir("prune");
+        let dir = backup_dir(&data);
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "auto_20260316_200000.zip",
+            "auto_20260316_080000.zip",
+            "auto_20260315_090000.zip",
+            "manual.zip",
+        ] {
+            fs::write(dir.join(name), b"zip").unwrap();
+        }
+        let next = next_backup_path(&data, at(16, 20));
+        assert!(next.ends_with("auto_20260316_200000_1.zip"));
+
+        let listed = list_backups(&data).unwrap();
+        assert_eq!(listed.len(), 3);
+        assert_eq!(listed[0].0.name, "auto_20260316_200000.zip");
+
+        let config = AutoBackupConfig {
+            keep_daily: 1,
+            keep_weekly: 0,
+            ..AutoBackupConfig::default()
+        };
+        let removed = apply_retention(&data, &config).unwrap();
+        assert_eq!(
+            removed,
+            vec!["auto_20260316_080000.zip", "auto_20260315_090000.zip"]
+        );
+        assert!(dir.join("manual.zip").exists());
+        let _ = fs::remove_dir_all(&data);
+    }
+}
@@ -0,0 +1,501 @@
+use crate::auto_backup;
+use crate::bundle::{self, BundleFileIndex};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 增量备份链目录：backups/chains/<base id>/
+const CHAIN_DIR: &str = "chains";
+const CHAIN_FILE: &str = "chain.json";
+const CHAIN_VERSION: u32 = 1;
+/// 单条备份链最多的增量数量，超过后自动开始新的全量基线
+pub const MAX_CHAIN_DELTAS: usize = 30;
+/// 保留的备份链数量；增量依赖各自的基线，只能整条链一起删除
+pub const MAX_CHAINS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PointKind {
+    Base,
+    Delta,
+}
+
+/// 某个备份点时刻的单个文件状态；`stored_in` 指向实际保存内容的备份点
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileState {
+    pub sha256: String,
+    pub size: u64,
+    pub modified: Option<i64>,
+    pub stored_in: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupPoint {
+    pub id: String,
+    pub kind: PointKind,
+    /// 本备份点写入的 zip；没有文件变化时为空
+    pub file: Option<String>,
+    pub created_at: String,
+    pub files: BTreeMap<String, FileState>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupChain {
+    pub version: u32,
+    pub base_id: String,
+    pub points: Vec<BackupPoint>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupPointInfo {
+    pub id: String,
+    pub chain_id: String,
+    pub kind: PointKind,
+    pub created_at: String,
+    pub files: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub bytes: u64,
+}
+
+/// 本地文件的大小与修改时间（用于快速判断是否变化）
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    pub rel: String,
+    pub size: u64,
+    pub modified: Option<i64>,
+}
+
+impl LocalFile {
+    pub fn stat(data_dir: &Path, rel: &str) -> Self {
+        let path = data_dir.join(rel);
+        Self {
+            rel: rel.to_string(),
+            size: fs::metadata(&path).map_or(0, |m| m.len()),
+            modified: bundle::file_modified_ms(&path),
+        }
+    }
+}
+
+/// 新备份点的文件清单与需要写入的变化文件
+#[derive(Debug, Clone, Default)]
+pub struct DeltaPlan {
+    pub files: BTreeMap<String, FileState>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl DeltaPlan {
+    /// 以实际写入的文件为准修正清单：应写入本次包（`complete` 时为全部文件）但写入前
+    /// 已被删除的文件移出清单，写入时新出现的文件补入清单，摘要、大小与修改时间取写入时的值
+    pub fn apply_index(
+        &mut self,
+        index: &BundleFileIndex,
+        data_dir: &Path,
+        point_id: &str,
+        previous: Option<&BTreeMap<String, FileState>>,
+        complete: bool,
+    ) {
+        self.files.retain(|rel, state| {
+            index.checksums.contains_key(rel) || (!complete && state.stored_in != point_id)
+        });
+        self.changed.retain(|rel| index.checksums.contains_key(rel));
+        for (rel, sha) in &index.checksums {
+            let local = LocalFile::stat(data_dir, rel);
+            let modified = index.modified.get(rel).copied().or(local.modified);
+            if let Some(state) = self.files.get_mut(rel) {
+                state.sha256.clone_from(sha);
+                state.size = local.size;
+                state.modified = modified;
+            } else {
+                self.files.insert(
+                    rel.clone(),
+                    FileState {
+                        sha256: sha.clone(),
+                        size: local.size,
+                        modified,
+                        stored_in: point_id.to_string(),
+                    },
+                );
+                self.changed.push(rel.clone());
+            }
+        }
+        if let Some(previous) = previous {
+            self.removed = previous
+                .keys()
+                .filter(|rel| !self.files.contains_key(*rel))
+                .cloned()
+                .collect();
+        }
+    }
+}
+
+impl BackupChain {
+    pub fn latest(&self) -> Option<&BackupPoint> {
+        self.points.last()
+    }
+
+    pub fn deltas(&self) -> usize {
+        self.points
+            .iter()
+            .filter(|p| p.kind == PointKind::Delta)
+            .count()
+    }
+
+    pub fn point(&self, id: &str) -> Option<&BackupPoint> {
+        self.points.iter().find(|p| p.id == id)
+    }
+}
+
+pub fn chains_root(data_dir: &Path) -> PathBuf {
+    auto_backup::backup_dir(data_dir).join(CHAIN_DIR)
+}
+
+pub fn chain_dir(data_dir: &Path, base_id: &str) -> PathBuf {
+    chains_root(data_dir).join(base_id)
+}
+
+pub fn load_chain(dir: &Path) -> Result<BackupChain, String> {
+    let raw = fs::read_to_string(dir.join(CHAIN_FILE)).map_err(|e| e.to_string())?;
+    let chain: BackupChain =
+        serde_json::from_str(&raw).map_err(|e| format!("invalid chain.json: {e}"))?;
+    if chain.version > CHAIN_VERSION {
+        return Err(format!(
+            "backup chain version {} is newer than supported {CHAIN_VERSION}",
+            chain.version
+        ));
+    }
+    Ok(chain)
+}
+
+/// 先写临时文件再替换，避免中断时损坏链清单
+pub fn save_chain(dir: &Path, chain: &BackupChain) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(chain).map_err(|e| e.to_string())?;
+    let part = dir.join(format!("{CHAIN_FILE}.part"));
+    fs::write(&part, json).map_err(|e| e.to_string())?;
+    fs::rename(&part, dir.join(CHAIN_FILE)).map_err(|e| e.to_string())
+}
+
+/// 所有备份链（按基线时间升序）
+pub fn list_chains(data_dir: &Path) -> Result<Vec<BackupChain>, String> {
+    let root = chains_root(data_dir);
+    let mut chains = Vec::new();
+    if !root.is_dir() {
+        return Ok(chains);
+    }
+    for entry in fs::read_dir(&root).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if !path.join(CHAIN_FILE).is_file() {
+            continue;
+        }
+        match load_chain(&path) {
+            Ok(chain) => chains.push(chain),
+            Err(err) => eprintln!("[backup_chain] skip {}: {}", path.display(), err),
+        }
+    }
+    chains.sort_by(|a, b| a.base_id.cmp(&b.base_id));
+    Ok(chains)
+}
+
+/// 删除最旧的备份链，只保留最近 `keep` 条；返回被删除链的基线 id
+pub fn prune_chains(data_dir: &Path, keep: usize) -> Result<Vec<String>, String> {
+    let chains = list_chains(data_dir)?;
+    let excess = chains.len().saturating_sub(keep.max(1));
+    let mut removed = Vec::new();
+    for chain in chains.into_iter().take(excess) {
+        fs::remove_dir_all(chain_dir(data_dir, &chain.base_id)).map_err(|e| e.to_string())?;
+        removed.push(chain.base_id);
+    }
+    Ok(removed)
+}
+
+/// 备份点 id（时间戳，重复时追加序号）
+pub fn new_point_id(now: DateTime<Utc>, existing: &[BackupChain]) -> String {
+    let stamp = now.format("%Y%m%d_%H%M%S").to_string();
+    let taken = |id: &str| {
+        existing
+            .iter()
+            .any(|c| c.base_id == id || c.point(id).is_some())
+    };
+    let mut id = stamp.clone();
+    let mut seq = 1;
+    while taken(&id) {
+        id = format!("{stamp}_{seq}");
+        seq += 1;
+    }
+    id
+}
+
+pub fn point_file_name(kind: PointKind, id: &str) -> String {
+    match kind {
+        PointKind::Base => format!("base_{id}.zip"),
+        PointKind::Delta => format!("delta_{id}.zip"),
+    }
+}
+
+/// 对比上一个备份点，得出新备份点的清单；大小与修改时间均未变时不重新计算摘要
+pub fn plan_delta(
+    previous: Option<&BTreeMap<String, FileState>>,
+    current: &[LocalFile],
+    point_id: &str,
+    mut hash: impl FnMut(&str) -> Result<String, String>,
+) -> Result<DeltaPlan, String> {
+    let mut plan = DeltaPlan::default();
+    for local in current {
+        let prev = previous.and_then(|files| files.get(&local.rel));
+        if let Some(prev) = prev {
+            if prev.size == local.size && prev.modified.is_some() && prev.modified == local.modified
+            {
+                plan.files.insert(local.rel.clone(), prev.clone());
+                continue;
+            }
+        }
+        let sha256 = hash(&local.rel)?;
+        let stored_in = match prev {
+            Some(prev) if prev.sha256 == sha256 => prev.stored_in.clone(),
+            _ => {
+                plan.changed.push(local.rel.clone());
+                point_id.to_string()
+            }
+        };
+        plan.files.insert(
+            local.rel.clone(),
+            FileState {
+                sha256,
+                size: local.size,
+                modified: local.modified,
+                stored_in,
+            },
+        );
+    }
+    if let Some(previous) = previous {
+        plan.removed = previous
+            .keys()
+            .filter(|rel| !plan.files.contains_key(*rel))
+            .cloned()
+            .collect();
+    }
+    Ok(plan)
+}
+
+/// 还原某个备份点需要读取的 (zip 文件, 文件列表)
+pub fn restore_sources(
+    chain: &BackupChain,
+    point_id: &str,
+) -> Result<Vec<(String, Vec<String>)>, String> {
+    let point = chain
+        .point(point_id)
+        .ok_or_else(|| format!("backup point not found: {point_id}"))?;
+    let mut by_point: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (rel, state) in &point.files {
+        by_point
+            .entry(state.stored_in.as_str())
+            .or_default()
+            .push(rel.clone());
+    }
+    by_point
+        .into_iter()
+        .map(|(source_id, files)| {
+            let file = chain
+                .point(source_id)
+                .and_then(|p| p.file.clone())
+                .ok_or_else(|| format!("backup chain is missing point {source_id}"))?;
+            Ok((file, files))
+        })
+        .collect()
+}
+
+pub fn point_info(data_dir: &Path, chain: &BackupChain, point: &BackupPoint) -> BackupPointInfo {
+    let bytes = point.file.as_ref().map_or(0, |file| {
+        fs::metadata(chain_dir(data_dir, &chain.base_id).join(file)).map_or(0, |m| m.len())
+    });
+    BackupPointInfo {
+        id: point.id.clone(),
+        chain_id: chain.base_id.clone(),
+        kind: point.kind,
+        created_at: point.created_at.clone(),
+        files: point.files.len(),
+        changed: point.changed.len(),
+        removed: point.removed.len(),
+        bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(rel: &str, size: u64, modified: i64) -> LocalFile {
+        LocalFile {
+            rel: rel.to_string(),
+            size,
+            modified: Some(modified),
+        }
+    }
+
+    fn point(id: &str, kind: PointKind, plan: DeltaPlan) -> BackupPoint {
+        BackupPoint {
+            id: id.to_string(),
+            kind,
+            file: Some(point_file_name(kind, id)),
+            created_at: String::new(),
+            files: plan.files,
+            changed: plan.changed,
+            removed: plan.removed,
+        }
+    }
+
+    #[test]
+    fn delta_plan_tracks_changes_and_removals() {
+        let base = plan_delta(
+            None,
+            &[local("a.json", 1, 10), local("b.json", 2, 10)],
+            "p1",
+            |rel| Ok(format!("{rel}-v1")),
+        )
+        .unwrap();
+        assert_eq!(base.changed, vec!["a.json", "b.json"]);
+
+        let mut hashed = Vec::new();
+        let delta = plan_delta(
+            Some(&base.files),
+            &[
+                local("a.json", 1, 10),
+                local("b.json", 2, 20),
+                local("c.json", 3, 20),
+            ],
+            "p2",
+            |rel| {
+                hashed.push(rel.to_string());
+                Ok(match rel {
+                    "b.json" => "b.json-v1".to_string(),
+                    _ => format!("{rel}-v2"),
+                })
+            },
+        )
+        .unwrap();
+        // a 未变不重新计算；b 仅 mtime 变化，内容仍在 p1
+        assert_eq!(hashed, vec!["b.json", "c.json"]);
+        assert_eq!(delta.changed, vec!["c.json"]);
+        assert_eq!(delta.files["b.json"].stored_in, "p1");
+        assert_eq!(delta.files["b.json"].modified, Some(20));
+
+        let removal = plan_delta(Some(&delta.files), &[local("c.json", 3, 20)], "p3", |_| {
+            Err("unexpected hash".to_string())
+        })
+        .unwrap();
+        assert_eq!(removal.removed, vec!["a.json", "b.json"]);
+        assert!(removal.changed.is_empty());
+    }
+
+    #[test]
+    fn apply_index_follows_written_files() {
+        let data = crate::test_support::make_temp_dir("chain_index");
+        fs::write(data.join("b.json"), b"bb").unwrap();
+        fs::write(data.join("new.json"), b"new").unwrap();
+        let base = plan_delta(
+            None,
+            &[local("a.json", 1, 10), local("b.json", 1, 10)],
+            "p1",
+            |rel| Ok(rel.to_string()),
+        )
+        .unwrap();
+        let mut delta = plan_delta(
+            Some(&base.files),
+            &[local("a.json", 2, 20), local("b.json", 2, 20)],
+            "p2",
+            |rel| Ok(format!("{rel}-v2")),
+        )
+        .unwrap();
+        assert_eq!(delta.changed, vec!["a.json", "b.json"]);
+
+        // a 在扫描后、写入前被删除；new 在写入时出现
+        let mut index = BundleFileIndex::default();
+        index.record("b.json", "b-written".to_string(), Some(30));
+        index.record("new.json", "new-written".to_string(), Some(31));
+        delta.apply_index(&index, &data, "p2", Some(&base.files), false);
+        assert_eq!(delta.changed, vec!["b.json", "new.json"]);
+        assert_eq!(delta.removed, vec!["a.json"]);
+        assert!(!delta.files.contains_key("a.json"));
+        assert_eq!(delta.files["b.json"].sha256, "b-written");
+        assert_eq!(delta.files["b.json"].size, 2);
+        assert_eq!(delta.files["new.json"].stored_in, "p2");
+        assert_eq!(delta.files["new.json"].size, 3);
+
+        fs::remove_dir_all(data).ok();
+    }
+
+    #[test]
+    fn restore_sources_follow_chain() {
+        let base = plan_delta(
+            None,
+            &[local("a.json", 1, 10), local("b.json", 1, 10)],
+            "p1",
+            |rel| Ok(rel.to_string()),
+        )
+        .unwrap();
+        let delta = plan_delta(
+            Some(&base.files),
+            &[local("a.json", 1, 10), local("b.json", 5, 30)],
+            "p2",
+            |_| Ok("b2".to_string()),
+        )
+        .unwrap();
+        let chain = BackupChain {
+            version: CHAIN_VERSION,
+            base_id: "p1".to_string(),
+            points: vec![
+                point("p1", PointKind::Base, base),
+                point("p2", PointKind::Delta, delta),
+            ],
+        };
+        assert_eq!(
+            restore_sources(&chain, "p2").unwrap(),
+            vec![
+                ("base_p1.zip".to_string(), vec!["a.json".to_string()]),
+                ("delta_p2.zip".to_string(), vec!["b.json".to_string()]),
+            ]
+        );
+        assert_eq!(
+            restore_sources(&chain, "p1").unwrap(),
+            vec![(
+                "base_p1.zip".to_string(),
+                vec!["a.json".to_string(), "b.json".to_string()]
+            )]
+        );
+        assert!(restore_sources(&chain, "p9").is_err());
+    }
+
+    #[test]
+    fn prune_keeps_newest_chains() {
+        let data = crate::test_support::make_temp_dir("chain_prune");
+        for base_id in ["20240101_000000", "20240201_000000", "20240301_000000"] {
+            let chain = BackupChain {
+                version: CHAIN_VERSION,
+                base_id: base_id.to_string(),
+                points: Vec::new(),
+            };
+            save_chain(&chain_dir(&data, base_id), &chain).unwrap();
+        }
+        let removed = prune_chains(&data, 2).unwrap();
+        assert_eq!(removed, vec!["20240101_000000"]);
+        let left: Vec<String> = list_chains(&data)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.base_id)
+            .collect();
+        assert_eq!(left, vec!["20240201_000000", "20240301_000000"]);
+        // 至少保留刚写入的链
+        assert_eq!(prune_chains(&data, 0).unwrap(), vec!["20240201_000000"]);
+        let _ = fs::remove_dir_all(&data);
+    }
+}
@@ -0,0 +1,1137 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const SNAPSHOT_MARKER: &str = ".pre_import.json";
+/// 导入后连续这么多次启动都未被前端确认成功时，自动回滚到导入前快照
+const SNAPSHOT_UNCONFIRMED_LAUNCHES: u32 = 2;
+
+const BUNDLE_FORMAT_PREFIX: &str = "tauri-chat-app-backup-v";
+/// 当前导出使用的资料包格式版本
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+pub fn bundle_format() -> String {
+    format!("{BUNDLE_FORMAT_PREFIX}{BUNDLE_FORMAT_VERSION}")
+}
+
+/// 旧格式资料包的升级步骤：作用于 bundle.json 与解压后的目录
+pub struct BundleUpgrade {
+    pub from: u32,
+    pub to: u32,
+    pub apply: fn(&mut serde_json::Value, &Path) -> Result<(), String>,
+}
+
+const BUNDLE_UPGRADES: &[BundleUpgrade] = &[];
+
+/// 解析后的 bundle.json
+#[derive(Debug, Clone)]
+pub struct BundleManifest {
+    pub format_version: u32,
+    pub app_version: Option<String>,
+    /// 相对路径 -> SHA-256（旧资料包没有该字段）
+    pub checksums: Option<BTreeMap<String, String>>,
+    /// 相对路径 -> 导出时的修改时间（毫秒）
+    pub modified: BTreeMap<String, i64>,
+    pub created_at: Option<String>,
+    /// 部分导出时包内实际包含的范围；完整资料包为 None
+    pub selection: Option<BundleSelection>,
+    /// 增量备份链中的增量包，只含变化的文件，不能单独导入
+    pub chain_delta: bool,
+    pub raw: serde_json::Value,
+}
+
+impl BundleManifest {
+    pub fn parse(raw: serde_json::Value) -> Result<Self, String> {
+        let Some(obj) = raw.as_object() else {
+            return Err("invalid bundle.json: expected object".to_string());
+        };
+        let format = obj
+            .get("format")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "invalid bundle.json: format missing".to_string())?;
+        let format_version = format
+            .strip_prefix(BUNDLE_FORMAT_PREFIX)
+            .and_then(|v| v.parse::<u32>().ok())
+            .ok_or_else(|| format!("unsupported bundle format: {format}"))?;
+        if format_version > BUNDLE_FORMAT_VERSION {
+            return Err(format!(
+                "bundle format v{format_version} is newer than supported v{BUNDLE_FORMAT_VERSION}, please update the app"
+            ));
+        }
+        let app_version = obj
+            .get("appVersion")
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        let checksums = obj.get("checksums").and_then(|v| v.as_object()).map(|map| {
+            map.iter()
+                .filter_map(|(k, v)| v.as_str().map(|h| (k.clone(), h.to_ascii_lowercase())))
+                .collect()
+        });
+        let modified = obj
+            .get("modified")
+            .and_then(|v| v.as_object())
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(k, v)| v.as_i64().map(|ms| (k.clone(), ms)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let created_at = obj
+            .get("createdAt")
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        let selection = Self::parse_selection(obj)?;
+        let chain_delta = obj
+            .get("chainDelta")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false);
+        Ok(Self {
+            format_version,
+            app_version,
+            checksums,
+            modified,
+            created_at,
+            selection,
+            chain_delta,
+            raw,
+        })
+    }
+
+    /// partial 资料包优先读取导出时的 selection，旧清单退回 categories 列表
+    fn parse_selection(
+        obj: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Option<BundleSelection>, String> {
+        if !obj
+            .get("partial")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+        {
+            return Ok(None);
+        }
+        if let Some(selection) = obj.get("selection").filter(|v| v.is_object()) {
+            return serde_json::from_value(selection.clone())
+                .map(Some)
+                .map_err(|e| format!("invalid bundle.json selection: {e}"));
+        }
+        let categories = obj
+            .get("categories")
+            .cloned()
+            .ok_or_else(|| "invalid bundle.json: partial bundle without categories".to_string())?;
+        let include: Vec<BundleCategory> = serde_json::from_value(categories)
+            .map_err(|e| format!("invalid bundle.json categories: {e}"))?;
+        Ok(Some(BundleSelection {
+            include: Some(include),
+            ..BundleSelection::default()
+        }))
+    }
+
+    /// 校验单个文件的摘要；清单无校验信息时返回 Ok
+    pub fn verify_file(&self, rel: &str, sha256: &str) -> Result<(), String> {
+        let Some(checksums) = &self.checksums else {
+            return Ok(());
+        };
+        match checksums.get(rel) {
+            Some(expected) if expected == sha256 => Ok(()),
+            Some(_) => Err(format!("checksum mismatch: {rel}")),
+            None => Err(format!("file not listed in bundle.json: {rel}")),
+        }
+    }
+
+    pub fn needs_upgrade(&self) -> bool {
+        self.format_version < BUNDLE_FORMAT_VERSION
+    }
+
+    /// 依次执行已注册的升级步骤，直到当前格式版本
+    pub fn upgrade(&mut self, dir: &Path) -> Result<(), String> {
+        self.upgrade_with(dir, BUNDLE_UPGRADES, BUNDLE_FORMAT_VERSION)
+    }
+
+    fn upgrade_with(
+        &mut self,
+        dir: &Path,
+        steps: &[BundleUpgrade],
+        target: u32,
+    ) -> Result<(), String> {
+        while self.format_version < target {
+            let step = steps
+                .iter()
+                .find(|step| step.from == self.format_version)
+                .ok_or_else(|| {
+                    format!("no upgrade path for bundle format v{}", self.format_version)
+                })?;
+            (step.apply)(&mut self.raw, dir)
+                .map_err(|e| format!("bundle upgrade v{} failed: {e}", step.from))?;
+            self.format_version = step.to;
+        }
+        if let Some(obj) = self.raw.as_object_mut() {
+            obj.insert(
+                "format".to_string(),
+                serde_json::Value::String(format!("{BUNDLE_FORMAT_PREFIX}{target}")),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// 资料包内容分类（按数据目录下的相对路径划分）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BundleCategory {
+    Chats,
+    Memories,
+    WorldInfo,
+    Presets,
+    Regex,
+    Wallpapers,
+    Attachments,
+    RawReplies,
+    Media,
+    Contacts,
+    Personas,
+    Moments,
+    Other,
+}
+
+impl BundleCategory {
+    pub const ALL: [BundleCategory; 13] = [
+        Self::Chats,
+        Self::Memories,
+        Self::WorldInfo,
+        Self::Presets,
+        Self::Regex,
+        Self::Wallpapers,
+        Self::Attachments,
+        Self::RawReplies,
+        Self::Media,
+        Self::Contacts,
+        Self::Personas,
+        Self::Moments,
+        Self::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Chats => "chats",
+            Self::Memories => "memories",
+            Self::WorldInfo => "world_info",
+            Self::Presets => "presets",
+            Self::Regex => "regex",
+            Self::Wallpapers => "wallpapers",
+            Self::Attachments => "attachments",
+            Self::RawReplies => "raw_replies",
+            Self::Media => "media",
+            Self::Contacts => "contacts",
+            Self::Personas => "personas",
+            Self::Moments => "moments",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// KV 文件名（去掉 `__scope` 后缀与扩展名）
+fn kv_base(name: &str) -> Option<(&str, &str)> {
+    let stem = name.strip_suffix(".json")?;
+    Some(stem.split_once("__").unwrap_or((stem, "")))
+}
+
+/// 根据相对路径（`/` 分隔）判断所属分类
+pub fn category_for_path(rel: &str) -> BundleCategory {
+    let rel = rel.trim_start_matches('/');
+    let (head, rest) = rel.split_once('/').unwrap_or((rel, ""));
+    match head {
+        "chat_store_v2" | "chats" => return BundleCategory::Chats,
+        "worldinfo" => return BundleCategory::WorldInfo,
+        "wallpapers" => return BundleCategory::Wallpapers,
+        "attachments" => return BundleCategory::Attachments,
+        "raw_replies" => return BundleCategory::RawReplies,
+        "media" => return BundleCategory::Media,
+        "characters" => return BundleCategory::Contacts,
+        _ => {}
+    }
+    if !rest.is_empty() {
+        return BundleCategory::Other;
+    }
+    if head.starts_with("memories") && head.contains(".db") {
+        return BundleCategory::Memories;
+    }
+    match kv_base(head).map(|(base, _)| base) {
+        Some("chat_store_v2") => BundleCategory::Chats,
+        Some("worldinfo_store") => BundleCategory::WorldInfo,
+        Some("prompt_preset_store_v1") => BundleCategory::Presets,
+        Some("regex_store_v1") => BundleCategory::Regex,
+        Some("contacts_store_v1" | "contact_groups_v1") => BundleCategory::Contacts,
+        Some("user_personas_v1" | "user_personas_active_id_v1") => BundleCategory::Personas,
+        Some("moments_store_v1") => BundleCategory::Moments,
+        _ => BundleCategory::Other,
+    }
+}
+
+/// 单文件聊天存储（`chat_store_v2__<scope>.json`），按会话合并
+pub fn is_chat_kv_store(rel: &str) -> bool {
+    !rel.contains('/') && kv_base(rel).is_some_and(|(base, _)| base == "chat_store_v2")
+}
+
+/// 分片聊天存储中文件所属的会话目录（`chat_store_v2/scope_x/session_y`）
+pub fn chat_session_dir(rel: &str) -> Option<String> {
+    match rel
+        .trim_start_matches('/')
+        .split('/')
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["chat_store_v2", scope_dir, session_dir, _, ..]
+            if scope_dir.starts_with("scope_") && session_dir.starts_with("session_") =>
+        {
+            Some(format!("chat_store_v2/{scope_dir}/{session_dir}"))
+        }
+        _ => None,
+    }
+}
+
+/// 聊天数据所属的 (scope, session)；无法判断的部分为 None
+fn chat_location(rel: &str) -> (Option<String>, Option<&str>) {
+    let parts: Vec<&str> = rel.trim_start_matches('/').split('/').collect();
+    match parts.as_slice() {
+        ["chat_store_v2", scope_dir, rest @ ..] => {
+            let scope = scope_dir.strip_prefix("scope_").map(String::from);
+            let session = rest.first().and_then(|s| s.strip_prefix("session_"));
+            (scope, session)
+        }
+        [file] => match kv_base(file) {
+            Some(("chat_store_v2", scope)) => (
+                Some(if scope.is_empty() { "default" } else { scope }.to_string()),
+                None,
+            ),
+            _ => (None, None),
+        },
+        ["chats", ..] => (Some("default".to_string()), None),
+        _ => (None, None),
+    }
+}
+
+/// 导出/导入时的内容选择；默认包含全部分类
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BundleSelection {
+    pub include: Option<Vec<BundleCategory>>,
+    #[serde(default)]
+    pub exclude: Vec<BundleCategory>,
+    pub chat_scopes: Option<Vec<String>>,
+    pub chat_sessions: Option<Vec<String>>,
+}
+
+impl BundleSelection {
+    pub fn is_full(&self) -> bool {
+        self.include.is_none()
+            && self.exclude.is_empty()
+            && self.chat_scopes.is_none()
+            && self.chat_sessions.is_none()
+    }
+
+    pub fn includes_category(&self, category: BundleCategory) -> bool {
+        if self.exclude.contains(&category) {
+            return false;
+        }
+        self.include
+            .as_ref()
+            .is_none_or(|list| list.contains(&category))
+    }
+
+    /// 判断相对路径是否被选中；目录也可调用，用于提前剪枝
+    pub fn allows(&self, rel: &str) -> bool {
+        let category = category_for_path(rel);
+        if !self.includes_category(category) {
+            return false;
+        }
+        if category != BundleCategory::Chats {
+            return true;
+        }
+        let (scope, session) = chat_location(rel);
+        if let (Some(scopes), Some(scope)) = (&self.chat_scopes, scope.as_deref()) {
+            if !scopes.iter().any(|s| s.trim() == scope) {
+                return false;
+            }
+        }
+        if let (Some(sessions), Some(session)) = (&self.chat_sessions, session) {
+            if !sessions.iter().any(|s| s.trim() == session) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// 选中的分类列表（写入 bundle.json）
+    pub fn categories(&self) -> Vec<BundleCategory> {
+        BundleCategory::ALL
+            .into_iter()
+            .filter(|c| self.includes_category(*c))
+            .collect()
+    }
+
+    /// 两个选择的交集：路径需同时被两者选中
+    pub fn intersect(&self, other: &Self) -> Self {
+        let categories: Vec<BundleCategory> = self
+            .categories()
+            .into_iter()
+            .filter(|c| other.includes_category(*c))
+            .collect();
+        let both = |a: &Option<Vec<String>>, b: &Option<Vec<String>>| match (a, b) {
+            (None, x) | (x, None) => x.clone(),
+            (Some(a), Some(b)) => Some(
+                a.iter()
+                    .filter(|v| b.iter().any(|w| w.trim() == v.trim()))
+                    .cloned()
+                    .collect(),
+            ),
+        };
+        Self {
+            include: (categories.len() < BundleCategory::ALL.len()).then_some(categories),
+            exclude: Vec::new(),
+            chat_scopes: both(&self.chat_scopes, &other.chat_scopes),
+            chat_sessions: both(&self.chat_sessions, &other.chat_sessions),
+        }
+    }
+}
+
+/// 写入时同步计算 SHA-256
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub fn finish(self) -> (W, String) {
+        (self.inner, format!("{:x}", self.hasher.finalize()))
+    }
+
+    pub fn finish_hex(self) -> String {
+        self.finish().1
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut hashing = HashingWriter::new(std::io::sink());
+    std::io::copy(&mut file, &mut hashing).map_err(|e| e.to_string())?;
+    Ok(hashing.finish_hex())
+}
+
+pub fn file_modified_ms(path: &Path) -> Option<i64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let elapsed = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    i64::try_from(elapsed.as_millis()).ok()
+}
+
+/// 导出时记录的文件清单：分类统计、逐文件 SHA-256 与修改时间
+#[derive(Debug, Default)]
+pub struct BundleFileIndex {
+    pub counts: CategoryCounts,
+    pub checksums: BTreeMap<String, String>,
+    pub modified: BTreeMap<String, i64>,
+}
+
+impl BundleFileIndex {
+    pub fn record(&mut self, rel: &str, sha256: String, modified_ms: Option<i64>) {
+        self.counts.add(rel);
+        self.checksums.insert(rel.to_string(), sha256);
+        if let Some(ms) = modified_ms {
+            self.modified.insert(rel.to_string(), ms);
+        }
+    }
+}
+
+fn scope_label(scope: &str) -> String {
+    if scope.is_empty() {
+        "default".to_string()
+    } else {
+        scope.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewSession {
+    pub scope: String,
+    pub id: String,
+    pub contact_id: Option<String>,
+    pub messages: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewContact {
+    pub scope: String,
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PreviewMediaCounts {
+    pub wallpapers: usize,
+    pub attachments: usize,
+    pub media: usize,
+    pub raw_replies: usize,
+}
+
+/// 资料包内容摘要（会话、联系人、记忆 scope、世界书、媒体数量）
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BundleContentSummary {
+    pub sessions: Vec<PreviewSession>,
+    pub contacts: Vec<PreviewContact>,
+    pub memory_scopes: Vec<String>,
+    pub world_books: Vec<String>,
+    pub media: PreviewMediaCounts,
+    pub categories: CategoryCounts,
+    #[serde(skip)]
+    seen_sessions: BTreeSet<(String, String)>,
+}
+
+impl BundleContentSummary {
+    /// 是否需要读取文件内容才能生成摘要
+    pub fn wants_content(rel: &str) -> bool {
+        matches!(
+            kv_base(rel).map(|(base, _)| base),
+            Some("chat_store_v2" | "contacts_store_v1" | "worldinfo_store")
+        )
+    }
+
+    pub fn observe(&mut self, rel: &str, content: Option<&[u8]>) {
+        self.categories.add(rel);
+        match category_for_path(rel) {
+            BundleCategory::Wallpapers => self.media.wallpapers += 1,
+            BundleCategory::Attachments => self.media.attachments += 1,
+            BundleCategory::Media => self.media.media += 1,
+            BundleCategory::RawReplies => self.media.raw_replies += 1,
+            BundleCategory::Memories => {
+                let scope = rel
+                    .strip_prefix("memories")
+                    .and_then(|rest| rest.strip_suffix(".db"))
+                    .map(|rest| scope_label(rest.trim_start_matches("__")));
+                if let Some(scope) = scope {
+                    self.memory_scopes.push(scope);
+                }
+            }
+            BundleCategory::WorldInfo => {
+                if let Some(name) = rel
+                    .strip_prefix("worldinfo/")
+                    .and_then(|n| n.strip_suffix(".json"))
+                {
+                    self.world_books.push(name.to_string());
+                }
+            }
+            _ => {}
+        }
+        if let ["chat_store_v2", scope_dir, session_dir, ..] =
+            rel.split('/').collect::<Vec<_>>().as_slice()
+        {
+            if let (Some(scope), Some(session)) = (
+                scope_dir.strip_prefix("scope_"),
+                session_dir.strip_prefix("session_"),
+            ) {
+                self.push_session(scope.to_string(), session.to_string(), None, None);
+            }
+        }
+        let (Some((base, scope)), Some(content)) = (kv_base(rel), content) else {
+            return;
+        };
+        let Ok(value) = serde_json::from_slice::<serde_json::Value>(content) else {
+            return;
+        };
+        let scope = scope_label(scope);
+        match base {
+            "chat_store_v2" => {
+                let Some(sessions) = value.get("sessions").and_then(|v| v.as_object()) else {
+                    return;
+                };
+                for (id, session) in sessions {
+                    let contact_id = session
+                        .get("contactId")
+                        .and_then(|v| v.as_str())
+                        .map(String::from);
+                    let messages = session
+                        .get("messages")
+                        .and_then(|v| v.as_array())
+                        .map(Vec::len);
+                    self.push_session(scope.clone(), id.clone(), contact_id, messages);
+                }
+            }
+            "contacts_store_v1" => {
+                let Some(contacts) = value.get("contacts").and_then(|v| v.as_object()) else {
+                    return;
+                };
+                for (id, contact) in contacts {
+                    let name = contact
+                        .get("name")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string();
+                    self.contacts.push(PreviewContact {
+                        scope: scope.clone(),
+                        id: id.clone(),
+                        name,
+                    });
+                }
+            }
+            "worldinfo_store" => {
+                if let Some(books) = value.as_object() {
+                    self.world_books.extend(books.keys().cloned());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push_session(
+        &mut self,
+        scope: String,
+        id: String,
+        contact_id: Option<String>,
+        messages: Option<usize>,
+    ) {
+        if !self.seen_sessions.insert((scope.clone(), id.clone())) {
+            return;
+        }
+        self.sessions.push(PreviewSession {
+            scope,
+            id,
+            contact_id,
+            messages,
+        });
+    }
+
+    pub fn finish(&mut self) {
+        self.memory_scopes.sort();
+        self.memory_scopes.dedup();
+        self.world_books.sort();
+        self.world_books.dedup();
+    }
+}
+
+/// 按分类统计的文件数
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CategoryCounts(pub BTreeMap<BundleCategory, usize>);
+
+impl CategoryCounts {
+    pub fn add(&mut self, rel: &str) {
+        *self.0.entry(category_for_path(rel)).or_insert(0) += 1;
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let map: serde_json::Map<String, serde_json::Value> = self
+            .0
+            .iter()
+            .map(|(k, v)| (k.as_str().to_string(), serde_json::Value::from(*v)))
+            .collect();
+        serde_json::Value::Object(map)
+    }
+}
+
+static FALLBACK_WORK_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// 数据目录的上级不可写时（Android、沙盒安装），暂存与快照目录改放在此目录（应用缓存目录）
+pub fn set_fallback_work_root(dir: PathBuf) {
+    let _ = fs::create_dir_all(&dir);
+    let _ = FALLBACK_WORK_ROOT.set(dir);
+}
+
+fn is_writable_dir(dir: &Path) -> bool {
+    let probe = dir.join(format!(".write_probe_{}", std::process::id()));
+    match fs::create_dir(&probe) {
+        Ok(()) => {
+            let _ = fs::remove_dir(&probe);
+            true
+        }
+        Err(err) => err.kind() == std::io::ErrorKind::AlreadyExists,
+    }
+}
+
+/// 数据目录旁的工作目录（暂存、快照等）；上级不可写时落到 `set_fallback_work_root` 设置的目录
+pub fn work_dir(data_dir: &Path, suffix: &str) -> PathBuf {
+    let name = data_dir
+        .file_name()
+        .map_or_else(|| "data".to_string(), |n| n.to_string_lossy().to_string());
+    let file_name = format!("{name}.{suffix}");
+    let sibling = data_dir.with_file_name(&file_name);
+    if sibling.exists() || data_dir.parent().is_some_and(is_writable_dir) {
+        return sibling;
+    }
+    FALLBACK_WORK_ROOT
+        .get()
+        .map_or(sibling, |root| root.join(file_name))
+}
+
+/// 移动文件或目录：优先 rename，跨文件系统时退回复制后删除
+fn move_path(from: &Path, to: &Path) -> Result<(), String> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if from.is_dir() {
+        crate::commands::copy_dir_recursive(from, to)?;
+        fs::remove_dir_all(from).map_err(|e| e.to_string())
+    } else {
+        fs::copy(from, to).map_err(|e| e.to_string())?;
+        fs::remove_file(from).map_err(|e| e.to_string())
+    }
+}
+
+/// 把相对路径列表从一个根目录移到另一个根目录；源中不存在的条目跳过
+pub fn move_entries(from_root: &Path, to_root: &Path, rels: &[String]) -> Result<(), String> {
+    for rel in rels {
+        let from = from_root.join(rel);
+        if from.exists() {
+            move_path(&from, &to_root.join(rel))?;
+        }
+    }
+    Ok(())
+}
+
+/// replace 导入的暂存目录（与数据目录同级，便于整体 rename）
+pub fn staging_dir(data_dir: &Path) -> PathBuf {
+    work_dir(data_dir, "import_staging")
+}
+
+/// merge 导入时在其中合并数据目录的副本，成功后整体换入
+pub fn merge_staging_dir(data_dir: &Path) -> PathBuf {
+    work_dir(data_dir, "merge_staging")
+}
+
+/// 导入前数据目录的快照，保留到前端确认导入后的数据能正常启动
+pub fn snapshot_dir(data_dir: &Path) -> PathBuf {
+    work_dir(data_dir, "pre_import")
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSnapshotInfo {
+    pub path: String,
+    pub created_at: Option<String>,
+    pub launches: u32,
+}
+
+/// 快照目录中的标记文件：创建时间与之后未确认的启动次数
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct SnapshotMarker {
+    created_at: Option<String>,
+    launches: u32,
+    /// 导入时从原数据目录移到新目录的条目（如 backups/），回滚时再移回快照
+    moved: Vec<String>,
+}
+
+fn read_snapshot_marker(snapshot: &Path) -> SnapshotMarker {
+    fs::read_to_string(snapshot.join(SNAPSHOT_MARKER))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn write_snapshot_marker(snapshot: &Path, marker: &SnapshotMarker) -> Result<(), String> {
+    let json = serde_json::to_string(marker).map_err(|e| e.to_string())?;
+    fs::write(snapshot.join(SNAPSHOT_MARKER), json).map_err(|e| e.to_string())
+}
+
+/// 将暂存目录换入为数据目录，原数据目录保留为快照；失败时回滚。
+/// `moved` 为事先从数据目录移入暂存目录的条目，记录在快照中以便回滚时移回
+pub fn swap_in_staging(data_dir: &Path, staging: &Path, moved: &[String]) -> Result<(), String> {
+    let snapshot = snapshot_dir(data_dir);
+    if snapshot.exists() {
+        fs::remove_dir_all(&snapshot).map_err(|e| e.to_string())?;
+    }
+    if data_dir.exists() {
+        fs::rename(data_dir, &snapshot).map_err(|e| format!("snapshot data dir failed: {e}"))?;
+    }
+    if let Err(err) = fs::rename(staging, data_dir) {
+        if snapshot.exists() {
+            let _ = fs::rename(&snapshot, data_dir);
+        }
+        return Err(format!("swap in imported data failed: {err}"));
+    }
+    if snapshot.exists() {
+        let marker = SnapshotMarker {
+            created_at: Some(chrono::Utc::now().to_rfc3339()),
+            launches: 0,
+            moved: moved.to_vec(),
+        };
+        let _ = write_snapshot_marker(&snapshot, &marker);
+    }
+    Ok(())
+}
+
+pub fn import_snapshot_info(data_dir: &Path) -> Option<ImportSnapshotInfo> {
+    let snapshot = snapshot_dir(data_dir);
+    if !snapshot.is_dir() {
+        return None;
+    }
+    let marker = read_snapshot_marker(&snapshot);
+    Some(ImportSnapshotInfo {
+        path: snapshot.to_string_lossy().to_string(),
+        created_at: marker.created_at,
+        launches: marker.launches,
+    })
+}
+
+/// 用快照恢复导入前的数据目录
+pub fn restore_import_snapshot(data_dir: &Path) -> Result<(), String> {
+    let snapshot = snapshot_dir(data_dir);
+    if !snapshot.is_dir() {
+        return Err("no import snapshot to restore".to_string());
+    }
+    let discard = staging_dir(data_dir);
+    if discard.exists() {
+        fs::remove_dir_all(&discard).map_err(|e| e.to_string())?;
+    }
+    let moved = read_snapshot_marker(&snapshot).moved;
+    move_entries(data_dir, &snapshot, &moved)?;
+    let swapped = if data_dir.exists() {
+        fs::rename(data_dir, &discard).map_err(|e| e.to_string())
+    } else {
+        Ok(())
+    };
+    let swapped = swapped.and_then(|()| {
+        fs::rename(&snapshot, data_dir).map_err(|err| {
+            if discard.exists() {
+                let _ = fs::rename(&discard, data_dir);
+            }
+            format!("restore snapshot failed: {err}")
+        })
+    });
+    if let Err(err) = swapped {
+        let _ = move_entries(&snapshot, data_dir, &moved);
+        return Err(err);
+    }
+    let _ = fs::remove_file(data_dir.join(SNAPSHOT_MARKER));
+    let _ = fs::remove_dir_all(&discard);
+    Ok(())
+}
+
+/// 启动时调用（在打开数据库之前）：清理中断导入留下的暂存目录，并累计快照的启动次数。
+/// 导入的数据连续多次启动都未被确认时（例如导致崩溃），自动回滚到快照；返回是否回滚
+pub fn check_import_snapshot_on_launch(data_dir: &Path) -> Result<bool, String> {
+    for staging in [staging_dir(data_dir), merge_staging_dir(data_dir)] {
+        if staging.exists() {
+            fs::remove_dir_all(&staging).map_err(|e| e.to_string())?;
+        }
+    }
+    let snapshot = snapshot_dir(data_dir);
+    if !snapshot.is_dir() {
+        return Ok(false);
+    }
+    let mut marker = read_snapshot_marker(&snapshot);
+    marker.launches += 1;
+    if marker.launches > SNAPSHOT_UNCONFIRMED_LAUNCHES {
+        restore_import_snapshot(data_dir)?;
+        return Ok(true);
+    }
+    write_snapshot_marker(&snapshot, &marker)?;
+    Ok(false)
+}
+
+/// 前端确认导入后的数据已正常加载，此时才删除导入前快照；返回是否删除了快照
+pub fn confirm_import_snapshot(data_dir: &Path) -> Result<bool, String> {
+    let snapshot = snapshot_dir(data_dir);
+    if !snapshot.exists() {
+        return Ok(false);
+    }
+    fs::remove_dir_all(&snapshot).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::make_temp_dir;
+
+    #[test]
+    fn classify_paths() {
+        assert_eq!(
+            category_for_path("chat_store_v2/scope_a/session_1/thread_x/p1.json"),
+            BundleCategory::Chats
+        );
+        assert_eq!(
+            category_for_path("chat_store_v2__persona.json"),
+            BundleCategory::Chats
+        );
+        assert_eq!(
+            category_for_path("memories__persona.db-wal"),
+            BundleCategory::Memories
+        );
+        assert_eq!(
+            category_for_path("worldinfo_store.json"),
+            BundleCategory::WorldInfo
+        );
+        assert_eq!(
+            category_for_path("regex_store_v1.json"),
+            BundleCategory::Regex
+        );
+        assert_eq!(
+            category_for_path("wallpapers/s1/a.png"),
+            BundleCategory::Wallpapers
+        );
+        assert_eq!(
+            category_for_path("app_settings_v1.json"),
+            BundleCategory::Other
+        );
+    }
+
+    #[test]
+    fn selection_filters_chats_by_scope_and_session() {
+        let selection = BundleSelection {
+            include: Some(vec![BundleCategory::Chats, BundleCategory::Memories]),
+            exclude: Vec::new(),
+            chat_scopes: Some(vec!["a".to_string()]),
+            chat_sessions: Some(vec!["s1".to_string()]),
+        };
+        assert!(selection.allows("chat_store_v2"));
+        assert!(selection.allows("chat_store_v2/scope_a/index.json"));
+        assert!(selection.allows("chat_store_v2/scope_a/session_s1/thread_t/p.json"));
+        assert!(!selection.allows("chat_store_v2/scope_a/session_s2"));
+        assert!(!selection.allows("chat_store_v2/scope_b"));
+        assert!(selection.allows("chat_store_v2__a.json"));
+        assert!(!selection.allows("chat_store_v2.json"));
+        assert!(selection.allows("memories.db"));
+        assert!(!selection.allows("wallpapers/x.png"));
+    }
+
+    #[test]
+    fn partial_manifest_limits_import_selection() {
+        let partial = BundleManifest::parse(serde_json::json!({
+            "format": bundle_format(),
+            "partial": true,
+            "categories": ["regex", "chats"]
+        }))
+        .unwrap();
+        let bundled = partial.selection.unwrap();
+        let requested = BundleSelection {
+            include: Some(vec![BundleCategory::Chats, BundleCategory::Memories]),
+            chat_scopes: Some(vec!["a".to_string(), "b".to_string()]),
+            ..BundleSelection::default()
+        };
+        let effective = requested.intersect(&bundled);
+        assert_eq!(effective.categories(), vec![BundleCategory::Chats]);
+        assert!(effective.allows("chat_store_v2/scope_a"));
+        assert!(!effective.allows("memories.db"));
+        assert!(!effective.allows("regex_store_v1.json"));
+
+        let scoped = BundleManifest::parse(serde_json::json!({
+            "format": bundle_format(),
+            "partial": true,
+            "selection": { "chat_scopes": ["b"] }
+        }))
+        .unwrap();
+        let effective = requested.intersect(&scoped.selection.unwrap());
+        assert!(!effective.allows("chat_store_v2/scope_a"));
+        assert!(effective.allows("chat_store_v2/scope_b"));
+
+        let full = BundleManifest::parse(serde_json::json!({
+            "format": bundle_format(),
+            "partial": false,
+            "categories": ["regex"]
+        }))
+        .unwrap();
+        assert!(full.selection.is_none());
+        assert!(!full.chain_delta);
+        let delta = BundleManifest::parse(serde_json::json!({
+            "format": bundle_format(),
+            "partial": true,
+            "chainDelta": true,
+            "selection": {}
+        }))
+        .unwrap();
+        assert!(delta.chain_delta);
+        assert!(BundleSelection::default()
+            .intersect(&BundleSelection::default())
+            .is_full());
+    }
+
+    #[test]
+    fn manifest_format_checks() {
+        let current = BundleManifest::parse(serde_json::json!({
+            "format": bundle_format(),
+            "appVersion": "0.1.0"
+        }))
+        .unwrap();
+        assert!(!current.needs_upgrade());
+        assert_eq!(current.app_version.as_deref(), Some("0.1.0"));
+
+        let newer = serde_json::json!({ "format": "tauri-chat-app-backup-v99" });
+        assert!(BundleManifest::parse(newer).unwrap_err().contains("newer"));
+        let unknown = serde_json::json!({ "format": "something-else" });
+        assert!(BundleManifest::parse(unknown).is_err());
+        assert!(BundleManifest::parse(serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn manifest_checksums_verify_files() {
+        let mut hasher = HashingWriter::new(Vec::new());
+        hasher.write_all(b"hello").unwrap();
+        let digest = hasher.finish_hex();
+        assert_eq!(
+            digest,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        let manifest = BundleManifest::parse(serde_json::json!({
+            "format": bundle_format(),
+            "checksums": { "a.json": digest.to_uppercase() }
+        }))
+        .unwrap();
+        assert!(manifest.verify_file("a.json", &digest).is_ok());
+        assert!(manifest.verify_file("a.json", "00").is_err());
+        assert!(manifest.verify_file("b.json", &digest).is_err());
+    }
+
+    #[test]
+    fn summary_collects_sessions_and_contacts() {
+        let mut summary = BundleContentSummary::default();
+        let chats = serde_json::json!({
+            "sessions": { "s1": { "contactId": "c1", "messages": [1, 2, 3] } }
+        });
+        summary.observe("chat_store_v2__p1.json", Some(chats.to_string().as_bytes()));
+        let contacts = serde_json::json!({ "contacts": { "c1": { "name": "Alice" } } });
+        summary.observe(
+            "contacts_store_v1.json",
+            Some(contacts.to_string().as_bytes()),
+        );
+        summary.observe("chat_store_v2/scope_p1/session_s2/thread_t/p.json", None);
+        summary.observe("chat_store_v2/scope_p1/session_s2/thread_t/q.json", None);
+        summary.observe("memories__p1.db", None);
+        summary.observe("memories__p1.db-wal", None);
+        summary.observe("memories.db", None);
+        summary.observe("wallpapers/a.png", None);
+        summary.finish();
+
+        assert_eq!(summary.sessions.len(), 2);
+        assert_eq!(summary.sessions[0].scope, "p1");
+        assert_eq!(summary.sessions[0].messages, Some(3));
+        assert_eq!(summary.contacts[0].scope, "default");
+        assert_eq!(summary.memory_scopes, vec!["default", "p1"]);
+        assert_eq!(summary.media.wallpapers, 1);
+    }
+
+    #[test]
+    fn manifest_upgrade_chain() {
+        fn rename_legacy(manifest: &mut serde_json::Value, dir: &Path) -> Result<(), String> {
+            manifest["legacy"] = serde_json::Value::Bool(false);
+            fs::rename(dir.join("old.json"), dir.join("new.json")).map_err(|e| e.to_string())
+        }
+        let steps = [BundleUpgrade {
+            from: 1,
+            to: 2,
+            apply: rename_legacy,
+        }];
+        let dir = make_temp_dir("upgrade");
+        fs::write(dir.join("old.json"), "{}").unwrap();
+        let mut manifest =
+            BundleManifest::parse(serde_json::json!({ "format": bundle_format() })).unwrap();
+        manifest.upgrade_with(&dir, &steps, 2).unwrap();
+        assert_eq!(manifest.format_version, 2);
+        assert_eq!(manifest.raw["format"], "tauri-chat-app-backup-v2");
+        assert!(dir.join("new.json").exists());
+        assert!(manifest.upgrade_with(&dir, &steps, 3).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn swap_and_restore_snapshot() {
+        let root = make_temp_dir("swap");
+        let data_dir = root.join("app");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(data_dir.join("old.json"), "old").unwrap();
+        fs::create_dir_all(data_dir.join("backups")).unwrap();
+        fs::write(data_dir.join("backups/auto.zip"), "zip").unwrap();
+        let staging = staging_dir(&data_dir);
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(staging.join("new.json"), "new").unwrap();
+
+        // backups/ 移动而不是复制：换入后只存在于新数据目录
+        let moved = vec!["backups".to_string()];
+        move_entries(&data_dir, &staging, &moved).unwrap();
+        swap_in_staging(&data_dir, &staging, &moved).unwrap();
+        assert!(data_dir.join("new.json").exists());
+        assert!(!data_dir.join("old.json").exists());
+        assert!(data_dir.join("backups/auto.zip").exists());
+        assert!(!snapshot_dir(&data_dir).join("backups").exists());
+        assert!(import_snapshot_info(&data_dir).is_some());
+
+        restore_import_snapshot(&data_dir).unwrap();
+        assert!(data_dir.join("old.json").exists());
+        assert!(!data_dir.join("new.json").exists());
+        assert!(data_dir.join("backups/auto.zip").exists());
+        assert!(!data_dir.join(SNAPSHOT_MARKER).exists());
+        assert!(import_snapshot_info(&data_dir).is_none());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn work_dir_falls_back_when_parent_is_not_writable() {
+        let root = make_temp_dir("work_root");
+        let data_dir = root.join("app");
+        assert_eq!(
+            work_dir(&data_dir, "pre_import"),
+            root.join("app.pre_import")
+        );
+        set_fallback_work_root(root.join("cache"));
+        let missing = root.join("missing_parent").join("app");
+        assert_eq!(
+            work_dir(&missing, "pre_import"),
+            root.join("cache").join("app.pre_import")
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn snapshot_survives_until_confirmed_or_rolls_back() {
+        let root = make_temp_dir("snapshot_launch");
+        let data_dir = root.join("app");
+        let import = |content: &str| {
+            fs::create_dir_all(&data_dir).unwrap();
+            fs::write(data_dir.join("data.json"), "old").unwrap();
+            let staging = staging_dir(&data_dir);
+            fs::create_dir_all(&staging).unwrap();
+            fs::write(staging.join("data.json"), content).unwrap();
+            swap_in_staging(&data_dir, &staging, &[]).unwrap();
+        };
+
+        import("new");
+        assert!(!check_import_snapshot_on_launch(&data_dir).unwrap());
+        assert_eq!(import_snapshot_info(&data_dir).unwrap().launches, 1);
+        assert!(confirm_import_snapshot(&data_dir).unwrap());
+        assert!(import_snapshot_info(&data_dir).is_none());
+        assert!(!check_import_snapshot_on_launch(&data_dir).unwrap());
+
+        // 导入的数据一直没能启动成功：超过次数后回滚
+        import("bad");
+        for _ in 0..SNAPSHOT_UNCONFIRMED_LAUNCHES {
+            assert!(!check_import_snapshot_on_launch(&data_dir).unwrap());
+        }
+        assert!(check_import_snapshot_on_launch(&data_dir).unwrap());
+        assert_eq!(
+            fs::read_to_string(data_dir.join("data.json")).unwrap(),
+            "old"
+        );
+        assert!(import_snapshot_info(&data_dir).is_none());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
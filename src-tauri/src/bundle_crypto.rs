@@ -0,0 +1,347 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use base64::Engine;
+use serde_json::Value;
+use sha2::Sha256;
+use std::io::{Read, Seek, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub const ENCRYPTED_FORMAT: &str = "tauri-chat-app-backup-encrypted-v1";
+pub const PAYLOAD_ENTRY: &str = "payload.bin";
+
+const CIPHER: &str = "aes-256-gcm-stream";
+const KDF: &str = "pbkdf2-hmac-sha256";
+const DEFAULT_ITERATIONS: u32 = 600_000;
+const MIN_ITERATIONS: u32 = 10_000;
+const SALT_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// 资料包加密参数（写入外层 bundle.json 的 `encryption` 字段）
+///
+/// 载荷按块做 AES-256-GCM，nonce = 7 字节前缀 + 4 字节块序号 + 1 字节末块标记，
+/// 可以检测块被截断、重排或替换。
+#[derive(Debug, Clone)]
+pub struct EnvelopeParams {
+    salt: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    iterations: u32,
+    chunk_size: usize,
+}
+
+impl EnvelopeParams {
+    pub fn generate() -> Self {
+        Self::with_iterations(DEFAULT_ITERATIONS)
+    }
+
+    fn with_iterations(iterations: u32) -> Self {
+        let mut salt = vec![0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        Self {
+            salt,
+            nonce_prefix,
+            iterations,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "cipher": CIPHER,
+            "kdf": KDF,
+            "iterations": self.iterations,
+            "salt": BASE64_ENGINE.encode(&self.salt),
+            "noncePrefix": BASE64_ENGINE.encode(self.nonce_prefix),
+            "chunkSize": self.chunk_size,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let text = |key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_str)
+                .ok_or_else(|| format!("encryption.{key} missing"))
+        };
+        if text("cipher")? != CIPHER {
+            return Err("unsupported bundle cipher".to_string());
+        }
+        if text("kdf")? != KDF {
+            return Err("unsupported bundle kdf".to_string());
+        }
+        let iterations = value
+            .get("iterations")
+            .and_then(Value::as_u64)
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= MIN_ITERATIONS)
+            .ok_or_else(|| "invalid kdf iterations".to_string())?;
+        let salt = BASE64_ENGINE
+            .decode(text("salt")?)
+            .map_err(|e| e.to_string())?;
+        let prefix = BASE64_ENGINE
+            .decode(text("noncePrefix")?)
+            .map_err(|e| e.to_string())?;
+        let nonce_prefix: [u8; NONCE_PREFIX_LEN] = prefix
+            .try_into()
+            .map_err(|_| "invalid nonce prefix".to_string())?;
+        let chunk_size = value
+            .get("chunkSize")
+            .and_then(Value::as_u64)
+            .and_then(|v| usize::try_from(v).ok())
+            .filter(|v| *v > 0 && *v <= MAX_CHUNK_SIZE)
+            .ok_or_else(|| "invalid chunk size".to_string())?;
+        if salt.len() < SALT_LEN {
+            return Err("invalid kdf salt".to_string());
+        }
+        Ok(Self {
+            salt,
+            nonce_prefix,
+            iterations,
+            chunk_size,
+        })
+    }
+
+    fn cipher(&self, passphrase: &str) -> Aes256Gcm {
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &self.salt, self.iterations, &mut key);
+        let cipher = Aes256Gcm::new((&key).into());
+        key.fill(0);
+        cipher
+    }
+
+    fn nonce(&self, counter: u32, last: bool) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+        nonce[11] = u8::from(last);
+        nonce
+    }
+}
+
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, String> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+    Ok(filled)
+}
+
+fn next_counter(counter: u32) -> Result<u32, String> {
+    counter
+        .checked_add(1)
+        .ok_or_else(|| "bundle payload too large".to_string())
+}
+
+pub fn encrypt_stream<R: Read, W: Write>(
+    params: &EnvelopeParams,
+    passphrase: &str,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64, String> {
+    let cipher = params.cipher(passphrase);
+    let mut current = vec![0u8; params.chunk_size];
+    let mut next = vec![0u8; params.chunk_size];
+    let mut len = read_full(reader, &mut current)?;
+    let mut counter = 0u32;
+    let mut written = 0u64;
+    loop {
+        let next_len = if len == params.chunk_size {
+            read_full(reader, &mut next)?
+        } else {
+            0
+        };
+        let last = next_len == 0;
+        let nonce = params.nonce(counter, last);
+        let sealed = cipher
+            .encrypt(Nonce::from_slice(&nonce), &current[..len])
+            .map_err(|_| "encrypt bundle failed".to_string())?;
+        writer.write_all(&sealed).map_err(|e| e.to_string())?;
+        written += sealed.len() as u64;
+        if last {
+            break;
+        }
+        std::mem::swap(&mut current, &mut next);
+        len = next_len;
+        counter = next_counter(counter)?;
+    }
+    Ok(written)
+}
+
+pub fn decrypt_stream<R: Read, W: Write>(
+    params: &EnvelopeParams,
+    passphrase: &str,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64, String> {
+    let cipher = params.cipher(passphrase);
+    let block = params.chunk_size + TAG_LEN;
+    let mut current = vec![0u8; block];
+    let mut next = vec![0u8; block];
+    let mut len = read_full(reader, &mut current)?;
+    let mut counter = 0u32;
+    let mut written = 0u64;
+    loop {
+        if len < TAG_LEN {
+            return Err("bundle payload truncated".to_string());
+        }
+        let next_len = if len == block {
+            read_full(reader, &mut next)?
+        } else {
+            0
+        };
+        let last = next_len == 0;
+        let nonce = params.nonce(counter, last);
+        let plain = cipher
+            .decrypt(Nonce::from_slice(&nonce), &current[..len])
+            .map_err(|_| {
+                if counter == 0 {
+                    "wrong passphrase or corrupted bundle".to_string()
+                } else {
+                    "bundle payload corrupted".to_string()
+                }
+            })?;
+        writer.write_all(&plain).map_err(|e| e.to_string())?;
+        written += plain.len() as u64;
+        if last {
+            break;
+        }
+        std::mem::swap(&mut current, &mut next);
+        len = next_len;
+        counter = next_counter(counter)?;
+    }
+    Ok(written)
+}
+
+/// 读取外层 bundle.json；未加密的资料包返回 None
+pub fn read_envelope<R: Read + Seek>(reader: R) -> Result<Option<EnvelopeParams>, String> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| e.to_string())?;
+    let Ok(mut entry) = archive.by_name("bundle.json") else {
+        return Ok(None);
+    };
+    let mut raw = String::new();
+    entry.read_to_string(&mut raw).map_err(|e| e.to_string())?;
+    let Ok(manifest) = serde_json::from_str::<Value>(&raw) else {
+        return Ok(None);
+    };
+    if manifest.get("format").and_then(Value::as_str) != Some(ENCRYPTED_FORMAT) {
+        return Ok(None);
+    }
+    let encryption = manifest
+        .get("encryption")
+        .ok_or_else(|| "encrypted bundle missing encryption params".to_string())?;
+    EnvelopeParams::from_json(encryption).map(Some)
+}
+
+/// 将明文资料包加密写入外层 zip（bundle.json + payload.bin）
+pub fn write_encrypted_bundle<R: Read, W: Write + Seek>(
+    plain: &mut R,
+    output: W,
+    passphrase: &str,
+) -> Result<(), String> {
+    if passphrase.is_empty() {
+        return Err("passphrase empty".to_string());
+    }
+    let params = EnvelopeParams::generate();
+    let mut writer = ZipWriter::new(output);
+    let manifest = serde_json::json!({
+        "format": ENCRYPTED_FORMAT,
+        "createdAt": chrono::Utc::now().to_rfc3339(),
+        "appVersion": env!("CARGO_PKG_VERSION"),
+        "encryption": params.to_json(),
+    });
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    writer
+        .start_file("bundle.json", deflated)
+        .map_err(|e| e.to_string())?;
+    writer
+        .write_all(manifest.to_string().as_bytes())
+        .map_err(|e| e.to_string())?;
+    let stored = FileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+    writer
+        .start_file(PAYLOAD_ENTRY, stored)
+        .map_err(|e| e.to_string())?;
+    encrypt_stream(&params, passphrase, plain, &mut writer)?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// 解密外层 zip 中的 payload.bin，输出内层明文资料包
+pub fn decrypt_bundle_payload<R: Read + Seek, W: Write>(
+    reader: R,
+    params: &EnvelopeParams,
+    passphrase: &str,
+    output: &mut W,
+) -> Result<u64, String> {
+    if passphrase.is_empty() {
+        return Err("bundle is encrypted: passphrase required".to_string());
+    }
+    let mut archive = ZipArchive::new(reader).map_err(|e| e.to_string())?;
+    let mut payload = archive
+        .by_name(PAYLOAD_ENTRY)
+        .map_err(|_| "encrypted bundle missing payload".to_string())?;
+    decrypt_stream(params, passphrase, &mut payload, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn test_params(chunk_size: usize) -> EnvelopeParams {
+        let mut params = EnvelopeParams::with_iterations(MIN_ITERATIONS);
+        params.chunk_size = chunk_size;
+        params
+    }
+
+    fn roundtrip(params: &EnvelopeParams, data: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::new();
+        encrypt_stream(params, "secret", &mut Cursor::new(data), &mut sealed).unwrap();
+        let mut plain = Vec::new();
+        decrypt_stream(params, "secret", &mut Cursor::new(sealed), &mut plain).unwrap();
+        plain
+    }
+
+    #[test]
+    fn stream_roundtrip_chunk_boundaries() {
+        let params = test_params(16);
+        for len in [0usize, 1, 15, 16, 17, 32, 100] {
+            let data: Vec<u8> = (0..len).map(|i| u8::try_from(i % 251).unwrap()).collect();
+            assert_eq!(roundtrip(&params, &data), data, "len {len}");
+        }
+    }
+
+    #[test]
+    fn wrong_passphrase_and_truncation_fail() {
+        let params = test_params(16);
+        let data = vec![7u8; 40];
+        let mut sealed = Vec::new();
+        encrypt_stream(&params, "secret", &mut Cursor::new(&data), &mut sealed).unwrap();
+
+        let mut out = Vec::new();
+        let err = decrypt_stream(&params, "nope", &mut Cursor::new(&sealed), &mut out).unwrap_err();
+        assert_eq!(err, "wrong passphrase or corrupted bundle");
+
+        let truncated = &sealed[..sealed.len() - (16 + TAG_LEN)];
+        let mut out = Vec::new();
+        assert!(decrypt_stream(&params, "secret", &mut Cursor::new(truncated), &mut out).is_err());
+
+        let restored = EnvelopeParams::from_json(&params.to_json()).unwrap();
+        let mut out = Vec::new();
+        decrypt_stream(&restored, "secret", &mut Cursor::new(&sealed), &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+}
@@ -0,0 +1,1188 @@
+use crate::auto_backup::BACKUP_DIR;
+use crate::bundle::{self, category_for_path, BundleCategory};
+use crate::commands::copy_dir_recursive;
+use crate::memory_db::ACTOR_IMPORT;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// merge 导入时的冲突策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergePolicy {
+    /// 按 `updatedAt` / `updated_at`（缺失时按文件修改时间）保留较新的一方
+    #[default]
+    NewestWins,
+    KeepLocal,
+    /// 冲突记录以 `<id>_imported` 另存一份
+    KeepBoth,
+}
+
+/// 记录级合并统计（JSON 记录与数据库行）
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MergeStats {
+    pub added: usize,
+    pub updated: usize,
+    pub kept_local: usize,
+    pub duplicated: usize,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MergeReport {
+    pub files_added: usize,
+    pub files_merged: usize,
+    pub files_overwritten: usize,
+    pub files_kept_local: usize,
+    pub files_duplicated: usize,
+    pub unchanged: usize,
+    pub records: MergeStats,
+    pub warnings: Vec<String>,
+}
+
+const IMPORTED_SUFFIX: &str = "_imported";
+const MEMORY_DB_TABLES: [&str; 2] = ["templates", "memories"];
+
+fn record_time(value: &Value) -> Option<i64> {
+    ["updatedAt", "updated_at", "timestamp"]
+        .iter()
+        .find_map(|key| {
+            let field = value.get(*key)?;
+            field.as_i64().or_else(|| {
+                chrono::DateTime::parse_from_rfc3339(field.as_str()?)
+                    .ok()
+                    .map(|dt| dt.timestamp_millis())
+            })
+        })
+}
+
+fn record_id(value: &Value) -> Option<String> {
+    match value.get("id")? {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// 以 id 为键的对象集合，如 `{ sessions: { s1: { id: "s1", ... } } }`
+fn is_id_map(map: &Map<String, Value>) -> bool {
+    !map.is_empty()
+        && map.values().all(Value::is_object)
+        && map
+            .iter()
+            .any(|(key, value)| record_id(value).as_deref() == Some(key))
+}
+
+fn is_id_array(items: &[Value]) -> bool {
+    !items.is_empty() && items.iter().all(|item| record_id(item).is_some())
+}
+
+fn imported_key(base: &str, taken: impl Fn(&str) -> bool) -> String {
+    let mut key = format!("{base}{IMPORTED_SUFFIX}");
+    let mut n = 2;
+    while taken(&key) {
+        key = format!("{base}{IMPORTED_SUFFIX}_{n}");
+        n += 1;
+    }
+    key
+}
+
+fn with_id(mut record: Value, id: &str) -> Value {
+    if let Some(obj) = record.as_object_mut() {
+        if obj.contains_key("id") {
+            obj.insert("id".to_string(), Value::String(id.to_string()));
+        }
+    }
+    record
+}
+
+/// 两条记录冲突时是否采用导入方；None 表示保留两份
+fn resolve_conflict(
+    local: &Value,
+    incoming: &Value,
+    policy: MergePolicy,
+    prefer_incoming: bool,
+) -> Option<bool> {
+    match policy {
+        MergePolicy::KeepBoth => None,
+        MergePolicy::KeepLocal => Some(false),
+        MergePolicy::NewestWins => Some(match (record_time(local), record_time(incoming)) {
+            (Some(l), Some(i)) => i > l,
+            _ => prefer_incoming,
+        }),
+    }
+}
+
+fn count_resolution(stats: &mut MergeStats, take_incoming: bool) {
+    if take_incoming {
+        stats.updated += 1;
+    } else {
+        stats.kept_local += 1;
+    }
+}
+
+/// 合并两份 JSON：id 集合按记录合并，其余字段按 `prefer_incoming` 取值
+pub fn merge_json(
+    local: &mut Value,
+    incoming: Value,
+    policy: MergePolicy,
+    prefer_incoming: bool,
+    stats: &mut MergeStats,
+) {
+    match (local, incoming) {
+        (Value::Object(l), Value::Object(i)) => {
+            merge_object(l, i, policy, prefer_incoming, stats);
+        }
+        (Value::Array(l), Value::Array(i))
+            if is_id_array(&i) && (l.is_empty() || is_id_array(l)) =>
+        {
+            merge_id_array(l, i, policy, prefer_incoming, stats);
+        }
+        (l, i) => {
+            if *l != i && prefer_incoming {
+                *l = i;
+            }
+        }
+    }
+}
+
+fn merge_object(
+    local: &mut Map<String, Value>,
+    incoming: Map<String, Value>,
+    policy: MergePolicy,
+    prefer_incoming: bool,
+    stats: &mut MergeStats,
+) {
+    let id_map = is_id_map(&incoming) && (local.is_empty() || is_id_map(local));
+    for (key, value) in incoming {
+        let Some(existing) = local.get_mut(&key) else {
+            if id_map {
+                stats.added += 1;
+            }
+            local.insert(key, value);
+            continue;
+        };
+        if *existing == value {
+            continue;
+        }
+        if !id_map {
+            merge_json(existing, value, policy, prefer_incoming, stats);
+            continue;
+        }
+        if let Some(take_incoming) = resolve_conflict(existing, &value, policy, prefer_incoming) {
+            merge_json(existing, value, policy, take_incoming, stats);
+            count_resolution(stats, take_incoming);
+        } else {
+            let new_key = imported_key(&key, |k| local.contains_key(k));
+            local.insert(new_key.clone(), with_id(value, &new_key));
+            stats.duplicated += 1;
+        }
+    }
+}
+
+fn merge_id_array(
+    local: &mut Vec<Value>,
+    incoming: Vec<Value>,
+    policy: MergePolicy,
+    prefer_incoming: bool,
+    stats: &mut MergeStats,
+) {
+    let mut positions: HashMap<String, usize> = local
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, item)| record_id(item).map(|id| (id, idx)))
+        .collect();
+    let mut appended = false;
+    for value in incoming {
+        let Some(id) = record_id(&value) else {
+            continue;
+        };
+        let Some(&idx) = positions.get(&id) else {
+            positions.insert(id, local.len());
+            local.push(value);
+            stats.added += 1;
+            appended = true;
+            continue;
+        };
+        if local[idx] == value {
+            continue;
+        }
+        if let Some(take_incoming) = resolve_conflict(&local[idx], &value, policy, prefer_incoming)
+        {
+            merge_json(&mut local[idx], value, policy, take_incoming, stats);
+            count_resolution(stats, take_incoming);
+        } else {
+            let new_id = imported_key(&id, |k| positions.contains_key(k));
+            positions.insert(new_id.clone(), local.len());
+            local.push(with_id(value, &new_id));
+            stats.duplicated += 1;
+            appended = true;
+        }
+    }
+    // 追加了新记录时按时间重排（如聊天消息）
+    if appended && local.iter().all(|item| record_time(item).is_some()) {
+        local.sort_by_key(|item| record_time(item).unwrap_or_default());
+    }
+}
+
+fn session_time(session: &Value) -> Option<i64> {
+    let last_message = session
+        .get("messages")
+        .and_then(Value::as_array)
+        .and_then(|messages| messages.iter().filter_map(record_time).max());
+    record_time(session).max(last_message)
+}
+
+/// 单文件聊天存储按会话合并：会话整体取舍，不在会话内逐条混合消息；
+/// 当前会话等其余字段保留本地。不是会话结构时按普通 JSON 合并
+fn merge_chat_json(
+    local: &mut Value,
+    mut incoming: Value,
+    policy: MergePolicy,
+    prefer_incoming: bool,
+    stats: &mut MergeStats,
+) {
+    let is_sessions = |value: &Value| value.get("sessions").is_some_and(Value::is_object);
+    if !is_sessions(local) || !is_sessions(&incoming) {
+        merge_json(local, incoming, policy, prefer_incoming, stats);
+        return;
+    }
+    let Some(Value::Object(incoming_sessions)) = incoming.get_mut("sessions").map(Value::take)
+    else {
+        return;
+    };
+    let Some(sessions) = local.get_mut("sessions").and_then(Value::as_object_mut) else {
+        return;
+    };
+    for (id, session) in incoming_sessions {
+        let Some(existing) = sessions.get(&id) else {
+            sessions.insert(id, session);
+            stats.added += 1;
+            continue;
+        };
+        if *existing == session {
+            continue;
+        }
+        let take_incoming = match policy {
+            MergePolicy::KeepBoth => None,
+            MergePolicy::KeepLocal => Some(false),
+            MergePolicy::NewestWins => {
+                Some(match (session_time(existing), session_time(&session)) {
+                    (Some(l), Some(i)) => i > l,
+                    _ => prefer_incoming,
+                })
+            }
+        };
+        match take_incoming {
+            Some(true) => {
+                sessions.insert(id, session);
+                stats.updated += 1;
+            }
+            Some(false) => stats.kept_local += 1,
+            None => {
+                let new_id = imported_key(&id, |k| sessions.contains_key(k));
+                sessions.insert(new_id.clone(), with_id(session, &new_id));
+                stats.duplicated += 1;
+            }
+        }
+    }
+}
+
+pub fn is_memory_db_sidecar(rel: &str) -> bool {
+    category_for_path(rel) == BundleCategory::Memories
+        && (rel.ends_with(".db-wal") || rel.ends_with(".db-shm"))
+}
+
+fn has_extension(rel: &str, ext: &str) -> bool {
+    Path::new(rel)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(ext))
+}
+
+fn is_memory_db(rel: &str) -> bool {
+    category_for_path(rel) == BundleCategory::Memories && has_extension(rel, "db")
+}
+
+fn is_mergeable_json(rel: &str) -> bool {
+    has_extension(rel, "json")
+        && !matches!(
+            category_for_path(rel),
+            BundleCategory::Wallpapers
+                | BundleCategory::Attachments
+                | BundleCategory::Media
+                | BundleCategory::RawReplies
+        )
+}
+
+fn table_columns(conn: &Connection, schema: &str, table: &str) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA {schema}.table_info({table})"))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())
+}
+
+/// 逐行合并记忆数据库（按 id 匹配，按 `updated_at` 判定新旧）；
+/// 模板被记忆行引用无法另存副本，`keep_both` 时冲突的模板保留本地并写入 warnings
+pub fn merge_memory_db(
+    local: &Path,
+    incoming: &Path,
+    policy: MergePolicy,
+    warnings: &mut Vec<String>,
+) -> Result<MergeStats, String> {
+    let mut conn = Connection::open(local).map_err(|e| e.to_string())?;
+    conn.execute(
+        "ATTACH DATABASE ?1 AS incoming",
+        params![incoming.to_string_lossy()],
+    )
+    .map_err(|e| e.to_string())?;
+    let result = merge_attached_tables(&mut conn, policy, warnings);
+    let _ = conn.execute("DETACH DATABASE incoming", []);
+    result
+}
+
+fn merge_attached_tables(
+    conn: &mut Connection,
+    policy: MergePolicy,
+    warnings: &mut Vec<String>,
+) -> Result<MergeStats, String> {
+    let mut stats = MergeStats::default();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let history = has_table(&tx, "main", "memory_history")?;
+    // templates 先于 memories，保证外键引用存在
+    for table in MEMORY_DB_TABLES {
+        if has_table(&tx, "incoming", table)? {
+            let history = history && table == "memories";
+            merge_attached_table(&tx, table, policy, history, &mut stats, warnings)?;
+        }
+    }
+    if has_table(&tx, "main", "memory_embeddings")?
+        && has_table(&tx, "incoming", "memory_embeddings")?
+    {
+        merge_attached_embeddings(&tx)?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(stats)
+}
+
+fn has_table(conn: &Connection, schema: &str, table: &str) -> Result<bool, String> {
+    conn.query_row(
+        &format!("SELECT name FROM {schema}.sqlite_master WHERE type = 'table' AND name = ?1"),
+        params![table],
+        |row| row.get::<_, String>(0),
+    )
+    .optional()
+    .map(|name| name.is_some())
+    .map_err(|e| e.to_string())
+}
+
+fn merge_attached_table(
+    tx: &Transaction<'_>,
+    table: &str,
+    policy: MergePolicy,
+    history: bool,
+    stats: &mut MergeStats,
+    warnings: &mut Vec<String>,
+) -> Result<(), String> {
+    let local_cols = table_columns(tx, "main", table)?;
+    let incoming_cols = table_columns(tx, "incoming", table)?;
+    let cols: Vec<&str> = local_cols
+        .iter()
+        .filter(|c| incoming_cols.contains(c))
+        .map(String::as_str)
+        .collect();
+    if !cols.contains(&"id") || !cols.contains(&"updated_at") {
+        return Err(format!("memory db table {table} missing id/updated_at"));
+    }
+    let col_list = cols.join(", ");
+    let inc_list = cols
+        .iter()
+        .map(|c| format!("i.{c}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let added = format!("FROM incoming.{table} i WHERE i.id NOT IN (SELECT id FROM main.{table})");
+    if history {
+        record_import_history(tx, "create", "i.id", "NULL", &added)?;
+    }
+    stats.added += tx
+        .execute(
+            &format!("INSERT INTO main.{table} ({col_list}) SELECT {inc_list} {added}"),
+            [],
+        )
+        .map_err(|e| e.to_string())?;
+
+    let conflict = format!(
+        "SELECT i.id FROM incoming.{table} i JOIN main.{table} m ON m.id = i.id \
+         WHERE i.updated_at IS NOT m.updated_at"
+    );
+    let conflicts: usize = tx
+        .query_row(&format!("SELECT COUNT(*) FROM ({conflict})"), [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .map_err(|e| e.to_string())
+        .map(|n| usize::try_from(n).unwrap_or_default())?;
+
+    match table_policy(table, policy, conflicts, warnings) {
+        MergePolicy::NewestWins => {
+            if history {
+                record_import_history(
+                    tx,
+                    "update",
+                    "i.id",
+                    "m.row_data",
+                    &format!(
+                        "FROM incoming.{table} i JOIN main.{table} m ON m.id = i.id \
+                         WHERE i.updated_at > m.updated_at"
+                    ),
+                )?;
+            }
+            let updated = tx
+                .execute(
+                    &format!(
+                        "UPDATE main.{table} SET ({col_list}) = \
+                         (SELECT {inc_list} FROM incoming.{table} i WHERE i.id = main.{table}.id) \
+                         WHERE id IN (SELECT i.id FROM incoming.{table} i JOIN main.{table} m \
+                         ON m.id = i.id WHERE i.updated_at > m.updated_at)"
+                    ),
+                    [],
+                )
+                .map_err(|e| e.to_string())?;
+            stats.updated += updated;
+            stats.kept_local += conflicts.saturating_sub(updated);
+        }
+        MergePolicy::KeepLocal => stats.kept_local += conflicts,
+        MergePolicy::KeepBoth => {
+            let copy_list = cols
+                .iter()
+                .map(|c| {
+                    if *c == "id" {
+                        format!("i.id || '{IMPORTED_SUFFIX}'")
+                    } else {
+                        format!("i.{c}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let duplicated = format!(
+                "FROM incoming.{table} i WHERE i.id IN ({conflict}) \
+                 AND i.id || '{IMPORTED_SUFFIX}' NOT IN (SELECT id FROM main.{table})"
+            );
+            if history {
+                let id = format!("i.id || '{IMPORTED_SUFFIX}'");
+                record_import_history(tx, "create", &id, "NULL", &duplicated)?;
+            }
+            stats.duplicated += tx
+                .execute(
+                    &format!(
+                        "INSERT INTO main.{table} ({col_list}) SELECT {copy_list} {duplicated}"
+                    ),
+                    [],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// 为合并写入或覆盖的记忆行记录 `import` 历史，`source` 为以 `i` 指代导入行的 FROM 子句
+fn record_import_history(
+    tx: &Transaction<'_>,
+    op: &str,
+    memory_id: &str,
+    old_data: &str,
+    source: &str,
+) -> Result<(), String> {
+    tx.execute(
+        &format!(
+            "INSERT INTO main.memory_history (memory_id, op, template_id, table_id, contact_id, \
+             group_id, old_data, new_data, actor, created_at) SELECT {memory_id}, ?1, \
+             i.template_id, i.table_id, i.contact_id, i.group_id, {old_data}, i.row_data, ?2, ?3 \
+             {source}"
+        ),
+        params![op, ACTOR_IMPORT, chrono::Utc::now().timestamp_millis()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// 内容与导入方一致的记忆行沿用导入方的向量（含 `keep_both` 另存的副本），本地已有的不覆盖
+fn merge_attached_embeddings(tx: &Transaction<'_>) -> Result<(), String> {
+    for memory_id in [
+        "e.memory_id".to_string(),
+        format!("e.memory_id || '{IMPORTED_SUFFIX}'"),
+    ] {
+        tx.execute(
+            &format!(
+                "INSERT INTO main.memory_embeddings \
+                 (memory_id, model, dim, vector, quantized, scale, embedded_at) \
+                 SELECT {memory_id}, e.model, e.dim, e.vector, e.quantized, e.scale, e.embedded_at \
+                 FROM incoming.memory_embeddings e \
+                 JOIN incoming.memories i ON i.id = e.memory_id \
+                 JOIN main.memories m ON m.id = {memory_id} \
+                 WHERE m.row_data = i.row_data \
+                 AND m.id NOT IN (SELECT memory_id FROM main.memory_embeddings)"
+            ),
+            [],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn table_policy(
+    table: &str,
+    policy: MergePolicy,
+    conflicts: usize,
+    warnings: &mut Vec<String>,
+) -> MergePolicy {
+    if table != "templates" || policy != MergePolicy::KeepBoth {
+        return policy;
+    }
+    if conflicts > 0 {
+        warnings.push(format!(
+            "{conflicts} conflicting memory templates kept local: keep_both only applies to memory rows"
+        ));
+    }
+    MergePolicy::KeepLocal
+}
+
+fn imported_file_path(target: &Path) -> std::path::PathBuf {
+    let stem = target
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match target.extension() {
+        Some(ext) => format!("{stem}{IMPORTED_SUFFIX}.{}", ext.to_string_lossy()),
+        None => format!("{stem}{IMPORTED_SUFFIX}"),
+    };
+    target.with_file_name(name)
+}
+
+fn copy_file(src: &Path, dst: &Path) -> Result<(), String> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::copy(src, dst).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+type JsonMerge = fn(&mut Value, Value, MergePolicy, bool, &mut MergeStats);
+
+fn merge_json_file(
+    staged: &Path,
+    target: &Path,
+    merge: JsonMerge,
+    policy: MergePolicy,
+    prefer_incoming: bool,
+    stats: &mut MergeStats,
+) -> Result<bool, String> {
+    let read = |path: &Path| {
+        fs::read(path)
+            .ok()
+            .and_then(|raw| serde_json::from_slice::<Value>(&raw).ok())
+    };
+    let (Some(mut local), Some(incoming)) = (read(target), read(staged)) else {
+        return Ok(false);
+    };
+    merge(&mut local, incoming, policy, prefer_incoming, stats);
+    let json = serde_json::to_string_pretty(&local).map_err(|e| e.to_string())?;
+    fs::write(target, json).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// 在数据目录的副本上合并暂存内容，成功后整体换入（原目录保留为导入前快照）；
+/// 任一步失败时数据目录保持原样。`before_swap` 是换入前最后的取消检查点
+pub fn merge_staged(
+    staging: &Path,
+    data_dir: &Path,
+    policy: MergePolicy,
+    bundle_modified: &BTreeMap<String, i64>,
+    before_swap: &mut dyn FnMut() -> Result<(), String>,
+) -> Result<MergeReport, String> {
+    let merged = bundle::merge_staging_dir(data_dir);
+    if merged.exists() {
+        fs::remove_dir_all(&merged).map_err(|e| e.to_string())?;
+    }
+    // 自动备份不参与合并，换入前整体移入副本而不是复制
+    let moved = vec![BACKUP_DIR.to_string()];
+    let result = copy_data_dir(data_dir, &merged, &moved)
+        .and_then(|()| merge_into(staging, &merged, policy, bundle_modified))
+        .and_then(|report| {
+            before_swap()?;
+            bundle::move_entries(data_dir, &merged, &moved)?;
+            if let Err(err) = bundle::swap_in_staging(data_dir, &merged, &moved) {
+                let _ = bundle::move_entries(&merged, data_dir, &moved);
+                return Err(err);
+            }
+            Ok(report)
+        });
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&merged);
+    }
+    result
+}
+
+/// 复制数据目录作为合并副本，跳过 `skip` 中的顶层条目
+fn copy_data_dir(data_dir: &Path, merged: &Path, skip: &[String]) -> Result<(), String> {
+    fs::create_dir_all(merged).map_err(|e| e.to_string())?;
+    if !data_dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(data_dir).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if skip.contains(&name) {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            copy_dir_recursive(&path, &merged.join(&name))?;
+        } else {
+            fs::copy(&path, merged.join(&name)).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// 将暂存目录中的资料包内容合并进 `data_dir`（就地修改，只对 `merge_staged` 的副本调用）
+fn merge_into(
+    staging: &Path,
+    data_dir: &Path,
+    policy: MergePolicy,
+    bundle_modified: &BTreeMap<String, i64>,
+) -> Result<MergeReport, String> {
+    let mut report = MergeReport::default();
+    merge_chat_sessions(staging, data_dir, policy, bundle_modified, &mut report)?;
+    merge_dir(
+        staging,
+        staging,
+        data_dir,
+        policy,
+        bundle_modified,
+        &mut report,
+    )?;
+    Ok(report)
+}
+
+/// 目录下全部文件的相对路径（`/` 分隔，已排序）
+fn tree_files(base: &Path, dir: &Path, out: &mut Vec<String>) -> Result<(), String> {
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_dir() {
+            tree_files(base, &path, out)?;
+        } else if let Ok(rel) = path.strip_prefix(base) {
+            out.push(rel.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    out.sort();
+    Ok(())
+}
+
+/// 分片聊天存储以会话目录为单位合并：同一会话的分片互相依赖，不能逐文件取舍
+fn merge_chat_sessions(
+    staging: &Path,
+    data_dir: &Path,
+    policy: MergePolicy,
+    bundle_modified: &BTreeMap<String, i64>,
+    report: &mut MergeReport,
+) -> Result<(), String> {
+    let base = staging.join("chat_store_v2");
+    if !base.is_dir() {
+        return Ok(());
+    }
+    for scope in fs::read_dir(&base).map_err(|e| e.to_string())? {
+        let scope = scope.map_err(|e| e.to_string())?.path();
+        if !scope.is_dir() {
+            continue;
+        }
+        for session in fs::read_dir(&scope).map_err(|e| e.to_string())? {
+            let session = session.map_err(|e| e.to_string())?.path();
+            let rel = session
+                .strip_prefix(staging)
+                .map_err(|_| "invalid staging path".to_string())?
+                .to_string_lossy()
+                .replace('\\', "/");
+            if session.is_dir() && bundle::chat_session_dir(&format!("{rel}/")).is_some() {
+                merge_chat_session(&session, &rel, data_dir, policy, bundle_modified, report)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn same_contents(a: &Path, b: &Path, files: &[String]) -> Result<bool, String> {
+    for file in files {
+        if bundle::sha256_file(&a.join(file))? != bundle::sha256_file(&b.join(file))? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn merge_chat_session(
+    incoming: &Path,
+    rel: &str,
+    data_dir: &Path,
+    policy: MergePolicy,
+    bundle_modified: &BTreeMap<String, i64>,
+    report: &mut MergeReport,
+) -> Result<(), String> {
+    let target = data_dir.join(rel);
+    let mut incoming_files = Vec::new();
+    tree_files(incoming, incoming, &mut incoming_files)?;
+    if !target.exists() {
+        copy_dir_recursive(incoming, &target)?;
+        report.files_added += incoming_files.len();
+        report.records.added += 1;
+        return Ok(());
+    }
+    let mut local_files = Vec::new();
+    tree_files(&target, &target, &mut local_files)?;
+    if local_files == incoming_files && same_contents(incoming, &target, &incoming_files)? {
+        report.unchanged += incoming_files.len();
+        return Ok(());
+    }
+    let take_incoming = match policy {
+        MergePolicy::NewestWins => {
+            let incoming_time = incoming_files
+                .iter()
+                .filter_map(|file| bundle_modified.get(&format!("{rel}/{file}")).copied())
+                .max();
+            let local_time = local_files
+                .iter()
+                .filter_map(|file| bundle::file_modified_ms(&target.join(file)))
+                .max();
+            incoming_time
+                .zip(local_time)
+                .is_some_and(|(incoming, local)| incoming > local)
+        }
+        MergePolicy::KeepLocal => false,
+        MergePolicy::KeepBoth => {
+            // 会话由所在 scope 的 index.json 引用，另存的副本不会被加载
+            report.warnings.push(format!(
+                "{rel}: keep_both is not supported for chat sessions, kept local"
+            ));
+            false
+        }
+    };
+    if take_incoming {
+        fs::remove_dir_all(&target).map_err(|e| e.to_string())?;
+        copy_dir_recursive(incoming, &target)?;
+        report.files_overwritten += incoming_files.len();
+        report.records.updated += 1;
+    } else {
+        report.files_kept_local += incoming_files.len();
+        report.records.kept_local += 1;
+    }
+    Ok(())
+}
+
+fn merge_dir(
+    staging: &Path,
+    dir: &Path,
+    data_dir: &Path,
+    policy: MergePolicy,
+    bundle_modified: &BTreeMap<String, i64>,
+    report: &mut MergeReport,
+) -> Result<(), String> {
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path();
+        if path.is_dir() {
+            merge_dir(staging, &path, data_dir, policy, bundle_modified, report)?;
+            continue;
+        }
+        let rel = path
+            .strip_prefix(staging)
+            .map_err(|_| "invalid staging path".to_string())?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if bundle::chat_session_dir(&rel).is_some() {
+            // 已在 merge_chat_sessions 中按会话处理
+            continue;
+        }
+        let target = data_dir.join(&rel);
+        if is_memory_db_sidecar(&rel) {
+            // 本地没有对应数据库时随数据库一起复制，否则由逐行合并处理
+            let db_rel = rel.trim_end_matches("-wal").trim_end_matches("-shm");
+            if !data_dir.join(db_rel).exists() {
+                copy_file(&path, &target)?;
+            }
+            continue;
+        }
+        if !target.exists() {
+            copy_file(&path, &target)?;
+            report.files_added += 1;
+            continue;
+        }
+        if bundle::sha256_file(&path)? == bundle::sha256_file(&target)? {
+            report.unchanged += 1;
+            continue;
+        }
+        let prefer_incoming = match policy {
+            MergePolicy::NewestWins => bundle_modified
+                .get(&rel)
+                .copied()
+                .zip(bundle::file_modified_ms(&target))
+                .is_some_and(|(incoming, local)| incoming > local),
+            MergePolicy::KeepLocal | MergePolicy::KeepBoth => false,
+        };
+        if is_memory_db(&rel) {
+            let stats = merge_memory_db(&target, &path, policy, &mut report.warnings)
+                .map_err(|e| format!("merge {rel} failed: {e}"))?;
+            add_stats(&mut report.records, &stats);
+            report.files_merged += 1;
+            continue;
+        }
+        if is_mergeable_json(&rel) {
+            let mut stats = MergeStats::default();
+            let merge: JsonMerge = if bundle::is_chat_kv_store(&rel) {
+                merge_chat_json
+            } else {
+                merge_json
+            };
+            if merge_json_file(&path, &target, merge, policy, prefer_incoming, &mut stats)? {
+                add_stats(&mut report.records, &stats);
+                report.files_merged += 1;
+                continue;
+            }
+            report
+                .warnings
+                .push(format!("{rel}: invalid json, merged as file"));
+        }
+        match policy {
+            MergePolicy::KeepBoth => {
+                copy_file(&path, &imported_file_path(&target))?;
+                report.files_duplicated += 1;
+            }
+            _ if prefer_incoming => {
+                copy_file(&path, &target)?;
+                report.files_overwritten += 1;
+            }
+            _ => report.files_kept_local += 1,
+        }
+    }
+    Ok(())
+}
+
+fn add_stats(total: &mut MergeStats, stats: &MergeStats) {
+    total.added += stats.added;
+    total.updated += stats.updated;
+    total.kept_local += stats.kept_local;
+    total.duplicated += stats.duplicated;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::make_temp_dir;
+    use serde_json::json;
+
+    #[test]
+    fn merge_sessions_by_id() {
+        let mut local = json!({
+            "sessions": {
+                "s1": { "id": "s1", "updatedAt": 10, "messages": [{ "id": "m1", "timestamp": 1 }] },
+                "s2": { "id": "s2", "updatedAt": 50, "title": "local" }
+            },
+            "currentSessionId": "s1"
+        });
+        let incoming = json!({
+            "sessions": {
+                "s1": { "id": "s1", "updatedAt": 20, "messages": [
+                    { "id": "m1", "timestamp": 1 }, { "id": "m2", "timestamp": 2 }
+                ] },
+                "s2": { "id": "s2", "updatedAt": 40, "title": "incoming" },
+                "s3": { "id": "s3", "updatedAt": 5 }
+            },
+            "currentSessionId": "s3"
+        });
+        let mut stats = MergeStats::default();
+        merge_json(
+            &mut local,
+            incoming.clone(),
+            MergePolicy::NewestWins,
+            false,
+            &mut stats,
+        );
+        assert_eq!(
+            local["sessions"]["s1"]["messages"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(local["sessions"]["s1"]["updatedAt"], 20);
+        assert_eq!(local["sessions"]["s2"]["title"], "local");
+        assert!(local["sessions"]["s3"].is_object());
+        assert_eq!(local["currentSessionId"], "s1");
+        assert_eq!(stats.updated, 1);
+        assert_eq!(stats.kept_local, 1);
+        assert_eq!(stats.added, 2);
+
+        let mut both = json!({ "contacts": { "c1": { "id": "c1", "name": "A" } } });
+        let mut stats = MergeStats::default();
+        merge_json(
+            &mut both,
+            json!({ "contacts": { "c1": { "id": "c1", "name": "B" } } }),
+            MergePolicy::KeepBoth,
+            false,
+            &mut stats,
+        );
+        assert_eq!(both["contacts"]["c1"]["name"], "A");
+        assert_eq!(both["contacts"]["c1_imported"]["id"], "c1_imported");
+        assert_eq!(stats.duplicated, 1);
+    }
+
+    #[test]
+    fn merge_memory_rows_by_updated_at() {
+        let dir = make_temp_dir("rows");
+        let schema = include_str!("memory_schema.sql");
+        let seed = |path: &Path, rows: &[(&str, &str, i64)]| {
+            let conn = Connection::open(path).unwrap();
+            conn.execute_batch(schema).unwrap();
+            for sql in [
+                include_str!("memory_embeddings_v3.sql"),
+                include_str!("memory_history_v4.sql"),
+                include_str!("memory_history_state_v6.sql"),
+            ] {
+                conn.execute_batch(sql).unwrap();
+            }
+            conn.execute(
+                "INSERT INTO templates (id, name, schema, created_at, updated_at) VALUES ('t', 't', '{}', 0, 0)",
+                [],
+            )
+            .unwrap();
+            for (id, data, updated) in rows {
+                conn.execute(
+                    "INSERT INTO memories (id, template_id, table_id, row_data, created_at, updated_at) \
+                     VALUES (?1, 't', 'tb', ?2, 0, ?3)",
+                    params![id, data, updated],
+                )
+                .unwrap();
+            }
+            for (id, ..) in rows {
+                conn.execute(
+                    "INSERT INTO memory_embeddings (memory_id, model, dim, vector, quantized, scale, embedded_at) \
+                     VALUES (?1, 'm', 1, x'00', x'00', 1.0, 0)",
+                    params![id],
+                )
+                .unwrap();
+            }
+        };
+        let local = dir.join("local.db");
+        let incoming = dir.join("incoming.db");
+        seed(&local, &[("b", "local-b", 30)]);
+        Connection::open(&local)
+            .unwrap()
+            .execute(
+                "INSERT INTO memories (id, template_id, table_id, row_data, created_at, updated_at) \
+                 VALUES ('a', 't', 'tb', 'local-a', 0, 10)",
+                [],
+            )
+            .unwrap();
+        seed(
+            &incoming,
+            &[("a", "inc-a", 20), ("b", "inc-b", 20), ("c", "inc-c", 1)],
+        );
+
+        let mut warnings = Vec::new();
+        let stats =
+            merge_memory_db(&local, &incoming, MergePolicy::NewestWins, &mut warnings).unwrap();
+        assert_eq!((stats.added, stats.updated, stats.kept_local), (1, 1, 1));
+        assert!(warnings.is_empty());
+        let conn = Connection::open(&local).unwrap();
+        let data = |id: &str| -> String {
+            conn.query_row(
+                "SELECT row_data FROM memories WHERE id = ?1",
+                params![id],
+                |r| r.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(data("a"), "inc-a");
+        assert_eq!(data("b"), "local-b");
+        assert_eq!(data("c"), "inc-c");
+        // 改动的行写入 import 历史，内容一致的行沿用导入方的向量
+        let history: Vec<(String, String, Option<String>)> = conn
+            .prepare("SELECT memory_id, op, old_data FROM memory_history WHERE actor = 'import' ORDER BY memory_id")
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            history,
+            vec![
+                (
+                    "a".to_string(),
+                    "update".to_string(),
+                    Some("local-a".to_string())
+                ),
+                ("c".to_string(), "create".to_string(), None),
+            ]
+        );
+        let embedded: Vec<String> = conn
+            .prepare("SELECT memory_id FROM memory_embeddings ORDER BY memory_id")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(embedded, ["a", "b", "c"]);
+
+        // 模板无法另存副本，keep_both 下冲突的模板保留本地并给出警告
+        Connection::open(&incoming)
+            .unwrap()
+            .execute("UPDATE templates SET name = 'inc', updated_at = 5", [])
+            .unwrap();
+        let stats =
+            merge_memory_db(&local, &incoming, MergePolicy::KeepBoth, &mut warnings).unwrap();
+        assert_eq!(stats.duplicated, 1);
+        assert_eq!(stats.kept_local, 1);
+        assert_eq!(data("b_imported"), "inc-b");
+        let copied: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM memory_embeddings WHERE memory_id = 'b_imported'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(copied, 1);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("memory templates kept local"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn read_json(path: &Path) -> Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn staged_merge_swaps_in_and_merges_chats_per_session() {
+        let dir = make_temp_dir("staged_merge");
+        let data = dir.join("data");
+        let staging = dir.join("staging");
+        let kv = "chat_store_v2__p1.json";
+        let part = "chat_store_v2/scope_p1/session_x/thread_t/p.json";
+        write(
+            &data.join(kv),
+            &json!({
+                "sessions": {
+                    "s1": { "id": "s1", "updatedAt": 10, "messages": [{ "id": "m1", "timestamp": 1, "text": "a" }] },
+                    "s2": { "id": "s2", "updatedAt": 50, "messages": [] }
+                },
+                "currentSessionId": "s1"
+            })
+            .to_string(),
+        );
+        write(&data.join(part), "[\"local\"]");
+        write(&data.join("backups/auto.zip"), "zip");
+        write(
+            &staging.join(kv),
+            &json!({
+                "sessions": {
+                    "s1": { "id": "s1", "updatedAt": 20, "messages": [{ "id": "m2", "timestamp": 2, "text": "b" }] },
+                    "s2": { "id": "s2", "updatedAt": 40, "messages": [{ "id": "m3", "timestamp": 3 }] },
+                    "s3": { "id": "s3", "updatedAt": 5, "messages": [] }
+                },
+                "currentSessionId": "s3"
+            })
+            .to_string(),
+        );
+        write(&staging.join(part), "[\"incoming\"]");
+        write(
+            &staging.join("chat_store_v2/scope_p1/session_x/thread_t/q.json"),
+            "[]",
+        );
+        write(
+            &staging.join("chat_store_v2/scope_p1/session_y/thread_t/p.json"),
+            "[]",
+        );
+        let modified = BTreeMap::from([(part.to_string(), i64::MAX)]);
+
+        // 换入前失败时数据目录保持原样，也不留下合并副本
+        let err = merge_staged(
+            &staging,
+            &data,
+            MergePolicy::NewestWins,
+            &modified,
+            &mut || Err("cancelled".to_string()),
+        )
+        .unwrap_err();
+        assert_eq!(err, "cancelled");
+        assert_eq!(fs::read_to_string(data.join(part)).unwrap(), "[\"local\"]");
+        assert!(!bundle::merge_staging_dir(&data).exists());
+        assert!(data.join("backups/auto.zip").exists());
+
+        let report = merge_staged(
+            &staging,
+            &data,
+            MergePolicy::NewestWins,
+            &modified,
+            &mut || Ok(()),
+        )
+        .unwrap();
+        assert!(bundle::snapshot_dir(&data).join(part).exists());
+        assert_eq!(
+            fs::read_to_string(data.join("backups/auto.zip")).unwrap(),
+            "zip"
+        );
+        assert!(!bundle::snapshot_dir(&data).join("backups").exists());
+        let store = read_json(&data.join(kv));
+        // 较新的会话整体取用，不与本地消息混合
+        assert_eq!(store["sessions"]["s1"]["messages"][0]["id"], "m2");
+        assert_eq!(
+            store["sessions"]["s1"]["messages"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(store["sessions"]["s2"]["messages"], json!([]));
+        assert!(store["sessions"]["s3"].is_object());
+        assert_eq!(store["currentSessionId"], "s1");
+        assert_eq!(
+            fs::read_to_string(data.join(part)).unwrap(),
+            "[\"incoming\"]"
+        );
+        assert!(data
+            .join("chat_store_v2/scope_p1/session_x/thread_t/q.json")
+            .exists());
+        assert!(data.join("chat_store_v2/scope_p1/session_y").is_dir());
+        assert_eq!(report.records.added, 2);
+        assert_eq!(report.records.updated, 2);
+        assert_eq!(report.records.kept_local, 1);
+
+        // keep_both 另存冲突的会话；分片会话无法另存，保留本地并给出警告
+        write(&staging.join(part), "[\"changed\"]");
+        let report = merge_staged(
+            &staging,
+            &data,
+            MergePolicy::KeepBoth,
+            &modified,
+            &mut || Ok(()),
+        )
+        .unwrap();
+        let store = read_json(&data.join(kv));
+        assert_eq!(store["sessions"]["s2_imported"]["id"], "s2_imported");
+        assert_eq!(
+            fs::read_to_string(data.join(part)).unwrap(),
+            "[\"incoming\"]"
+        );
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("session_x"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,285 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+pub const PROGRESS_EVENT: &str = "bundle-progress";
+pub const CANCELLED_ERROR: &str = "operation cancelled";
+pub const DATA_DIR_BUSY_ERROR: &str = "another operation is using the data directory";
+
+const EMIT_INTERVAL: Duration = Duration::from_millis(150);
+
+/// 对数据目录的访问方式：导入/恢复/拉取等写入操作独占，导出/备份等只读操作可共享
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataAccess {
+    Shared,
+    Exclusive,
+}
+
+#[derive(Default)]
+struct LockState {
+    readers: usize,
+    writer: bool,
+}
+
+/// 数据目录读写锁；取不到时立即失败而不是等待
+#[derive(Default)]
+pub struct DataDirLock {
+    state: Mutex<LockState>,
+}
+
+impl DataDirLock {
+    pub fn acquire(self: &Arc<Self>, access: DataAccess) -> Result<DataDirGuard, String> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "data directory lock poisoned".to_string())?;
+        let free = match access {
+            DataAccess::Shared => !state.writer,
+            DataAccess::Exclusive => !state.writer && state.readers == 0,
+        };
+        if !free {
+            return Err(DATA_DIR_BUSY_ERROR.to_string());
+        }
+        match access {
+            DataAccess::Shared => state.readers += 1,
+            DataAccess::Exclusive => state.writer = true,
+        }
+        Ok(DataDirGuard {
+            lock: self.clone(),
+            access,
+        })
+    }
+}
+
+/// 持有期间占用数据目录，drop 时释放
+pub struct DataDirGuard {
+    lock: Arc<DataDirLock>,
+    access: DataAccess,
+}
+
+impl Drop for DataDirGuard {
+    fn drop(&mut self) {
+        let mut state = match self.lock.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match self.access {
+            DataAccess::Shared => state.readers = state.readers.saturating_sub(1),
+            DataAccess::Exclusive => state.writer = false,
+        }
+    }
+}
+
+/// 进行中的导入/导出操作（operation id -> 取消标记）与数据目录锁
+#[derive(Default)]
+pub struct BundleOperations {
+    inner: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    data_dir: Arc<DataDirLock>,
+}
+
+impl BundleOperations {
+    /// 局域网同步的监听线程等不经过 `register` 的写入方共用同一把锁
+    pub fn data_dir_lock(&self) -> Arc<DataDirLock> {
+        self.data_dir.clone()
+    }
+
+    pub fn lock_data_dir(&self, access: DataAccess) -> Result<DataDirGuard, String> {
+        self.data_dir.acquire(access)
+    }
+
+    pub fn register(&self, operation_id: &str) -> Result<Arc<AtomicBool>, String> {
+        let mut map = self
+            .inner
+            .lock()
+            .map_err(|_| "operation state lock poisoned".to_string())?;
+        if map.contains_key(operation_id) {
+            return Err(format!("operation already running: {operation_id}"));
+        }
+        let flag = Arc::new(AtomicBool::new(false));
+        map.insert(operation_id.to_string(), flag.clone());
+        Ok(flag)
+    }
+
+    pub fn finish(&self, operation_id: &str) {
+        if let Ok(mut map) = self.inner.lock() {
+            map.remove(operation_id);
+        }
+    }
+
+    pub fn cancel(&self, operation_id: &str) -> bool {
+        let Ok(map) = self.inner.lock() else {
+            return false;
+        };
+        map.get(operation_id).is_some_and(|flag| {
+            flag.store(true, Ordering::SeqCst);
+            true
+        })
+    }
+}
+
+pub fn new_operation_id(kind: &str) -> String {
+    format!(
+        "{kind}_{}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    )
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleProgress {
+    pub operation_id: String,
+    pub phase: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub current_path: Option<String>,
+}
+
+impl BundleProgress {
+    fn initial(operation_id: &str) -> Self {
+        Self {
+            operation_id: operation_id.to_string(),
+            phase: "starting".to_string(),
+            files_done: 0,
+            files_total: 0,
+            bytes_done: 0,
+            bytes_total: 0,
+            current_path: None,
+        }
+    }
+}
+
+/// 节流发送进度事件，并在每一步检查取消标记
+pub struct ProgressReporter {
+    app: Option<AppHandle>,
+    cancel: Arc<AtomicBool>,
+    state: BundleProgress,
+    last_emit: Option<Instant>,
+}
+
+impl ProgressReporter {
+    pub fn new(app: AppHandle, operation_id: &str, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            app: Some(app),
+            cancel,
+            state: BundleProgress::initial(operation_id),
+            last_emit: None,
+        }
+    }
+
+    /// 不发送事件的进度器，供测试使用
+    #[cfg(test)]
+    pub fn detached(operation_id: &str, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            app: None,
+            cancel,
+            state: BundleProgress::initial(operation_id),
+            last_emit: None,
+        }
+    }
+
+    pub fn check_cancelled(&self) -> Result<(), String> {
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(CANCELLED_ERROR.to_string());
+        }
+        Ok(())
+    }
+
+    /// 进入新阶段；totals 为 None 时沿用之前的总量
+    pub fn phase(&mut self, phase: &str, totals: Option<(usize, u64)>) -> Result<(), String> {
+        self.check_cancelled()?;
+        self.state.phase = phase.to_string();
+        if let Some((files, bytes)) = totals {
+            self.state.files_total = files;
+            self.state.bytes_total = bytes;
+            self.state.files_done = 0;
+            self.state.bytes_done = 0;
+        }
+        self.state.current_path = None;
+        self.emit(true);
+        Ok(())
+    }
+
+    pub fn file_done(&mut self, path: &str, bytes: u64) -> Result<(), String> {
+        self.check_cancelled()?;
+        self.state.files_done += 1;
+        self.state.bytes_done += bytes;
+        self.state.current_path = Some(path.to_string());
+        self.emit(false);
+        Ok(())
+    }
+
+    pub fn finish(&mut self) {
+        self.state.phase = "done".to_string();
+        self.state.current_path = None;
+        self.emit(true);
+    }
+
+    fn emit(&mut self, force: bool) {
+        let Some(app) = &self.app else {
+            return;
+        };
+        let now = Instant::now();
+        if !force
+            && self
+                .last_emit
+                .is_some_and(|t| now.duration_since(t) < EMIT_INTERVAL)
+        {
+            return;
+        }
+        self.last_emit = Some(now);
+        let _ = app.emit(PROGRESS_EVENT, self.state.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_marks_registered_operation() {
+        let ops = BundleOperations::default();
+        let flag = ops.register("export_1").unwrap();
+        assert!(ops.register("export_1").is_err());
+        assert!(!ops.cancel("missing"));
+        assert!(ops.cancel("export_1"));
+
+        let mut reporter = ProgressReporter {
+            app: None,
+            cancel: flag,
+            state: BundleProgress::initial("export_1"),
+            last_emit: None,
+        };
+        assert_eq!(
+            reporter.file_done("a.json", 1),
+            Err(CANCELLED_ERROR.to_string())
+        );
+        ops.finish("export_1");
+        assert!(!ops.cancel("export_1"));
+        assert!(ops.register("export_1").is_ok());
+    }
+
+    #[test]
+    fn data_dir_lock_is_shared_or_exclusive() {
+        let ops = BundleOperations::default();
+        let export = ops.lock_data_dir(DataAccess::Shared).unwrap();
+        let backup = ops.lock_data_dir(DataAccess::Shared).unwrap();
+        assert_eq!(
+            ops.lock_data_dir(DataAccess::Exclusive).err().as_deref(),
+            Some(DATA_DIR_BUSY_ERROR)
+        );
+        drop(export);
+        assert!(ops.lock_data_dir(DataAccess::Exclusive).is_err());
+        drop(backup);
+
+        let import = ops.data_dir_lock().acquire(DataAccess::Exclusive).unwrap();
+        assert!(ops.lock_data_dir(DataAccess::Exclusive).is_err());
+        assert!(ops.lock_data_dir(DataAccess::Shared).is_err());
+        drop(import);
+        assert!(ops.lock_data_dir(DataAccess::Exclusive).is_ok());
+    }
+}
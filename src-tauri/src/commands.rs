@@ -1,18 +1,36 @@
+use crate::auto_backup::{self, AutoBackupConfig, AutoBackupEntry};
+use crate::backup_chain::{self, BackupChain, BackupPoint, BackupPointInfo, LocalFile, PointKind};
+use crate::bundle::{
+    self, BundleCategory, BundleContentSummary, BundleFileIndex, BundleManifest, BundleSelection,
+    CategoryCounts, HashingWriter, ImportSnapshotInfo,
+};
+use crate::bundle_crypto;
+use crate::bundle_merge::{self, MergePolicy, MergeReport};
+use crate::bundle_progress::{self, BundleOperations, DataAccess, ProgressReporter};
+use crate::embeddings::{self, EmbeddingConfigInput, EmbeddingConfigView};
+use crate::lan_sync::{self, LanConnectOptions, LanHostInfo, LanSyncResult, LanSyncState};
+use crate::media_packs::{self, MediaPackImportResult, MediaPackInfo};
 use crate::memory_db::{
-    MemoryCreateInput, MemoryDb, MemoryQuery, MemoryRecord, MemoryUpdateInput, TemplateInput,
-    TemplateQuery, TemplateRecord,
+    MemoryCreateInput, MemoryDb, MemoryHistoryEntry, MemoryOp, MemoryOpsResult, MemoryPage,
+    MemoryPageQuery, MemoryQuery, MemoryRecord, MemorySearchHit, MemoryTrash, MemoryUpdateInput,
+    TemplateInput, TemplateQuery, TemplateRecord, TemplateUpgradeInput, TemplateUpgradePreview,
+    TemplateUpgradeResult, TrashPurgeResult, UndoResult, ACTOR_USER,
 };
+use crate::memory_template_file::{self, TemplateConflict, TemplateImportResult};
+use crate::s3_backup::{self, S3BackupEntry, S3ConfigInput, S3ConfigView};
+use crate::secret_store;
 use crate::storage::{simple_decrypt, simple_encrypt, ChatMessage};
+use crate::webdav_sync::{self, SyncStatus, WebDavConfigInput, WebDavConfigView};
 use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
 use base64::Engine;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::{Read, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
-use tauri::{AppHandle, Manager, State};
+use tauri::{AppHandle, Emitter, Manager, State};
 use zip::write::FileOptions;
 use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
@@ -100,7 +118,7 @@ fn chat_store_v2_thread_dir(
         .join(format!("thread_{thread_key}")))
 }
 
-fn write_json_file(path: &Path, data: &Value) -> Result<(), String> {
+pub(crate) fn write_json_file(path: &Path, data: &Value) -> Result<(), String> {
     let json = serde_json::to_string_pretty(data).map_err(|e| e.to_string())?;
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).map_err(|e| e.to_string())?;
@@ -166,7 +184,7 @@ fn raw_reply_path(app: &AppHandle, session_id: &str, message_id: &str) -> Result
         .join(format!("{mid}.txt")))
 }
 
-fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<(), String> {
+pub(crate) fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<(), String> {
     if !src.exists() {
         return Err(format!("source directory missing: {}", src.display()));
     }
@@ -191,6 +209,14 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<(), String> {
 pub struct MediaBundleInfo {
     pub ready: bool,
     pub copied: bool,
+    pub upgraded: bool,
+    pub previous_version: Option<i64>,
+    pub version: Option<i64>,
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    /// 用户改动过、升级时保留未覆盖的内置文件
+    pub kept_modified: Vec<String>,
+    pub kept_user_items: usize,
     pub base_dir: String,
     pub manifest: Option<Value>,
     pub warning: Option<String>,
@@ -235,12 +261,72 @@ pub struct DataBundleResult {
     pub path: String,
     pub bytes: u64,
     pub files: usize,
+    pub encrypted: bool,
+    pub categories: CategoryCounts,
+}
+
+#[derive(serde::Serialize)]
+pub struct DataBundleVerifyResult {
+    pub ok: bool,
+    pub has_checksums: bool,
+    pub checked: usize,
+    pub mismatched: Vec<String>,
+    pub missing: Vec<String>,
+    pub unlisted: Vec<String>,
+    pub format_version: u32,
+    pub source_app_version: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct BundleOverwrite {
+    pub path: String,
+    pub local_modified: Option<i64>,
+    pub bundle_modified: Option<i64>,
+    pub local_sha256: String,
+    pub bundle_sha256: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct DataBundlePreview {
+    pub mode: String,
+    pub format_version: u32,
+    pub source_app_version: Option<String>,
+    pub created_at: Option<String>,
+    pub files: usize,
+    pub bytes: u64,
+    pub summary: BundleContentSummary,
+    /// merge：本地不存在、将新增的文件数
+    pub new_files: usize,
+    /// merge：内容相同的文件数
+    pub unchanged: usize,
+    /// merge：内容不同、将被覆盖的文件数
+    pub changed: usize,
+    /// merge：将覆盖比资料包更新的本地文件
+    pub overwrites: Vec<BundleOverwrite>,
+    /// replace：本地存在但资料包中没有、将被移除的文件数
+    pub removed_local: usize,
 }
 
 #[derive(serde::Serialize)]
 pub struct DataBundleImportResult {
     pub files: usize,
     pub skipped: usize,
+    pub categories: CategoryCounts,
+    pub format_version: u32,
+    pub upgraded_from: Option<u32>,
+    pub source_app_version: Option<String>,
+    pub merge: Option<MergeReport>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncResult {
+    pub direction: &'static str,
+    pub revision: u64,
+    pub changed: bool,
+    pub files: usize,
+    pub bytes: u64,
+    pub import: Option<DataBundleImportResult>,
 }
 
 fn decode_base64_payload(payload: &str) -> Result<Vec<u8>, String> {
@@ -594,154 +680,711 @@ fn publish_bundle_to_downloads(
         .or_else(|_| publish_bundle_legacy(app, source_path, file_name))
 }
 
-fn clear_data_dir(dir: &Path) -> Result<(), String> {
-    if !dir.exists() {
-        return Ok(());
-    }
-    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path = entry.path();
-        if path.is_dir() {
-            fs::remove_dir_all(&path).map_err(|e| e.to_string())?;
-        } else {
-            fs::remove_file(&path).map_err(|e| e.to_string())?;
-        }
+fn bundle_rel_name(base: &Path, path: &Path) -> Result<String, String> {
+    let rel = path
+        .strip_prefix(base)
+        .map_err(|_| "invalid base path".to_string())?;
+    Ok(rel.to_string_lossy().replace('\\', "/"))
+}
+
+/// rel 为数据目录内的相对路径（`/` 分隔）；敏感配置按文件名匹配，
+/// 自动备份只排除顶层的 `backups/`，同名的子目录照常打包
+pub(crate) fn is_sensitive_bundle_path(rel: &str) -> bool {
+    let rel = rel.trim_start_matches('/');
+    if rel.split('/').next() == Some(auto_backup::BACKUP_DIR) {
+        return true;
+    }
+    let name = rel
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    // 数据库迁移/损坏时留下的备份只在本机排障用
+    if Path::new(name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("bak"))
+    {
+        return true;
     }
-    Ok(())
-}
-
-fn is_sensitive_bundle_path(path: &Path) -> bool {
-    let name = match path.file_name().and_then(|s| s.to_str()) {
-        Some(value) => value,
-        None => return false,
-    };
+    // 同步配置同样只保留在本机，不打包也不被导入覆盖
     matches!(
         name,
         "config.json"
             | "llm_profiles_v1.json"
             | "llm_keyring_v1.json"
             | "llm_keyring_master_v1.json"
+            | secret_store::MASTER_KEY_FILE
+            | webdav_sync::CONFIG_FILE
+            | webdav_sync::STATE_FILE
+            | s3_backup::CONFIG_FILE
+            | embeddings::CONFIG_FILE
     )
 }
 
+/// 写入资料包时的遍历状态
+struct BundleWriteState<'a> {
+    skip: &'a [&'a Path],
+    selection: &'a BundleSelection,
+    /// 仅写入这些文件（增量备份）；None 表示全部
+    only: Option<&'a BTreeSet<String>>,
+    index: BundleFileIndex,
+    progress: &'a mut ProgressReporter,
+}
+
 fn add_dir_to_zip<W: Write + Seek>(
     writer: &mut ZipWriter<W>,
     base: &Path,
     dir: &Path,
     options: FileOptions,
-    skip: Option<&Path>,
+    state: &mut BundleWriteState,
 ) -> Result<usize, String> {
-    if is_sensitive_bundle_path(dir) {
+    let rel = bundle_rel_name(base, dir)?;
+    if is_sensitive_bundle_path(&rel) {
         return Ok(0);
     }
-    if let Some(skip_path) = skip {
-        if dir == skip_path {
-            return Ok(0);
-        }
+    if state.skip.contains(&dir) {
+        return Ok(0);
+    }
+    if !rel.is_empty() && !state.selection.allows(&rel) {
+        return Ok(0);
     }
     if dir.is_dir() {
-        let rel = dir
-            .strip_prefix(base)
-            .map_err(|_| "invalid base path".to_string())?;
-        if !rel.as_os_str().is_empty() {
-            let name = format!("{}/", rel.to_string_lossy().replace('\\', "/"));
+        if !rel.is_empty() && state.only.is_none() {
             writer
-                .add_directory(name, options)
+                .add_directory(format!("{rel}/"), options)
                 .map_err(|e| e.to_string())?;
         }
         let mut count = 0;
         for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
             let entry = entry.map_err(|e| e.to_string())?;
-            count += add_dir_to_zip(writer, base, &entry.path(), options, skip)?;
+            count += add_dir_to_zip(writer, base, &entry.path(), options, state)?;
         }
         return Ok(count);
     }
-    let rel = dir
-        .strip_prefix(base)
-        .map_err(|_| "invalid base path".to_string())?;
-    let name = rel.to_string_lossy().replace('\\', "/");
+    if state.only.is_some_and(|only| !only.contains(&rel)) {
+        return Ok(0);
+    }
     writer
-        .start_file(name, options)
+        .start_file(rel.as_str(), options)
         .map_err(|e| e.to_string())?;
     let mut file = fs::File::open(dir).map_err(|e| e.to_string())?;
-    std::io::copy(&mut file, writer).map_err(|e| e.to_string())?;
+    let mut hashing = HashingWriter::new(&mut *writer);
+    let bytes = std::io::copy(&mut file, &mut hashing).map_err(|e| e.to_string())?;
+    state
+        .index
+        .record(&rel, hashing.finish_hex(), bundle::file_modified_ms(dir));
+    state.progress.file_done(&rel, bytes)?;
     Ok(1)
 }
 
+fn write_bundle_zip(
+    data_dir: &Path,
+    output_path: &Path,
+    skip: &[&Path],
+    selection: &BundleSelection,
+    only: Option<&BTreeSet<String>>,
+    progress: &mut ProgressReporter,
+) -> Result<(usize, BundleFileIndex), String> {
+    progress.phase("scanning", None)?;
+    let mut total_bytes = 0u64;
+    let mut local_files = list_local_files(data_dir, data_dir, selection)?;
+    if let Some(only) = only {
+        local_files.retain(|rel| only.contains(rel));
+    }
+    for rel in &local_files {
+        total_bytes += fs::metadata(data_dir.join(rel)).map_or(0, |m| m.len());
+    }
+    progress.phase("writing", Some((local_files.len(), total_bytes)))?;
+
+    let file = fs::File::create(output_path).map_err(|e| e.to_string())?;
+    let mut writer = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut state = BundleWriteState {
+        skip,
+        selection,
+        only,
+        index: BundleFileIndex::default(),
+        progress,
+    };
+    let files = add_dir_to_zip(&mut writer, data_dir, data_dir, options, &mut state)?;
+    let index = state.index;
+
+    // bundle.json 最后写入，以便记录实际包含的分类与文件摘要
+    let manifest = bundle_manifest_json(selection, &index, only.is_some());
+    writer
+        .start_file("bundle.json", options)
+        .map_err(|e| e.to_string())?;
+    writer
+        .write_all(manifest.to_string().as_bytes())
+        .map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok((files, index))
+}
+
+/// `chain_delta` 为增量备份点写入的包：只含变化文件，标记为部分包且拒绝单独导入
+fn bundle_manifest_json(
+    selection: &BundleSelection,
+    index: &BundleFileIndex,
+    chain_delta: bool,
+) -> Value {
+    let categories: Vec<&str> = selection
+        .categories()
+        .into_iter()
+        .map(BundleCategory::as_str)
+        .collect();
+    serde_json::json!({
+        "format": bundle::bundle_format(),
+        "createdAt": chrono::Utc::now().to_rfc3339(),
+        "appVersion": env!("CARGO_PKG_VERSION"),
+        "excluded": [
+            "config.json",
+            "llm_profiles_v1.json",
+            "llm_keyring_v1.json",
+            "llm_keyring_master_v1.json",
+            "backups/",
+            secret_store::MASTER_KEY_FILE,
+            webdav_sync::CONFIG_FILE,
+            webdav_sync::STATE_FILE,
+            s3_backup::CONFIG_FILE,
+            embeddings::CONFIG_FILE
+        ],
+        "partial": chain_delta || !selection.is_full(),
+        "chainDelta": chain_delta,
+        "selection": selection,
+        "categories": categories,
+        "categoryCounts": index.counts.to_json(),
+        "checksumAlgorithm": "sha256",
+        "checksums": index.checksums,
+        "modified": index.modified,
+    })
+}
+
+fn temp_bundle_path(app: &AppHandle, tag: &str) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_cache_dir()
+        .unwrap_or_else(|_| std::env::temp_dir());
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let ts = chrono::Utc::now().timestamp_millis();
+    Ok(dir.join(format!("bundle_{tag}_{ts}.zip")))
+}
+
+/// 加密资料包先解密到缓存目录再导入；口令错误时不会触碰数据目录
+fn import_bundle_auto<R: Read + Seek>(
+    app: &AppHandle,
+    data_dir: &Path,
+    memory_db: &MemoryDb,
+    mut reader: R,
+    options: &BundleImportOptions,
+    progress: &mut ProgressReporter,
+) -> Result<DataBundleImportResult, String> {
+    let envelope = bundle_crypto::read_envelope(&mut reader)?;
+    reader.rewind().map_err(|e| e.to_string())?;
+    let Some(params) = envelope else {
+        return import_bundle_from_reader(data_dir, memory_db, reader, options, progress);
+    };
+    progress.phase("decrypting", None)?;
+    let temp_path = decrypt_bundle_to_temp(app, reader, &params, options.passphrase.as_deref())?;
+    let result = fs::File::open(&temp_path)
+        .map_err(|e| e.to_string())
+        .and_then(|plain| import_bundle_from_reader(data_dir, memory_db, plain, options, progress));
+    let _ = fs::remove_file(&temp_path);
+    result
+}
+
+/// 解密资料包到临时文件，调用方负责删除
+fn decrypt_bundle_to_temp<R: Read + Seek>(
+    app: &AppHandle,
+    reader: R,
+    params: &bundle_crypto::EnvelopeParams,
+    passphrase: Option<&str>,
+) -> Result<PathBuf, String> {
+    let passphrase = passphrase
+        .filter(|p| !p.is_empty())
+        .ok_or_else(|| "bundle is encrypted: passphrase required".to_string())?;
+    let temp_path = temp_bundle_path(app, "decrypted")?;
+    let result = fs::File::create(&temp_path)
+        .map_err(|e| e.to_string())
+        .and_then(|mut out| {
+            bundle_crypto::decrypt_bundle_payload(reader, params, passphrase, &mut out)
+        });
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    Ok(temp_path)
+}
+
 fn import_bundle_from_reader<R: Read + Seek>(
     data_dir: &Path,
     memory_db: &MemoryDb,
     reader: R,
-    mode: &str,
+    options: &BundleImportOptions,
+    progress: &mut ProgressReporter,
 ) -> Result<DataBundleImportResult, String> {
+    // 先校验压缩包与 bundle.json，再动现有数据
+    let mut archive =
+        ZipArchive::new(reader).map_err(|e| format!("invalid bundle archive: {e}"))?;
+    let mut manifest = read_bundle_manifest(&mut archive)?;
+    // 部分资料包只覆盖其中包含的范围，范围外的现有数据原样保留
+    let selection = &match &manifest.selection {
+        Some(bundled) => options.selection.intersect(bundled),
+        None => options.selection.clone(),
+    };
     memory_db.close_all();
+
+    // 解压到暂存目录；replace 直接整体换入，merge 先在数据目录副本上合并再换入，原目录均保留为快照
+    let staging = bundle::staging_dir(data_dir);
+    if staging.exists() {
+        fs::remove_dir_all(&staging).map_err(|e| e.to_string())?;
+    }
+    fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+    let upgraded_from = manifest.needs_upgrade().then_some(manifest.format_version);
+    let mut moved = Vec::new();
+    let result = extract_bundle_entries(&mut archive, &manifest, &staging, selection, progress)
+        .and_then(|mut result| {
+            // 升级只作用于包内文件
+            if manifest.needs_upgrade() {
+                progress.phase("upgrading", None)?;
+                manifest.upgrade(&staging)?;
+            }
+            memory_db.close_all();
+            if options.is_merge() {
+                progress.phase("merging", None)?;
+                let report = bundle_merge::merge_staged(
+                    &staging,
+                    data_dir,
+                    options.merge_policy,
+                    &manifest.modified,
+                    &mut || progress.phase("swapping", None),
+                )?;
+                result.merge = Some(report);
+                fs::remove_dir_all(&staging).map_err(|e| e.to_string())?;
+            } else {
+                carry_over_unselected(data_dir, data_dir, &staging, selection, &mut moved)?;
+                // 换入前最后一次检查取消，之后不可中断
+                progress.phase("swapping", None)?;
+                bundle::swap_in_staging(data_dir, &staging, &moved)?;
+            }
+            Ok(result)
+        });
+    if result.is_err() {
+        // 已移入暂存目录的现有文件先移回数据目录
+        let _ = bundle::move_entries(&staging, data_dir, &moved);
+        let _ = fs::remove_dir_all(&staging);
+    }
+    result.map(|result| with_manifest_info(result, &manifest, upgraded_from))
+}
+
+/// 资料包导入参数
+struct BundleImportOptions {
+    mode: String,
+    passphrase: Option<String>,
+    selection: BundleSelection,
+    merge_policy: MergePolicy,
+}
+
+impl BundleImportOptions {
+    fn new(
+        mode: Option<String>,
+        passphrase: Option<String>,
+        selection: Option<BundleSelection>,
+        merge_policy: Option<MergePolicy>,
+    ) -> Self {
+        Self {
+            mode: mode.unwrap_or_else(|| "replace".to_string()).to_lowercase(),
+            passphrase,
+            selection: selection.unwrap_or_default(),
+            merge_policy: merge_policy.unwrap_or_default(),
+        }
+    }
+
+    fn is_merge(&self) -> bool {
+        self.mode == "merge"
+    }
+}
+
+fn with_manifest_info(
+    result: DataBundleImportResult,
+    manifest: &BundleManifest,
+    upgraded_from: Option<u32>,
+) -> DataBundleImportResult {
+    DataBundleImportResult {
+        format_version: manifest.format_version,
+        upgraded_from,
+        source_app_version: manifest.app_version.clone(),
+        ..result
+    }
+}
+
+/// 读取并校验 bundle.json（格式未知或更新时拒绝导入）
+fn read_bundle_manifest<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> Result<BundleManifest, String> {
+    let mut entry = archive
+        .by_name("bundle.json")
+        .map_err(|_| "invalid bundle: bundle.json missing".to_string())?;
+    let mut raw = String::new();
+    entry
+        .read_to_string(&mut raw)
+        .map_err(|e| format!("invalid bundle.json: {e}"))?;
+    let manifest: Value =
+        serde_json::from_str(&raw).map_err(|e| format!("invalid bundle.json: {e}"))?;
+    let manifest = BundleManifest::parse(manifest)?;
+    if manifest.chain_delta {
+        return Err(
+            "this is an incremental backup delta: restore it from the backup point list"
+                .to_string(),
+        );
+    }
+    Ok(manifest)
+}
+
+/// 将不在本次导入范围内的现有文件移入暂存目录（敏感配置与 backups/ 始终保留）。
+/// 移动而不复制，避免大体积的 backups/ 在快照之外再占一份空间；移动的条目记入 `moved`
+fn carry_over_unselected(
+    base: &Path,
+    dir: &Path,
+    staging: &Path,
+    selection: &BundleSelection,
+    moved: &mut Vec<String>,
+) -> Result<(), String> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let entries: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| e.to_string())?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    for path in entries {
+        let rel = bundle_rel_name(base, &path)?;
+        if is_sensitive_bundle_path(&rel) || !selection.allows(&rel) {
+            bundle::move_entries(base, staging, std::slice::from_ref(&rel))?;
+            moved.push(rel);
+        } else if path.is_dir() {
+            carry_over_unselected(base, &path, staging, selection, moved)?;
+        }
+    }
+    Ok(())
+}
+
+fn zip_time_ms(time: zip::DateTime) -> Option<i64> {
+    chrono::NaiveDate::from_ymd_opt(
+        i32::from(time.year()),
+        u32::from(time.month()),
+        u32::from(time.day()),
+    )
+    .and_then(|d| {
+        d.and_hms_opt(
+            u32::from(time.hour()),
+            u32::from(time.minute()),
+            u32::from(time.second()),
+        )
+    })
+    .map(|dt| dt.and_utc().timestamp_millis())
+}
+
+pub(crate) fn list_local_files(
+    base: &Path,
+    dir: &Path,
+    selection: &BundleSelection,
+) -> Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    if !dir.is_dir() {
+        return Ok(out);
+    }
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path();
+        let rel = bundle_rel_name(base, &path)?;
+        if is_sensitive_bundle_path(&rel) || !selection.allows(&rel) {
+            continue;
+        }
+        if path.is_dir() {
+            out.extend(list_local_files(base, &path, selection)?);
+        } else {
+            out.push(rel);
+        }
+    }
+    Ok(out)
+}
+
+/// 只读预览：不写入数据目录
+fn preview_bundle_reader<R: Read + Seek>(
+    reader: R,
+    data_dir: &Path,
+    mode: &str,
+    selection: &BundleSelection,
+    progress: &mut ProgressReporter,
+) -> Result<DataBundlePreview, String> {
+    let mut archive =
+        ZipArchive::new(reader).map_err(|e| format!("invalid bundle archive: {e}"))?;
+    let manifest = read_bundle_manifest(&mut archive)?;
+    progress.phase(
+        "previewing",
+        Some((archive.len(), archive_bytes(&mut archive))),
+    )?;
+    let mut preview = DataBundlePreview {
+        mode: mode.to_string(),
+        format_version: manifest.format_version,
+        source_app_version: manifest.app_version.clone(),
+        created_at: manifest.created_at.clone(),
+        files: 0,
+        bytes: 0,
+        summary: BundleContentSummary::default(),
+        new_files: 0,
+        unchanged: 0,
+        changed: 0,
+        overwrites: Vec::new(),
+        removed_local: 0,
+    };
+    let mut bundle_files = HashSet::new();
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| format!("read bundle entry failed: {e}"))?;
+        let name = file.name().to_string();
+        if name == "bundle.json" || name.ends_with('/') {
+            continue;
+        }
+        if is_sensitive_bundle_path(&name) || !selection.allows(&name) {
+            continue;
+        }
+        if file.enclosed_name().is_none() {
+            continue;
+        }
+        preview.files += 1;
+        preview.bytes += file.size();
+        let bundle_modified = manifest
+            .modified
+            .get(&name)
+            .copied()
+            .or_else(|| zip_time_ms(file.last_modified()));
+        let (content, bundle_sha256) = if BundleContentSummary::wants_content(&name) {
+            let mut hashing = HashingWriter::new(Vec::new());
+            std::io::copy(&mut file, &mut hashing)
+                .map_err(|e| format!("read bundle entry failed: {name} ({e})"))?;
+            let (buf, sha) = hashing.finish();
+            (Some(buf), sha)
+        } else if let Some(sha) = manifest.checksums.as_ref().and_then(|c| c.get(&name)) {
+            (None, sha.clone())
+        } else {
+            let mut hashing = HashingWriter::new(std::io::sink());
+            std::io::copy(&mut file, &mut hashing)
+                .map_err(|e| format!("read bundle entry failed: {name} ({e})"))?;
+            (None, hashing.finish_hex())
+        };
+        preview.summary.observe(&name, content.as_deref());
+        progress.file_done(&name, file.size())?;
+
+        let local = data_dir.join(&name);
+        if mode == "merge" && local.is_file() {
+            let local_sha256 = bundle::sha256_file(&local)?;
+            let local_modified = bundle::file_modified_ms(&local);
+            if local_sha256 == bundle_sha256 {
+                preview.unchanged += 1;
+            } else {
+                preview.changed += 1;
+                if local_modified
+                    .zip(bundle_modified)
+                    .is_some_and(|(l, b)| l > b)
+                {
+                    preview.overwrites.push(BundleOverwrite {
+                        path: name.clone(),
+                        local_modified,
+                        bundle_modified,
+                        local_sha256,
+                        bundle_sha256,
+                    });
+                }
+            }
+        } else if mode == "merge" {
+            preview.new_files += 1;
+        }
+        bundle_files.insert(name);
+    }
+    preview.summary.finish();
     if mode != "merge" {
-        clear_data_dir(data_dir)?;
+        preview.removed_local = list_local_files(data_dir, data_dir, selection)?
+            .iter()
+            .filter(|rel| !bundle_files.contains(*rel))
+            .count();
     }
-    let mut archive = ZipArchive::new(reader).map_err(|e| e.to_string())?;
+    Ok(preview)
+}
+
+fn verify_bundle_reader<R: Read + Seek>(
+    reader: R,
+    progress: &mut ProgressReporter,
+) -> Result<DataBundleVerifyResult, String> {
+    let mut archive =
+        ZipArchive::new(reader).map_err(|e| format!("invalid bundle archive: {e}"))?;
+    let manifest = read_bundle_manifest(&mut archive)?;
+    progress.phase(
+        "verifying",
+        Some((archive.len(), archive_bytes(&mut archive))),
+    )?;
+    let mut checked = 0usize;
+    let mut mismatched = Vec::new();
+    let mut unlisted = Vec::new();
+    let mut seen = HashSet::new();
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| format!("read bundle entry failed: {e}"))?;
+        let name = file.name().to_string();
+        if name == "bundle.json" || name.ends_with('/') {
+            continue;
+        }
+        let mut hashing = HashingWriter::new(std::io::sink());
+        if std::io::copy(&mut file, &mut hashing).is_err() {
+            mismatched.push(name);
+            continue;
+        }
+        checked += 1;
+        progress.file_done(&name, file.size())?;
+        let digest = hashing.finish_hex();
+        match manifest.checksums.as_ref().map(|c| c.get(&name)) {
+            Some(Some(expected)) if *expected != digest => mismatched.push(name.clone()),
+            Some(None) => unlisted.push(name.clone()),
+            _ => {}
+        }
+        seen.insert(name);
+    }
+    let missing: Vec<String> = manifest
+        .checksums
+        .iter()
+        .flat_map(|c| c.keys())
+        .filter(|name| !seen.contains(*name))
+        .cloned()
+        .collect();
+    Ok(DataBundleVerifyResult {
+        ok: mismatched.is_empty() && missing.is_empty() && unlisted.is_empty(),
+        has_checksums: manifest.checksums.is_some(),
+        checked,
+        mismatched,
+        missing,
+        unlisted,
+        format_version: manifest.format_version,
+        source_app_version: manifest.app_version,
+    })
+}
+
+/// 压缩包内全部条目解压后的总字节数（用于进度）
+fn archive_bytes<R: Read + Seek>(archive: &mut ZipArchive<R>) -> u64 {
+    (0..archive.len())
+        .filter_map(|i| archive.by_index_raw(i).ok().map(|entry| entry.size()))
+        .sum()
+}
+
+fn extract_bundle_entries<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    manifest: &BundleManifest,
+    target_dir: &Path,
+    selection: &BundleSelection,
+    progress: &mut ProgressReporter,
+) -> Result<DataBundleImportResult, String> {
+    progress.phase("extracting", Some((archive.len(), archive_bytes(archive))))?;
     let mut files = 0usize;
     let mut skipped = 0usize;
+    let mut categories = CategoryCounts::default();
+    let mut present = HashSet::new();
     for i in 0..archive.len() {
-        let mut file = match archive.by_index(i) {
-            Ok(entry) => entry,
-            Err(err) => {
-                skipped += 1;
-                eprintln!("[import_bundle] read entry failed: {}", err);
-                continue;
-            }
-        };
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| format!("read bundle entry failed: {e}"))?;
         let name = file.name().to_string();
         if name == "bundle.json" {
             continue;
         }
-        if is_sensitive_bundle_path(Path::new(&name)) {
+        if is_sensitive_bundle_path(&name) {
             continue;
         }
-        if name.ends_with('/') {
-            let Some(rel) = file.enclosed_name() else {
-                skipped += 1;
-                eprintln!("[import_bundle] unsafe path: {}", name);
-                continue;
-            };
-            let out_dir = data_dir.join(rel);
-            if let Err(err) = fs::create_dir_all(&out_dir) {
-                skipped += 1;
-                eprintln!("[import_bundle] mkdir failed: {} ({})", name, err);
-            }
+        if !selection.allows(name.trim_end_matches('/')) {
             continue;
         }
-        let Some(rel) = file.enclosed_name() else {
+        present.insert(name.clone());
+        let Some(rel) = file.enclosed_name().map(Path::to_path_buf) else {
             skipped += 1;
             eprintln!("[import_bundle] unsafe path: {}", name);
             continue;
         };
-        let out_path = data_dir.join(rel);
+        let out_path = target_dir.join(rel);
+        if name.ends_with('/') {
+            fs::create_dir_all(&out_path).map_err(|e| format!("mkdir failed: {name} ({e})"))?;
+            continue;
+        }
         if let Some(parent) = out_path.parent() {
-            if let Err(err) = fs::create_dir_all(parent) {
-                skipped += 1;
-                eprintln!("[import_bundle] mkdir failed: {} ({})", name, err);
-                continue;
-            }
+            fs::create_dir_all(parent).map_err(|e| format!("mkdir failed: {name} ({e})"))?;
         }
-        let mut outfile = match fs::File::create(&out_path) {
-            Ok(f) => f,
-            Err(err) => {
-                skipped += 1;
-                eprintln!("[import_bundle] create file failed: {} ({})", name, err);
-                continue;
-            }
-        };
-        if let Err(err) = std::io::copy(&mut file, &mut outfile) {
-            skipped += 1;
-            eprintln!("[import_bundle] write failed: {} ({})", name, err);
-            continue;
+        // 先写入 .part 并校验摘要，通过后再落到目标路径
+        let part_path = PathBuf::from(format!("{}.part", out_path.to_string_lossy()));
+        let written = fs::File::create(&part_path)
+            .map_err(|e| format!("create file failed: {name} ({e})"))
+            .and_then(|outfile| {
+                let mut hashing = HashingWriter::new(outfile);
+                std::io::copy(&mut file, &mut hashing)
+                    .map_err(|e| format!("write failed: {name} ({e})"))?;
+                manifest.verify_file(&name, &hashing.finish_hex())
+            })
+            .and_then(|()| fs::rename(&part_path, &out_path).map_err(|e| e.to_string()));
+        if let Err(err) = written {
+            let _ = fs::remove_file(&part_path);
+            return Err(err);
         }
         files += 1;
-    }
-    Ok(DataBundleImportResult { files, skipped })
+        categories.add(&name);
+        progress.file_done(&name, file.size())?;
+    }
+    // bundle.json 列出但包内缺失的文件说明资料包不完整
+    let missing: Vec<&str> = manifest
+        .checksums
+        .iter()
+        .flat_map(|c| c.keys())
+        .map(String::as_str)
+        .filter(|rel| !is_sensitive_bundle_path(rel) && selection.allows(rel))
+        .filter(|rel| !present.contains(*rel))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "bundle is missing listed files: {}",
+            missing.join(", ")
+        ));
+    }
+    Ok(DataBundleImportResult {
+        files,
+        skipped,
+        categories,
+        format_version: bundle::BUNDLE_FORMAT_VERSION,
+        upgraded_from: None,
+        source_app_version: None,
+        merge: None,
+    })
 }
 
-/// Ensure bundled media assets exist in app data dir.
+fn find_media_resource_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    let resource_dir = app
+        .path()
+        .resource_dir()
+        .map_err(|err| format!("resource_dir unavailable: {err}"))?;
+    let candidates = [
+        resource_dir.join("media"),
+        resource_dir.join("resources").join("media"),
+        resource_dir
+            .join("src-tauri")
+            .join("resources")
+            .join("media"),
+    ];
+    candidates
+        .into_iter()
+        .find(|dir| dir.exists())
+        .ok_or_else(|| "media bundle not found in resources".to_string())
+}
+
+/// Ensure bundled media assets exist in app data dir, upgrading them when the app ships a newer
+/// manifest version.
 #[tauri::command]
 pub async fn ensure_media_bundle(app: AppHandle) -> Result<MediaBundleInfo, String> {
     let data_dir = get_data_dir(&app)?;
@@ -752,63 +1395,129 @@ pub async fn ensure_media_bundle(app: AppHandle) -> Result<MediaBundleInfo, Stri
     let manifest_path = target_dir.join("manifest.json");
 
     let mut copied = false;
+    let mut upgraded = false;
     let mut warning = None;
-
-    if !manifest_path.exists() {
-        match app.path().resource_dir() {
-            Ok(resource_dir) => {
-                let candidates = [
-                    resource_dir.join("media"),
-                    resource_dir.join("resources").join("media"),
-                    resource_dir
-                        .join("src-tauri")
-                        .join("resources")
-                        .join("media"),
-                ];
-                let mut picked = None;
-                for dir in candidates {
-                    if dir.exists() {
-                        picked = Some(dir);
-                        break;
+    let mut sync = media_packs::MediaSync::default();
+    let mut kept_user_items = 0usize;
+
+    let installed = match media_packs::load_builtin_manifest(&target_dir) {
+        Ok(installed) => installed,
+        // 损坏的清单已移到一旁，按未安装处理，重新复制内置资源
+        Err(err) if !manifest_path.exists() => {
+            warning = Some(err);
+            None
+        }
+        Err(err) => return Err(err),
+    };
+    let previous_version = installed.as_ref().map(media_packs::manifest_version);
+
+    match find_media_resource_dir(&app) {
+        Ok(src_dir) => match media_packs::read_bundled_manifest(&src_dir) {
+            Some(bundled) => {
+                let bundled_version = media_packs::manifest_version(&bundled);
+                let needs_sync = previous_version.is_none_or(|v| bundled_version > v);
+                if needs_sync {
+                    let previous = media_packs::installed_file_hashes(installed.as_ref());
+                    let synced = media_packs::sync_media_files(
+                        &src_dir,
+                        &target_dir,
+                        "",
+                        &previous,
+                        &mut sync,
+                    )
+                    .and_then(|()| {
+                        let (mut merged, kept) =
+                            media_packs::merge_builtin_media_manifest(&bundled, installed.as_ref());
+                        media_packs::set_file_hashes(&mut merged, &sync.hashes);
+                        kept_user_items = kept;
+                        write_json_file(&manifest_path, &merged)
+                    });
+                    match synced {
+                        Ok(()) => {
+                            copied = previous_version.is_none();
+                            upgraded = previous_version.is_some();
+                        }
+                        Err(err) => {
+                            warning = Some(format!("copy media bundle failed: {err}"));
+                        }
                     }
                 }
-                if let Some(src_dir) = picked {
-                    if let Err(err) = copy_dir_recursive(&src_dir, &target_dir) {
-                        warning = Some(format!("copy media bundle failed: {}", err));
-                    } else {
-                        copied = true;
-                    }
-                } else {
-                    warning = Some("media bundle not found in resources".to_string());
+            }
+            None => {
+                if !manifest_path.exists() {
+                    warning = Some("media bundle manifest missing in resources".to_string());
                 }
             }
-            Err(err) => {
-                warning = Some(format!("resource_dir unavailable: {}", err));
+        },
+        Err(err) => {
+            if !manifest_path.exists() {
+                warning = Some(err);
             }
         }
     }
 
-    let manifest = if manifest_path.exists() {
-        match fs::read_to_string(&manifest_path) {
-            Ok(json) => serde_json::from_str::<Value>(&json).ok(),
-            Err(err) => {
-                warning = Some(format!("read media manifest failed: {}", err));
-                None
-            }
-        }
-    } else {
-        None
-    };
+    let builtin = media_packs::load_builtin_manifest(&target_dir)?;
+    let version = builtin.as_ref().map(media_packs::manifest_version);
+    let manifest = media_packs::merged_manifest(&target_dir, builtin);
 
     Ok(MediaBundleInfo {
         ready: manifest.is_some(),
         copied,
+        upgraded,
+        previous_version: if upgraded { previous_version } else { None },
+        version,
+        added: sync.added,
+        updated: sync.updated,
+        kept_modified: sync.kept,
+        kept_user_items,
         base_dir: target_dir.to_string_lossy().to_string(),
         manifest,
         warning,
     })
 }
 
+/// 导入用户表情包（zip 或目录）到 `media/packs/<pack_id>/`
+#[tauri::command]
+pub async fn import_media_pack(
+    app: AppHandle,
+    path: String,
+    overwrite: Option<bool>,
+) -> Result<MediaPackImportResult, String> {
+    let media_dir = get_data_dir(&app)?.join("media");
+    let builtin = media_packs::load_builtin_manifest(&media_dir)?;
+    media_packs::import_pack(
+        &media_dir,
+        Path::new(path.trim()),
+        builtin.as_ref(),
+        overwrite.unwrap_or(false),
+    )
+}
+
+/// 列出已安装的用户表情包
+#[tauri::command]
+pub async fn list_media_packs(app: AppHandle) -> Result<Vec<MediaPackInfo>, String> {
+    let media_dir = get_data_dir(&app)?.join("media");
+    Ok(media_packs::list_packs(&media_dir))
+}
+
+/// 启用/停用用户表情包
+#[tauri::command]
+pub async fn set_media_pack_enabled(
+    app: AppHandle,
+    pack_id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    let media_dir = get_data_dir(&app)?.join("media");
+    media_packs::set_pack_enabled(&media_dir, &pack_id, enabled)
+}
+
+/// 删除用户表情包
+#[tauri::command]
+pub async fn delete_media_pack(app: AppHandle, pack_id: String) -> Result<bool, String> {
+    let media_dir = get_data_dir(&app)?.join("media");
+    media_packs::delete_pack(&media_dir, &pack_id)
+}
+
 /// 保存聊天壁纸到本地（AppData）
 #[tauri::command]
 pub async fn save_wallpaper(
@@ -1151,15 +1860,97 @@ pub async fn cleanup_wallpapers(
     Ok(WallpaperCleanupResult { removed, kept })
 }
 
-/// 导出本地资料包（聊天记录/联系人/壁纸/记忆表格等）
+/// 在阻塞线程池中执行资料包操作，登记 operation id 以便取消
+async fn run_bundle_operation<T, F>(
+    app: AppHandle,
+    kind: &str,
+    access: DataAccess,
+    operation_id: Option<String>,
+    task: F,
+) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce(&AppHandle, &mut ProgressReporter) -> Result<T, String> + Send + 'static,
+{
+    let operation_id = operation_id
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| bundle_progress::new_operation_id(kind));
+    tauri::async_runtime::spawn_blocking(move || run_bundle_task(&app, &operation_id, access, task))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+}
+
+/// 同步执行资料包操作（调用方已在阻塞线程中）；数据目录被其它操作占用时立即失败
+fn run_bundle_task<T, F>(
+    app: &AppHandle,
+    operation_id: &str,
+    access: DataAccess,
+    task: F,
+) -> Result<T, String>
+where
+    F: FnOnce(&AppHandle, &mut ProgressReporter) -> Result<T, String>,
+{
+    let operations = app.state::<BundleOperations>();
+    let _guard = operations.lock_data_dir(access)?;
+    let cancel = operations.register(operation_id)?;
+    let mut progress = ProgressReporter::new(app.clone(), operation_id, cancel);
+    let result = task(app, &mut progress);
+    if result.is_ok() {
+        progress.finish();
+    }
+    operations.finish(operation_id);
+    result
+}
+
+/// 导出本地资料包（聊天记录/联系人/壁纸/记忆表格等，可选口令加密）
 #[tauri::command]
 pub async fn export_data_bundle(
     app: AppHandle,
-    state: State<'_, MemoryDb>,
     path: Option<String>,
+    passphrase: Option<String>,
+    selection: Option<BundleSelection>,
+    operation_id: Option<String>,
 ) -> Result<DataBundleResult, String> {
-    state.close_all();
-    let data_dir = get_data_dir(&app)?;
+    run_bundle_operation(
+        app,
+        "export",
+        DataAccess::Shared,
+        operation_id,
+        move |app, progress| export_bundle_blocking(app, path, passphrase, selection, progress),
+    )
+    .await
+}
+
+/// 把暂存的明文资料包加密写到 `output_path`；无论成功、失败还是取消都会删除明文
+fn encrypt_staged_bundle(
+    plain_path: &Path,
+    output_path: &Path,
+    passphrase: &str,
+    progress: &mut ProgressReporter,
+) -> Result<(), String> {
+    let encrypted = progress.phase("encrypting", None).and_then(|()| {
+        let mut plain = fs::File::open(plain_path).map_err(|e| e.to_string())?;
+        let out = fs::File::create(output_path).map_err(|e| e.to_string())?;
+        bundle_crypto::write_encrypted_bundle(&mut plain, out, passphrase)
+    });
+    let _ = fs::remove_file(plain_path);
+    if encrypted.is_err() {
+        let _ = fs::remove_file(output_path);
+    }
+    encrypted
+}
+
+fn export_bundle_blocking(
+    app: &AppHandle,
+    path: Option<String>,
+    passphrase: Option<String>,
+    selection: Option<BundleSelection>,
+    progress: &mut ProgressReporter,
+) -> Result<DataBundleResult, String> {
+    app.state::<MemoryDb>().close_all();
+    let data_dir = get_data_dir(app)?;
     let ts = chrono::Utc::now().format("%Y%m%d_%H%M%S");
     let file_name = format!("chatapp_backup_{ts}.zip");
     let mut output_path: PathBuf;
@@ -1176,7 +1967,7 @@ pub async fn export_data_bundle(
         }
         #[cfg(not(target_os = "android"))]
         {
-            output_path = resolve_export_dir(&app)?.join(&file_name);
+            output_path = resolve_export_dir(app)?.join(&file_name);
         }
     } else {
         output_path = PathBuf::from(trimmed);
@@ -1190,42 +1981,42 @@ pub async fn export_data_bundle(
     if let Some(parent) = output_path.parent() {
         fs::create_dir_all(parent).map_err(|e| e.to_string())?;
     }
-    let file = fs::File::create(&output_path).map_err(|e| e.to_string())?;
-    let mut writer = ZipWriter::new(file);
-    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
-
-    let manifest = serde_json::json!({
-        "format": "tauri-chat-app-backup-v1",
-        "createdAt": chrono::Utc::now().to_rfc3339(),
-        "appVersion": env!("CARGO_PKG_VERSION"),
-        "excluded": [
-            "config.json",
-            "llm_profiles_v1.json",
-            "llm_keyring_v1.json",
-            "llm_keyring_master_v1.json"
-        ]
-    });
-    writer
-        .start_file("bundle.json", options)
-        .map_err(|e| e.to_string())?;
-    writer
-        .write_all(manifest.to_string().as_bytes())
-        .map_err(|e| e.to_string())?;
-
-    let files = add_dir_to_zip(
-        &mut writer,
-        &data_dir,
+    let passphrase = passphrase.filter(|p| !p.is_empty());
+    // 加密导出时明文只暂存在缓存目录，不落在用户选择的位置旁边
+    let plain_path = if passphrase.is_some() {
+        temp_bundle_path(app, "export")?.with_extension("zip.part")
+    } else {
+        output_path.clone()
+    };
+    let selection = selection.unwrap_or_default();
+    let written = write_bundle_zip(
         &data_dir,
-        options,
-        Some(&output_path),
-    )?;
-    writer.finish().map_err(|e| e.to_string())?;
+        &plain_path,
+        &[&output_path, &plain_path],
+        &selection,
+        None,
+        progress,
+    )
+    .and_then(|written| {
+        if let Some(passphrase) = passphrase.as_deref() {
+            encrypt_staged_bundle(&plain_path, &output_path, passphrase, progress)?;
+        }
+        Ok(written)
+    });
+    let (files, index) = match written {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = fs::remove_file(&plain_path);
+            let _ = fs::remove_file(&output_path);
+            return Err(err);
+        }
+    };
     let bytes = fs::metadata(&output_path).map_err(|e| e.to_string())?.len();
     let mut result_path = output_path.to_string_lossy().to_string();
     #[cfg(target_os = "android")]
     {
         if publish_download {
-            let published = publish_bundle_to_downloads(&app, &output_path, &file_name)?;
+            let published = publish_bundle_to_downloads(app, &output_path, &file_name)?;
             let _ = fs::remove_file(&output_path);
             result_path = published;
         }
@@ -1235,27 +2026,42 @@ pub async fn export_data_bundle(
         path: result_path,
         bytes,
         files,
+        encrypted: passphrase.is_some(),
+        categories: index.counts,
     })
 }
 
-/// 导入本地资料包
+/// 导入本地资料包（加密资料包需提供口令）
 #[tauri::command]
 pub async fn import_data_bundle(
     app: AppHandle,
     path: String,
     mode: Option<String>,
-    state: State<'_, MemoryDb>,
+    passphrase: Option<String>,
+    selection: Option<BundleSelection>,
+    merge_policy: Option<MergePolicy>,
+    operation_id: Option<String>,
 ) -> Result<DataBundleImportResult, String> {
-    let mode = mode.unwrap_or_else(|| "replace".to_string()).to_lowercase();
-    let data_dir = get_data_dir(&app)?;
-    let path_buf = PathBuf::from(path);
-    if mode != "merge" && path_buf.starts_with(&data_dir) {
-        let bytes = fs::read(&path_buf).map_err(|e| e.to_string())?;
-        let cursor = std::io::Cursor::new(bytes);
-        return import_bundle_from_reader(&data_dir, &state, cursor, &mode);
-    }
-    let file = fs::File::open(&path_buf).map_err(|e| e.to_string())?;
-    import_bundle_from_reader(&data_dir, &state, file, &mode)
+    let options = BundleImportOptions::new(mode, passphrase, selection, merge_policy);
+    run_bundle_operation(
+        app,
+        "import",
+        DataAccess::Exclusive,
+        operation_id,
+        move |app, progress| {
+            let data_dir = get_data_dir(app)?;
+            let memory_db = app.state::<MemoryDb>();
+            let path_buf = PathBuf::from(path);
+            if !options.is_merge() && path_buf.starts_with(&data_dir) {
+                let bytes = fs::read(&path_buf).map_err(|e| e.to_string())?;
+                let cursor = std::io::Cursor::new(bytes);
+                return import_bundle_auto(app, &data_dir, &memory_db, cursor, &options, progress);
+            }
+            let file = fs::File::open(&path_buf).map_err(|e| e.to_string())?;
+            import_bundle_auto(app, &data_dir, &memory_db, file, &options, progress)
+        },
+    )
+    .await
 }
 
 /// 导入本地资料包（base64/dataURL）
@@ -1264,13 +2070,850 @@ pub async fn import_data_bundle_bytes(
     app: AppHandle,
     data: String,
     mode: Option<String>,
-    state: State<'_, MemoryDb>,
+    passphrase: Option<String>,
+    selection: Option<BundleSelection>,
+    merge_policy: Option<MergePolicy>,
+    operation_id: Option<String>,
 ) -> Result<DataBundleImportResult, String> {
+    let options = BundleImportOptions::new(mode, passphrase, selection, merge_policy);
+    run_bundle_operation(
+        app,
+        "import",
+        DataAccess::Exclusive,
+        operation_id,
+        move |app, progress| {
+            let data_dir = get_data_dir(app)?;
+            let memory_db = app.state::<MemoryDb>();
+            let bytes = decode_base64_payload(&data)?;
+            let cursor = std::io::Cursor::new(bytes);
+            import_bundle_auto(app, &data_dir, &memory_db, cursor, &options, progress)
+        },
+    )
+    .await
+}
+
+/// 取消进行中的资料包导入/导出
+#[tauri::command]
+pub async fn cancel_bundle_operation(
+    operation_id: String,
+    state: State<'_, BundleOperations>,
+) -> Result<bool, String> {
+    Ok(state.cancel(operation_id.trim()))
+}
+
+/// 校验资料包完整性（逐文件 SHA-256），不修改数据目录
+#[tauri::command]
+pub async fn verify_data_bundle(
+    app: AppHandle,
+    path: String,
+    passphrase: Option<String>,
+    operation_id: Option<String>,
+) -> Result<DataBundleVerifyResult, String> {
+    run_bundle_operation(
+        app,
+        "verify",
+        DataAccess::Shared,
+        operation_id,
+        move |app, progress| verify_bundle_blocking(app, &path, passphrase.as_deref(), progress),
+    )
+    .await
+}
+
+fn verify_bundle_blocking(
+    app: &AppHandle,
+    path: &str,
+    passphrase: Option<&str>,
+    progress: &mut ProgressReporter,
+) -> Result<DataBundleVerifyResult, String> {
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let envelope = bundle_crypto::read_envelope(&mut file)?;
+    file.rewind().map_err(|e| e.to_string())?;
+    let Some(params) = envelope else {
+        return verify_bundle_reader(file, progress);
+    };
+    progress.phase("decrypting", None)?;
+    let temp_path = decrypt_bundle_to_temp(app, file, &params, passphrase)?;
+    let result = fs::File::open(&temp_path)
+        .map_err(|e| e.to_string())
+        .and_then(|plain| verify_bundle_reader(plain, progress));
+    let _ = fs::remove_file(&temp_path);
+    result
+}
+
+/// 预览资料包导入会带来的变化（不修改数据目录）
+#[tauri::command]
+pub async fn preview_data_bundle(
+    app: AppHandle,
+    path: String,
+    mode: Option<String>,
+    passphrase: Option<String>,
+    selection: Option<BundleSelection>,
+    operation_id: Option<String>,
+) -> Result<DataBundlePreview, String> {
     let mode = mode.unwrap_or_else(|| "replace".to_string()).to_lowercase();
+    let selection = selection.unwrap_or_default();
+    run_bundle_operation(
+        app,
+        "preview",
+        DataAccess::Shared,
+        operation_id,
+        move |app, progress| {
+            let data_dir = get_data_dir(app)?;
+            let mut file = fs::File::open(&path).map_err(|e| e.to_string())?;
+            let envelope = bundle_crypto::read_envelope(&mut file)?;
+            file.rewind().map_err(|e| e.to_string())?;
+            let Some(params) = envelope else {
+                return preview_bundle_reader(file, &data_dir, &mode, &selection, progress);
+            };
+            progress.phase("decrypting", None)?;
+            let temp_path = decrypt_bundle_to_temp(app, file, &params, passphrase.as_deref())?;
+            let result = fs::File::open(&temp_path)
+                .map_err(|e| e.to_string())
+                .and_then(|plain| {
+                    preview_bundle_reader(plain, &data_dir, &mode, &selection, progress)
+                });
+            let _ = fs::remove_file(&temp_path);
+            result
+        },
+    )
+    .await
+}
+
+/// 查看上一次 replace 导入前保留的数据快照
+#[tauri::command]
+pub async fn get_import_snapshot(app: AppHandle) -> Result<Option<ImportSnapshotInfo>, String> {
+    let data_dir = get_data_dir(&app)?;
+    Ok(bundle::import_snapshot_info(&data_dir))
+}
+
+/// 回滚到导入前的数据快照
+#[tauri::command]
+pub async fn restore_import_snapshot(
+    app: AppHandle,
+    state: State<'_, MemoryDb>,
+) -> Result<(), String> {
+    let data_dir = get_data_dir(&app)?;
+    let operations = app.state::<BundleOperations>();
+    let _guard = operations.lock_data_dir(DataAccess::Exclusive)?;
+    state.close_all();
+    bundle::restore_import_snapshot(&data_dir)
+}
+
+/// 前端加载数据成功后调用，确认导入结果可用并删除导入前快照
+#[tauri::command]
+pub async fn confirm_import_snapshot(app: AppHandle) -> Result<bool, String> {
+    let data_dir = get_data_dir(&app)?;
+    bundle::confirm_import_snapshot(&data_dir)
+}
+
+const AUTO_BACKUP_OPERATION: &str = "auto_backup";
+const AUTO_BACKUP_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// 导出一份自动备份到 backups/ 并执行保留策略
+fn create_auto_backup_blocking(
+    app: &AppHandle,
+    config: &AutoBackupConfig,
+) -> Result<AutoBackupEntry, String> {
+    let data_dir = get_data_dir(app)?;
+    let path = auto_backup::next_backup_path(&data_dir, chrono::Utc::now());
+    let part_path = path.with_extension("zip.part");
+    let exported = run_bundle_task(
+        app,
+        AUTO_BACKUP_OPERATION,
+        DataAccess::Shared,
+        |app, progress| {
+            let part = part_path.to_string_lossy().to_string();
+            export_bundle_blocking(app, Some(part), None, None, progress)
+        },
+    )
+    .and_then(|_| fs::rename(&part_path, &path).map_err(|e| e.to_string()));
+    if let Err(err) = exported {
+        let _ = fs::remove_file(&part_path);
+        return Err(err);
+    }
+    auto_backup::update_state(&data_dir, |state| {
+        state.last_backup_at = Some(chrono::Utc::now().timestamp_millis());
+        state.messages_since = 0;
+    })?;
+    auto_backup::apply_retention(&data_dir, config)?;
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    auto_backup::list_backups(&data_dir)?
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .find(|entry| entry.name == name)
+        .ok_or_else(|| "auto backup not found after export".to_string())
+}
+
+/// 到期则执行自动备份；数据目录正被导入等操作占用时跳过，下次检查再试
+fn run_auto_backup_if_due(app: &AppHandle) -> Result<Option<AutoBackupEntry>, String> {
+    let data_dir = get_data_dir(app)?;
+    let config = auto_backup::load_config(&data_dir);
+    let state = auto_backup::load_state(&data_dir);
+    let now = chrono::Utc::now().timestamp_millis();
+    if auto_backup::due_reason(&config, &state, now).is_none() {
+        return Ok(None);
+    }
+    match create_auto_backup_blocking(app, &config) {
+        Err(err) if err == bundle_progress::DATA_DIR_BUSY_ERROR => Ok(None),
+        result => result.map(Some),
+    }
+}
+
+/// 启动自动备份调度线程（按间隔检查是否到期）
+pub fn start_auto_backup_scheduler(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(AUTO_BACKUP_CHECK_INTERVAL);
+        if let Err(err) = run_auto_backup_if_due(&app) {
+            eprintln!("[auto_backup] {err}");
+        }
+    });
+}
+
+/// 读取自动备份设置
+#[tauri::command]
+pub async fn get_auto_backup_config(app: AppHandle) -> Result<AutoBackupConfig, String> {
+    let data_dir = get_data_dir(&app)?;
+    Ok(auto_backup::load_config(&data_dir))
+}
+
+/// 保存自动备份设置
+#[tauri::command]
+pub async fn save_auto_backup_config(
+    app: AppHandle,
+    config: AutoBackupConfig,
+) -> Result<(), String> {
+    let data_dir = get_data_dir(&app)?;
+    auto_backup::save_config(&data_dir, &config)
+}
+
+/// 记录新增消息数；达到阈值时在后台触发自动备份
+#[tauri::command]
+pub async fn record_auto_backup_messages(app: AppHandle, count: u64) -> Result<bool, String> {
+    let data_dir = get_data_dir(&app)?;
+    auto_backup::update_state(&data_dir, |state| {
+        state.messages_since = state.messages_since.saturating_add(count);
+    })?;
+    let config = auto_backup::load_config(&data_dir);
+    let state = auto_backup::load_state(&data_dir);
+    let now = chrono::Utc::now().timestamp_millis();
+    if auto_backup::due_reason(&config, &state, now) != Some("messages") {
+        return Ok(false);
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(err) = run_auto_backup_if_due(&app) {
+            eprintln!("[auto_backup] {err}");
+        }
+    });
+    Ok(true)
+}
+
+/// 立即创建一份自动备份
+#[tauri::command]
+pub async fn create_auto_backup(app: AppHandle) -> Result<AutoBackupEntry, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let data_dir = get_data_dir(&app)?;
+        let config = auto_backup::load_config(&data_dir);
+        create_auto_backup_blocking(&app, &config)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result)
+}
+
+/// 列出自动备份（最新在前）
+#[tauri::command]
+pub async fn list_auto_backups(app: AppHandle) -> Result<Vec<AutoBackupEntry>, String> {
+    let data_dir = get_data_dir(&app)?;
+    Ok(auto_backup::list_backups(&data_dir)?
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .collect())
+}
+
+/// 从自动备份恢复（走资料包导入流程）
+#[tauri::command]
+pub async fn restore_auto_backup(
+    app: AppHandle,
+    name: String,
+    mode: Option<String>,
+    merge_policy: Option<MergePolicy>,
+    operation_id: Option<String>,
+) -> Result<DataBundleImportResult, String> {
+    let data_dir = get_data_dir(&app)?;
+    let entry = auto_backup::list_backups(&data_dir)?
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .find(|entry| entry.name == name.trim())
+        .ok_or_else(|| format!("auto backup not found: {name}"))?;
+    import_data_bundle(
+        app,
+        entry.path,
+        mode,
+        None,
+        None,
+        merge_policy,
+        operation_id,
+    )
+    .await
+}
+
+/// 写入一个增量备份点：无可用链或要求全量时建立新基线，否则只写入变化的文件
+fn create_backup_point_blocking(
+    app: &AppHandle,
+    full: bool,
+    progress: &mut ProgressReporter,
+) -> Result<BackupPointInfo, String> {
+    app.state::<MemoryDb>().close_all();
+    let data_dir = get_data_dir(app)?;
+    let chains = backup_chain::list_chains(&data_dir)?;
+    let point_id = backup_chain::new_point_id(chrono::Utc::now(), &chains);
+    let mut chain = match chains.into_iter().last() {
+        Some(chain) if !full && chain.deltas() < backup_chain::MAX_CHAIN_DELTAS => chain,
+        _ => BackupChain {
+            version: 1,
+            base_id: point_id.clone(),
+            points: Vec::new(),
+        },
+    };
+    let kind = if chain.points.is_empty() {
+        PointKind::Base
+    } else {
+        PointKind::Delta
+    };
+    let dir = backup_chain::chain_dir(&data_dir, &chain.base_id);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    progress.phase("scanning", None)?;
+    let selection = BundleSelection::default();
+    let local: Vec<LocalFile> = list_local_files(&data_dir, &data_dir, &selection)?
+        .iter()
+        .map(|rel| LocalFile::stat(&data_dir, rel))
+        .collect();
+    let previous = chain.latest().map(|p| &p.files);
+    let mut plan = backup_chain::plan_delta(previous, &local, &point_id, |rel| {
+        progress.check_cancelled()?;
+        bundle::sha256_file(&data_dir.join(rel))
+    })?;
+
+    let mut file = None;
+    if kind == PointKind::Base || !plan.changed.is_empty() {
+        let name = backup_chain::point_file_name(kind, &point_id);
+        let path = dir.join(&name);
+        let part_path = path.with_extension("zip.part");
+        // 基线包含全部文件，是可单独导入的完整资料包；增量包只写入变化的文件
+        let only: Option<BTreeSet<String>> =
+            (kind == PointKind::Delta).then(|| plan.changed.iter().cloned().collect());
+        let written = write_bundle_zip(
+            &data_dir,
+            &part_path,
+            &[],
+            &selection,
+            only.as_ref(),
+            progress,
+        )
+        .and_then(|(_, index)| {
+            fs::rename(&part_path, &path).map_err(|e| e.to_string())?;
+            Ok(index)
+        });
+        match written {
+            Ok(index) => {
+                let previous = chain.latest().map(|p| &p.files);
+                plan.apply_index(
+                    &index,
+                    &data_dir,
+                    &point_id,
+                    previous,
+                    kind == PointKind::Base,
+                );
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&part_path);
+                return Err(err);
+            }
+        }
+        file = Some(name);
+    }
+    chain.points.push(BackupPoint {
+        id: point_id,
+        kind,
+        file,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        files: plan.files,
+        changed: plan.changed,
+        removed: plan.removed,
+    });
+    backup_chain::save_chain(&dir, &chain)?;
+    if kind == PointKind::Base {
+        backup_chain::prune_chains(&data_dir, backup_chain::MAX_CHAINS)?;
+    }
+    let point = chain.latest().ok_or("backup chain is empty")?;
+    Ok(backup_chain::point_info(&data_dir, &chain, point))
+}
+
+/// 按备份链把某个时间点的全部文件重新组装成一个完整资料包
+fn assemble_backup_point(
+    data_dir: &Path,
+    point_id: &str,
+    output_path: &Path,
+    progress: &mut ProgressReporter,
+) -> Result<(), String> {
+    let chain = backup_chain::list_chains(data_dir)?
+        .into_iter()
+        .find(|chain| chain.point(point_id).is_some())
+        .ok_or_else(|| format!("backup point not found: {point_id}"))?;
+    let point = chain.point(point_id).ok_or("backup point not found")?;
+    let dir = backup_chain::chain_dir(data_dir, &chain.base_id);
+    let sources = backup_chain::restore_sources(&chain, point_id)?;
+    let total: usize = sources.iter().map(|(_, files)| files.len()).sum();
+    progress.phase("assembling", Some((total, 0)))?;
+
+    let out = fs::File::create(output_path).map_err(|e| e.to_string())?;
+    let mut writer = ZipWriter::new(out);
+    let mut index = BundleFileIndex::default();
+    for (source, files) in sources {
+        let file = fs::File::open(dir.join(&source)).map_err(|e| format!("{source}: {e}"))?;
+        let mut archive = ZipArchive::new(file).map_err(|e| format!("{source}: {e}"))?;
+        for rel in files {
+            let entry = archive
+                .by_name(&rel)
+                .map_err(|e| format!("{source}/{rel}: {e}"))?;
+            let bytes = entry.size();
+            writer.raw_copy_file(entry).map_err(|e| e.to_string())?;
+            let state = &point.files[&rel];
+            index.record(&rel, state.sha256.clone(), state.modified);
+            progress.file_done(&rel, bytes)?;
+        }
+    }
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let manifest = bundle_manifest_json(&BundleSelection::default(), &index, false);
+    writer
+        .start_file("bundle.json", options)
+        .map_err(|e| e.to_string())?;
+    writer
+        .write_all(manifest.to_string().as_bytes())
+        .map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// 创建增量备份点（full=true 时强制新建全量基线）
+#[tauri::command]
+pub async fn create_incremental_backup(
+    app: AppHandle,
+    full: Option<bool>,
+    operation_id: Option<String>,
+) -> Result<BackupPointInfo, String> {
+    run_bundle_operation(
+        app,
+        "backup",
+        DataAccess::Shared,
+        operation_id,
+        move |app, progress| create_backup_point_blocking(app, full.unwrap_or(false), progress),
+    )
+    .await
+}
+
+/// 列出所有增量备份点（最新在前）
+#[tauri::command]
+pub async fn list_backup_points(app: AppHandle) -> Result<Vec<BackupPointInfo>, String> {
+    let data_dir = get_data_dir(&app)?;
+    let mut points: Vec<BackupPointInfo> = backup_chain::list_chains(&data_dir)?
+        .iter()
+        .flat_map(|chain| {
+            chain
+                .points
+                .iter()
+                .map(|point| backup_chain::point_info(&data_dir, chain, point))
+        })
+        .collect();
+    points.reverse();
+    Ok(points)
+}
+
+/// 还原到某个增量备份点（组装完整资料包后走导入流程）
+#[tauri::command]
+pub async fn restore_backup_point(
+    app: AppHandle,
+    point_id: String,
+    mode: Option<String>,
+    merge_policy: Option<MergePolicy>,
+    operation_id: Option<String>,
+) -> Result<DataBundleImportResult, String> {
+    let options = BundleImportOptions::new(mode, None, None, merge_policy);
+    run_bundle_operation(
+        app,
+        "restore",
+        DataAccess::Exclusive,
+        operation_id,
+        move |app, progress| {
+            let data_dir = get_data_dir(app)?;
+            let temp_path = temp_bundle_path(app, "restore")?;
+            let result = assemble_backup_point(&data_dir, point_id.trim(), &temp_path, progress)
+                .and_then(|()| fs::File::open(&temp_path).map_err(|e| e.to_string()))
+                .and_then(|file| {
+                    let memory_db = app.state::<MemoryDb>();
+                    import_bundle_from_reader(&data_dir, &memory_db, file, &options, progress)
+                });
+            let _ = fs::remove_file(&temp_path);
+            result
+        },
+    )
+    .await
+}
+
+/// 读取 `WebDAV` 同步配置（不返回密码）
+#[tauri::command]
+pub async fn get_webdav_config(app: AppHandle) -> Result<WebDavConfigView, String> {
+    let data_dir = get_data_dir(&app)?;
+    Ok(webdav_sync::load_config_view(&data_dir))
+}
+
+/// 保存 `WebDAV` 同步配置（密码加密保存在本机）
+#[tauri::command]
+pub async fn save_webdav_config(
+    app: AppHandle,
+    config: WebDavConfigInput,
+) -> Result<WebDavConfigView, String> {
+    let data_dir = get_data_dir(&app)?;
+    fs::create_dir_all(&data_dir).map_err(|e| e.to_string())?;
+    webdav_sync::save_config(&data_dir, config)
+}
+
+/// 同步状态：本地与远端相对上次同步的变化及冲突文件
+#[tauri::command]
+pub async fn sync_status(app: AppHandle) -> Result<SyncStatus, String> {
+    webdav_sync::status(get_data_dir(&app)?).await
+}
+
+/// 推送本地数据到 `WebDAV`；远端有未拉取的修改时需先拉取（force 可强制覆盖）
+#[tauri::command]
+pub async fn sync_push(
+    app: AppHandle,
+    force: Option<bool>,
+    operation_id: Option<String>,
+) -> Result<SyncResult, String> {
+    let force = force.unwrap_or(false);
     let data_dir = get_data_dir(&app)?;
-    let bytes = decode_base64_payload(&data)?;
-    let cursor = std::io::Cursor::new(bytes);
-    import_bundle_from_reader(&data_dir, &state, cursor, &mode)
+    let client = webdav_sync::client(&data_dir)?;
+    client.ensure_dir().await?;
+    let remote = client.fetch_manifest().await?;
+    let mut state = webdav_sync::load_state(&data_dir);
+    let revision = remote.as_ref().map_or(0, |(manifest, _)| manifest.revision) + 1;
+    let remote_manifest = remote.as_ref().map(|(manifest, _)| manifest.clone());
+    let worker_state = state.clone();
+    let prepared = run_bundle_operation(
+        app,
+        "sync",
+        DataAccess::Shared,
+        operation_id,
+        move |app, progress| {
+            app.state::<MemoryDb>().close_all();
+            let data_dir = get_data_dir(app)?;
+            progress.phase("scanning", None)?;
+            let remote = remote_manifest.as_ref();
+            webdav_sync::prepare_push(&data_dir, &worker_state, remote, revision, force, || {
+                let path = temp_bundle_path(app, "sync")?;
+                let selection = BundleSelection::default();
+                match write_bundle_zip(&data_dir, &path, &[], &selection, None, progress) {
+                    Ok((_, index)) => Ok((path, index)),
+                    Err(err) => {
+                        let _ = fs::remove_file(&path);
+                        Err(err)
+                    }
+                }
+            })
+        },
+    )
+    .await?;
+    let Some((local, bundle_path)) = prepared else {
+        return Ok(SyncResult {
+            direction: "push",
+            revision: revision - 1,
+            changed: false,
+            files: state.files.len(),
+            bytes: 0,
+            import: None,
+        });
+    };
+    let files = local.files.len();
+    let bytes = webdav_sync::upload_push(
+        &client,
+        &data_dir,
+        &mut state,
+        remote.as_ref(),
+        revision,
+        local,
+        &bundle_path,
+    )
+    .await?;
+    Ok(SyncResult {
+        direction: "push",
+        revision,
+        changed: true,
+        files,
+        bytes,
+        import: None,
+    })
+}
+
+/// 从 `WebDAV` 拉取并导入；本地有未推送的修改时默认按 merge 导入
+#[tauri::command]
+pub async fn sync_pull(
+    app: AppHandle,
+    mode: Option<String>,
+    merge_policy: Option<MergePolicy>,
+    force: Option<bool>,
+    operation_id: Option<String>,
+) -> Result<SyncResult, String> {
+    let data_dir = get_data_dir(&app)?;
+    let client = webdav_sync::client(&data_dir)?;
+    let state = webdav_sync::load_state(&data_dir);
+    let (remote, bytes) = webdav_sync::fetch_pull(&client, &state, force.unwrap_or(false)).await?;
+    let revision = remote.revision;
+    let files = remote.files.len();
+    let Some(bytes) = bytes else {
+        return Ok(SyncResult {
+            direction: "pull",
+            revision,
+            changed: false,
+            files,
+            bytes: 0,
+            import: None,
+        });
+    };
+    let size = bytes.len() as u64;
+    let import = run_bundle_operation(
+        app,
+        "sync",
+        DataAccess::Exclusive,
+        operation_id,
+        move |app, progress| {
+            let data_dir = get_data_dir(app)?;
+            webdav_sync::apply_pull(&data_dir, state, &remote, mode, |mode| {
+                let options = BundleImportOptions::new(Some(mode), None, None, merge_policy);
+                let memory_db = app.state::<MemoryDb>();
+                let cursor = std::io::Cursor::new(bytes);
+                import_bundle_from_reader(&data_dir, &memory_db, cursor, &options, progress)
+            })
+        },
+    )
+    .await?;
+    Ok(SyncResult {
+        direction: "pull",
+        revision,
+        changed: true,
+        files,
+        bytes: size,
+        import: Some(import),
+    })
+}
+
+/// 作为被连接方等待局域网同步，返回配对码与监听地址
+#[tauri::command]
+pub async fn lan_sync_start_host(
+    app: AppHandle,
+    state: State<'_, LanSyncState>,
+    merge_policy: Option<MergePolicy>,
+    port: Option<u16>,
+) -> Result<LanHostInfo, String> {
+    let data_dir = get_data_dir(&app)?;
+    let close_app = app.clone();
+    let notify_app = app.clone();
+    state.start_host(lan_sync::HostOptions {
+        data_dir,
+        policy: merge_policy.unwrap_or_default(),
+        port: port.unwrap_or(0),
+        lock: app.state::<BundleOperations>().data_dir_lock(),
+        before_write: std::sync::Arc::new(move || close_app.state::<MemoryDb>().close_all()),
+        notify: std::sync::Arc::new(move |info| {
+            let _ = notify_app.emit(lan_sync::HOST_EVENT, info);
+        }),
+    })
+}
+
+#[tauri::command]
+pub async fn lan_sync_stop_host(state: State<'_, LanSyncState>) -> Result<bool, String> {
+    Ok(state.stop_host())
+}
+
+#[tauri::command]
+pub async fn lan_sync_host_status(
+    state: State<'_, LanSyncState>,
+) -> Result<Option<LanHostInfo>, String> {
+    Ok(state.host_info())
+}
+
+/// 连接局域网内的另一台设备（`host:port` + 配对码）并同步
+#[tauri::command]
+pub async fn lan_sync_connect(
+    app: AppHandle,
+    address: String,
+    code: String,
+    two_way: Option<bool>,
+    merge_policy: Option<MergePolicy>,
+    selection: Option<BundleSelection>,
+    operation_id: Option<String>,
+) -> Result<LanSyncResult, String> {
+    run_bundle_operation(
+        app,
+        "lan_sync",
+        DataAccess::Exclusive,
+        operation_id,
+        move |app, progress| {
+            let data_dir = get_data_dir(app)?;
+            progress.phase("connecting", None)?;
+            let options = LanConnectOptions {
+                address,
+                code,
+                selection: selection.unwrap_or_default(),
+                policy: merge_policy.unwrap_or_default(),
+                two_way: two_way.unwrap_or(true),
+            };
+            let memory_db = app.state::<MemoryDb>();
+            lan_sync::connect_and_sync(
+                &data_dir,
+                &options,
+                &|| memory_db.close_all(),
+                &mut |rel, bytes| progress.file_done(rel, bytes),
+            )
+        },
+    )
+    .await
+}
+
+/// 读取 S3 备份配置（不返回 Secret Key）
+#[tauri::command]
+pub async fn get_s3_backup_config(app: AppHandle) -> Result<S3ConfigView, String> {
+    let data_dir = get_data_dir(&app)?;
+    Ok(s3_backup::load_config_view(&data_dir))
+}
+
+/// 保存 S3 备份配置（Secret Key 加密保存在本机）
+#[tauri::command]
+pub async fn save_s3_backup_config(
+    app: AppHandle,
+    config: S3ConfigInput,
+) -> Result<S3ConfigView, String> {
+    let data_dir = get_data_dir(&app)?;
+    fs::create_dir_all(&data_dir).map_err(|e| e.to_string())?;
+    s3_backup::save_config(&data_dir, config)
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3UploadResult {
+    pub name: String,
+    pub bytes: u64,
+    pub files: usize,
+    pub encrypted: bool,
+    pub pruned: Vec<String>,
+}
+
+/// 导出资料包并上传到 S3，确认上传完整后按保留数量清理旧备份
+#[tauri::command]
+pub async fn upload_s3_backup(
+    app: AppHandle,
+    passphrase: Option<String>,
+    selection: Option<BundleSelection>,
+    operation_id: Option<String>,
+) -> Result<S3UploadResult, String> {
+    let data_dir = get_data_dir(&app)?;
+    let (client, keep_last) = s3_backup::client(&data_dir)?;
+    let name = s3_backup::new_backup_name(chrono::Utc::now());
+    let exported = run_bundle_operation(
+        app,
+        "s3_upload",
+        DataAccess::Shared,
+        operation_id,
+        move |app, progress| {
+            let path = temp_bundle_path(app, "s3")?;
+            let target = Some(path.to_string_lossy().to_string());
+            export_bundle_blocking(app, target, passphrase, selection, progress)
+        },
+    )
+    .await?;
+    let bundle_path = PathBuf::from(&exported.path);
+    let pruned = client
+        .upload_and_prune(&name, &bundle_path, keep_last)
+        .await?;
+    Ok(S3UploadResult {
+        name,
+        bytes: exported.bytes,
+        files: exported.files,
+        encrypted: exported.encrypted,
+        pruned,
+    })
+}
+
+/// 列出 S3 上的备份（按时间从旧到新）
+#[tauri::command]
+pub async fn list_s3_backups(app: AppHandle) -> Result<Vec<S3BackupEntry>, String> {
+    let data_dir = get_data_dir(&app)?;
+    s3_backup::client(&data_dir)?.0.list_backups().await
+}
+
+/// 下载 S3 备份到本地路径
+#[tauri::command]
+pub async fn download_s3_backup(app: AppHandle, name: String, path: String) -> Result<u64, String> {
+    let data_dir = get_data_dir(&app)?;
+    let target = PathBuf::from(path.trim());
+    if target.as_os_str().is_empty() {
+        return Err("download path is required".to_string());
+    }
+    let bytes = s3_backup::client(&data_dir)?
+        .0
+        .download_backup(&name)
+        .await?;
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::write(&target, &bytes).map_err(|e| e.to_string())?;
+    Ok(bytes.len() as u64)
+}
+
+/// 下载 S3 备份并导入（加密备份需提供口令）
+#[tauri::command]
+pub async fn restore_s3_backup(
+    app: AppHandle,
+    name: String,
+    mode: Option<String>,
+    passphrase: Option<String>,
+    merge_policy: Option<MergePolicy>,
+    operation_id: Option<String>,
+) -> Result<DataBundleImportResult, String> {
+    let data_dir = get_data_dir(&app)?;
+    let bytes = s3_backup::client(&data_dir)?
+        .0
+        .download_backup(&name)
+        .await?;
+    let path = temp_bundle_path(&app, "s3_restore")?;
+    fs::write(&path, bytes).map_err(|e| e.to_string())?;
+    let result = import_data_bundle(
+        app,
+        path.to_string_lossy().to_string(),
+        mode,
+        passphrase,
+        None,
+        merge_policy,
+        operation_id,
+    )
+    .await;
+    let _ = fs::remove_file(&path);
+    result
+}
+
+/// 只保留最近 `keep_last` 个 S3 备份（缺省使用配置值）
+#[tauri::command]
+pub async fn prune_s3_backups(
+    app: AppHandle,
+    keep_last: Option<usize>,
+) -> Result<Vec<String>, String> {
+    let data_dir = get_data_dir(&app)?;
+    let (client, configured) = s3_backup::client(&data_dir)?;
+    client.prune(keep_last.unwrap_or(configured)).await
 }
 
 /// 保存配置
@@ -1845,8 +3488,9 @@ pub async fn create_memory(
     db: State<'_, MemoryDb>,
     scope_id: Option<String>,
     input: MemoryCreateInput,
+    actor: Option<String>,
 ) -> Result<String, String> {
-    db.create_memory(scope_id, input)
+    db.create_memory_as(scope_id, input, actor.as_deref().unwrap_or(ACTOR_USER))
 }
 
 #[tauri::command]
@@ -1854,8 +3498,9 @@ pub async fn update_memory(
     db: State<'_, MemoryDb>,
     scope_id: Option<String>,
     input: MemoryUpdateInput,
+    actor: Option<String>,
 ) -> Result<(), String> {
-    db.update_memory(scope_id, input)
+    db.update_memory_as(scope_id, input, actor.as_deref().unwrap_or(ACTOR_USER))
 }
 
 #[tauri::command]
@@ -1863,8 +3508,42 @@ pub async fn delete_memory(
     db: State<'_, MemoryDb>,
     scope_id: Option<String>,
     id: String,
+    actor: Option<String>,
+) -> Result<(), String> {
+    db.delete_memory_as(scope_id, &id, actor.as_deref().unwrap_or(ACTOR_USER))
+}
+
+/// 某条记忆的变更历史（最新在前）
+#[tauri::command]
+pub async fn get_memory_history(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    memory_id: String,
+    limit: Option<usize>,
+) -> Result<Vec<MemoryHistoryEntry>, String> {
+    db.get_memory_history(scope_id, &memory_id, limit)
+}
+
+#[tauri::command]
+pub async fn revert_memory(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    history_id: i64,
+    actor: Option<String>,
 ) -> Result<(), String> {
-    db.delete_memory(scope_id, id)
+    db.revert_memory(scope_id, history_id, actor.as_deref().unwrap_or(ACTOR_USER))
+}
+
+/// 撤销某联系人或群组最近 count 条自动修改
+#[tauri::command]
+pub async fn undo_memory_changes(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    contact_id: Option<String>,
+    group_id: Option<String>,
+    count: usize,
+) -> Result<UndoResult, String> {
+    db.undo_automated_changes(scope_id, contact_id, group_id, count)
 }
 
 #[tauri::command]
@@ -1876,13 +3555,153 @@ pub async fn get_memories(
     db.get_memories(scope_id, query)
 }
 
+/// 分页查询记忆，支持排序与 keyset 游标
+#[tauri::command]
+pub async fn get_memories_page(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    query: MemoryPageQuery,
+) -> Result<MemoryPage, String> {
+    db.get_memories_page(scope_id, query)
+}
+
+/// 全文检索记忆行，按相关度排序并返回摘要片段
+#[tauri::command]
+pub async fn search_memories(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    query: String,
+    filters: Option<MemoryQuery>,
+    limit: Option<usize>,
+) -> Result<Vec<MemorySearchHit>, String> {
+    db.search_memories(scope_id, &query, filters, limit)
+}
+
+/// 读取向量接口配置（不返回 API Key）
+#[tauri::command]
+pub async fn get_embedding_config(app: AppHandle) -> Result<EmbeddingConfigView, String> {
+    let data_dir = get_data_dir(&app)?;
+    Ok(embeddings::load_config_view(&data_dir))
+}
+
+#[tauri::command]
+pub async fn save_embedding_config(
+    app: AppHandle,
+    config: EmbeddingConfigInput,
+) -> Result<EmbeddingConfigView, String> {
+    let data_dir = get_data_dir(&app)?;
+    fs::create_dir_all(&data_dir).map_err(|e| e.to_string())?;
+    embeddings::save_config(&data_dir, config)
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddingIndexResult {
+    pub indexed: usize,
+    pub remaining: usize,
+}
+
+/// 为尚无向量的记忆生成 embedding；limit 限制本次处理的行数，便于前端分批调用
+#[tauri::command]
+pub async fn index_memory_embeddings(
+    app: AppHandle,
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    limit: Option<usize>,
+) -> Result<EmbeddingIndexResult, String> {
+    let data_dir = get_data_dir(&app)?;
+    let config = embeddings::load_config(&data_dir)?.ok_or("embedding is not configured")?;
+    let limit = limit.unwrap_or(usize::MAX).max(1);
+    let pending = db.pending_embeddings(scope_id.clone(), &config.model, limit)?;
+    let mut indexed = 0;
+    for batch in pending.chunks(embeddings::BATCH_SIZE) {
+        let inputs: Vec<String> = batch.iter().map(|(_, text)| text.clone()).collect();
+        let vectors = embeddings::embed(&config, &inputs).await?;
+        let items: Vec<(String, Vec<f32>)> = batch
+            .iter()
+            .map(|(id, _)| id.clone())
+            .zip(vectors)
+            .collect();
+        indexed += db.save_embeddings(scope_id.clone(), &config.model, &items)?;
+    }
+    let remaining = db.count_pending_embeddings(scope_id, &config.model)?;
+    Ok(EmbeddingIndexResult { indexed, remaining })
+}
+
+/// 语义检索参数；提供 `query_vector` 时不再调用接口生成查询向量
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SemanticSearchInput {
+    pub query: Option<String>,
+    pub query_vector: Option<Vec<f32>>,
+    pub model: Option<String>,
+    pub filters: Option<MemoryQuery>,
+    pub limit: Option<usize>,
+    pub quantized: bool,
+}
+
+#[tauri::command]
+pub async fn semantic_search_memories(
+    app: AppHandle,
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    input: SemanticSearchInput,
+) -> Result<Vec<MemorySearchHit>, String> {
+    let data_dir = get_data_dir(&app)?;
+    let config = embeddings::load_config(&data_dir)?;
+    let (model, vector) = if let Some(vector) = input.query_vector {
+        let model = input
+            .model
+            .or_else(|| config.map(|c| c.model))
+            .ok_or("embedding model is required")?;
+        (model, vector)
+    } else {
+        let config = config.ok_or("embedding is not configured")?;
+        let query = input.query.unwrap_or_default();
+        if query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let vector = embeddings::embed(&config, &[query])
+            .await?
+            .pop()
+            .ok_or("embedding response is empty")?;
+        (config.model, vector)
+    };
+    db.semantic_search_memories(
+        scope_id,
+        &model,
+        &vector,
+        input.filters,
+        input.limit,
+        input.quantized,
+    )
+}
+
 #[tauri::command]
 pub async fn batch_create_memories(
     db: State<'_, MemoryDb>,
     scope_id: Option<String>,
     memories: Vec<MemoryCreateInput>,
+    actor: Option<String>,
 ) -> Result<usize, String> {
-    db.batch_create_memories(scope_id, memories)
+    db.batch_create_memories_as(scope_id, memories, actor.as_deref().unwrap_or(ACTOR_USER))
+}
+
+/// 一次提交模型产出的整组表格修改；atomic 默认为 true
+#[tauri::command]
+pub async fn apply_memory_ops(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    ops: Vec<MemoryOp>,
+    atomic: Option<bool>,
+    actor: Option<String>,
+) -> Result<MemoryOpsResult, String> {
+    db.apply_memory_ops(
+        scope_id,
+        ops,
+        atomic.unwrap_or(true),
+        actor.as_deref().unwrap_or(ACTOR_USER),
+    )
 }
 
 #[tauri::command]
@@ -1890,8 +3709,9 @@ pub async fn batch_delete_memories(
     db: State<'_, MemoryDb>,
     scope_id: Option<String>,
     ids: Vec<String>,
+    actor: Option<String>,
 ) -> Result<usize, String> {
-    db.batch_delete_memories(scope_id, ids)
+    db.batch_delete_memories_as(scope_id, ids, actor.as_deref().unwrap_or(ACTOR_USER))
 }
 
 #[tauri::command]
@@ -1899,8 +3719,9 @@ pub async fn save_template(
     db: State<'_, MemoryDb>,
     scope_id: Option<String>,
     input: TemplateInput,
+    check_schema: Option<bool>,
 ) -> Result<(), String> {
-    db.save_template(scope_id, input)
+    db.save_template(scope_id, input, check_schema.unwrap_or(false))
 }
 
 #[tauri::command]
@@ -1917,6 +3738,224 @@ pub async fn delete_template(
     db: State<'_, MemoryDb>,
     scope_id: Option<String>,
     id: String,
+    cascade: Option<bool>,
+    actor: Option<String>,
 ) -> Result<(), String> {
-    db.delete_template(scope_id, id)
+    db.delete_template(
+        scope_id,
+        &id,
+        cascade.unwrap_or(false),
+        actor.as_deref().unwrap_or(ACTOR_USER),
+    )
+}
+
+/// 导出模板到文件（扩展名为 .zip 时写 zip，否则写 JSON）
+#[tauri::command]
+pub async fn export_template(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    id: String,
+    path: String,
+    include_samples: Option<bool>,
+) -> Result<(), String> {
+    let document = db.export_template(scope_id, &id, include_samples.unwrap_or(false))?;
+    memory_template_file::write_document(Path::new(path.trim()), &document)
+}
+
+/// 从 JSON 或 zip 文件导入模板
+#[tauri::command]
+pub async fn import_template(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    path: String,
+    on_conflict: Option<TemplateConflict>,
+) -> Result<TemplateImportResult, String> {
+    let document = memory_template_file::read_document(Path::new(path.trim()))?;
+    db.import_template(scope_id, document, on_conflict.unwrap_or_default())
+}
+
+/// 预览模板升级对已有记忆的影响
+#[tauri::command]
+pub async fn preview_template_upgrade(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    input: TemplateUpgradeInput,
+) -> Result<TemplateUpgradePreview, String> {
+    db.preview_template_upgrade(scope_id, &input)
+}
+
+/// 升级模板并按列映射迁移已有记忆
+#[tauri::command]
+pub async fn upgrade_template(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    input: TemplateUpgradeInput,
+    actor: Option<String>,
+) -> Result<TemplateUpgradeResult, String> {
+    db.upgrade_template(scope_id, input, actor.as_deref().unwrap_or(ACTOR_USER))
+}
+
+#[tauri::command]
+pub async fn list_memory_trash(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+) -> Result<MemoryTrash, String> {
+    db.list_trash(scope_id)
+}
+
+#[tauri::command]
+pub async fn restore_memory(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    id: String,
+    actor: Option<String>,
+) -> Result<(), String> {
+    db.restore_memory(scope_id, &id, actor.as_deref().unwrap_or(ACTOR_USER))
+}
+
+#[tauri::command]
+pub async fn restore_template(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    id: String,
+    actor: Option<String>,
+) -> Result<usize, String> {
+    db.restore_template(scope_id, &id, actor.as_deref().unwrap_or(ACTOR_USER))
+}
+
+/// 清理回收站中超过 `older_than_days` 天的内容（默认 30 天，0 表示全部）
+#[tauri::command]
+pub async fn purge_memory_trash(
+    db: State<'_, MemoryDb>,
+    scope_id: Option<String>,
+    older_than_days: Option<u32>,
+) -> Result<TrashPurgeResult, String> {
+    let older_than_ms = older_than_days.map(|days| i64::from(days) * 24 * 60 * 60 * 1000);
+    db.purge_trash(scope_id, older_than_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::make_temp_dir;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    #[test]
+    fn cancelled_encryption_removes_plaintext() {
+        let dir = make_temp_dir("encrypt_cancel");
+        let plain = dir.join("bundle_export.zip.part");
+        let output = dir.join("out.zip");
+        fs::write(&plain, b"plain bundle").unwrap();
+
+        let cancel = Arc::new(AtomicBool::new(true));
+        let mut progress = ProgressReporter::detached("export_1", cancel.clone());
+        let err = encrypt_staged_bundle(&plain, &output, "pw", &mut progress).unwrap_err();
+        assert_eq!(err, bundle_progress::CANCELLED_ERROR);
+        assert!(!plain.exists());
+        assert!(!output.exists());
+
+        fs::write(&plain, b"plain bundle").unwrap();
+        cancel.store(false, std::sync::atomic::Ordering::SeqCst);
+        encrypt_staged_bundle(&plain, &output, "pw", &mut progress).unwrap();
+        assert!(!plain.exists());
+        assert!(output.exists());
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn carry_over_moves_kept_paths_without_copying() {
+        let dir = make_temp_dir("carry_over");
+        let data = dir.join("data");
+        let staging = dir.join("staging");
+        fs::create_dir_all(data.join("backups")).unwrap();
+        fs::create_dir_all(data.join("wallpapers")).unwrap();
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(data.join("backups/auto.zip"), b"zip").unwrap();
+        fs::write(data.join("wallpapers/a.png"), b"png").unwrap();
+        fs::create_dir_all(data.join("worldinfo")).unwrap();
+        fs::write(data.join("worldinfo/book.json"), b"{}").unwrap();
+
+        let selection = BundleSelection {
+            include: Some(vec![BundleCategory::WorldInfo]),
+            ..BundleSelection::default()
+        };
+        let mut moved = Vec::new();
+        carry_over_unselected(&data, &data, &staging, &selection, &mut moved).unwrap();
+        moved.sort();
+        assert_eq!(moved, vec!["backups".to_string(), "wallpapers".to_string()]);
+        assert!(!data.join("backups").exists());
+        assert_eq!(fs::read(staging.join("backups/auto.zip")).unwrap(), b"zip");
+        assert_eq!(fs::read(staging.join("wallpapers/a.png")).unwrap(), b"png");
+        assert!(data.join("worldinfo/book.json").exists());
+        assert!(!staging.join("worldinfo").exists());
+
+        bundle::move_entries(&staging, &data, &moved).unwrap();
+        assert!(data.join("backups/auto.zip").exists());
+        assert!(data.join("wallpapers/a.png").exists());
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn extract_fails_when_listed_file_is_missing() {
+        let dir = make_temp_dir("extract_missing");
+        let present = "worldinfo/a.json";
+        let digest = {
+            let mut hashing = HashingWriter::new(std::io::sink());
+            hashing.write_all(b"{}").unwrap();
+            hashing.finish_hex()
+        };
+        let manifest = serde_json::json!({
+            "format": format!("tauri-chat-app-backup-v{}", bundle::BUNDLE_FORMAT_VERSION),
+            "checksums": { present: digest, "worldinfo/b.json": digest },
+        });
+        let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = FileOptions::default();
+        zip.start_file("bundle.json", options).unwrap();
+        zip.write_all(manifest.to_string().as_bytes()).unwrap();
+        zip.start_file(present, options).unwrap();
+        zip.write_all(b"{}").unwrap();
+        let mut archive = ZipArchive::new(zip.finish().unwrap()).unwrap();
+        let manifest = read_bundle_manifest(&mut archive).unwrap();
+
+        let mut progress = ProgressReporter::detached("import_1", Arc::new(AtomicBool::new(false)));
+        let err = extract_bundle_entries(
+            &mut archive,
+            &manifest,
+            &dir,
+            &BundleSelection::default(),
+            &mut progress,
+        )
+        .map(|result| result.files)
+        .unwrap_err();
+        assert_eq!(err, "bundle is missing listed files: worldinfo/b.json");
+
+        // 选择范围外的缺失文件不影响导入
+        let selection = BundleSelection {
+            exclude: vec![BundleCategory::WorldInfo],
+            ..BundleSelection::default()
+        };
+        extract_bundle_entries(&mut archive, &manifest, &dir, &selection, &mut progress).unwrap();
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn only_top_level_backups_are_sensitive() {
+        assert!(is_sensitive_bundle_path("backups"));
+        assert!(is_sensitive_bundle_path("backups/"));
+        assert!(is_sensitive_bundle_path("backups/chains/base/chain.json"));
+        assert!(is_sensitive_bundle_path("config.json"));
+        assert!(is_sensitive_bundle_path("nested/llm_keyring_v1.json"));
+        assert!(!is_sensitive_bundle_path("media/backups"));
+        assert!(!is_sensitive_bundle_path("attachments/backups/a.png"));
+        assert!(!is_sensitive_bundle_path("backups.json"));
+        assert!(is_sensitive_bundle_path(
+            "memories.db.pre_migrate.1700000000000.bak"
+        ));
+        assert!(is_sensitive_bundle_path("memories/scope.db.corrupt.1.bak"));
+        assert!(!is_sensitive_bundle_path(""));
+    }
 }
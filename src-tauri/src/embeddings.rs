@@ -0,0 +1,356 @@
+use crate::secret_store;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// 本机 embedding 接口配置（API Key 加密保存，不参与资料包导出）
+pub const CONFIG_FILE: &str = "embedding_config_v1.json";
+
+/// 单次请求的最大文本数
+pub const BATCH_SIZE: usize = 32;
+const REQUEST_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct StoredConfig {
+    url: String,
+    model: String,
+    api_key: Option<String>,
+    dimensions: Option<usize>,
+}
+
+/// 前端提交的配置；`api_key` 为 None 时保留原值，空字符串表示清除
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EmbeddingConfigInput {
+    pub url: String,
+    pub model: String,
+    pub api_key: Option<String>,
+    pub dimensions: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddingConfigView {
+    pub url: String,
+    pub model: String,
+    pub dimensions: Option<usize>,
+    pub has_api_key: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct EmbeddingConfig {
+    pub url: String,
+    pub model: String,
+    pub api_key: String,
+    pub dimensions: Option<usize>,
+}
+
+fn read_stored(data_dir: &Path) -> StoredConfig {
+    fs::read_to_string(data_dir.join(CONFIG_FILE))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn view(stored: &StoredConfig) -> EmbeddingConfigView {
+    EmbeddingConfigView {
+        url: stored.url.clone(),
+        model: stored.model.clone(),
+        dimensions: stored.dimensions,
+        has_api_key: stored.api_key.is_some(),
+    }
+}
+
+pub fn load_config_view(data_dir: &Path) -> EmbeddingConfigView {
+    view(&read_stored(data_dir))
+}
+
+pub fn save_config(
+    data_dir: &Path,
+    input: EmbeddingConfigInput,
+) -> Result<EmbeddingConfigView, String> {
+    let url = input.url.trim().to_string();
+    if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err("embedding url must start with http:// or https://".to_string());
+    }
+    let model = input.model.trim().to_string();
+    if !url.is_empty() && model.is_empty() {
+        return Err("embedding model is required".to_string());
+    }
+    let api_key = match input.api_key {
+        None => read_stored(data_dir).api_key,
+        Some(key) if key.is_empty() => None,
+        Some(key) => Some(secret_store::seal(data_dir, &key)?),
+    };
+    let stored = StoredConfig {
+        url,
+        model,
+        api_key,
+        dimensions: input.dimensions.filter(|d| *d > 0),
+    };
+    let json = serde_json::to_string_pretty(&stored).map_err(|e| e.to_string())?;
+    fs::write(data_dir.join(CONFIG_FILE), json).map_err(|e| e.to_string())?;
+    Ok(view(&stored))
+}
+
+/// 读取可用的配置；未配置 URL 时返回 None
+pub fn load_config(data_dir: &Path) -> Result<Option<EmbeddingConfig>, String> {
+    let stored = read_stored(data_dir);
+    if stored.url.is_empty() {
+        return Ok(None);
+    }
+    let api_key = match stored.api_key.as_deref() {
+        Some(sealed) => secret_store::open(data_dir, sealed)?,
+        None => String::new(),
+    };
+    Ok(Some(EmbeddingConfig {
+        url: stored.url,
+        model: stored.model,
+        api_key,
+        dimensions: stored.dimensions,
+    }))
+}
+
+// 向量分量本身就是 f32 精度，按 f64 解析后收窄不会损失有效信息
+#[allow(clippy::cast_possible_truncation)]
+fn parse_vector(value: &Value) -> Option<Vec<f32>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|x| x.as_f64().map(|f| f as f32))
+        .collect()
+}
+
+/// 兼容 `OpenAI` 风格（data[].embedding）与 `Ollama` 风格（embeddings[]）的响应
+fn parse_embeddings(body: &Value, expected: usize) -> Result<Vec<Vec<f32>>, String> {
+    let vectors: Option<Vec<Vec<f32>>> =
+        if let Some(data) = body.get("data").and_then(Value::as_array) {
+            let mut items: Vec<(u64, &Value)> = data
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let index = item
+                        .get("index")
+                        .and_then(Value::as_u64)
+                        .unwrap_or(i as u64);
+                    (index, item)
+                })
+                .collect();
+            items.sort_by_key(|(index, _)| *index);
+            items
+                .into_iter()
+                .map(|(_, item)| item.get("embedding").and_then(parse_vector))
+                .collect()
+        } else {
+            body.get("embeddings")
+                .and_then(Value::as_array)
+                .and_then(|list| list.iter().map(parse_vector).collect())
+        };
+    let vectors = vectors.ok_or("invalid embedding response")?;
+    if vectors.len() != expected {
+        return Err(format!(
+            "embedding response has {} vectors, expected {expected}",
+            vectors.len()
+        ));
+    }
+    Ok(vectors)
+}
+
+/// 调用配置的 embedding 接口
+pub async fn embed(config: &EmbeddingConfig, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+    if inputs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut body = serde_json::json!({ "model": config.model, "input": inputs });
+    if let Some(dimensions) = config.dimensions {
+        body["dimensions"] = Value::from(dimensions);
+    }
+    let mut req = client.post(&config.url).json(&body);
+    if !config.api_key.is_empty() {
+        req = req.bearer_auth(&config.api_key);
+    }
+    let resp = req.send().await.map_err(|e| e.to_string())?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        let detail: String = text.chars().take(200).collect();
+        return Err(format!("embedding request failed: {status} {detail}"));
+    }
+    let value: Value = resp.json().await.map_err(|e| e.to_string())?;
+    parse_embeddings(&value, inputs.len())
+}
+
+// ---- 向量运算（纯 CPU，离线可用） ----
+
+/// 记忆行中参与向量化的文本：所有字符串值按出现顺序拼接
+pub fn memory_text(value: &Value) -> String {
+    fn collect(value: &Value, out: &mut Vec<String>) {
+        match value {
+            Value::String(s) if !s.trim().is_empty() => out.push(s.trim().to_string()),
+            Value::Array(items) => items.iter().for_each(|item| collect(item, out)),
+            Value::Object(map) => map.values().for_each(|item| collect(item, out)),
+            _ => {}
+        }
+    }
+    let mut parts = Vec::new();
+    collect(value, &mut parts);
+    if parts.is_empty() {
+        value.to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// 归一化为单位向量，之后余弦相似度即点积；零向量或含 NaN 时返回 None
+pub fn normalize(vector: &[f32]) -> Option<Vec<f32>> {
+    let norm = vector
+        .iter()
+        .map(|x| f64::from(*x) * f64::from(*x))
+        .sum::<f64>()
+        .sqrt();
+    if vector.is_empty() || !norm.is_finite() || norm == 0.0 {
+        return None;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    Some(
+        vector
+            .iter()
+            .map(|x| (f64::from(*x) / norm) as f32)
+            .collect(),
+    )
+}
+
+pub fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub fn decode_vector(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+/// int8 对称量化：`value ≈ q * scale / 127`
+pub struct Quantized {
+    pub values: Vec<i8>,
+    pub scale: f32,
+}
+
+pub fn quantize(vector: &[f32]) -> Quantized {
+    let scale = vector.iter().fold(0f32, |max, x| max.max(x.abs()));
+    if scale == 0.0 {
+        return Quantized {
+            values: vec![0; vector.len()],
+            scale: 0.0,
+        };
+    }
+    // 结果已限制在 [-127, 127]，转换不会截断
+    #[allow(clippy::cast_possible_truncation)]
+    let values = vector
+        .iter()
+        .map(|x| (x / scale * 127.0).round().clamp(-127.0, 127.0) as i8)
+        .collect();
+    Quantized { values, scale }
+}
+
+pub fn encode_quantized(values: &[i8]) -> Vec<u8> {
+    values.iter().map(|x| x.to_le_bytes()[0]).collect()
+}
+
+pub fn decode_quantized(bytes: &[u8]) -> Vec<i8> {
+    bytes.iter().map(|b| i8::from_le_bytes([*b])).collect()
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| f64::from(*x) * f64::from(*y))
+        .sum()
+}
+
+/// 量化向量的近似点积（整数累加后再缩放）
+pub fn quantized_dot(a: &[i8], a_scale: f32, b: &[i8], b_scale: f32) -> f64 {
+    let acc: i32 = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| i32::from(*x) * i32::from(*y))
+        .sum();
+    f64::from(acc) * (f64::from(a_scale) / 127.0) * (f64::from(b_scale) / 127.0)
+}
+
+/// 按得分降序保留前 k 个
+pub fn top_k<T>(mut scored: Vec<(T, f64)>, k: usize) -> Vec<(T, f64)> {
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(k);
+    scored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn quantized_scores_track_exact_cosine() {
+        let query = normalize(&[0.9, 0.1, 0.0, 0.4]).unwrap();
+        let rows = [
+            normalize(&[1.0, 0.0, 0.0, 0.5]).unwrap(),
+            normalize(&[0.0, 1.0, 0.2, 0.0]).unwrap(),
+            normalize(&[0.5, 0.5, 0.5, 0.5]).unwrap(),
+        ];
+        let decoded = decode_vector(&encode_vector(&rows[0]));
+        assert_eq!(decoded, rows[0]);
+        assert!((dot(&rows[0], &rows[0]) - 1.0).abs() < 1e-6);
+        assert!(normalize(&[0.0, 0.0]).is_none());
+
+        let q = quantize(&query);
+        let exact: Vec<(usize, f64)> = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (i, dot(&query, row)))
+            .collect();
+        let approx: Vec<(usize, f64)> = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let r = quantize(row);
+                let values = decode_quantized(&encode_quantized(&r.values));
+                (i, quantized_dot(&q.values, q.scale, &values, r.scale))
+            })
+            .collect();
+        for ((_, e), (_, a)) in exact.iter().zip(&approx) {
+            assert!((e - a).abs() < 0.02, "{e} vs {a}");
+        }
+        let order = |scored: Vec<(usize, f64)>| -> Vec<usize> {
+            top_k(scored, 3).into_iter().map(|(i, _)| i).collect()
+        };
+        assert_eq!(order(exact), vec![0, 2, 1]);
+        assert_eq!(order(approx), vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn parses_openai_and_ollama_responses() {
+        let openai = json!({ "data": [
+            { "index": 1, "embedding": [0.0, 1.0] },
+            { "index": 0, "embedding": [1.0, 0.0] }
+        ]});
+        assert_eq!(
+            parse_embeddings(&openai, 2).unwrap(),
+            vec![vec![1.0, 0.0], vec![0.0, 1.0]]
+        );
+        let ollama = json!({ "embeddings": [[0.5, 0.5]] });
+        assert_eq!(parse_embeddings(&ollama, 1).unwrap(), vec![vec![0.5, 0.5]]);
+        assert!(parse_embeddings(&ollama, 2).is_err());
+        assert_eq!(
+            memory_text(&json!({ "a": "hiking", "b": [" trip ", 3], "c": { "d": "" } })),
+            "hiking trip"
+        );
+    }
+}
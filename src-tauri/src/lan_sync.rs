@@ -0,0 +1,1223 @@
+use crate::bundle::{self, BundleCategory, BundleSelection, HashingWriter};
+use crate::bundle_merge::{self, MergePolicy, MergeReport};
+use crate::bundle_progress::{DataAccess, DataDirLock};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::Identity;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// 局域网同步：配对码认证（SPAKE2 / ristretto255）+ AES-256-GCM 加密通道
+pub const HOST_EVENT: &str = "lan-sync-host";
+const PROTOCOL_LABEL: &[u8] = b"tauri-chat-app/lan-sync/v1";
+const HELLO_MAGIC: &[u8] = b"LSYNC1";
+const CODE_DIGITS: usize = 6;
+const CODE_TTL_MS: i64 = 10 * 60 * 1000;
+const MAX_FAILED_ATTEMPTS: u32 = 3;
+const CHUNK_SIZE: usize = 512 * 1024;
+const MAX_FRAME: usize = 32 * 1024 * 1024;
+/// 握手阶段对端尚未认证，只接受很小的帧（握手消息均为几十字节）
+const HANDSHAKE_MAX_FRAME: usize = 256;
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+const STAGING_SUFFIX: &str = "lan_staging";
+const SERVE_SUFFIX: &str = "lan_serve";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 对端清单中的单个文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LanFile {
+    sha256: String,
+    size: u64,
+    modified: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Manifest {
+        selection: BundleSelection,
+    },
+    Get {
+        path: String,
+        offset: u64,
+    },
+    /// 拉取完成后交换角色，由对端反向拉取本机数据
+    Reverse {
+        selection: BundleSelection,
+    },
+    End,
+}
+
+/// 单向拉取的结果
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanPullReport {
+    pub files: usize,
+    pub fetched: usize,
+    pub unchanged: usize,
+    pub rejected: usize,
+    pub bytes: u64,
+    pub merge: Option<MergeReport>,
+}
+
+/// 连接方一次同步的结果
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanSyncResult {
+    pub pulled: LanPullReport,
+    pub two_way: bool,
+    pub sent_files: usize,
+    pub sent_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LanHostState {
+    Waiting,
+    Syncing,
+    Done,
+    Failed,
+    Expired,
+    Stopped,
+}
+
+/// 本机等待连接时的状态，通过 [`HOST_EVENT`] 事件推送给前端
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanHostInfo {
+    pub code: String,
+    pub port: u16,
+    pub addresses: Vec<String>,
+    pub expires_at: i64,
+    pub state: LanHostState,
+    pub failed_attempts: u32,
+    pub error: Option<String>,
+    /// 双向同步时本机拉取对端数据的结果
+    pub report: Option<LanPullReport>,
+}
+
+impl LanHostInfo {
+    fn is_active(&self) -> bool {
+        matches!(self.state, LanHostState::Waiting | LanHostState::Syncing)
+    }
+}
+
+pub struct HostOptions {
+    pub data_dir: PathBuf,
+    pub policy: MergePolicy,
+    pub port: u16,
+    /// 与资料包导入/导出共用的数据目录锁：提供数据时共享，反向拉取合并时独占
+    pub lock: Arc<DataDirLock>,
+    /// 合并写入数据目录前调用（关闭记忆库连接）
+    pub before_write: Arc<dyn Fn() + Send + Sync>,
+    pub notify: Arc<dyn Fn(&LanHostInfo) + Send + Sync>,
+}
+
+pub struct LanConnectOptions {
+    pub address: String,
+    pub code: String,
+    pub selection: BundleSelection,
+    pub policy: MergePolicy,
+    pub two_way: bool,
+}
+
+struct HostSession {
+    info: Mutex<LanHostInfo>,
+    stop: AtomicBool,
+}
+
+impl HostSession {
+    fn snapshot(&self) -> LanHostInfo {
+        self.info.lock().map_or_else(
+            |poisoned| poisoned.into_inner().clone(),
+            |info| info.clone(),
+        )
+    }
+
+    fn update(&self, f: impl FnOnce(&mut LanHostInfo)) -> LanHostInfo {
+        let mut info = self
+            .info
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        f(&mut info);
+        info.clone()
+    }
+}
+
+/// 同一时间只允许一个等待中的配对会话
+#[derive(Default)]
+pub struct LanSyncState {
+    session: Mutex<Option<Arc<HostSession>>>,
+}
+
+impl LanSyncState {
+    pub fn start_host(&self, options: HostOptions) -> Result<LanHostInfo, String> {
+        let mut guard = self
+            .session
+            .lock()
+            .map_err(|_| "lan sync lock poisoned".to_string())?;
+        if guard.as_ref().is_some_and(|s| s.snapshot().is_active()) {
+            return Err("LAN sync host is already running".to_string());
+        }
+        let listener = TcpListener::bind(("0.0.0.0", options.port)).map_err(|e| e.to_string())?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        let info = LanHostInfo {
+            code: new_pairing_code(),
+            port,
+            addresses: local_addresses(),
+            expires_at: chrono::Utc::now().timestamp_millis() + CODE_TTL_MS,
+            state: LanHostState::Waiting,
+            failed_attempts: 0,
+            error: None,
+            report: None,
+        };
+        let session = Arc::new(HostSession {
+            info: Mutex::new(info.clone()),
+            stop: AtomicBool::new(false),
+        });
+        let worker = Arc::clone(&session);
+        thread::Builder::new()
+            .name("lan-sync-host".to_string())
+            .spawn(move || host_loop(&worker, &listener, &options))
+            .map_err(|e| e.to_string())?;
+        *guard = Some(session);
+        Ok(info)
+    }
+
+    /// 停止等待连接；已开始传输的会话会继续完成
+    pub fn stop_host(&self) -> bool {
+        let Ok(guard) = self.session.lock() else {
+            return false;
+        };
+        guard.as_ref().is_some_and(|session| {
+            session.stop.store(true, Ordering::SeqCst);
+            session.snapshot().state == LanHostState::Waiting
+        })
+    }
+
+    pub fn host_info(&self) -> Option<LanHostInfo> {
+        let guard = self.session.lock().ok()?;
+        guard.as_ref().map(|session| session.snapshot())
+    }
+}
+
+/// 启动时清理上次中断遗留的暂存目录
+pub fn discard_leftovers(data_dir: &Path) {
+    for suffix in [STAGING_SUFFIX, SERVE_SUFFIX] {
+        let dir = bundle::work_dir(data_dir, suffix);
+        if dir.exists() {
+            let _ = fs::remove_dir_all(&dir);
+        }
+    }
+}
+
+fn new_pairing_code() -> String {
+    format!("{:06}", OsRng.next_u32() % 1_000_000)
+}
+
+fn normalize_code(code: &str) -> Result<String, String> {
+    let digits: String = code.chars().filter(char::is_ascii_digit).collect();
+    if digits.len() != CODE_DIGITS || code.chars().any(char::is_alphabetic) {
+        return Err("pairing code must be 6 digits".to_string());
+    }
+    Ok(digits)
+}
+
+/// 通过 UDP connect 取本机出口地址（不发送数据）
+fn local_addresses() -> Vec<String> {
+    let probe = UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| socket.connect("192.0.2.1:9").map(|()| socket))
+        .and_then(|socket| socket.local_addr());
+    match probe {
+        Ok(addr) if !addr.ip().is_unspecified() => vec![addr.ip().to_string()],
+        _ => vec!["127.0.0.1".to_string()],
+    }
+}
+
+// ---- 帧与握手 ----
+
+fn write_frame(stream: &mut TcpStream, payload: &[u8]) -> Result<(), String> {
+    let len = u32::try_from(payload.len()).map_err(|_| "frame too large".to_string())?;
+    stream
+        .write_all(&len.to_be_bytes())
+        .and_then(|()| stream.write_all(payload))
+        .map_err(|e| e.to_string())
+}
+
+fn read_frame(stream: &mut TcpStream, max_len: usize) -> Result<Vec<u8>, String> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).map_err(|e| e.to_string())?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err("frame too large".to_string());
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).map_err(|e| e.to_string())?;
+    Ok(payload)
+}
+
+fn prepare_stream(stream: &TcpStream) -> Result<(), String> {
+    stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    stream
+        .set_write_timeout(Some(IO_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let _ = stream.set_nodelay(true);
+    Ok(())
+}
+
+// 配对握手为 SPAKE2（ristretto255），配对码只作为低熵口令参与运算，不在网络上出现：
+// - 口令标量 w 与盲化点 M/N 都由协议标签派生，M/N 的离散对数无人知晓
+// - 客户端发 X = x·G + w·M，主机回 Y = y·G + w·N；双方得到同一个 K = x·y·G
+// - 会话记录 = SHA-256(标签 ‖ X ‖ Y ‖ K ‖ w)，X/Y/K 均为 32 字节规范编码，拼接无歧义；
+//   X 在前 Y 在后固定顺序，任一消息被替换或交换都会得到不同的记录
+// - 所有会话密钥都由记录经 HKDF 派生，每个用途一个 info，互不复用
+// - 客户端先发确认 MAC，主机验证通过后才回自己的 MAC；错误的码拿不到任何可离线比对的值，
+//   主机在发出 Y 之后的任何失败都计为一次猜码
+// - K 为单位元时直接拒绝（对端消息构造异常）
+
+fn password_scalar(code: &str) -> Scalar {
+    Scalar::hash_from_bytes::<Sha512>(&[PROTOCOL_LABEL, b"/code/", code.as_bytes()].concat())
+}
+
+fn blinding_point(tag: &[u8]) -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(&[PROTOCOL_LABEL, tag].concat())
+}
+
+fn decode_point(bytes: &[u8]) -> Result<RistrettoPoint, String> {
+    CompressedRistretto::from_slice(bytes)
+        .ok()
+        .and_then(|point| point.decompress())
+        .ok_or_else(|| "invalid handshake message".to_string())
+}
+
+/// 本端握手消息：`secret·G + password·blind`
+fn spake_message(secret: &Scalar, password: &Scalar, blind: &[u8]) -> [u8; 32] {
+    (RISTRETTO_BASEPOINT_POINT * secret + blinding_point(blind) * password)
+        .compress()
+        .to_bytes()
+}
+
+/// 去掉对端消息中的口令盲化后求共享点；对端用 `peer_blind` 盲化
+fn spake_shared(
+    peer: &RistrettoPoint,
+    peer_blind: &[u8],
+    password: &Scalar,
+    secret: &Scalar,
+) -> Result<RistrettoPoint, String> {
+    let shared = (peer - blinding_point(peer_blind) * password) * secret;
+    if shared == RistrettoPoint::identity() {
+        return Err("invalid handshake message".to_string());
+    }
+    Ok(shared)
+}
+
+struct SessionKeys {
+    client_to_host: [u8; 32],
+    host_to_client: [u8; 32],
+    client_confirm: [u8; 32],
+    host_confirm: [u8; 32],
+    transcript: [u8; 32],
+}
+
+fn derive_keys(
+    client_msg: &[u8; 32],
+    host_msg: &[u8; 32],
+    shared: &RistrettoPoint,
+    password: &Scalar,
+) -> SessionKeys {
+    let mut hasher = Sha256::new();
+    hasher.update(PROTOCOL_LABEL);
+    hasher.update(client_msg);
+    hasher.update(host_msg);
+    hasher.update(shared.compress().as_bytes());
+    hasher.update(password.as_bytes());
+    let transcript: [u8; 32] = hasher.finalize().into();
+    let hk = Hkdf::<Sha256>::new(None, &transcript);
+    let expand = |info: &[u8]| {
+        let mut out = [0u8; 32];
+        hk.expand(info, &mut out)
+            .expect("32 bytes is a valid HKDF output length");
+        out
+    };
+    SessionKeys {
+        client_to_host: expand(b"client->host"),
+        host_to_client: expand(b"host->client"),
+        client_confirm: expand(b"client confirm"),
+        host_confirm: expand(b"host confirm"),
+        transcript,
+    }
+}
+
+fn confirm_mac(key: &[u8; 32], transcript: &[u8; 32]) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(transcript);
+    mac
+}
+
+enum PairingError {
+    WrongCode,
+    Io(String),
+}
+
+fn client_handshake(mut stream: TcpStream, code: &str) -> Result<SecureChannel, String> {
+    let password = password_scalar(code);
+    let secret = Scalar::random(&mut OsRng);
+    let client_msg = spake_message(&secret, &password, b"/M");
+    write_frame(&mut stream, &[HELLO_MAGIC, &client_msg].concat())?;
+    let host_point = decode_point(&read_frame(&mut stream, HANDSHAKE_MAX_FRAME)?)?;
+    let host_msg = host_point.compress().to_bytes();
+    let shared = spake_shared(&host_point, b"/N", &password, &secret)?;
+    let keys = derive_keys(&client_msg, &host_msg, &shared, &password);
+    let tag = confirm_mac(&keys.client_confirm, &keys.transcript).finalize();
+    write_frame(&mut stream, &tag.into_bytes())?;
+    let host_tag = read_frame(&mut stream, HANDSHAKE_MAX_FRAME)
+        .map_err(|_| "pairing code was rejected".to_string())?;
+    confirm_mac(&keys.host_confirm, &keys.transcript)
+        .verify_slice(&host_tag)
+        .map_err(|_| "pairing code was rejected".to_string())?;
+    Ok(SecureChannel::new(
+        stream,
+        &keys.client_to_host,
+        &keys.host_to_client,
+    ))
+}
+
+fn host_handshake(mut stream: TcpStream, code: &str) -> Result<SecureChannel, PairingError> {
+    let hello = read_frame(&mut stream, HANDSHAKE_MAX_FRAME).map_err(PairingError::Io)?;
+    let client_point = hello
+        .strip_prefix(HELLO_MAGIC)
+        .ok_or_else(|| PairingError::Io("unknown client".to_string()))
+        .and_then(|bytes| decode_point(bytes).map_err(PairingError::Io))?;
+    let client_msg = client_point.compress().to_bytes();
+    let password = password_scalar(code);
+    let secret = Scalar::random(&mut OsRng);
+    let host_msg = spake_message(&secret, &password, b"/N");
+    write_frame(&mut stream, &host_msg).map_err(PairingError::Io)?;
+    // 对端已拿到本机消息，此后的任何失败都计为一次猜码
+    let shared = spake_shared(&client_point, b"/M", &password, &secret)
+        .map_err(|_| PairingError::WrongCode)?;
+    let keys = derive_keys(&client_msg, &host_msg, &shared, &password);
+    let client_tag =
+        read_frame(&mut stream, HANDSHAKE_MAX_FRAME).map_err(|_| PairingError::WrongCode)?;
+    confirm_mac(&keys.client_confirm, &keys.transcript)
+        .verify_slice(&client_tag)
+        .map_err(|_| PairingError::WrongCode)?;
+    let tag = confirm_mac(&keys.host_confirm, &keys.transcript).finalize();
+    write_frame(&mut stream, &tag.into_bytes()).map_err(PairingError::Io)?;
+    Ok(SecureChannel::new(
+        stream,
+        &keys.host_to_client,
+        &keys.client_to_host,
+    ))
+}
+
+// 加密通道的 nonce 规则：
+// - 两个方向各用一把由会话记录派生的密钥，同一计数值在两把密钥下不构成 nonce 复用
+// - nonce = 4 字节 0 ‖ 64 位大端计数器；每个方向从 0 开始，每帧加一，永不回退或回绕
+// - 接收方只按自己的计数器解密，重放、乱序、丢帧或把本端发出的帧反射回来都会认证失败
+// - 密钥随每次握手的随机标量更新，不跨连接复用
+
+/// 握手后的加密通道：每个方向独立密钥，nonce 为递增计数器
+struct SecureChannel {
+    stream: TcpStream,
+    send_cipher: Aes256Gcm,
+    recv_cipher: Aes256Gcm,
+    send_counter: u64,
+    recv_counter: u64,
+}
+
+fn counter_nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+fn next_counter(counter: u64) -> Result<u64, String> {
+    counter
+        .checked_add(1)
+        .ok_or_else(|| "secure channel exhausted".to_string())
+}
+
+impl SecureChannel {
+    fn new(stream: TcpStream, send_key: &[u8; 32], recv_key: &[u8; 32]) -> Self {
+        Self {
+            stream,
+            send_cipher: Aes256Gcm::new(send_key.into()),
+            recv_cipher: Aes256Gcm::new(recv_key.into()),
+            send_counter: 0,
+            recv_counter: 0,
+        }
+    }
+
+    fn send(&mut self, plaintext: &[u8]) -> Result<(), String> {
+        // 先确认计数器还能前进，保证同一 nonce 不会被用来加密两次
+        let next = next_counter(self.send_counter)?;
+        let ciphertext = self
+            .send_cipher
+            .encrypt(
+                Nonce::from_slice(&counter_nonce(self.send_counter)),
+                plaintext,
+            )
+            .map_err(|_| "encrypt frame failed".to_string())?;
+        self.send_counter = next;
+        write_frame(&mut self.stream, &ciphertext)
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>, String> {
+        let ciphertext = read_frame(&mut self.stream, MAX_FRAME)?;
+        let plaintext = self
+            .recv_cipher
+            .decrypt(
+                Nonce::from_slice(&counter_nonce(self.recv_counter)),
+                ciphertext.as_slice(),
+            )
+            .map_err(|_| "frame authentication failed".to_string())?;
+        self.recv_counter = next_counter(self.recv_counter)?;
+        Ok(plaintext)
+    }
+
+    fn send_request(&mut self, request: &Request) -> Result<(), String> {
+        self.send(&serde_json::to_vec(request).map_err(|e| e.to_string())?)
+    }
+
+    fn call(&mut self, request: &Request) -> Result<Vec<u8>, String> {
+        self.send_request(request)?;
+        let reply = self.recv()?;
+        match reply.split_first() {
+            Some((0, body)) => Ok(body.to_vec()),
+            Some((1, message)) => Err(String::from_utf8_lossy(message).to_string()),
+            _ => Err("invalid reply from peer".to_string()),
+        }
+    }
+
+    fn reply(&mut self, result: Result<&[u8], String>) -> Result<(), String> {
+        let frame = match result {
+            Ok(body) => [&[0u8][..], body].concat(),
+            Err(message) => [&[1u8][..], message.as_bytes()].concat(),
+        };
+        self.send(&frame)
+    }
+}
+
+// ---- 提供数据 ----
+
+struct ServedFile {
+    path: PathBuf,
+    entry: LanFile,
+}
+
+enum ServeOutcome {
+    End,
+    Reverse(BundleSelection),
+}
+
+#[derive(Default)]
+struct ServeStats {
+    files: usize,
+    bytes: u64,
+}
+
+/// 记忆库以 `VACUUM INTO` 生成一致快照后再提供
+fn snapshot_sqlite(source: &Path, target: &Path) -> Result<(), String> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let conn = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())?;
+    conn.execute("VACUUM INTO ?1", [target.to_string_lossy()])
+        .map_err(|e| format!("snapshot {} failed: {e}", source.display()))?;
+    Ok(())
+}
+
+fn build_served(
+    data_dir: &Path,
+    scratch: &Path,
+    selection: &BundleSelection,
+) -> Result<BTreeMap<String, ServedFile>, String> {
+    if scratch.exists() {
+        fs::remove_dir_all(scratch).map_err(|e| e.to_string())?;
+    }
+    let mut served = BTreeMap::new();
+    for rel in crate::commands::list_local_files(data_dir, data_dir, selection)? {
+        if bundle_merge::is_memory_db_sidecar(&rel) {
+            continue;
+        }
+        let source = data_dir.join(&rel);
+        let path = if bundle::category_for_path(&rel) == BundleCategory::Memories
+            && Path::new(&rel)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("db"))
+        {
+            let target = scratch.join(&rel);
+            snapshot_sqlite(&source, &target)?;
+            target
+        } else {
+            source.clone()
+        };
+        let size = fs::metadata(&path).map_err(|e| e.to_string())?.len();
+        let entry = LanFile {
+            sha256: bundle::sha256_file(&path)?,
+            size,
+            modified: bundle::file_modified_ms(&source),
+        };
+        served.insert(rel, ServedFile { path, entry });
+    }
+    Ok(served)
+}
+
+fn read_chunk(path: &Path, offset: u64) -> Result<Vec<u8>, String> {
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| e.to_string())?;
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    file.take(CHUNK_SIZE as u64)
+        .read_to_end(&mut chunk)
+        .map_err(|e| e.to_string())?;
+    Ok(chunk)
+}
+
+/// 响应对端请求，直到对端结束或要求反向拉取
+fn serve(
+    channel: &mut SecureChannel,
+    data_dir: &Path,
+) -> Result<(ServeOutcome, ServeStats), String> {
+    let scratch = bundle::work_dir(data_dir, SERVE_SUFFIX);
+    let result = serve_requests(channel, data_dir, &scratch);
+    let _ = fs::remove_dir_all(&scratch);
+    result
+}
+
+fn serve_requests(
+    channel: &mut SecureChannel,
+    data_dir: &Path,
+    scratch: &Path,
+) -> Result<(ServeOutcome, ServeStats), String> {
+    let mut served = BTreeMap::new();
+    let mut stats = ServeStats::default();
+    loop {
+        let request: Request =
+            serde_json::from_slice(&channel.recv()?).map_err(|e| e.to_string())?;
+        match request {
+            Request::Manifest { selection } => match build_served(data_dir, scratch, &selection) {
+                Ok(files) => {
+                    served = files;
+                    let manifest: BTreeMap<&String, &LanFile> = served
+                        .iter()
+                        .map(|(rel, file)| (rel, &file.entry))
+                        .collect();
+                    let body = serde_json::to_vec(&manifest).map_err(|e| e.to_string())?;
+                    channel.reply(Ok(&body))?;
+                }
+                Err(err) => channel.reply(Err(err))?,
+            },
+            Request::Get { path, offset } => {
+                // 只提供清单中列出的文件
+                let Some(file) = served.get(&path) else {
+                    channel.reply(Err(format!("file not offered: {path}")))?;
+                    continue;
+                };
+                match read_chunk(&file.path, offset) {
+                    Ok(chunk) => {
+                        if offset == 0 {
+                            stats.files += 1;
+                        }
+                        stats.bytes += chunk.len() as u64;
+                        channel.reply(Ok(&chunk))?;
+                    }
+                    Err(err) => channel.reply(Err(err))?,
+                }
+            }
+            Request::Reverse { selection } => {
+                channel.reply(Ok(&[]))?;
+                return Ok((ServeOutcome::Reverse(selection), stats));
+            }
+            Request::End => return Ok((ServeOutcome::End, stats)),
+        }
+    }
+}
+
+// ---- 拉取数据 ----
+
+fn is_safe_rel(rel: &str) -> bool {
+    !rel.is_empty()
+        && !rel.contains('\\')
+        && !rel.contains(':')
+        && rel
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+}
+
+fn is_rejected_path(rel: &str) -> bool {
+    !is_safe_rel(rel) || crate::commands::is_sensitive_bundle_path(rel)
+}
+
+fn fetch_file(
+    channel: &mut SecureChannel,
+    rel: &str,
+    remote: &LanFile,
+    target: &Path,
+) -> Result<(), String> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let file = fs::File::create(target).map_err(|e| e.to_string())?;
+    let mut hashing = HashingWriter::new(file);
+    let mut offset = 0u64;
+    while offset < remote.size {
+        let chunk = channel.call(&Request::Get {
+            path: rel.to_string(),
+            offset,
+        })?;
+        if chunk.is_empty() {
+            return Err(format!("{rel} was truncated by peer"));
+        }
+        hashing.write_all(&chunk).map_err(|e| e.to_string())?;
+        offset += chunk.len() as u64;
+    }
+    if offset != remote.size {
+        return Err(format!("{rel} size mismatch"));
+    }
+    let (mut file, sha256) = hashing.finish();
+    file.flush().map_err(|e| e.to_string())?;
+    if sha256 != remote.sha256 {
+        return Err(format!("{rel} checksum mismatch"));
+    }
+    Ok(())
+}
+
+fn is_unchanged(local: &Path, remote: &LanFile) -> bool {
+    fs::metadata(local).is_ok_and(|meta| meta.is_file() && meta.len() == remote.size)
+        && bundle::sha256_file(local).is_ok_and(|sha| sha == remote.sha256)
+}
+
+/// 拉取对端变化的文件到暂存目录，再按合并策略经副本合并后换入本机
+fn pull(
+    channel: &mut SecureChannel,
+    data_dir: &Path,
+    selection: &BundleSelection,
+    policy: MergePolicy,
+    before_write: &dyn Fn(),
+    on_file: &mut dyn FnMut(&str, u64) -> Result<(), String>,
+) -> Result<LanPullReport, String> {
+    let body = channel.call(&Request::Manifest {
+        selection: selection.clone(),
+    })?;
+    let manifest: BTreeMap<String, LanFile> =
+        serde_json::from_slice(&body).map_err(|e| format!("invalid peer manifest: {e}"))?;
+    let staging = bundle::work_dir(data_dir, STAGING_SUFFIX);
+    if staging.exists() {
+        fs::remove_dir_all(&staging).map_err(|e| e.to_string())?;
+    }
+    fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+    let result = pull_into(
+        channel,
+        data_dir,
+        &staging,
+        &manifest,
+        policy,
+        before_write,
+        on_file,
+    );
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn pull_into(
+    channel: &mut SecureChannel,
+    data_dir: &Path,
+    staging: &Path,
+    manifest: &BTreeMap<String, LanFile>,
+    policy: MergePolicy,
+    before_write: &dyn Fn(),
+    on_file: &mut dyn FnMut(&str, u64) -> Result<(), String>,
+) -> Result<LanPullReport, String> {
+    let mut report = LanPullReport {
+        files: manifest.len(),
+        ..LanPullReport::default()
+    };
+    let mut modified = BTreeMap::new();
+    for (rel, remote) in manifest {
+        if is_rejected_path(rel) {
+            report.rejected += 1;
+            continue;
+        }
+        if is_unchanged(&data_dir.join(rel), remote) {
+            report.unchanged += 1;
+            continue;
+        }
+        fetch_file(channel, rel, remote, &staging.join(rel))?;
+        report.fetched += 1;
+        report.bytes += remote.size;
+        if let Some(ms) = remote.modified {
+            modified.insert(rel.clone(), ms);
+        }
+        on_file(rel, remote.size)?;
+    }
+    if report.fetched > 0 {
+        // 与导入一致：先在数据目录副本上合并再整体换入，原目录留作可回滚的快照
+        before_write();
+        report.merge = Some(bundle_merge::merge_staged(
+            staging,
+            data_dir,
+            policy,
+            &modified,
+            &mut || {
+                before_write();
+                Ok(())
+            },
+        )?);
+    }
+    Ok(report)
+}
+
+// ---- 会话 ----
+
+fn host_loop(session: &HostSession, listener: &TcpListener, options: &HostOptions) {
+    let notify = |info: LanHostInfo| (options.notify)(&info);
+    let code = session.snapshot().code;
+    let expires_at = session.snapshot().expires_at;
+    loop {
+        if session.stop.load(Ordering::SeqCst) {
+            notify(session.update(|info| info.state = LanHostState::Stopped));
+            return;
+        }
+        if chrono::Utc::now().timestamp_millis() >= expires_at {
+            notify(session.update(|info| info.state = LanHostState::Expired));
+            return;
+        }
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL);
+                continue;
+            }
+            Err(err) => {
+                notify(session.update(|info| {
+                    info.state = LanHostState::Failed;
+                    info.error = Some(err.to_string());
+                }));
+                return;
+            }
+        };
+        if let Err(err) = prepare_stream(&stream) {
+            eprintln!("[lan_sync] prepare connection failed: {err}");
+            continue;
+        }
+        match host_handshake(stream, &code) {
+            Ok(mut channel) => {
+                notify(session.update(|info| info.state = LanHostState::Syncing));
+                let result = run_host_session(&mut channel, options);
+                notify(session.update(|info| match result {
+                    Ok(report) => {
+                        info.state = LanHostState::Done;
+                        info.report = report;
+                    }
+                    Err(err) => {
+                        info.state = LanHostState::Failed;
+                        info.error = Some(err);
+                    }
+                }));
+                return;
+            }
+            Err(PairingError::WrongCode) => {
+                let info = session.update(|info| {
+                    info.failed_attempts += 1;
+                    if info.failed_attempts >= MAX_FAILED_ATTEMPTS {
+                        info.state = LanHostState::Failed;
+                        info.error = Some("too many wrong pairing codes".to_string());
+                    }
+                });
+                let burned = info.state == LanHostState::Failed;
+                notify(info);
+                if burned {
+                    return;
+                }
+            }
+            Err(PairingError::Io(err)) => eprintln!("[lan_sync] handshake failed: {err}"),
+        }
+    }
+}
+
+fn run_host_session(
+    channel: &mut SecureChannel,
+    options: &HostOptions,
+) -> Result<Option<LanPullReport>, String> {
+    let shared = options.lock.acquire(DataAccess::Shared)?;
+    let outcome = serve(channel, &options.data_dir)?.0;
+    drop(shared);
+    match outcome {
+        ServeOutcome::End => Ok(None),
+        ServeOutcome::Reverse(selection) => {
+            let _exclusive = options.lock.acquire(DataAccess::Exclusive)?;
+            let report = pull(
+                channel,
+                &options.data_dir,
+                &selection,
+                options.policy,
+                &*options.before_write,
+                &mut |_, _| Ok(()),
+            )?;
+            channel.send_request(&Request::End)?;
+            Ok(Some(report))
+        }
+    }
+}
+
+fn connect(address: &str) -> Result<TcpStream, String> {
+    let addrs: Vec<_> = address
+        .to_socket_addrs()
+        .map_err(|e| format!("invalid address {address}: {e}"))?
+        .collect();
+    let mut last_error = format!("cannot resolve {address}");
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                prepare_stream(&stream)?;
+                return Ok(stream);
+            }
+            Err(err) => last_error = err.to_string(),
+        }
+    }
+    Err(last_error)
+}
+
+/// 连接对端（`host:port`）并拉取其数据；双向模式下再由对端反向拉取本机数据
+pub fn connect_and_sync(
+    data_dir: &Path,
+    options: &LanConnectOptions,
+    before_write: &dyn Fn(),
+    on_file: &mut dyn FnMut(&str, u64) -> Result<(), String>,
+) -> Result<LanSyncResult, String> {
+    let code = normalize_code(&options.code)?;
+    let mut channel = client_handshake(connect(&options.address)?, &code)?;
+    let pulled = pull(
+        &mut channel,
+        data_dir,
+        &options.selection,
+        options.policy,
+        before_write,
+        on_file,
+    )?;
+    let mut result = LanSyncResult {
+        pulled,
+        two_way: options.two_way,
+        sent_files: 0,
+        sent_bytes: 0,
+    };
+    if !options.two_way {
+        channel.send_request(&Request::End)?;
+        return Ok(result);
+    }
+    channel.call(&Request::Reverse {
+        selection: options.selection.clone(),
+    })?;
+    match serve(&mut channel, data_dir)? {
+        (ServeOutcome::End, stats) => {
+            result.sent_files = stats.files;
+            result.sent_bytes = stats.bytes;
+            Ok(result)
+        }
+        (ServeOutcome::Reverse(_), _) => Err("unexpected reverse request from peer".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::make_temp_dir;
+    use std::time::Instant;
+
+    fn make_data_dir(tag: &str) -> PathBuf {
+        let data_dir = make_temp_dir(tag).join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+        data_dir
+    }
+
+    fn write(dir: &Path, rel: &str, content: &str) {
+        let path = dir.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn start_host(state: &LanSyncState, data_dir: &Path, lock: Arc<DataDirLock>) -> LanHostInfo {
+        state
+            .start_host(HostOptions {
+                data_dir: data_dir.to_path_buf(),
+                policy: MergePolicy::NewestWins,
+                port: 0,
+                lock,
+                before_write: Arc::new(|| {}),
+                notify: Arc::new(|_| {}),
+            })
+            .unwrap()
+    }
+
+    fn wait_finished(state: &LanSyncState) -> LanHostInfo {
+        let deadline = Instant::now() + Duration::from_secs(20);
+        loop {
+            let info = state.host_info().unwrap();
+            if !info.is_active() || Instant::now() > deadline {
+                return info;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    fn connect_options(port: u16, code: &str, two_way: bool) -> LanConnectOptions {
+        LanConnectOptions {
+            address: format!("127.0.0.1:{port}"),
+            code: code.to_string(),
+            selection: BundleSelection::default(),
+            policy: MergePolicy::NewestWins,
+            two_way,
+        }
+    }
+
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (host, _) = listener.accept().unwrap();
+        (client, host)
+    }
+
+    fn run_spake(client_code: &str, host_code: &str) -> (SessionKeys, SessionKeys) {
+        let (client_pw, host_pw) = (password_scalar(client_code), password_scalar(host_code));
+        let (x, y) = (Scalar::random(&mut OsRng), Scalar::random(&mut OsRng));
+        let client_msg = spake_message(&x, &client_pw, b"/M");
+        let host_msg = spake_message(&y, &host_pw, b"/N");
+        let client_shared =
+            spake_shared(&decode_point(&host_msg).unwrap(), b"/N", &client_pw, &x).unwrap();
+        let host_shared =
+            spake_shared(&decode_point(&client_msg).unwrap(), b"/M", &host_pw, &y).unwrap();
+        (
+            derive_keys(&client_msg, &host_msg, &client_shared, &client_pw),
+            derive_keys(&client_msg, &host_msg, &host_shared, &host_pw),
+        )
+    }
+
+    fn seal(key: &[u8; 32], counter: u64, plaintext: &[u8]) -> Vec<u8> {
+        Aes256Gcm::new(key.into())
+            .encrypt(Nonce::from_slice(&counter_nonce(counter)), plaintext)
+            .unwrap()
+    }
+
+    #[test]
+    fn handshake_keys_agree_only_with_same_code() {
+        let (client, host) = run_spake("123456", "123456");
+        assert_eq!(client.transcript, host.transcript);
+        assert_eq!(client.client_to_host, host.client_to_host);
+        assert_eq!(client.host_to_client, host.host_to_client);
+        // 每个用途一把密钥
+        let keys = [
+            client.client_to_host,
+            client.host_to_client,
+            client.client_confirm,
+            client.host_confirm,
+        ];
+        for (i, a) in keys.iter().enumerate() {
+            assert!(keys[i + 1..].iter().all(|b| a != b));
+        }
+
+        let (client, host) = run_spake("123456", "123457");
+        assert_ne!(client.transcript, host.transcript);
+        assert_ne!(client.client_to_host, host.client_to_host);
+        let tag = confirm_mac(&client.client_confirm, &client.transcript).finalize();
+        assert!(confirm_mac(&host.client_confirm, &host.transcript)
+            .verify_slice(&tag.into_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn transcript_binds_message_order_and_rejects_identity() {
+        let password = password_scalar("123456");
+        let (x, y) = (Scalar::random(&mut OsRng), Scalar::random(&mut OsRng));
+        let client_msg = spake_message(&x, &password, b"/M");
+        let host_msg = spake_message(&y, &password, b"/N");
+        let shared = spake_shared(&decode_point(&host_msg).unwrap(), b"/N", &password, &x).unwrap();
+        let keys = derive_keys(&client_msg, &host_msg, &shared, &password);
+        let swapped = derive_keys(&host_msg, &client_msg, &shared, &password);
+        assert_ne!(keys.transcript, swapped.transcript);
+        // 把对端的消息当成自己的发回（反射）时无法求出同一个共享点
+        let reflected =
+            spake_shared(&decode_point(&client_msg).unwrap(), b"/N", &password, &x).unwrap();
+        assert_ne!(reflected, shared);
+        // 恰好抵消盲化的消息会得到单位元，直接拒绝
+        let degenerate = blinding_point(b"/M") * password;
+        assert!(spake_shared(&degenerate, b"/M", &password, &y).is_err());
+    }
+
+    #[test]
+    fn secure_channel_rejects_replayed_reordered_and_reflected_frames() {
+        let (client_key, host_key) = ([1u8; 32], [2u8; 32]);
+        let (client_stream, host_stream) = socket_pair();
+        let mut client_raw = client_stream.try_clone().unwrap();
+        let mut host_raw = host_stream.try_clone().unwrap();
+        let mut client = SecureChannel::new(client_stream, &client_key, &host_key);
+        let mut host = SecureChannel::new(host_stream, &host_key, &client_key);
+
+        client.send(b"one").unwrap();
+        assert_eq!(host.recv().unwrap(), b"one");
+        // 重放第 0 帧
+        write_frame(&mut client_raw, &seal(&client_key, 0, b"one")).unwrap();
+        assert!(host.recv().is_err());
+        // 跳过第 1 帧
+        write_frame(&mut client_raw, &seal(&client_key, 2, b"three")).unwrap();
+        assert!(host.recv().is_err());
+        // 把客户端自己发出的帧反射回客户端
+        write_frame(&mut host_raw, &seal(&client_key, 0, b"one")).unwrap();
+        assert!(client.recv().is_err());
+        // 失败的帧不推进计数器，后续正常帧仍可解密
+        client.send(b"two").unwrap();
+        assert_eq!(host.recv().unwrap(), b"two");
+        host.send(b"back").unwrap();
+        assert_eq!(client.recv().unwrap(), b"back");
+
+        assert_ne!(counter_nonce(1), counter_nonce(1 << 32));
+        client.send_counter = u64::MAX;
+        assert!(client.send(b"overflow").is_err());
+        assert_eq!(client.send_counter, u64::MAX);
+    }
+
+    #[test]
+    fn wrong_code_is_rejected_and_burns_after_limit() {
+        let host_dir = make_data_dir("burn_host");
+        let client_dir = make_data_dir("burn_client");
+        write(&host_dir, "attachments/secret.bin", "host data");
+        let state = LanSyncState::default();
+        let info = start_host(&state, &host_dir, Arc::default());
+        let wrong = if info.code == "000000" {
+            "111111"
+        } else {
+            "000000"
+        };
+
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            let options = connect_options(info.port, wrong, false);
+            let err =
+                connect_and_sync(&client_dir, &options, &|| {}, &mut |_, _| Ok(())).unwrap_err();
+            assert!(err.contains("rejected"), "{err}");
+        }
+        let finished = wait_finished(&state);
+        assert_eq!(finished.state, LanHostState::Failed);
+        assert_eq!(finished.failed_attempts, MAX_FAILED_ATTEMPTS);
+        // 配对码已作废，正确的码也不能再连接
+        let options = connect_options(info.port, &info.code, false);
+        assert!(connect_and_sync(&client_dir, &options, &|| {}, &mut |_, _| Ok(())).is_err());
+        assert!(!client_dir.join("attachments/secret.bin").exists());
+
+        let _ = fs::remove_dir_all(host_dir.parent().unwrap());
+        let _ = fs::remove_dir_all(client_dir.parent().unwrap());
+    }
+
+    #[test]
+    fn two_way_sync_merges_both_devices() {
+        let host_dir = make_data_dir("two_way_host");
+        let client_dir = make_data_dir("two_way_client");
+        write(&host_dir, "attachments/from_host.txt", "host attachment");
+        write(&host_dir, "shared.txt", "same");
+        write(&host_dir, "config.json", "{\"apiKey\":\"host-secret\"}");
+        write(&client_dir, "media/from_client.txt", "client media");
+        write(&client_dir, "shared.txt", "same");
+        write(
+            &host_dir,
+            "contacts_store_v1.json",
+            r#"{"contacts":[{"id":"a","name":"Alice","updatedAt":1}]}"#,
+        );
+        write(
+            &client_dir,
+            "contacts_store_v1.json",
+            r#"{"contacts":[{"id":"b","name":"Bob","updatedAt":2}]}"#,
+        );
+
+        let state = LanSyncState::default();
+        let info = start_host(&state, &host_dir, Arc::default());
+        let mut fetched = Vec::new();
+        let options = connect_options(info.port, &format!(" {} ", info.code), true);
+        let result = connect_and_sync(&client_dir, &options, &|| {}, &mut |rel, _| {
+            fetched.push(rel.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(result.pulled.unchanged, 1);
+        assert!(fetched.contains(&"attachments/from_host.txt".to_string()));
+        assert!(!fetched.iter().any(|rel| rel == "config.json"));
+        assert!(result.sent_files >= 2);
+
+        let finished = wait_finished(&state);
+        assert_eq!(finished.state, LanHostState::Done);
+        assert!(finished.report.is_some());
+
+        for dir in [&host_dir, &client_dir] {
+            assert!(dir.join("attachments/from_host.txt").is_file());
+            assert!(dir.join("media/from_client.txt").is_file());
+            let contacts = fs::read_to_string(dir.join("contacts_store_v1.json")).unwrap();
+            assert!(
+                contacts.contains("Alice") && contacts.contains("Bob"),
+                "{contacts}"
+            );
+        }
+        assert!(!client_dir.join("config.json").exists());
+        assert!(!bundle::work_dir(&client_dir, STAGING_SUFFIX).exists());
+
+        let _ = fs::remove_dir_all(host_dir.parent().unwrap());
+        let _ = fs::remove_dir_all(client_dir.parent().unwrap());
+    }
+
+    #[test]
+    fn host_respects_data_dir_lock_and_caps_handshake_frames() {
+        let host_dir = make_data_dir("locked_host");
+        let client_dir = make_data_dir("locked_client");
+        write(&host_dir, "attachments/a.txt", "host data");
+        let lock = Arc::new(DataDirLock::default());
+        let state = LanSyncState::default();
+        let info = start_host(&state, &host_dir, lock.clone());
+
+        // 未认证的对端发来超长帧：直接断开，不计为猜码
+        let mut raw = TcpStream::connect(("127.0.0.1", info.port)).unwrap();
+        raw.write_all(&u32::try_from(MAX_FRAME).unwrap().to_be_bytes())
+            .unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(raw.read(&mut buf).unwrap_or(0), 0);
+        assert_eq!(state.host_info().unwrap().failed_attempts, 0);
+
+        // 本机正在导入时，对端连接后得不到数据
+        let importing = lock.acquire(DataAccess::Exclusive).unwrap();
+        let options = connect_options(info.port, &info.code, false);
+        assert!(connect_and_sync(&client_dir, &options, &|| {}, &mut |_, _| Ok(())).is_err());
+        let finished = wait_finished(&state);
+        assert_eq!(finished.state, LanHostState::Failed);
+        assert_eq!(
+            finished.error.as_deref(),
+            Some(crate::bundle_progress::DATA_DIR_BUSY_ERROR)
+        );
+        assert!(!client_dir.join("attachments/a.txt").exists());
+        drop(importing);
+        assert!(lock.acquire(DataAccess::Exclusive).is_ok());
+
+        let _ = fs::remove_dir_all(host_dir.parent().unwrap());
+        let _ = fs::remove_dir_all(client_dir.parent().unwrap());
+    }
+}
@@ -1,8 +1,25 @@
 // Library entry point for Android and other platforms
 
+mod auto_backup;
+mod backup_chain;
+mod bundle;
+mod bundle_crypto;
+mod bundle_merge;
+mod bundle_progress;
 mod commands;
+mod embeddings;
+mod lan_sync;
+mod media_packs;
 mod memory_db;
+mod memory_template_file;
+mod memory_template_upgrade;
+mod memory_validation;
+mod s3_backup;
+mod secret_store;
 mod storage;
+#[cfg(test)]
+mod test_support;
+mod webdav_sync;
 
 use commands::WallpaperStreamState;
 use tauri::Manager;
@@ -33,6 +50,10 @@ pub fn run() {
             commands::chat_store_v2_delete_thread,
             commands::chat_store_v2_delete_session,
             commands::ensure_media_bundle,
+            commands::import_media_pack,
+            commands::list_media_packs,
+            commands::set_media_pack_enabled,
+            commands::delete_media_pack,
             commands::save_wallpaper,
             commands::save_wallpaper_chunked,
             commands::save_wallpaper_stream_start,
@@ -45,6 +66,37 @@ pub fn run() {
             commands::export_data_bundle,
             commands::import_data_bundle,
             commands::import_data_bundle_bytes,
+            commands::cancel_bundle_operation,
+            commands::verify_data_bundle,
+            commands::preview_data_bundle,
+            commands::get_import_snapshot,
+            commands::restore_import_snapshot,
+            commands::confirm_import_snapshot,
+            commands::get_auto_backup_config,
+            commands::save_auto_backup_config,
+            commands::record_auto_backup_messages,
+            commands::create_auto_backup,
+            commands::list_auto_backups,
+            commands::restore_auto_backup,
+            commands::create_incremental_backup,
+            commands::list_backup_points,
+            commands::restore_backup_point,
+            commands::get_webdav_config,
+            commands::save_webdav_config,
+            commands::sync_status,
+            commands::sync_push,
+            commands::sync_pull,
+            commands::lan_sync_start_host,
+            commands::lan_sync_stop_host,
+            commands::lan_sync_host_status,
+            commands::lan_sync_connect,
+            commands::get_s3_backup_config,
+            commands::save_s3_backup_config,
+            commands::upload_s3_backup,
+            commands::list_s3_backups,
+            commands::download_s3_backup,
+            commands::restore_s3_backup,
+            commands::prune_s3_backups,
             commands::http_request,
             commands::log_js,
             commands::save_raw_reply,
@@ -55,18 +107,52 @@ pub fn run() {
             commands::update_memory,
             commands::delete_memory,
             commands::get_memories,
+            commands::get_memories_page,
+            commands::search_memories,
+            commands::get_memory_history,
+            commands::revert_memory,
+            commands::undo_memory_changes,
+            commands::get_embedding_config,
+            commands::save_embedding_config,
+            commands::index_memory_embeddings,
+            commands::semantic_search_memories,
             commands::batch_create_memories,
+            commands::apply_memory_ops,
             commands::batch_delete_memories,
             commands::save_template,
             commands::get_templates,
             commands::delete_template,
+            commands::export_template,
+            commands::import_template,
+            commands::preview_template_upgrade,
+            commands::upgrade_template,
+            commands::list_memory_trash,
+            commands::restore_memory,
+            commands::restore_template,
+            commands::purge_memory_trash,
         ])
         .setup(|_app| {
             let handle = _app.handle();
+            if let Ok(cache_dir) = handle.path().app_cache_dir() {
+                bundle::set_fallback_work_root(cache_dir.join("bundle_work"));
+            }
+            if let Ok(data_dir) = handle.path().app_data_dir() {
+                match bundle::check_import_snapshot_on_launch(&data_dir) {
+                    Ok(true) => {
+                        eprintln!("[bundle] imported data never started; restored snapshot")
+                    }
+                    Ok(false) => {}
+                    Err(err) => eprintln!("[bundle] check import snapshot failed: {err}"),
+                }
+                lan_sync::discard_leftovers(&data_dir);
+            }
             let memory_db = memory_db::MemoryDb::new(&handle)
                 .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
             _app.manage(memory_db);
             _app.manage(WallpaperStreamState::default());
+            _app.manage(bundle_progress::BundleOperations::default());
+            _app.manage(lan_sync::LanSyncState::default());
+            commands::start_auto_backup_scheduler(handle.clone());
             #[cfg(all(debug_assertions, not(any(target_os = "android", target_os = "ios"))))]
             {
                 use tauri::Manager;
@@ -0,0 +1,1010 @@
+use crate::bundle;
+use crate::commands::{copy_dir_recursive, write_json_file};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use zip::ZipArchive;
+
+const PACKS_DIR: &str = "packs";
+const PACK_STATE_FILE: &str = "packs.json";
+const MANIFEST_FILE: &str = "manifest.json";
+/// 已安装清单中记录内置文件摘要的字段
+const FILE_HASHES_KEY: &str = "fileHashes";
+const BUILTIN_OWNER: &str = "builtin";
+const MAX_PACK_ID_LEN: usize = 64;
+const MAX_PACK_FILES: usize = 2000;
+const MAX_PACK_FILE_BYTES: u64 = 16 * 1024 * 1024;
+const ALLOWED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "apng", "mp3", "ogg", "wav", "m4a", "aac",
+];
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaPackInfo {
+    pub id: String,
+    pub name: String,
+    pub version: Option<Value>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub enabled: bool,
+    pub items: usize,
+    pub installed_at: i64,
+    pub base_dir: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaAliasCollision {
+    pub kind: String,
+    pub key: String,
+    pub item_id: String,
+    pub existing_owner: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaPackImportResult {
+    pub pack: MediaPackInfo,
+    pub replaced: bool,
+    pub files: usize,
+    pub warnings: Vec<String>,
+    pub collisions: Vec<MediaAliasCollision>,
+}
+
+/// `media/packs/packs.json`：记录已安装的扩展包及启用状态
+#[derive(Debug, Clone)]
+struct PackStateEntry {
+    enabled: bool,
+    installed_at: i64,
+}
+
+pub fn packs_root(media_dir: &Path) -> PathBuf {
+    media_dir.join(PACKS_DIR)
+}
+
+fn normalize_kind(raw: &str) -> Option<&'static str> {
+    match raw.trim().to_lowercase().as_str() {
+        "image" | "img" => Some("image"),
+        "audio" | "voice" => Some("audio"),
+        "" | "sticker" | "emoji" => Some("sticker"),
+        _ => None,
+    }
+}
+
+fn normalize_key(raw: &str) -> String {
+    raw.trim().to_lowercase()
+}
+
+pub fn normalize_pack_id(raw: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(raw.len());
+    for ch in raw.trim().chars() {
+        if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' {
+            out.push(ch.to_ascii_lowercase());
+        } else {
+            out.push('_');
+        }
+    }
+    let mut cleaned = out.trim_matches('_').to_string();
+    if cleaned.len() > MAX_PACK_ID_LEN {
+        cleaned.truncate(MAX_PACK_ID_LEN);
+    }
+    if cleaned.is_empty() {
+        return Err("pack id empty".to_string());
+    }
+    Ok(cleaned)
+}
+
+fn is_safe_relative(path: &str) -> bool {
+    let p = Path::new(path);
+    !path.trim().is_empty()
+        && p.components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+fn read_state(media_dir: &Path) -> HashMap<String, PackStateEntry> {
+    let path = packs_root(media_dir).join(PACK_STATE_FILE);
+    let Ok(json) = fs::read_to_string(&path) else {
+        return HashMap::new();
+    };
+    let Ok(value) = serde_json::from_str::<Value>(&json) else {
+        eprintln!("[media_packs] invalid state file: {}", path.display());
+        return HashMap::new();
+    };
+    let mut out = HashMap::new();
+    if let Some(packs) = value.get("packs").and_then(Value::as_object) {
+        for (id, entry) in packs {
+            out.insert(
+                id.clone(),
+                PackStateEntry {
+                    enabled: entry
+                        .get("enabled")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(true),
+                    installed_at: entry
+                        .get("installedAt")
+                        .and_then(Value::as_i64)
+                        .unwrap_or(0),
+                },
+            );
+        }
+    }
+    out
+}
+
+fn write_state(media_dir: &Path, state: &HashMap<String, PackStateEntry>) -> Result<(), String> {
+    let mut packs = Map::new();
+    for (id, entry) in state {
+        packs.insert(
+            id.clone(),
+            serde_json::json!({ "enabled": entry.enabled, "installedAt": entry.installed_at }),
+        );
+    }
+    let data = serde_json::json!({ "version": 1, "packs": packs });
+    write_json_file(&packs_root(media_dir).join(PACK_STATE_FILE), &data)
+}
+
+fn read_manifest(path: &Path) -> Result<Value, String> {
+    let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let value: Value = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    if !value.is_object() {
+        return Err("manifest must be a JSON object".to_string());
+    }
+    Ok(value)
+}
+
+fn item_keys(item: &Value) -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Some(id) = item.get("id").and_then(Value::as_str) {
+        keys.push(normalize_key(id));
+    }
+    if let Some(aliases) = item.get("aliases").and_then(Value::as_array) {
+        for alias in aliases.iter().filter_map(Value::as_str) {
+            let key = normalize_key(alias);
+            if !key.is_empty() && !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    keys
+}
+
+fn item_kind(item: &Value) -> &'static str {
+    normalize_kind(item.get("kind").and_then(Value::as_str).unwrap_or("")).unwrap_or("sticker")
+}
+
+/// 已安装扩展包（按安装时间排序），返回 (id, 目录, 状态)
+fn installed_packs(media_dir: &Path) -> Vec<(String, PathBuf, PackStateEntry)> {
+    let root = packs_root(media_dir);
+    let state = read_state(media_dir);
+    let mut out = Vec::new();
+    let Ok(entries) = fs::read_dir(&root) else {
+        return out;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() || !path.join(MANIFEST_FILE).exists() {
+            continue;
+        }
+        let id = entry.file_name().to_string_lossy().to_string();
+        if id.starts_with('.') {
+            continue;
+        }
+        let pack_state = state.get(&id).cloned().unwrap_or(PackStateEntry {
+            enabled: true,
+            installed_at: 0,
+        });
+        out.push((id, path, pack_state));
+    }
+    out.sort_by(|a, b| a.2.installed_at.cmp(&b.2.installed_at).then(a.0.cmp(&b.0)));
+    out
+}
+
+fn pack_info(id: &str, dir: &Path, state: &PackStateEntry, manifest: &Value) -> MediaPackInfo {
+    let text = |key: &str| manifest.get(key).and_then(Value::as_str).map(String::from);
+    MediaPackInfo {
+        id: id.to_string(),
+        name: text("name").unwrap_or_else(|| id.to_string()),
+        version: manifest.get("version").cloned(),
+        author: text("author"),
+        description: text("description"),
+        enabled: state.enabled,
+        items: manifest
+            .get("items")
+            .and_then(Value::as_array)
+            .map_or(0, Vec::len),
+        installed_at: state.installed_at,
+        base_dir: dir.to_string_lossy().to_string(),
+    }
+}
+
+pub fn list_packs(media_dir: &Path) -> Vec<MediaPackInfo> {
+    installed_packs(media_dir)
+        .into_iter()
+        .filter_map(
+            |(id, dir, state)| match read_manifest(&dir.join(MANIFEST_FILE)) {
+                Ok(manifest) => Some(pack_info(&id, &dir, &state, &manifest)),
+                Err(err) => {
+                    eprintln!("[media_packs] skip pack {id}: {err}");
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+/// 收集已占用的 (kind, key) -> 来源（内置包或扩展包 id）
+fn occupied_keys(
+    media_dir: &Path,
+    builtin: Option<&Value>,
+    exclude_pack: Option<&str>,
+) -> HashMap<(String, String), String> {
+    let mut taken = HashMap::new();
+    let mut claim = |items: &[Value], owner: &str| {
+        for item in items {
+            let kind = item_kind(item).to_string();
+            for key in item_keys(item) {
+                taken
+                    .entry((kind.clone(), key))
+                    .or_insert_with(|| owner.to_string());
+            }
+        }
+    };
+    if let Some(items) = builtin
+        .and_then(|m| m.get("items"))
+        .and_then(Value::as_array)
+    {
+        claim(items, BUILTIN_OWNER);
+    }
+    for (id, dir, state) in installed_packs(media_dir) {
+        if !state.enabled || exclude_pack == Some(id.as_str()) {
+            continue;
+        }
+        if let Ok(manifest) = read_manifest(&dir.join(MANIFEST_FILE)) {
+            if let Some(items) = manifest.get("items").and_then(Value::as_array) {
+                claim(items, &id);
+            }
+        }
+    }
+    taken
+}
+
+/// 校验扩展包清单，返回清洗后的条目与警告
+fn validate_items(pack_dir: &Path, manifest: &Value) -> Result<(Vec<Value>, Vec<String>), String> {
+    let items = manifest
+        .get("items")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "manifest missing items".to_string())?;
+    let mut warnings = Vec::new();
+    let mut seen: HashSet<(String, String)> = HashSet::new();
+    let mut out = Vec::new();
+    for (idx, raw) in items.iter().enumerate() {
+        let Some(obj) = raw.as_object() else {
+            warnings.push(format!("item #{idx}: not an object"));
+            continue;
+        };
+        let raw_kind = obj.get("kind").and_then(Value::as_str).unwrap_or("");
+        let Some(kind) = normalize_kind(raw_kind) else {
+            warnings.push(format!("item #{idx}: unsupported kind '{raw_kind}'"));
+            continue;
+        };
+        let id = obj.get("id").and_then(Value::as_str).map_or("", str::trim);
+        if id.is_empty() {
+            warnings.push(format!("item #{idx}: missing id"));
+            continue;
+        }
+        let file = obj
+            .get("file")
+            .and_then(Value::as_str)
+            .map_or("", str::trim);
+        if !is_safe_relative(file) {
+            warnings.push(format!("item '{id}': invalid file path"));
+            continue;
+        }
+        let ext = Path::new(file)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if !ALLOWED_EXTENSIONS.contains(&ext.as_str()) {
+            warnings.push(format!("item '{id}': unsupported file type '{file}'"));
+            continue;
+        }
+        if !pack_dir.join(file).is_file() {
+            warnings.push(format!("item '{id}': file not found '{file}'"));
+            continue;
+        }
+        if !seen.insert((kind.to_string(), normalize_key(id))) {
+            warnings.push(format!("item '{id}': duplicate id"));
+            continue;
+        }
+        let mut item = obj.clone();
+        item.insert("kind".to_string(), Value::String(kind.to_string()));
+        item.insert("id".to_string(), Value::String(id.to_string()));
+        item.insert("file".to_string(), Value::String(file.replace('\\', "/")));
+        if let Some(aliases) = obj.get("aliases") {
+            let cleaned: Vec<Value> = aliases
+                .as_array()
+                .map(|list| {
+                    list.iter()
+                        .filter_map(Value::as_str)
+                        .map(str::trim)
+                        .filter(|a| !a.is_empty())
+                        .map(|a| Value::String(a.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            item.insert("aliases".to_string(), Value::Array(cleaned));
+        }
+        out.push(Value::Object(item));
+    }
+    if out.is_empty() {
+        return Err("media pack has no valid items".to_string());
+    }
+    Ok((out, warnings))
+}
+
+/// 在 zip 中定位 manifest.json 所在目录（允许包一层顶级目录）
+fn find_zip_root<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<String, String> {
+    let mut best: Option<String> = None;
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i).map_err(|e| e.to_string())?;
+        let name = entry.name().replace('\\', "/");
+        let Some(prefix) = name.strip_suffix(MANIFEST_FILE) else {
+            continue;
+        };
+        if !(prefix.is_empty() || prefix.ends_with('/')) {
+            continue;
+        }
+        let depth = prefix.matches('/').count();
+        if best
+            .as_ref()
+            .is_none_or(|cur| depth < cur.matches('/').count())
+        {
+            best = Some(prefix.to_string());
+        }
+    }
+    best.ok_or_else(|| "manifest.json not found in pack".to_string())
+}
+
+fn extract_zip_pack<R: Read + Seek>(reader: R, staging: &Path) -> Result<usize, String> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| e.to_string())?;
+    let root = find_zip_root(&mut archive)?;
+    let mut files = 0usize;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| e.to_string())?;
+        if entry.is_dir() {
+            continue;
+        }
+        let Some(enclosed) = entry.enclosed_name().map(Path::to_path_buf) else {
+            return Err(format!("unsafe path in pack: {}", entry.name()));
+        };
+        let normalized = enclosed.to_string_lossy().replace('\\', "/");
+        let Some(rel) = normalized.strip_prefix(&root) else {
+            continue;
+        };
+        if entry.size() > MAX_PACK_FILE_BYTES {
+            return Err(format!("pack file too large: {rel}"));
+        }
+        files += 1;
+        if files > MAX_PACK_FILES {
+            return Err("media pack has too many files".to_string());
+        }
+        let out_path = staging.join(rel);
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut out = fs::File::create(&out_path).map_err(|e| e.to_string())?;
+        std::io::copy(&mut entry, &mut out).map_err(|e| e.to_string())?;
+    }
+    Ok(files)
+}
+
+fn count_files(dir: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|e| {
+            let path = e.path();
+            if path.is_dir() {
+                count_files(&path)
+            } else {
+                1
+            }
+        })
+        .sum()
+}
+
+/// 从 zip 文件或目录导入扩展包到 `media/packs/<pack_id>/`
+pub fn import_pack(
+    media_dir: &Path,
+    source: &Path,
+    builtin: Option<&Value>,
+    overwrite: bool,
+) -> Result<MediaPackImportResult, String> {
+    let root = packs_root(media_dir);
+    fs::create_dir_all(&root).map_err(|e| e.to_string())?;
+    let staging = root.join(format!(
+        ".staging_{}",
+        chrono::Utc::now().timestamp_millis()
+    ));
+    let result = stage_and_install(media_dir, source, &staging, builtin, overwrite);
+    for dir in [staging.clone(), install_dir(&staging)] {
+        if dir.exists() {
+            let _ = fs::remove_dir_all(&dir);
+        }
+    }
+    result
+}
+
+/// 只含清单与有效条目文件的安装目录（与暂存目录同级）
+fn install_dir(staging: &Path) -> PathBuf {
+    let mut name = staging.file_name().unwrap_or_default().to_os_string();
+    name.push("_install");
+    staging.with_file_name(name)
+}
+
+/// 把通过校验的条目文件移入安装目录并写入清单；包内其余文件不安装。返回安装的文件数
+fn stage_validated(staging: &Path, install: &Path, manifest: &Value) -> Result<usize, String> {
+    fs::create_dir_all(install).map_err(|e| e.to_string())?;
+    let mut files = 0usize;
+    for file in manifest
+        .get("items")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("file").and_then(Value::as_str))
+    {
+        let to = install.join(file);
+        if to.exists() {
+            continue;
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::rename(staging.join(file), &to).map_err(|e| e.to_string())?;
+        files += 1;
+    }
+    write_json_file(&install.join(MANIFEST_FILE), manifest)?;
+    Ok(files)
+}
+
+/// 替换已安装的同名扩展包：旧目录先移到一旁，新目录换入成功后再删除，失败时换回
+fn swap_in_pack(install: &Path, target: &Path) -> Result<(), String> {
+    if !target.exists() {
+        return fs::rename(install, target).map_err(|e| e.to_string());
+    }
+    let mut name = std::ffi::OsString::from(".replaced_");
+    name.push(target.file_name().unwrap_or_default());
+    let old = target.with_file_name(name);
+    if old.exists() {
+        fs::remove_dir_all(&old).map_err(|e| e.to_string())?;
+    }
+    fs::rename(target, &old).map_err(|e| e.to_string())?;
+    if let Err(err) = fs::rename(install, target) {
+        let _ = fs::rename(&old, target);
+        return Err(err.to_string());
+    }
+    let _ = fs::remove_dir_all(&old);
+    Ok(())
+}
+
+fn stage_and_install(
+    media_dir: &Path,
+    source: &Path,
+    staging: &Path,
+    builtin: Option<&Value>,
+    overwrite: bool,
+) -> Result<MediaPackImportResult, String> {
+    let extracted = if source.is_dir() {
+        if !source.join(MANIFEST_FILE).exists() {
+            return Err("manifest.json not found in pack".to_string());
+        }
+        copy_dir_recursive(source, staging)?;
+        let files = count_files(staging);
+        if files > MAX_PACK_FILES {
+            return Err("media pack has too many files".to_string());
+        }
+        files
+    } else {
+        fs::create_dir_all(staging).map_err(|e| e.to_string())?;
+        let file = fs::File::open(source).map_err(|e| e.to_string())?;
+        extract_zip_pack(file, staging)?
+    };
+
+    let mut manifest = read_manifest(&staging.join(MANIFEST_FILE))?;
+    let raw_id = manifest
+        .get("id")
+        .and_then(Value::as_str)
+        .map(String::from)
+        .or_else(|| source.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    let pack_id = normalize_pack_id(&raw_id)?;
+    let (items, mut warnings) = validate_items(staging, &manifest)?;
+
+    let taken = occupied_keys(media_dir, builtin, Some(&pack_id));
+    let mut collisions = Vec::new();
+    for item in &items {
+        let kind = item_kind(item).to_string();
+        let item_id = item.get("id").and_then(Value::as_str).unwrap_or("");
+        for key in item_keys(item) {
+            if let Some(owner) = taken.get(&(kind.clone(), key.clone())) {
+                collisions.push(MediaAliasCollision {
+                    kind: kind.clone(),
+                    key,
+                    item_id: item_id.to_string(),
+                    existing_owner: owner.clone(),
+                });
+            }
+        }
+    }
+
+    let target = packs_root(media_dir).join(&pack_id);
+    let replaced = target.exists();
+    if replaced && !overwrite {
+        return Err(format!("media pack already installed: {pack_id}"));
+    }
+
+    if let Some(obj) = manifest.as_object_mut() {
+        obj.insert("id".to_string(), Value::String(pack_id.clone()));
+        obj.insert("items".to_string(), Value::Array(items));
+    }
+    let install = install_dir(staging);
+    let files = stage_validated(staging, &install, &manifest)?;
+    // 扣除 manifest.json 本身
+    let unused = extracted.saturating_sub(files + 1);
+    if unused > 0 {
+        warnings.push(format!(
+            "{unused} files not referenced by valid items were skipped"
+        ));
+    }
+    swap_in_pack(&install, &target)?;
+
+    let mut state = read_state(media_dir);
+    let entry = PackStateEntry {
+        enabled: state.get(&pack_id).is_none_or(|s| s.enabled),
+        installed_at: chrono::Utc::now().timestamp_millis(),
+    };
+    state.insert(pack_id.clone(), entry.clone());
+    write_state(media_dir, &state)?;
+
+    Ok(MediaPackImportResult {
+        pack: pack_info(&pack_id, &target, &entry, &manifest),
+        replaced,
+        files,
+        warnings,
+        collisions,
+    })
+}
+
+pub fn set_pack_enabled(media_dir: &Path, pack_id: &str, enabled: bool) -> Result<(), String> {
+    let id = normalize_pack_id(pack_id)?;
+    if !packs_root(media_dir).join(&id).join(MANIFEST_FILE).exists() {
+        return Err(format!("media pack not found: {id}"));
+    }
+    let mut state = read_state(media_dir);
+    let entry = state.entry(id).or_insert(PackStateEntry {
+        enabled,
+        installed_at: 0,
+    });
+    entry.enabled = enabled;
+    write_state(media_dir, &state)
+}
+
+pub fn delete_pack(media_dir: &Path, pack_id: &str) -> Result<bool, String> {
+    let id = normalize_pack_id(pack_id)?;
+    let dir = packs_root(media_dir).join(&id);
+    let existed = dir.exists();
+    if existed {
+        fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+    }
+    let mut state = read_state(media_dir);
+    if state.remove(&id).is_some() {
+        write_state(media_dir, &state)?;
+    }
+    Ok(existed)
+}
+
+/// 合并内置清单与已启用的扩展包；冲突的别名按先到先得（内置优先）
+pub fn merged_manifest(media_dir: &Path, builtin: Option<Value>) -> Option<Value> {
+    let packs: Vec<(String, Value)> = installed_packs(media_dir)
+        .into_iter()
+        .filter(|(_, _, state)| state.enabled)
+        .filter_map(|(id, dir, _)| {
+            read_manifest(&dir.join(MANIFEST_FILE))
+                .ok()
+                .map(|manifest| (id, manifest))
+        })
+        .collect();
+    if packs.is_empty() {
+        return builtin;
+    }
+    let mut merged = builtin.unwrap_or_else(|| serde_json::json!({ "version": 1, "items": [] }));
+    let mut taken: HashSet<(String, String)> = HashSet::new();
+    let mut items: Vec<Value> = merged
+        .get("items")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    for item in &items {
+        let kind = item_kind(item).to_string();
+        for key in item_keys(item) {
+            taken.insert((kind.clone(), key));
+        }
+    }
+    let mut pack_list = Vec::new();
+    for (id, manifest) in packs {
+        pack_list.push(serde_json::json!({
+            "id": id,
+            "name": manifest.get("name").cloned().unwrap_or(Value::String(id.clone())),
+            "version": manifest.get("version").cloned().unwrap_or(Value::Null),
+        }));
+        let Some(pack_items) = manifest.get("items").and_then(Value::as_array) else {
+            continue;
+        };
+        for raw in pack_items {
+            let Some(obj) = raw.as_object() else {
+                continue;
+            };
+            let kind = item_kind(raw).to_string();
+            let item_id = obj.get("id").and_then(Value::as_str).unwrap_or("");
+            let file = obj.get("file").and_then(Value::as_str).unwrap_or("");
+            if item_id.is_empty()
+                || !is_safe_relative(file)
+                || !taken.insert((kind.clone(), normalize_key(item_id)))
+            {
+                continue;
+            }
+            let mut item = obj.clone();
+            let aliases: Vec<Value> = obj
+                .get("aliases")
+                .and_then(Value::as_array)
+                .map(|list| {
+                    list.iter()
+                        .filter_map(Value::as_str)
+                        .filter(|a| taken.insert((kind.clone(), normalize_key(a))))
+                        .map(|a| Value::String(a.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            item.insert("aliases".to_string(), Value::Array(aliases));
+            item.insert(
+                "file".to_string(),
+                Value::String(format!("{PACKS_DIR}/{id}/{file}")),
+            );
+            item.insert("pack".to_string(), Value::String(id.clone()));
+            items.push(Value::Object(item));
+        }
+    }
+    if let Some(obj) = merged.as_object_mut() {
+        obj.insert("items".to_string(), Value::Array(items));
+        obj.insert("packs".to_string(), Value::Array(pack_list));
+    }
+    Some(merged)
+}
+
+/// 读取数据目录中已安装的内置清单；文件损坏时先备份为 `manifest.corrupt_<时间>.json`
+/// 再报错，损坏的内容不会被新清单覆盖
+pub fn load_builtin_manifest(media_dir: &Path) -> Result<Option<Value>, String> {
+    let path = media_dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    match read_manifest(&path) {
+        Ok(manifest) => Ok(Some(manifest)),
+        Err(err) => {
+            let backup = media_dir.join(format!(
+                "manifest.corrupt_{}.json",
+                chrono::Utc::now().timestamp_millis()
+            ));
+            fs::rename(&path, &backup).map_err(|e| e.to_string())?;
+            Err(format!(
+                "media manifest is corrupt ({err}); moved to {}",
+                backup.display()
+            ))
+        }
+    }
+}
+
+/// 读取应用资源中的内置清单（只读，不做备份）
+pub fn read_bundled_manifest(resource_dir: &Path) -> Option<Value> {
+    read_manifest(&resource_dir.join(MANIFEST_FILE)).ok()
+}
+
+pub fn manifest_version(manifest: &Value) -> i64 {
+    manifest.get("version").and_then(Value::as_i64).unwrap_or(0)
+}
+
+/// 内置媒体同步结果；`hashes` 为同步后仍与内置版本一致的文件摘要，写入清单的 `fileHashes`
+#[derive(Debug, Default)]
+pub struct MediaSync {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    /// 用户改动过的内置文件，保留不覆盖
+    pub kept: Vec<String>,
+    pub hashes: BTreeMap<String, String>,
+}
+
+/// 已安装清单中记录的内置文件摘要（相对路径 -> SHA-256）
+pub fn installed_file_hashes(installed: Option<&Value>) -> BTreeMap<String, String> {
+    installed
+        .and_then(|m| m.get(FILE_HASHES_KEY))
+        .and_then(Value::as_object)
+        .map(|map| {
+            map.iter()
+                .filter_map(|(rel, sha)| Some((rel.clone(), sha.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// 同步内置媒体文件：新增的直接复制；已存在且内容不同的，只有摘要与上次安装时一致
+/// （用户未改动）才覆盖。不删除用户文件
+pub fn sync_media_files(
+    src: &Path,
+    dst: &Path,
+    rel: &str,
+    previous: &BTreeMap<String, String>,
+    sync: &mut MediaSync,
+) -> Result<(), String> {
+    fs::create_dir_all(dst).map_err(|e| e.to_string())?;
+    for entry in fs::read_dir(src).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let rel_name = if rel.is_empty() {
+            name.clone()
+        } else {
+            format!("{rel}/{name}")
+        };
+        if rel_name == MANIFEST_FILE || rel_name == PACKS_DIR {
+            continue;
+        }
+        let path = entry.path();
+        let target = dst.join(&name);
+        if path.is_dir() {
+            sync_media_files(&path, &target, &rel_name, previous, sync)?;
+            continue;
+        }
+        let source_sha = bundle::sha256_file(&path)?;
+        if target.exists() {
+            let target_sha = bundle::sha256_file(&target)?;
+            if target_sha != source_sha {
+                if previous.get(&rel_name) != Some(&target_sha) {
+                    sync.kept.push(rel_name);
+                    continue;
+                }
+                fs::copy(&path, &target).map_err(|e| e.to_string())?;
+                sync.updated.push(rel_name.clone());
+            }
+        } else {
+            fs::copy(&path, &target).map_err(|e| e.to_string())?;
+            sync.added.push(rel_name.clone());
+        }
+        sync.hashes.insert(rel_name, source_sha);
+    }
+    Ok(())
+}
+
+/// 以新版内置清单为准，保留旧清单中用户自行添加的条目
+pub fn merge_builtin_media_manifest(bundled: &Value, installed: Option<&Value>) -> (Value, usize) {
+    let item_key = |item: &Value| {
+        let kind = item.get("kind").and_then(Value::as_str).unwrap_or("");
+        let id = item.get("id").and_then(Value::as_str).unwrap_or("");
+        (kind.trim().to_lowercase(), id.trim().to_lowercase())
+    };
+    let mut merged = bundled.clone();
+    let mut items = bundled
+        .get("items")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let known: HashSet<_> = items.iter().map(item_key).collect();
+    let mut kept = 0usize;
+    if let Some(old_items) = installed
+        .and_then(|m| m.get("items"))
+        .and_then(Value::as_array)
+    {
+        for item in old_items {
+            if !known.contains(&item_key(item)) {
+                items.push(item.clone());
+                kept += 1;
+            }
+        }
+    }
+    if let Some(obj) = merged.as_object_mut() {
+        obj.insert("items".to_string(), Value::Array(items));
+    }
+    (merged, kept)
+}
+
+/// 在清单中记录本次同步后的内置文件摘要，供下次升级判断文件是否被用户改动
+pub fn set_file_hashes(manifest: &mut Value, hashes: &BTreeMap<String, String>) {
+    if let Some(obj) = manifest.as_object_mut() {
+        let hashes = hashes
+            .iter()
+            .map(|(rel, sha)| (rel.clone(), Value::String(sha.clone())))
+            .collect();
+        obj.insert(FILE_HASHES_KEY.to_string(), Value::Object(hashes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::make_temp_dir;
+    use serde_json::json;
+
+    fn write_pack_source(dir: &Path, manifest: &Value, files: &[&str]) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), manifest.to_string()).unwrap();
+        for file in files {
+            fs::write(dir.join(file), b"png").unwrap();
+        }
+    }
+
+    #[test]
+    fn import_folder_pack_and_merge() {
+        let base = make_temp_dir("import");
+        let media_dir = base.join("media");
+        let builtin = json!({
+            "version": 1,
+            "items": [{ "kind": "sticker", "id": "hello", "file": "sticker_001.png", "aliases": ["hi"] }]
+        });
+        let source = base.join("cats");
+        write_pack_source(
+            &source,
+            &json!({
+                "id": "Cat Pack",
+                "name": "Cats",
+                "items": [
+                    { "kind": "sticker", "id": "cat", "file": "cat.png", "aliases": ["hi", "meow"] },
+                    { "kind": "sticker", "id": "missing", "file": "missing.png" },
+                    { "kind": "sticker", "id": "escape", "file": "../cat.png" }
+                ]
+            }),
+            &["cat.png", "notes.txt"],
+        );
+
+        let result = import_pack(&media_dir, &source, Some(&builtin), false).unwrap();
+        assert_eq!(result.pack.id, "cat_pack");
+        assert_eq!(result.pack.items, 1);
+        // 两个无效条目，另有一个未被有效条目引用的文件不安装
+        assert_eq!(result.warnings.len(), 3);
+        assert_eq!(result.files, 1);
+        assert!(!packs_root(&media_dir).join("cat_pack/notes.txt").exists());
+        assert_eq!(result.collisions.len(), 1);
+        assert_eq!(result.collisions[0].existing_owner, BUILTIN_OWNER);
+        assert!(import_pack(&media_dir, &source, Some(&builtin), false).is_err());
+
+        let result = import_pack(&media_dir, &source, Some(&builtin), true).unwrap();
+        assert!(result.replaced);
+        let leftovers: Vec<_> = fs::read_dir(packs_root(&media_dir))
+            .unwrap()
+            .filter_map(Result::ok)
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with('.'))
+            .collect();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+        assert!(packs_root(&media_dir).join("cat_pack/cat.png").exists());
+
+        let merged = merged_manifest(&media_dir, Some(builtin.clone())).unwrap();
+        let items = merged["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1]["file"], "packs/cat_pack/cat.png");
+        assert_eq!(items[1]["aliases"], json!(["meow"]));
+
+        set_pack_enabled(&media_dir, "cat_pack", false).unwrap();
+        let merged = merged_manifest(&media_dir, Some(builtin.clone())).unwrap();
+        assert_eq!(merged["items"].as_array().unwrap().len(), 1);
+        assert!(!list_packs(&media_dir)[0].enabled);
+
+        assert!(delete_pack(&media_dir, "cat_pack").unwrap());
+        assert!(list_packs(&media_dir).is_empty());
+
+        fs::remove_dir_all(base).ok();
+    }
+
+    #[test]
+    fn sync_builtin_files_keeps_user_content() {
+        let base = make_temp_dir("sync");
+        let src = base.join("resources");
+        let dst = base.join("media");
+        fs::create_dir_all(src.join("stickers")).unwrap();
+        fs::create_dir_all(src.join(PACKS_DIR)).unwrap();
+        fs::write(src.join(MANIFEST_FILE), "{}").unwrap();
+        fs::write(src.join("stickers/a.png"), b"new").unwrap();
+        fs::write(src.join("stickers/b.png"), b"same").unwrap();
+        fs::write(src.join("stickers/c.png"), b"fresh").unwrap();
+        fs::write(src.join(PACKS_DIR).join("x.png"), b"x").unwrap();
+        fs::create_dir_all(dst.join("stickers")).unwrap();
+        fs::write(dst.join(MANIFEST_FILE), "{\"user\":1}").unwrap();
+        fs::write(dst.join("stickers/a.png"), b"old").unwrap();
+        fs::write(dst.join("stickers/b.png"), b"same").unwrap();
+        fs::write(dst.join("stickers/mine.png"), b"user").unwrap();
+
+        let sha = |data: &[u8]| {
+            let mut hashing = bundle::HashingWriter::new(std::io::sink());
+            std::io::Write::write_all(&mut hashing, data).unwrap();
+            hashing.finish_hex()
+        };
+        fs::write(dst.join("stickers/edited.png"), b"mine").unwrap();
+        fs::write(src.join("stickers/edited.png"), b"builtin v2").unwrap();
+        let previous = BTreeMap::from([
+            ("stickers/a.png".to_string(), sha(b"old")),
+            ("stickers/edited.png".to_string(), sha(b"builtin v1")),
+        ]);
+
+        let mut sync = MediaSync::default();
+        sync_media_files(&src, &dst, "", &previous, &mut sync).unwrap();
+        assert_eq!(sync.added, vec!["stickers/c.png"]);
+        assert_eq!(sync.updated, vec!["stickers/a.png"]);
+        // 用户改动过的内置文件保留，也不记录摘要
+        assert_eq!(sync.kept, vec!["stickers/edited.png"]);
+        assert_eq!(fs::read(dst.join("stickers/edited.png")).unwrap(), b"mine");
+        assert!(!sync.hashes.contains_key("stickers/edited.png"));
+        assert_eq!(sync.hashes["stickers/a.png"], sha(b"new"));
+        assert_eq!(sync.hashes["stickers/b.png"], sha(b"same"));
+        assert_eq!(fs::read(dst.join("stickers/a.png")).unwrap(), b"new");
+        assert!(dst.join("stickers/mine.png").exists());
+        assert!(!dst.join(PACKS_DIR).exists());
+        assert_eq!(
+            fs::read_to_string(dst.join(MANIFEST_FILE)).unwrap(),
+            "{\"user\":1}"
+        );
+
+        fs::remove_dir_all(base).ok();
+    }
+
+    #[test]
+    fn merge_builtin_manifest_keeps_user_items() {
+        let bundled = json!({
+            "version": 2,
+            "items": [{ "kind": "sticker", "id": "Hi", "file": "stickers/hi.png" }]
+        });
+        let installed = json!({
+            "version": 1,
+            "items": [
+                { "kind": "sticker", "id": "hi", "file": "stickers/old.png" },
+                { "kind": "sticker", "id": "mine", "file": "stickers/mine.png" }
+            ]
+        });
+        let (merged, kept) = merge_builtin_media_manifest(&bundled, Some(&installed));
+        assert_eq!(kept, 1);
+        assert_eq!(manifest_version(&merged), 2);
+        let items = merged["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["file"], "stickers/hi.png");
+        assert_eq!(items[1]["id"], "mine");
+        assert_eq!(merge_builtin_media_manifest(&bundled, None).1, 0);
+    }
+
+    #[test]
+    fn corrupt_builtin_manifest_is_backed_up() {
+        let base = make_temp_dir("corrupt");
+        assert!(load_builtin_manifest(&base).unwrap().is_none());
+
+        fs::write(base.join(MANIFEST_FILE), "{ not json").unwrap();
+        assert!(load_builtin_manifest(&base).is_err());
+        assert!(!base.join(MANIFEST_FILE).exists());
+        let backups: Vec<_> = fs::read_dir(&base)
+            .unwrap()
+            .filter_map(Result::ok)
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with("manifest.corrupt_"))
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(
+            fs::read_to_string(base.join(&backups[0])).unwrap(),
+            "{ not json"
+        );
+
+        fs::write(base.join(MANIFEST_FILE), "{\"version\":3}").unwrap();
+        let manifest = load_builtin_manifest(&base).unwrap().unwrap();
+        assert_eq!(manifest_version(&manifest), 3);
+
+        fs::remove_dir_all(base).ok();
+    }
+}
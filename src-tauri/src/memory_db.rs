@@ -1,7 +1,17 @@
+use crate::embeddings;
+use crate::memory_template_file::{
+    self, TemplateConflict, TemplateDocument, TemplateImportResult, TemplatePayload, TemplateSample,
+};
+use crate::memory_template_upgrade::{self, SchemaDiff, TableMapping};
+use crate::memory_validation::{self, MemoryValidationError, SchemaViolation, ViolationCode};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rusqlite::backup::Progress;
 use rusqlite::types::Value as SqlValue;
-use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension};
+use rusqlite::{
+    params, params_from_iter, Connection, DatabaseName, OpenFlags, OptionalExtension, Transaction,
+};
 use serde::{Deserialize, Serialize};
-#[cfg(not(target_os = "android"))]
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -11,17 +21,94 @@ use std::sync::Mutex;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tauri::{AppHandle, Manager};
 
-const SCHEMA_VERSION: i64 = 1;
+const SCHEMA_VERSION: i64 = 7;
 const SCHEMA_SQL: &str = include_str!("memory_schema.sql");
 const SCHEMA_KEY: &str = "schema_version";
 
+/// 迁移内容：SQL 脚本，或在同一事务内执行的 Rust 数据转换
+enum MigrationStep {
+    Sql(&'static str),
+    /// 需要逐行改写 JSON 等 SQL 难以表达的转换；当前内置迁移均为 SQL
+    #[allow(dead_code)]
+    Rust(fn(&Transaction<'_>) -> Result<(), String>),
+}
+
 struct Migration {
     from: i64,
     to: i64,
-    sql: &'static str,
+    step: MigrationStep,
+    /// 需要重建被外键引用的表（如 templates）时置为 true：
+    /// 迁移期间关闭外键约束，提交前用 `foreign_key_check` 兜底
+    rebuilds_tables: bool,
 }
 
-const MIGRATIONS: &[Migration] = &[];
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        to: 2,
+        step: MigrationStep::Sql(include_str!("memory_fts_v2.sql")),
+        rebuilds_tables: false,
+    },
+    Migration {
+        from: 2,
+        to: 3,
+        step: MigrationStep::Sql(include_str!("memory_embeddings_v3.sql")),
+        rebuilds_tables: false,
+    },
+    Migration {
+        from: 3,
+        to: 4,
+        step: MigrationStep::Sql(include_str!("memory_history_v4.sql")),
+        rebuilds_tables: false,
+    },
+    Migration {
+        from: 4,
+        to: 5,
+        step: MigrationStep::Sql(include_str!("memory_trash_v5.sql")),
+        rebuilds_tables: false,
+    },
+    Migration {
+        from: 5,
+        to: 6,
+        step: MigrationStep::Sql(include_str!("memory_history_state_v6.sql")),
+        rebuilds_tables: false,
+    },
+    Migration {
+        from: 6,
+        to: 7,
+        step: MigrationStep::Sql(include_str!("memory_fts_rowid_v7.sql")),
+        rebuilds_tables: false,
+    },
+];
+
+const MEMORY_COLUMNS: &str = "id, template_id, table_id, contact_id, group_id, row_data, is_active, is_pinned, priority, sort_order, created_at, updated_at, deleted_at";
+const TEMPLATE_COLUMNS: &str = "id, name, author, version, description, schema, injection, created_at, updated_at, is_default, is_builtin, deleted_at";
+/// 回收站保留 30 天，打开数据库时清理过期项
+const TRASH_RETENTION_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+const PAGE_MAX_LIMIT: usize = 500;
+const SEARCH_DEFAULT_LIMIT: usize = 20;
+const SEARCH_MAX_LIMIT: usize = 200;
+const SEARCH_MAX_TERMS: usize = 8;
+/// trigram 分词下少于 3 个字符的词无法走索引，改用 LIKE 过滤
+const SEARCH_MIN_INDEXED_CHARS: usize = 3;
+const SNIPPET_OPEN: &str = "【";
+const SNIPPET_CLOSE: &str = "】";
+const SNIPPET_CONTEXT_CHARS: usize = 16;
+/// 量化检索先取 limit 的若干倍候选，再用原始向量重排
+const QUANTIZED_RERANK_FACTOR: usize = 4;
+
+/// 用户在界面上直接修改；其余 actor（如 LLM 表格更新）视为自动修改
+pub const ACTOR_USER: &str = "user";
+/// 撤销操作本身写入的历史，不会被再次撤销
+pub const ACTOR_UNDO: &str = "undo";
+/// 资料包 merge 导入写入的历史，不属于自动修改
+pub const ACTOR_IMPORT: &str = "import";
+const HISTORY_DEFAULT_LIMIT: usize = 50;
+/// 迁移成功后保留的迁移前备份份数
+const MIGRATION_BACKUPS_KEPT: usize = 1;
+/// 升级预览最多返回的行数
+const UPGRADE_PREVIEW_LIMIT: usize = 20;
+const HISTORY_COLUMNS: &str = "id, memory_id, op, template_id, table_id, contact_id, group_id, old_data, new_data, actor, created_at, undone_at, old_state, new_state";
 
 static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 
@@ -72,6 +159,40 @@ pub struct TemplateRecord {
     pub updated_at: i64,
     pub is_default: bool,
     pub is_builtin: bool,
+    pub deleted_at: Option<i64>,
+}
+
+/// 模板升级：新的模板定义，以及各表的列映射
+#[derive(Debug, Deserialize)]
+pub struct TemplateUpgradeInput {
+    pub template: TemplateInput,
+    #[serde(default)]
+    pub mappings: Vec<TableMapping>,
+}
+
+/// 升级会改动或不再通过校验的一行
+#[derive(Debug, Serialize)]
+pub struct TemplateUpgradeRow {
+    pub id: String,
+    pub table_id: String,
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<SchemaViolation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateUpgradePreview {
+    pub diff: SchemaDiff,
+    pub affected: usize,
+    pub invalid: usize,
+    pub rows: Vec<TemplateUpgradeRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateUpgradeResult {
+    pub diff: SchemaDiff,
+    pub updated: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -84,12 +205,73 @@ pub struct MemoryUpdateInput {
     pub sort_order: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct MemoryQuery {
     pub contact_id: Option<String>,
     pub group_id: Option<String>,
     pub template_id: Option<String>,
     pub scope: Option<String>,
+    pub table_id: Option<String>,
+    pub is_active: Option<bool>,
+    pub is_pinned: Option<bool>,
+}
+
+/// `get_memories_page` 的参数：筛选条件与 `MemoryQuery` 相同，另加排序与分页
+#[derive(Debug, Default, Deserialize)]
+pub struct MemoryPageQuery {
+    #[serde(flatten)]
+    pub filters: MemoryQuery,
+    pub limit: Option<usize>,
+    /// 上一页返回的 `next_cursor`
+    pub cursor: Option<String>,
+    pub sort: Option<MemorySort>,
+    pub descending: Option<bool>,
+}
+
+/// 排序方式；default 为置顶、优先级、更新时间依次降序
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemorySort {
+    #[default]
+    Default,
+    SortOrder,
+    UpdatedAt,
+    Priority,
+}
+
+impl MemorySort {
+    /// 排序键（末尾的 id 保证顺序稳定）与默认方向
+    fn keys(self) -> (&'static [&'static str], bool) {
+        match self {
+            Self::Default => (&["is_pinned", "priority", "updated_at", "id"], true),
+            Self::SortOrder => (&["sort_order", "id"], false),
+            Self::UpdatedAt => (&["updated_at", "id"], true),
+            Self::Priority => (&["priority", "id"], true),
+        }
+    }
+
+    fn cursor_values(self, memory: &MemoryRecord) -> Vec<serde_json::Value> {
+        let id = serde_json::Value::from(memory.id.as_str());
+        match self {
+            Self::Default => vec![
+                bool_to_int(memory.is_pinned).into(),
+                memory.priority.into(),
+                memory.updated_at.into(),
+                id,
+            ],
+            Self::SortOrder => vec![memory.sort_order.into(), id],
+            Self::UpdatedAt => vec![memory.updated_at.into(), id],
+            Self::Priority => vec![memory.priority.into(), id],
+        }
+    }
+}
+
+/// 分页结果：total 为满足筛选条件的总行数，`next_cursor` 为 None 表示没有下一页
+#[derive(Debug, Serialize)]
+pub struct MemoryPage {
+    pub items: Vec<MemoryRecord>,
+    pub total: usize,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -106,6 +288,116 @@ pub struct MemoryRecord {
     pub sort_order: i64,
     pub created_at: i64,
     pub updated_at: i64,
+    pub deleted_at: Option<i64>,
+}
+
+/// 记忆行 `row_data` 之外、随历史一起回退的字段
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryState {
+    pub is_active: bool,
+    pub is_pinned: bool,
+    pub priority: i64,
+    pub sort_order: i64,
+    pub created_at: i64,
+}
+
+impl MemoryState {
+    fn of(record: &MemoryRecord) -> Self {
+        Self {
+            is_active: record.is_active,
+            is_pinned: record.is_pinned,
+            priority: record.priority,
+            sort_order: record.sort_order,
+            created_at: record.created_at,
+        }
+    }
+}
+
+/// 一条记忆变更记录；`old_data` / `new_data` 为变更前后的 `row_data`，不存在时为 null
+#[derive(Debug, Serialize)]
+pub struct MemoryHistoryEntry {
+    pub id: i64,
+    pub memory_id: String,
+    pub op: String,
+    pub template_id: String,
+    pub table_id: String,
+    pub contact_id: Option<String>,
+    pub group_id: Option<String>,
+    pub old_data: Option<serde_json::Value>,
+    pub new_data: Option<serde_json::Value>,
+    pub actor: String,
+    pub created_at: i64,
+    pub undone_at: Option<i64>,
+    /// v6 之前的记录没有状态快照，为 null
+    pub old_state: Option<MemoryState>,
+    pub new_state: Option<MemoryState>,
+}
+
+/// 批量撤销结果：skipped 为之后又被改动过、无法安全撤销的记录
+#[derive(Debug, Default, Serialize)]
+pub struct UndoResult {
+    pub undone: Vec<i64>,
+    pub skipped: Vec<i64>,
+}
+
+/// `apply_memory_ops` 的单条操作，按 op 字段区分：
+/// insert / upsert 与 `MemoryCreateInput` 同形（upsert 必须带 id），
+/// update 与 `MemoryUpdateInput` 同形但 `row_data` 按列合并，delete 只需 id
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum MemoryOp {
+    Insert(MemoryCreateInput),
+    Upsert(MemoryCreateInput),
+    Update(MemoryUpdateInput),
+    Delete { id: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryOpStatus {
+    Inserted,
+    Updated,
+    Deleted,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemoryOpResult {
+    pub index: usize,
+    pub id: Option<String>,
+    pub status: MemoryOpStatus,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<SchemaViolation>,
+}
+
+/// applied 为 false 表示整批已回滚（atomic 且有操作失败）
+#[derive(Debug, Serialize)]
+pub struct MemoryOpsResult {
+    pub applied: bool,
+    pub results: Vec<MemoryOpResult>,
+}
+
+/// 回收站内容
+#[derive(Debug, Serialize)]
+pub struct MemoryTrash {
+    pub templates: Vec<TemplateRecord>,
+    pub memories: Vec<MemoryRecord>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TrashPurgeResult {
+    pub memories: usize,
+    pub templates: usize,
+}
+
+/// 全文检索结果：score 越大越相关
+#[derive(Debug, Serialize)]
+pub struct MemorySearchHit {
+    #[serde(flatten)]
+    pub memory: MemoryRecord,
+    pub snippet: String,
+    pub score: f64,
 }
 
 pub struct MemoryDb {
@@ -142,87 +434,51 @@ impl MemoryDb {
         scope_id: Option<String>,
         input: MemoryCreateInput,
     ) -> Result<String, String> {
-        self.with_conn(scope_id, |conn| {
-            let now = now_ms();
-            let id = input
-                .id
-                .as_deref()
-                .map(|s| s.trim())
-                .filter(|s| !s.is_empty())
-                .map(String::from)
-                .unwrap_or_else(generate_id);
-            let row_data = serde_json::to_string(&input.row_data).map_err(|e| e.to_string())?;
-            let is_active = bool_to_int(input.is_active.unwrap_or(true));
-            let is_pinned = bool_to_int(input.is_pinned.unwrap_or(false));
-            let priority = input.priority.unwrap_or(0);
-            let sort_order = input.sort_order.unwrap_or(0);
-            conn.execute(
-                "INSERT INTO memories (id, template_id, table_id, contact_id, group_id, row_data, is_active, is_pinned, priority, sort_order, created_at, updated_at)
-                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-                params![
-                    id,
-                    input.template_id,
-                    input.table_id,
-                    input.contact_id,
-                    input.group_id,
-                    row_data,
-                    is_active,
-                    is_pinned,
-                    priority,
-                    sort_order,
-                    now,
-                    now
-                ],
-            )
-            .map_err(|e| e.to_string())?;
+        self.create_memory_as(scope_id, input, ACTOR_USER)
+    }
+
+    /// 同 `create_memory`，历史中记为 actor 所做的修改
+    pub fn create_memory_as(
+        &self,
+        scope_id: Option<String>,
+        input: MemoryCreateInput,
+        actor: &str,
+    ) -> Result<String, String> {
+        self.with_conn(scope_id, move |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let violations = validate_create(&tx, &input)?;
+            if !violations.is_empty() {
+                return Err(MemoryValidationError::into_message(violations));
+            }
+            let id = insert_memory(&tx, &input, actor)?;
+            tx.commit().map_err(|e| e.to_string())?;
             Ok(id)
         })
     }
 
+    /// 直接覆盖模板。`check_schema` 为 true 时，已有记忆的模板拒绝删除表或列，
+    /// 这类改动需用 `upgrade_template` 迁移；前端编辑器等既有调用方不开启
     pub fn save_template(
         &self,
         scope_id: Option<String>,
         input: TemplateInput,
+        check_schema: bool,
     ) -> Result<(), String> {
         self.with_conn(scope_id, |conn| {
-            let now = now_ms();
-            let schema = serde_json::to_string(&input.schema).map_err(|e| e.to_string())?;
-            let injection = match input.injection {
-                Some(value) => Some(serde_json::to_string(&value).map_err(|e| e.to_string())?),
-                None => None,
-            };
-            let is_default = bool_to_int(input.is_default.unwrap_or(false));
-            let is_builtin = bool_to_int(input.is_builtin.unwrap_or(false));
-
-            conn.execute(
-                "INSERT INTO templates (id, name, author, version, description, schema, injection, created_at, updated_at, is_default, is_builtin)
-                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-                 ON CONFLICT(id) DO UPDATE SET
-                   name = excluded.name,
-                   author = excluded.author,
-                   version = excluded.version,
-                   description = excluded.description,
-                   schema = excluded.schema,
-                   injection = excluded.injection,
-                   updated_at = excluded.updated_at,
-                   is_default = excluded.is_default,
-                   is_builtin = excluded.is_builtin",
-                params![
-                    input.id,
-                    input.name,
-                    input.author,
-                    input.version,
-                    input.description,
-                    schema,
-                    injection,
-                    now,
-                    now,
-                    is_default,
-                    is_builtin
-                ],
-            )
-            .map_err(|e| e.to_string())?;
-            Ok(())
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let existing = tx
+                .query_row(
+                    &format!("SELECT {TEMPLATE_COLUMNS} FROM templates WHERE id = ?"),
+                    params![input.id],
+                    template_from_row,
+                )
+                .optional()
+                .map_err(|e| e.to_string())?;
+            if let Some(existing) = existing.filter(|_| check_schema) {
+                check_template_overwrite(&tx, &existing, &input.schema)?;
+            }
+            upsert_template(&tx, &input)?;
+            tx.commit().map_err(|e| e.to_string())
         })
     }
 
@@ -232,10 +488,8 @@ impl MemoryDb {
         query: TemplateQuery,
     ) -> Result<Vec<TemplateRecord>, String> {
         self.with_conn(scope_id, |conn| {
-            let mut sql = String::from(
-                "SELECT id, name, author, version, description, schema, injection, created_at, updated_at, is_default, is_builtin FROM templates",
-            );
-            let mut clauses: Vec<String> = Vec::new();
+            let mut sql = format!("SELECT {TEMPLATE_COLUMNS} FROM templates");
+            let mut clauses: Vec<String> = vec!["deleted_at IS NULL".to_string()];
             let mut values: Vec<SqlValue> = Vec::new();
 
             if let Some(id) = query.id {
@@ -251,31 +505,13 @@ impl MemoryDb {
                 values.push(SqlValue::Integer(bool_to_int(is_builtin)));
             }
 
-            if !clauses.is_empty() {
-                sql.push_str(" WHERE ");
-                sql.push_str(&clauses.join(" AND "));
-            }
+            sql.push_str(" WHERE ");
+            sql.push_str(&clauses.join(" AND "));
             sql.push_str(" ORDER BY updated_at DESC");
 
             let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
             let rows = stmt
-                .query_map(params_from_iter(values), |row| {
-                    let schema_raw: String = row.get(5)?;
-                    let injection_raw: Option<String> = row.get(6)?;
-                    Ok(TemplateRecord {
-                        id: row.get(0)?,
-                        name: row.get(1)?,
-                        author: row.get(2)?,
-                        version: row.get(3)?,
-                        description: row.get(4)?,
-                        schema: parse_json_value(schema_raw),
-                        injection: parse_json_optional(injection_raw),
-                        created_at: row.get(7)?,
-                        updated_at: row.get(8)?,
-                        is_default: row.get::<_, i64>(9)? != 0,
-                        is_builtin: row.get::<_, i64>(10)? != 0,
-                    })
-                })
+                .query_map(params_from_iter(values), template_from_row)
                 .map_err(|e| e.to_string())?;
 
             let mut out = Vec::new();
@@ -286,153 +522,388 @@ impl MemoryDb {
         })
     }
 
-    pub fn delete_template(&self, scope_id: Option<String>, id: String) -> Result<(), String> {
+    /// 把模板移入回收站。仍有记忆引用时：cascade 为 false 则拒绝，
+    /// 为 true 则把这些记忆一并移入回收站（恢复模板时一起恢复）
+    pub fn delete_template(
+        &self,
+        scope_id: Option<String>,
+        id: &str,
+        cascade: bool,
+        actor: &str,
+    ) -> Result<(), String> {
         self.with_conn(scope_id, |conn| {
-            let affected = conn
-                .execute("DELETE FROM templates WHERE id = ?", params![id])
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let referencing: Vec<String> = {
+                let mut stmt = tx
+                    .prepare("SELECT id FROM memories WHERE template_id = ? AND deleted_at IS NULL")
+                    .map_err(|e| e.to_string())?;
+                let rows = stmt
+                    .query_map(params![id], |row| row.get(0))
+                    .map_err(|e| e.to_string())?;
+                rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+            };
+            if !referencing.is_empty() && !cascade {
+                return Err(format!(
+                    "template is used by {} memories",
+                    referencing.len()
+                ));
+            }
+            let now = now_ms();
+            let affected = tx
+                .execute(
+                    "UPDATE templates SET deleted_at = ?1, updated_at = ?1 \
+                     WHERE id = ?2 AND deleted_at IS NULL",
+                    params![now, id],
+                )
                 .map_err(|e| e.to_string())?;
             if affected == 0 {
                 return Err("template not found".to_string());
             }
-            Ok(())
+            for memory_id in referencing {
+                delete_memory_row(&tx, &memory_id, now, actor)?;
+                tx.execute(
+                    "UPDATE memories SET deleted_with_template = 1 WHERE id = ?",
+                    params![memory_id],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+            tx.commit().map_err(|e| e.to_string())
         })
     }
 
-    pub fn update_memory(
+    /// 导出模板为分享文件内容；`include_samples` 时每张表附带最近的几行（去掉联系人/群组）
+    pub fn export_template(
         &self,
         scope_id: Option<String>,
-        input: MemoryUpdateInput,
-    ) -> Result<(), String> {
+        id: &str,
+        include_samples: bool,
+    ) -> Result<TemplateDocument, String> {
+        // 导出的文件必须能被再次导入
+        memory_template_file::validate_template_id(id)?;
         self.with_conn(scope_id, |conn| {
-            let mut sets: Vec<String> = Vec::new();
-            let mut values: Vec<SqlValue> = Vec::new();
-
-            if let Some(row_data) = input.row_data {
-                let raw = serde_json::to_string(&row_data).map_err(|e| e.to_string())?;
-                sets.push("row_data = ?".to_string());
-                values.push(SqlValue::Text(raw));
-            }
-            if let Some(is_active) = input.is_active {
-                sets.push("is_active = ?".to_string());
-                values.push(SqlValue::Integer(bool_to_int(is_active)));
-            }
-            if let Some(is_pinned) = input.is_pinned {
-                sets.push("is_pinned = ?".to_string());
-                values.push(SqlValue::Integer(bool_to_int(is_pinned)));
-            }
-            if let Some(priority) = input.priority {
-                sets.push("priority = ?".to_string());
-                values.push(SqlValue::Integer(priority));
-            }
-            if let Some(sort_order) = input.sort_order {
-                sets.push("sort_order = ?".to_string());
-                values.push(SqlValue::Integer(sort_order));
-            }
-
-            if sets.is_empty() {
-                return Err("no fields to update".to_string());
+            let template = conn
+                .query_row(
+                    &format!(
+                        "SELECT {TEMPLATE_COLUMNS} FROM templates WHERE id = ? AND deleted_at IS NULL"
+                    ),
+                    params![id],
+                    template_from_row,
+                )
+                .optional()
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| "template not found".to_string())?;
+            let mut samples: Vec<TemplateSample> = Vec::new();
+            if include_samples {
+                let mut stmt = conn
+                    .prepare(
+                        "SELECT table_id, row_data FROM memories \
+                         WHERE template_id = ? AND deleted_at IS NULL \
+                         ORDER BY updated_at DESC, id",
+                    )
+                    .map_err(|e| e.to_string())?;
+                let rows = stmt
+                    .query_map(params![id], |row| {
+                        Ok(TemplateSample {
+                            table_id: row.get(0)?,
+                            row_data: parse_row_data(row.get(1)?),
+                        })
+                    })
+                    .map_err(|e| e.to_string())?;
+                let mut per_table: HashMap<String, usize> = HashMap::new();
+                for row in rows {
+                    let sample = row.map_err(|e| e.to_string())?;
+                    let count = per_table.entry(sample.table_id.clone()).or_default();
+                    if *count < memory_template_file::SAMPLE_ROWS_PER_TABLE {
+                        *count += 1;
+                        samples.push(sample);
+                    }
+                }
             }
+            Ok(TemplateDocument {
+                format: memory_template_file::FORMAT.to_string(),
+                format_version: memory_template_file::FORMAT_VERSION,
+                exported_at: now_ms(),
+                template: TemplatePayload {
+                    id: template.id,
+                    name: template.name,
+                    author: template.author,
+                    version: template.version,
+                    description: template.description,
+                    schema: template.schema,
+                    injection: template.injection,
+                },
+                samples,
+            })
+        })
+    }
 
-            sets.push("updated_at = ?".to_string());
-            values.push(SqlValue::Integer(now_ms()));
-            values.push(SqlValue::Text(input.id));
-
-            let sql = format!("UPDATE memories SET {} WHERE id = ?", sets.join(", "));
-            let affected = conn
-                .execute(&sql, params_from_iter(values))
+    /// 导入模板文件，保留作者、版本与描述。id 冲突（含回收站中的模板）时
+    /// 按 conflict 改名另存，或覆盖原模板并提升版本号；覆盖只允许不需要迁移记忆的改动
+    pub fn import_template(
+        &self,
+        scope_id: Option<String>,
+        document: TemplateDocument,
+        conflict: TemplateConflict,
+    ) -> Result<TemplateImportResult, String> {
+        memory_template_file::validate_document(&document)?;
+        self.with_conn(scope_id, |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let payload = document.template;
+            let existing = tx
+                .query_row(
+                    &format!("SELECT {TEMPLATE_COLUMNS} FROM templates WHERE id = ?"),
+                    params![payload.id],
+                    template_from_row,
+                )
+                .optional()
                 .map_err(|e| e.to_string())?;
-            if affected == 0 {
-                return Err("memory not found".to_string());
+            let mut input = TemplateInput {
+                id: payload.id,
+                name: payload.name,
+                author: payload.author,
+                version: payload.version,
+                description: payload.description,
+                schema: payload.schema,
+                injection: payload.injection,
+                is_default: Some(false),
+                is_builtin: Some(false),
+            };
+            let (mut renamed, mut replaced) = (false, false);
+            match (existing, conflict) {
+                (None, _) => {}
+                (Some(_), TemplateConflict::Rename) => {
+                    let mut check = tx
+                        .prepare("SELECT 1 FROM templates WHERE id = ?")
+                        .map_err(|e| e.to_string())?;
+                    input.id = memory_template_file::renamed_id(&input.id, |candidate| {
+                        check.exists(params![candidate]).unwrap_or(true)
+                    })?;
+                    renamed = true;
+                }
+                (Some(existing), TemplateConflict::BumpVersion) => {
+                    check_template_overwrite(&tx, &existing, &input.schema)?;
+                    input.version = Some(memory_template_file::bumped_version(
+                        existing.version.as_deref(),
+                        input.version.as_deref(),
+                    ));
+                    input.is_default = Some(existing.is_default);
+                    input.is_builtin = Some(existing.is_builtin);
+                    replaced = true;
+                }
             }
-            Ok(())
+            upsert_template(&tx, &input)?;
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(TemplateImportResult {
+                id: input.id,
+                name: input.name,
+                version: input.version,
+                renamed,
+                replaced,
+                samples: document.samples,
+            })
         })
     }
 
-    pub fn delete_memory(&self, scope_id: Option<String>, id: String) -> Result<(), String> {
+    /// 预览模板升级：列差异、会被改写的行（最多 `UPGRADE_PREVIEW_LIMIT` 行）
+    /// 以及映射后不再符合新 schema 的行，不做任何写入
+    pub fn preview_template_upgrade(
+        &self,
+        scope_id: Option<String>,
+        input: &TemplateUpgradeInput,
+    ) -> Result<TemplateUpgradePreview, String> {
         self.with_conn(scope_id, |conn| {
-            let affected = conn
-                .execute("DELETE FROM memories WHERE id = ?", params![id])
-                .map_err(|e| e.to_string())?;
-            if affected == 0 {
-                return Err("memory not found".to_string());
-            }
-            Ok(())
+            let plan = plan_template_upgrade(conn, input)?;
+            let affected = plan.rows.iter().filter(|r| r.changed).count();
+            let invalid = plan
+                .rows
+                .iter()
+                .filter(|r| !r.violations.is_empty())
+                .count();
+            let rows = plan
+                .rows
+                .into_iter()
+                .filter(|r| r.changed || !r.violations.is_empty())
+                .take(UPGRADE_PREVIEW_LIMIT)
+                .map(|r| TemplateUpgradeRow {
+                    id: r.before.id,
+                    table_id: r.before.table_id,
+                    before: r.before.row_data,
+                    after: r.after,
+                    violations: r.violations,
+                })
+                .collect();
+            Ok(TemplateUpgradePreview {
+                diff: plan.diff,
+                affected,
+                invalid,
+                rows,
+            })
         })
     }
 
-    pub fn get_memories(
+    /// 在一个事务内保存新模板并按映射改写该模板的所有记忆；
+    /// 任一行映射后不符合新 schema 时整体回滚
+    pub fn upgrade_template(
         &self,
         scope_id: Option<String>,
-        query: MemoryQuery,
-    ) -> Result<Vec<MemoryRecord>, String> {
+        input: TemplateUpgradeInput,
+        actor: &str,
+    ) -> Result<TemplateUpgradeResult, String> {
         self.with_conn(scope_id, |conn| {
-            let mut sql = String::from(
-                "SELECT id, template_id, table_id, contact_id, group_id, row_data, is_active, is_pinned, priority, sort_order, created_at, updated_at FROM memories",
-            );
-            let mut clauses: Vec<String> = Vec::new();
-            let mut values: Vec<SqlValue> = Vec::new();
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let plan = plan_template_upgrade(&tx, &input)?;
+            let violations: Vec<SchemaViolation> = plan
+                .rows
+                .iter()
+                .flat_map(|r| r.violations.iter().cloned())
+                .collect();
+            if !violations.is_empty() {
+                return Err(MemoryValidationError::into_message(violations));
+            }
+            let mut template = input.template;
+            template.is_default = template.is_default.or(Some(plan.existing.is_default));
+            template.is_builtin = template.is_builtin.or(Some(plan.existing.is_builtin));
+            upsert_template(&tx, &template)?;
+            let mut updated = 0;
+            for row in plan.rows.into_iter().filter(|r| r.changed) {
+                let update = MemoryUpdateInput {
+                    id: row.before.id.clone(),
+                    row_data: Some(row.after),
+                    is_active: None,
+                    is_pinned: None,
+                    priority: None,
+                    sort_order: None,
+                };
+                update_memory_row(&tx, &row.before, &update, actor)?;
+                updated += 1;
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(TemplateUpgradeResult {
+                diff: plan.diff,
+                updated,
+            })
+        })
+    }
 
-            let scope = query
-                .scope
-                .unwrap_or_default()
-                .trim()
-                .to_lowercase();
-            if scope == "global" {
-                clauses.push("contact_id IS NULL AND group_id IS NULL".to_string());
-            } else if scope == "contact" {
-                let contact_id = query
-                    .contact_id
-                    .ok_or_else(|| "contact scope requires contact_id".to_string())?;
-                clauses.push("contact_id = ?".to_string());
-                values.push(SqlValue::Text(contact_id));
-            } else if scope == "group" {
-                let group_id = query
-                    .group_id
-                    .ok_or_else(|| "group scope requires group_id".to_string())?;
-                clauses.push("group_id = ?".to_string());
-                values.push(SqlValue::Text(group_id));
-            } else {
-                if let Some(contact_id) = query.contact_id {
-                    clauses.push("contact_id = ?".to_string());
-                    values.push(SqlValue::Text(contact_id));
-                }
-                if let Some(group_id) = query.group_id {
-                    clauses.push("group_id = ?".to_string());
-                    values.push(SqlValue::Text(group_id));
+    pub fn update_memory(
+        &self,
+        scope_id: Option<String>,
+        input: MemoryUpdateInput,
+    ) -> Result<(), String> {
+        self.update_memory_as(scope_id, input, ACTOR_USER)
+    }
+
+    /// 同 `update_memory`，历史中记为 actor 所做的修改
+    pub fn update_memory_as(
+        &self,
+        scope_id: Option<String>,
+        input: MemoryUpdateInput,
+        actor: &str,
+    ) -> Result<(), String> {
+        self.with_conn(scope_id, move |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let before = load_memory(&tx, &input.id)?.ok_or("memory not found")?;
+            if let Some(row_data) = &input.row_data {
+                let violations = validate_row_data(&tx, &before, row_data)?;
+                if !violations.is_empty() {
+                    return Err(MemoryValidationError::into_message(violations));
                 }
             }
-            if let Some(template_id) = query.template_id {
-                clauses.push("template_id = ?".to_string());
-                values.push(SqlValue::Text(template_id));
+            update_memory_row(&tx, &before, &input, actor)?;
+            tx.commit().map_err(|e| e.to_string())
+        })
+    }
+
+    /// 在同一事务中依次执行一组写操作，返回每条操作的结果。
+    /// atomic 时任一操作失败则整体回滚；否则只回滚失败的那条
+    pub fn apply_memory_ops(
+        &self,
+        scope_id: Option<String>,
+        ops: Vec<MemoryOp>,
+        atomic: bool,
+        actor: &str,
+    ) -> Result<MemoryOpsResult, String> {
+        self.with_conn(scope_id, |conn| {
+            let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+            let mut results = Vec::with_capacity(ops.len());
+            for (index, op) in ops.into_iter().enumerate() {
+                let sp = tx.savepoint().map_err(|e| e.to_string())?;
+                let result = match apply_memory_op(&sp, op, actor) {
+                    Ok((status, id)) => {
+                        sp.commit().map_err(|e| e.to_string())?;
+                        MemoryOpResult {
+                            index,
+                            id: Some(id),
+                            status,
+                            error: None,
+                            violations: Vec::new(),
+                        }
+                    }
+                    Err(failure) => {
+                        // Savepoint 在 drop 时回滚
+                        drop(sp);
+                        MemoryOpResult {
+                            index,
+                            id: failure.id,
+                            status: MemoryOpStatus::Failed,
+                            error: Some(failure.message),
+                            violations: failure.violations,
+                        }
+                    }
+                };
+                results.push(result);
             }
+            let failed = results.iter().any(|r| r.status == MemoryOpStatus::Failed);
+            let applied = !(atomic && failed);
+            if applied {
+                tx.commit().map_err(|e| e.to_string())?;
+            }
+            Ok(MemoryOpsResult { applied, results })
+        })
+    }
+
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn delete_memory(&self, scope_id: Option<String>, id: String) -> Result<(), String> {
+        self.delete_memory_as(scope_id, &id, ACTOR_USER)
+    }
 
-            if !clauses.is_empty() {
-                sql.push_str(" WHERE ");
-                sql.push_str(&clauses.join(" AND "));
+    /// 同 `delete_memory`，历史中记为 actor 所做的修改
+    pub fn delete_memory_as(
+        &self,
+        scope_id: Option<String>,
+        id: &str,
+        actor: &str,
+    ) -> Result<(), String> {
+        self.with_conn(scope_id, |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            if !delete_memory_row(&tx, id, now_ms(), actor)? {
+                return Err("memory not found".to_string());
             }
-            sql.push_str(" ORDER BY is_pinned DESC, priority DESC, updated_at DESC");
+            tx.commit().map_err(|e| e.to_string())
+        })
+    }
 
-            let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    /// 某条记忆的变更历史，最新的在前
+    pub fn get_memory_history(
+        &self,
+        scope_id: Option<String>,
+        memory_id: &str,
+        limit: Option<usize>,
+    ) -> Result<Vec<MemoryHistoryEntry>, String> {
+        let limit = limit.unwrap_or(HISTORY_DEFAULT_LIMIT).max(1);
+        self.with_conn(scope_id, |conn| {
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT {HISTORY_COLUMNS} FROM memory_history WHERE memory_id = ? \
+                     ORDER BY id DESC LIMIT ?"
+                ))
+                .map_err(|e| e.to_string())?;
             let rows = stmt
-                .query_map(params_from_iter(values), |row| {
-                    let row_data: String = row.get(5)?;
-                    Ok(MemoryRecord {
-                        id: row.get(0)?,
-                        template_id: row.get(1)?,
-                        table_id: row.get(2)?,
-                        contact_id: row.get(3)?,
-                        group_id: row.get(4)?,
-                        row_data: parse_row_data(row_data),
-                        is_active: row.get::<_, i64>(6)? != 0,
-                        is_pinned: row.get::<_, i64>(7)? != 0,
-                        priority: row.get(8)?,
-                        sort_order: row.get(9)?,
-                        created_at: row.get(10)?,
-                        updated_at: row.get(11)?,
-                    })
-                })
+                .query_map(
+                    params![memory_id, i64::try_from(limit).unwrap_or(i64::MAX)],
+                    history_from_row,
+                )
                 .map_err(|e| e.to_string())?;
-
             let mut out = Vec::new();
             for row in rows {
                 out.push(row.map_err(|e| e.to_string())?);
@@ -441,90 +912,653 @@ impl MemoryDb {
         })
     }
 
-    pub fn batch_create_memories(
+    /// 把记忆恢复到某条历史记录之后的状态（该记录为删除时即删除该行）
+    pub fn revert_memory(
         &self,
         scope_id: Option<String>,
-        memories: Vec<MemoryCreateInput>,
-    ) -> Result<usize, String> {
+        history_id: i64,
+        actor: &str,
+    ) -> Result<(), String> {
         self.with_conn(scope_id, |conn| {
             let tx = conn.transaction().map_err(|e| e.to_string())?;
-            let mut count = 0;
-            {
-                let mut stmt = tx
-                    .prepare(
-                        "INSERT INTO memories (id, template_id, table_id, contact_id, group_id, row_data, is_active, is_pinned, priority, sort_order, created_at, updated_at)
-                         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-                    )
-                    .map_err(|e| e.to_string())?;
-                for input in memories {
-                    let now = now_ms();
-                    let id = input
-                        .id
-                        .as_deref()
-                        .map(|s| s.trim())
-                        .filter(|s| !s.is_empty())
-                        .map(String::from)
-                        .unwrap_or_else(generate_id);
-                    let row_data = serde_json::to_string(&input.row_data).map_err(|e| e.to_string())?;
-                    let is_active = bool_to_int(input.is_active.unwrap_or(true));
-                    let is_pinned = bool_to_int(input.is_pinned.unwrap_or(false));
-                    let priority = input.priority.unwrap_or(0);
-                    let sort_order = input.sort_order.unwrap_or(0);
-                    stmt.execute(params![
-                        id,
-                        input.template_id,
-                        input.table_id,
-                        input.contact_id,
-                        input.group_id,
-                        row_data,
-                        is_active,
-                        is_pinned,
-                        priority,
-                        sort_order,
-                        now,
-                        now
-                    ])
-                    .map_err(|e| e.to_string())?;
-                    count += 1;
-                }
-            }
-            tx.commit().map_err(|e| e.to_string())?;
-            Ok(count)
+            let entry = load_history(&tx, history_id)?.ok_or("history entry not found")?;
+            restore_memory_state(
+                &tx,
+                &entry,
+                entry.new_data.as_ref(),
+                entry.new_state.as_ref(),
+                actor,
+            )?;
+            tx.commit().map_err(|e| e.to_string())
         })
     }
 
-    pub fn batch_delete_memories(
+    /// 撤销某联系人/群组最近 count 条自动修改（按时间倒序逐条回退）；
+    /// 之后又被改动过的行会跳过，避免覆盖较新的修改
+    pub fn undo_automated_changes(
         &self,
         scope_id: Option<String>,
-        ids: Vec<String>,
-    ) -> Result<usize, String> {
+        contact_id: Option<String>,
+        group_id: Option<String>,
+        count: usize,
+    ) -> Result<UndoResult, String> {
+        let (column, owner) = match (contact_id, group_id) {
+            (Some(contact_id), None) => ("contact_id", contact_id),
+            (None, Some(group_id)) => ("group_id", group_id),
+            _ => return Err("exactly one of contact_id or group_id is required".to_string()),
+        };
         self.with_conn(scope_id, |conn| {
             let tx = conn.transaction().map_err(|e| e.to_string())?;
-            let mut count = 0;
-            {
+            let entries = {
                 let mut stmt = tx
-                    .prepare("DELETE FROM memories WHERE id = ?")
+                    .prepare(&format!(
+                        "SELECT {HISTORY_COLUMNS} FROM memory_history \
+                         WHERE {column} = ? AND actor NOT IN (?, ?, ?) AND undone_at IS NULL \
+                         ORDER BY id DESC LIMIT ?"
+                    ))
+                    .map_err(|e| e.to_string())?;
+                let rows = stmt
+                    .query_map(
+                        params![
+                            owner,
+                            ACTOR_USER,
+                            ACTOR_UNDO,
+                            ACTOR_IMPORT,
+                            i64::try_from(count).unwrap_or(i64::MAX)
+                        ],
+                        history_from_row,
+                    )
                     .map_err(|e| e.to_string())?;
-                for id in ids {
-                    count += stmt.execute(params![id]).map_err(|e| e.to_string())?;
+                rows.collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| e.to_string())?
+            };
+            let mut result = UndoResult::default();
+            for entry in entries {
+                let current = load_memory(&tx, &entry.memory_id)?;
+                let state_changed = entry.new_state.as_ref().is_some_and(|state| {
+                    current.as_ref().map(MemoryState::of).as_ref() != Some(state)
+                });
+                if current.map(|m| m.row_data) != entry.new_data || state_changed {
+                    result.skipped.push(entry.id);
+                    continue;
                 }
+                restore_memory_state(
+                    &tx,
+                    &entry,
+                    entry.old_data.as_ref(),
+                    entry.old_state.as_ref(),
+                    ACTOR_UNDO,
+                )?;
+                tx.execute(
+                    "UPDATE memory_history SET undone_at = ? WHERE id = ?",
+                    params![now_ms(), entry.id],
+                )
+                .map_err(|e| e.to_string())?;
+                result.undone.push(entry.id);
             }
             tx.commit().map_err(|e| e.to_string())?;
-            Ok(count)
+            Ok(result)
         })
     }
 
-    fn with_conn<F, T>(&self, scope_id: Option<String>, f: F) -> Result<T, String>
-    where
-        F: FnOnce(&mut Connection) -> Result<T, String>,
-    {
-        let scope_key = normalize_scope_id(scope_id);
-        #[cfg(target_os = "android")]
-        {
-            let path = self.db_path_for(&scope_key);
+    pub fn get_memories(
+        &self,
+        scope_id: Option<String>,
+        query: MemoryQuery,
+    ) -> Result<Vec<MemoryRecord>, String> {
+        let query = MemoryPageQuery {
+            filters: query,
+            ..MemoryPageQuery::default()
+        };
+        self.get_memories_page(scope_id, query)
+            .map(|page| page.items)
+    }
+
+    /// 按条件查询一页记忆；设置 limit 时按 cursor 做 keyset 分页
+    pub fn get_memories_page(
+        &self,
+        scope_id: Option<String>,
+        query: MemoryPageQuery,
+    ) -> Result<MemoryPage, String> {
+        let sort = query.sort.unwrap_or_default();
+        let (keys, default_desc) = sort.keys();
+        let descending = query.descending.unwrap_or(default_desc);
+        let limit = query.limit.map(|n| n.clamp(1, PAGE_MAX_LIMIT));
+        let cursor = query
+            .cursor
+            .map(|cursor| decode_cursor(&cursor, keys.len()))
+            .transpose()?;
+        self.with_conn(scope_id, |conn| {
+            let (mut clauses, mut values) = memory_query_clauses(Some(query.filters))?;
+            let total: i64 = conn
+                .query_row(
+                    &format!(
+                        "SELECT COUNT(*) FROM memories WHERE {}",
+                        clauses.join(" AND ")
+                    ),
+                    params_from_iter(values.iter()),
+                    |row| row.get(0),
+                )
+                .map_err(|e| e.to_string())?;
+
+            let key_list = keys.join(", ");
+            if let Some(cursor) = cursor {
+                let placeholders = vec!["?"; keys.len()].join(", ");
+                let op = if descending { "<" } else { ">" };
+                clauses.push(format!("({key_list}) {op} ({placeholders})"));
+                values.extend(cursor);
+            }
+            let direction = if descending { "DESC" } else { "ASC" };
+            let order = keys
+                .iter()
+                .map(|key| format!("{key} {direction}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut sql = format!(
+                "SELECT {MEMORY_COLUMNS} FROM memories WHERE {} ORDER BY {order}",
+                clauses.join(" AND ")
+            );
+            if let Some(limit) = limit {
+                // 多取一行判断是否还有下一页
+                sql.push_str(" LIMIT ?");
+                values.push(SqlValue::Integer(
+                    i64::try_from(limit + 1).unwrap_or(i64::MAX),
+                ));
+            }
+
+            let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map(params_from_iter(values), memory_from_row)
+                .map_err(|e| e.to_string())?;
+
+            let mut items = Vec::new();
+            for row in rows {
+                items.push(row.map_err(|e| e.to_string())?);
+            }
+            let next_cursor = match limit {
+                Some(limit) if items.len() > limit => {
+                    items.truncate(limit);
+                    items
+                        .last()
+                        .map(|last| encode_cursor(&sort.cursor_values(last)))
+                }
+                _ => None,
+            };
+            Ok(MemoryPage {
+                items,
+                total: usize::try_from(total).unwrap_or_default(),
+                next_cursor,
+            })
+        })
+    }
+
+    pub fn list_trash(&self, scope_id: Option<String>) -> Result<MemoryTrash, String> {
+        self.with_conn(scope_id, |conn| {
+            let templates = {
+                let mut stmt = conn
+                    .prepare(&format!(
+                        "SELECT {TEMPLATE_COLUMNS} FROM templates \
+                         WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC"
+                    ))
+                    .map_err(|e| e.to_string())?;
+                let rows = stmt
+                    .query_map([], template_from_row)
+                    .map_err(|e| e.to_string())?;
+                rows.collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| e.to_string())?
+            };
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT {MEMORY_COLUMNS} FROM memories \
+                     WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC"
+                ))
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map([], memory_from_row)
+                .map_err(|e| e.to_string())?;
+            let memories = rows
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.to_string())?;
+            Ok(MemoryTrash {
+                templates,
+                memories,
+            })
+        })
+    }
+
+    /// 从回收站恢复记忆；所属模板也在回收站时需先恢复模板
+    pub fn restore_memory(
+        &self,
+        scope_id: Option<String>,
+        id: &str,
+        actor: &str,
+    ) -> Result<(), String> {
+        self.with_conn(scope_id, |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let template_trashed: Option<bool> = tx
+                .query_row(
+                    "SELECT t.deleted_at IS NOT NULL FROM memories m \
+                     JOIN templates t ON t.id = m.template_id \
+                     WHERE m.id = ? AND m.deleted_at IS NOT NULL",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| e.to_string())?;
+            match template_trashed {
+                None => return Err("memory not in trash".to_string()),
+                Some(true) => return Err("template is in trash".to_string()),
+                Some(false) => {}
+            }
+            restore_memory_row(&tx, id, actor)?;
+            tx.commit().map_err(|e| e.to_string())
+        })
+    }
+
+    /// 从回收站恢复模板，连同删除模板时级联移入回收站的记忆；返回恢复的记忆数
+    pub fn restore_template(
+        &self,
+        scope_id: Option<String>,
+        id: &str,
+        actor: &str,
+    ) -> Result<usize, String> {
+        self.with_conn(scope_id, |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let affected = tx
+                .execute(
+                    "UPDATE templates SET deleted_at = NULL, updated_at = ? \
+                     WHERE id = ? AND deleted_at IS NOT NULL",
+                    params![now_ms(), id],
+                )
+                .map_err(|e| e.to_string())?;
+            if affected == 0 {
+                return Err("template not in trash".to_string());
+            }
+            let cascaded: Vec<String> = {
+                let mut stmt = tx
+                    .prepare(
+                        "SELECT id FROM memories WHERE template_id = ? \
+                         AND deleted_at IS NOT NULL AND deleted_with_template = 1",
+                    )
+                    .map_err(|e| e.to_string())?;
+                let rows = stmt
+                    .query_map(params![id], |row| row.get(0))
+                    .map_err(|e| e.to_string())?;
+                rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+            };
+            for memory_id in &cascaded {
+                restore_memory_row(&tx, memory_id, actor)?;
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(cascaded.len())
+        })
+    }
+
+    /// 清理回收站；`older_than_ms` 为 None 时使用默认保留期，0 表示清空
+    pub fn purge_trash(
+        &self,
+        scope_id: Option<String>,
+        older_than_ms: Option<i64>,
+    ) -> Result<TrashPurgeResult, String> {
+        let cutoff = now_ms() - older_than_ms.unwrap_or(TRASH_RETENTION_MS).max(0);
+        self.with_conn(scope_id, |conn| purge_trash(conn, cutoff))
+    }
+
+    /// 全文检索 `row_data` 中的文本值，filters 与 `get_memories` 的条件相同
+    pub fn search_memories(
+        &self,
+        scope_id: Option<String>,
+        query: &str,
+        filters: Option<MemoryQuery>,
+        limit: Option<usize>,
+    ) -> Result<Vec<MemorySearchHit>, String> {
+        let terms = search_terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let limit = limit
+            .unwrap_or(SEARCH_DEFAULT_LIMIT)
+            .clamp(1, SEARCH_MAX_LIMIT);
+        self.with_conn(scope_id, |conn| {
+            let (mut clauses, mut values) = memory_query_clauses(filters)?;
+            let (indexed, short): (Vec<&String>, Vec<&String>) = terms
+                .iter()
+                .partition(|term| term.chars().count() >= SEARCH_MIN_INDEXED_CHARS);
+            let use_index = !indexed.is_empty();
+            if use_index {
+                clauses.insert(0, "memories_fts MATCH ?".to_string());
+                values.insert(0, SqlValue::Text(fts_match_expression(&indexed)));
+            }
+            for term in &short {
+                clauses.push("memories_fts.content LIKE ? ESCAPE '\\'".to_string());
+                values.push(SqlValue::Text(format!("%{}%", escape_like(term))));
+            }
+            let (snippet, score) = if use_index {
+                (
+                    format!(
+                        "snippet(memories_fts, 0, '{SNIPPET_OPEN}', '{SNIPPET_CLOSE}', '…', {})",
+                        SNIPPET_CONTEXT_CHARS * 2
+                    ),
+                    "-bm25(memories_fts)",
+                )
+            } else {
+                ("memories_fts.content".to_string(), "0.0")
+            };
+            let sql = format!(
+                "SELECT {MEMORY_COLUMNS}, {snippet}, {score} AS score \
+                 FROM memories_fts \
+                 JOIN memory_fts_keys ON memory_fts_keys.fts_rowid = memories_fts.rowid \
+                 JOIN memories ON memories.id = memory_fts_keys.memory_id \
+                 WHERE {} ORDER BY score DESC, is_pinned DESC, updated_at DESC LIMIT ?",
+                clauses.join(" AND ")
+            );
+            values.push(SqlValue::Integer(i64::try_from(limit).unwrap_or(i64::MAX)));
+
+            let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map(params_from_iter(values), |row| {
+                    let text: Option<String> = row.get(13)?;
+                    Ok((
+                        memory_from_row(row)?,
+                        text.unwrap_or_default(),
+                        row.get(14)?,
+                    ))
+                })
+                .map_err(|e| e.to_string())?;
+            let mut out = Vec::new();
+            for row in rows {
+                let (memory, text, score) = row.map_err(|e| e.to_string())?;
+                let snippet = if use_index {
+                    text
+                } else {
+                    plain_snippet(&text, short.first().map_or("", |term| term.as_str()))
+                };
+                out.push(MemorySearchHit {
+                    memory,
+                    snippet,
+                    score,
+                });
+            }
+            Ok(out)
+        })
+    }
+
+    /// 尚未生成（或模型不同）向量的记忆行及其文本
+    pub fn pending_embeddings(
+        &self,
+        scope_id: Option<String>,
+        model: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>, String> {
+        self.with_conn(scope_id, |conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT id, row_data FROM memories m WHERE deleted_at IS NULL AND NOT EXISTS \
+                     (SELECT 1 FROM memory_embeddings e WHERE e.memory_id = m.id AND e.model = ?) \
+                     ORDER BY updated_at DESC LIMIT ?",
+                )
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map(
+                    params![model, i64::try_from(limit).unwrap_or(i64::MAX)],
+                    |row| {
+                        let row_data: String = row.get(1)?;
+                        Ok((
+                            row.get(0)?,
+                            embeddings::memory_text(&parse_row_data(row_data)),
+                        ))
+                    },
+                )
+                .map_err(|e| e.to_string())?;
+            let mut out = Vec::new();
+            for row in rows {
+                out.push(row.map_err(|e| e.to_string())?);
+            }
+            Ok(out)
+        })
+    }
+
+    pub fn count_pending_embeddings(
+        &self,
+        scope_id: Option<String>,
+        model: &str,
+    ) -> Result<usize, String> {
+        self.with_conn(scope_id, |conn| {
+            let count: i64 = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM memories m WHERE deleted_at IS NULL AND NOT EXISTS \
+                     (SELECT 1 FROM memory_embeddings e WHERE e.memory_id = m.id AND e.model = ?)",
+                    params![model],
+                    |row| row.get(0),
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(usize::try_from(count).unwrap_or_default())
+        })
+    }
+
+    /// 保存向量（归一化并生成量化副本）；期间被删除的行会被跳过
+    pub fn save_embeddings(
+        &self,
+        scope_id: Option<String>,
+        model: &str,
+        items: &[(String, Vec<f32>)],
+    ) -> Result<usize, String> {
+        self.with_conn(scope_id, |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let mut count = 0;
+            {
+                let mut stmt = tx
+                    .prepare(
+                        "INSERT OR REPLACE INTO memory_embeddings \
+                         (memory_id, model, dim, vector, quantized, scale, embedded_at) \
+                         SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7 WHERE EXISTS (SELECT 1 FROM memories WHERE id = ?1)",
+                    )
+                    .map_err(|e| e.to_string())?;
+                let now = now_ms();
+                for (id, vector) in items {
+                    let vector = embeddings::normalize(vector)
+                        .ok_or_else(|| format!("invalid embedding for {id}"))?;
+                    let quantized = embeddings::quantize(&vector);
+                    count += stmt
+                        .execute(params![
+                            id,
+                            model,
+                            i64::try_from(vector.len()).unwrap_or_default(),
+                            embeddings::encode_vector(&vector),
+                            embeddings::encode_quantized(&quantized.values),
+                            f64::from(quantized.scale),
+                            now
+                        ])
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(count)
+        })
+    }
+
+    /// 按余弦相似度检索；quantized 时先用 int8 向量粗排再以原始向量重排
+    pub fn semantic_search_memories(
+        &self,
+        scope_id: Option<String>,
+        model: &str,
+        query: &[f32],
+        filters: Option<MemoryQuery>,
+        limit: Option<usize>,
+        quantized: bool,
+    ) -> Result<Vec<MemorySearchHit>, String> {
+        let query = embeddings::normalize(query).ok_or("invalid query embedding")?;
+        let limit = limit
+            .unwrap_or(SEARCH_DEFAULT_LIMIT)
+            .clamp(1, SEARCH_MAX_LIMIT);
+        self.with_conn(scope_id, |conn| {
+            let (mut clauses, mut values) = memory_query_clauses(filters)?;
+            clauses.insert(0, "e.model = ? AND e.dim = ?".to_string());
+            values.insert(0, SqlValue::Text(model.to_string()));
+            values.insert(
+                1,
+                SqlValue::Integer(i64::try_from(query.len()).unwrap_or_default()),
+            );
+            let column = if quantized {
+                "e.quantized, e.scale"
+            } else {
+                "e.vector, 0.0"
+            };
+            let sql = format!(
+                "SELECT e.memory_id, {column} FROM memory_embeddings e \
+                 JOIN memories ON memories.id = e.memory_id WHERE {}",
+                clauses.join(" AND ")
+            );
+            let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map(params_from_iter(values), |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, f64>(2)?,
+                    ))
+                })
+                .map_err(|e| e.to_string())?;
+            let query_q = embeddings::quantize(&query);
+            let mut scored = Vec::new();
+            for row in rows {
+                let (id, blob, scale) = row.map_err(|e| e.to_string())?;
+                let score = if quantized {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let scale = scale as f32;
+                    embeddings::quantized_dot(
+                        &query_q.values,
+                        query_q.scale,
+                        &embeddings::decode_quantized(&blob),
+                        scale,
+                    )
+                } else {
+                    embeddings::dot(&query, &embeddings::decode_vector(&blob))
+                };
+                scored.push((id, score));
+            }
+            drop(stmt);
+
+            let mut top = if quantized {
+                let candidates =
+                    embeddings::top_k(scored, limit.saturating_mul(QUANTIZED_RERANK_FACTOR));
+                let mut stmt = conn
+                    .prepare("SELECT vector FROM memory_embeddings WHERE memory_id = ?")
+                    .map_err(|e| e.to_string())?;
+                let mut reranked = Vec::with_capacity(candidates.len());
+                for (id, _) in candidates {
+                    let blob: Vec<u8> = stmt
+                        .query_row(params![id], |row| row.get(0))
+                        .map_err(|e| e.to_string())?;
+                    let score = embeddings::dot(&query, &embeddings::decode_vector(&blob));
+                    reranked.push((id, score));
+                }
+                embeddings::top_k(reranked, limit)
+            } else {
+                embeddings::top_k(scored, limit)
+            };
+
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT {MEMORY_COLUMNS} FROM memories WHERE id = ?"
+                ))
+                .map_err(|e| e.to_string())?;
+            let mut out = Vec::with_capacity(top.len());
+            for (id, score) in top.drain(..) {
+                let memory = stmt
+                    .query_row(params![id], memory_from_row)
+                    .map_err(|e| e.to_string())?;
+                let text = embeddings::memory_text(&memory.row_data);
+                out.push(MemorySearchHit {
+                    snippet: text.chars().take(SNIPPET_CONTEXT_CHARS * 4).collect(),
+                    memory,
+                    score,
+                });
+            }
+            Ok(out)
+        })
+    }
+
+    pub fn batch_create_memories(
+        &self,
+        scope_id: Option<String>,
+        memories: Vec<MemoryCreateInput>,
+    ) -> Result<usize, String> {
+        self.batch_create_memories_as(scope_id, memories, ACTOR_USER)
+    }
+
+    /// 同 `batch_create_memories`，历史中记为 actor 所做的修改
+    pub fn batch_create_memories_as(
+        &self,
+        scope_id: Option<String>,
+        memories: Vec<MemoryCreateInput>,
+        actor: &str,
+    ) -> Result<usize, String> {
+        self.with_conn(scope_id, move |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let mut count = 0;
+            let mut violations = Vec::new();
+            // 逐行校验后写入，后面的行会计入前面已写入行的 maxRows
+            for (index, input) in memories.iter().enumerate() {
+                let row_violations = validate_create(&tx, input)?;
+                if row_violations.is_empty() {
+                    insert_memory(&tx, input, actor)?;
+                    count += 1;
+                } else {
+                    violations.extend(row_violations.into_iter().map(|mut v| {
+                        v.index = Some(index);
+                        v
+                    }));
+                }
+            }
+            if !violations.is_empty() {
+                return Err(MemoryValidationError::into_message(violations));
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(count)
+        })
+    }
+
+    pub fn batch_delete_memories(
+        &self,
+        scope_id: Option<String>,
+        ids: Vec<String>,
+    ) -> Result<usize, String> {
+        self.batch_delete_memories_as(scope_id, ids, ACTOR_USER)
+    }
+
+    /// 同 `batch_delete_memories`，历史中记为 actor 所做的修改
+    pub fn batch_delete_memories_as(
+        &self,
+        scope_id: Option<String>,
+        ids: Vec<String>,
+        actor: &str,
+    ) -> Result<usize, String> {
+        self.with_conn(scope_id, |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let mut count = 0;
+            for id in ids {
+                if delete_memory_row(&tx, &id, now_ms(), actor)? {
+                    count += 1;
+                }
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(count)
+        })
+    }
+
+    fn with_conn<F, T>(&self, scope_id: Option<String>, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, String>,
+    {
+        let scope_key = normalize_scope_id(scope_id);
+        #[cfg(target_os = "android")]
+        {
+            let path = self.db_path_for(&scope_key);
             let existed = path.exists();
             let mut conn = open_connection(&path)?;
             ensure_schema(&mut conn, &path, existed)?;
+            purge_trash(&conn, now_ms() - TRASH_RETENTION_MS)?;
             return f(&mut conn);
         }
         #[cfg(not(target_os = "android"))]
@@ -538,6 +1572,7 @@ impl MemoryDb {
                 let existed = path.exists();
                 let mut conn = open_connection(&path)?;
                 ensure_schema(&mut conn, &path, existed)?;
+                purge_trash(&conn, now_ms() - TRASH_RETENTION_MS)?;
                 guard.insert(scope_key.clone(), conn);
             }
             let conn = guard
@@ -592,21 +1627,22 @@ fn ensure_schema(conn: &mut Connection, path: &Path, existed: bool) -> Result<()
         )
         .optional()
         .map_err(|e| e.to_string())?;
-    let current_version = version
+    let mut current_version = version
         .as_deref()
         .and_then(|v| v.parse::<i64>().ok())
         .unwrap_or(0);
 
+    // 新库先建 v1 基础表，再依次执行迁移
     if current_version == 0 {
         let tx = conn.transaction().map_err(|e| e.to_string())?;
         tx.execute_batch(SCHEMA_SQL).map_err(|e| e.to_string())?;
         tx.execute(
             "INSERT OR REPLACE INTO schema_info (key, value) VALUES (?, ?)",
-            params![SCHEMA_KEY, SCHEMA_VERSION.to_string()],
+            params![SCHEMA_KEY, "1"],
         )
         .map_err(|e| e.to_string())?;
         tx.commit().map_err(|e| e.to_string())?;
-        return Ok(());
+        current_version = 1;
     }
 
     if current_version > SCHEMA_VERSION {
@@ -617,7 +1653,14 @@ fn ensure_schema(conn: &mut Connection, path: &Path, existed: bool) -> Result<()
     }
 
     if current_version < SCHEMA_VERSION {
-        run_migrations(conn, current_version, SCHEMA_VERSION, path, existed)?;
+        run_migrations(
+            conn,
+            MIGRATIONS,
+            current_version,
+            SCHEMA_VERSION,
+            path,
+            existed,
+        )?;
     }
 
     if existed {
@@ -627,36 +1670,943 @@ fn ensure_schema(conn: &mut Connection, path: &Path, existed: bool) -> Result<()
     Ok(())
 }
 
-fn generate_id() -> String {
-    let now = now_ms();
-    let seq = ID_COUNTER.fetch_add(1, Ordering::Relaxed) % 1000;
-    format!("mem_{}_{}", now, seq)
-}
-
-fn now_ms() -> i64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis() as i64
+/// 读取模板中对应表的约束；模板或表不存在时记入 violations 并返回 None
+fn template_table_rules(
+    conn: &Connection,
+    template_id: &str,
+    table_id: &str,
+    violations: &mut Vec<SchemaViolation>,
+) -> Result<Option<memory_validation::TableRules>, String> {
+    let schema: Option<String> = conn
+        .query_row(
+            "SELECT schema FROM templates WHERE id = ? AND deleted_at IS NULL",
+            params![template_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let Some(schema) = schema else {
+        violations.push(SchemaViolation {
+            code: ViolationCode::UnknownTemplate,
+            table_id: table_id.to_string(),
+            column: None,
+            index: None,
+            message: format!("template {template_id} not found"),
+        });
+        return Ok(None);
+    };
+    match memory_validation::table_rules(&parse_json_value(schema), table_id) {
+        Ok(rules) => Ok(rules),
+        Err(violation) => {
+            violations.push(violation);
+            Ok(None)
+        }
+    }
 }
 
-fn bool_to_int(value: bool) -> i64 {
-    if value {
-        1
-    } else {
-        0
+/// 新建行的校验：表与列、scope，以及同一 scope 下的 maxRows
+fn validate_create(
+    conn: &Connection,
+    input: &MemoryCreateInput,
+) -> Result<Vec<SchemaViolation>, String> {
+    let mut violations = Vec::new();
+    let Some(rules) =
+        template_table_rules(conn, &input.template_id, &input.table_id, &mut violations)?
+    else {
+        return Ok(violations);
+    };
+    violations.extend(memory_validation::check_scope(
+        &rules,
+        input.contact_id.as_deref(),
+        input.group_id.as_deref(),
+    ));
+    violations.extend(memory_validation::check_row(&rules, &input.row_data));
+    if let Some(max_rows) = rules.max_rows {
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM memories WHERE template_id = ? AND table_id = ? \
+                 AND contact_id IS ? AND group_id IS ? AND deleted_at IS NULL",
+                params![
+                    input.template_id,
+                    input.table_id,
+                    input.contact_id,
+                    input.group_id
+                ],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if usize::try_from(count).unwrap_or_default() >= max_rows {
+            violations.push(SchemaViolation {
+                code: ViolationCode::MaxRowsExceeded,
+                table_id: input.table_id.clone(),
+                column: None,
+                index: None,
+                message: format!("table {} allows at most {max_rows} rows", input.table_id),
+            });
+        }
     }
+    Ok(violations)
 }
 
-fn parse_row_data(raw: String) -> serde_json::Value {
-    serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw))
+/// 修改已有行的 `row_data` 时只校验列
+fn validate_row_data(
+    conn: &Connection,
+    memory: &MemoryRecord,
+    row_data: &serde_json::Value,
+) -> Result<Vec<SchemaViolation>, String> {
+    let mut violations = Vec::new();
+    if let Some(rules) =
+        template_table_rules(conn, &memory.template_id, &memory.table_id, &mut violations)?
+    {
+        violations.extend(memory_validation::check_row(&rules, row_data));
+    }
+    Ok(violations)
 }
 
-fn parse_json_value(raw: String) -> serde_json::Value {
-    serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw))
-}
+/// 按 input 中提供的字段更新一行（不做 schema 校验）并记录历史
+fn update_memory_row(
+    conn: &Connection,
+    before: &MemoryRecord,
+    input: &MemoryUpdateInput,
+    actor: &str,
+) -> Result<(), String> {
+    let mut sets: Vec<String> = Vec::new();
+    let mut values: Vec<SqlValue> = Vec::new();
 
-fn parse_json_optional(raw: Option<String>) -> Option<serde_json::Value> {
+    if let Some(row_data) = &input.row_data {
+        let raw = serde_json::to_string(row_data).map_err(|e| e.to_string())?;
+        sets.push("row_data = ?".to_string());
+        values.push(SqlValue::Text(raw));
+    }
+    if let Some(is_active) = input.is_active {
+        sets.push("is_active = ?".to_string());
+        values.push(SqlValue::Integer(bool_to_int(is_active)));
+    }
+    if let Some(is_pinned) = input.is_pinned {
+        sets.push("is_pinned = ?".to_string());
+        values.push(SqlValue::Integer(bool_to_int(is_pinned)));
+    }
+    if let Some(priority) = input.priority {
+        sets.push("priority = ?".to_string());
+        values.push(SqlValue::Integer(priority));
+    }
+    if let Some(sort_order) = input.sort_order {
+        sets.push("sort_order = ?".to_string());
+        values.push(SqlValue::Integer(sort_order));
+    }
+
+    if sets.is_empty() {
+        return Err("no fields to update".to_string());
+    }
+
+    sets.push("updated_at = ?".to_string());
+    values.push(SqlValue::Integer(now_ms()));
+    values.push(SqlValue::Text(before.id.clone()));
+
+    let sql = format!("UPDATE memories SET {} WHERE id = ?", sets.join(", "));
+    conn.execute(&sql, params_from_iter(values))
+        .map_err(|e| e.to_string())?;
+    let after = load_memory(conn, &before.id)?;
+    record_history(conn, Some(before), after.as_ref(), actor)
+}
+
+/// 单条操作失败的原因
+struct MemoryOpFailure {
+    id: Option<String>,
+    message: String,
+    violations: Vec<SchemaViolation>,
+}
+
+impl MemoryOpFailure {
+    fn new(id: Option<&str>, message: impl Into<String>) -> Self {
+        Self {
+            id: id.map(String::from),
+            message: message.into(),
+            violations: Vec::new(),
+        }
+    }
+
+    fn invalid(id: Option<&str>, violations: Vec<SchemaViolation>) -> Self {
+        Self {
+            violations,
+            ..Self::new(id, "memory validation failed")
+        }
+    }
+}
+
+fn apply_memory_op(
+    conn: &Connection,
+    op: MemoryOp,
+    actor: &str,
+) -> Result<(MemoryOpStatus, String), MemoryOpFailure> {
+    match op {
+        MemoryOp::Insert(input) => insert_checked(conn, &input, actor),
+        MemoryOp::Upsert(input) => {
+            let id = input
+                .id
+                .as_deref()
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .ok_or_else(|| MemoryOpFailure::new(None, "upsert requires id"))?;
+            let fail = |message: String| MemoryOpFailure::new(Some(id), message);
+            let Some(before) = load_memory(conn, id).map_err(fail)? else {
+                return insert_checked(conn, &input, actor);
+            };
+            if before.template_id != input.template_id || before.table_id != input.table_id {
+                return Err(fail(
+                    "upsert cannot move a row to another table".to_string(),
+                ));
+            }
+            let update = MemoryUpdateInput {
+                id: id.to_string(),
+                row_data: Some(input.row_data),
+                is_active: input.is_active,
+                is_pinned: input.is_pinned,
+                priority: input.priority,
+                sort_order: input.sort_order,
+            };
+            update_checked(conn, &before, &update, actor)
+        }
+        MemoryOp::Update(mut input) => {
+            let id = input.id.clone();
+            let before = load_memory(conn, &id)
+                .map_err(|e| MemoryOpFailure::new(Some(&id), e))?
+                .ok_or_else(|| MemoryOpFailure::new(Some(&id), "memory not found"))?;
+            // 部分更新：row_data 按列合并，值为 null 的列被移除
+            if let Some(patch) = input.row_data.take() {
+                input.row_data = Some(merge_row_data(&before.row_data, patch));
+            }
+            update_checked(conn, &before, &input, actor)
+        }
+        MemoryOp::Delete { id } => match delete_memory_row(conn, &id, now_ms(), actor) {
+            Ok(true) => Ok((MemoryOpStatus::Deleted, id)),
+            Ok(false) => Err(MemoryOpFailure::new(Some(&id), "memory not found")),
+            Err(err) => Err(MemoryOpFailure::new(Some(&id), err)),
+        },
+    }
+}
+
+fn insert_checked(
+    conn: &Connection,
+    input: &MemoryCreateInput,
+    actor: &str,
+) -> Result<(MemoryOpStatus, String), MemoryOpFailure> {
+    let id = input.id.as_deref();
+    let violations = validate_create(conn, input).map_err(|e| MemoryOpFailure::new(id, e))?;
+    if !violations.is_empty() {
+        return Err(MemoryOpFailure::invalid(id, violations));
+    }
+    insert_memory(conn, input, actor)
+        .map(|id| (MemoryOpStatus::Inserted, id))
+        .map_err(|e| MemoryOpFailure::new(id, e))
+}
+
+fn update_checked(
+    conn: &Connection,
+    before: &MemoryRecord,
+    input: &MemoryUpdateInput,
+    actor: &str,
+) -> Result<(MemoryOpStatus, String), MemoryOpFailure> {
+    let id = Some(before.id.as_str());
+    if let Some(row_data) = &input.row_data {
+        let violations =
+            validate_row_data(conn, before, row_data).map_err(|e| MemoryOpFailure::new(id, e))?;
+        if !violations.is_empty() {
+            return Err(MemoryOpFailure::invalid(id, violations));
+        }
+    }
+    update_memory_row(conn, before, input, actor)
+        .map(|()| (MemoryOpStatus::Updated, before.id.clone()))
+        .map_err(|e| MemoryOpFailure::new(id, e))
+}
+
+fn merge_row_data(base: &serde_json::Value, patch: serde_json::Value) -> serde_json::Value {
+    let (Some(base), serde_json::Value::Object(patch)) = (base.as_object(), &patch) else {
+        return patch;
+    };
+    let mut merged = base.clone();
+    for (key, value) in patch {
+        if value.is_null() {
+            merged.remove(key);
+        } else {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+    serde_json::Value::Object(merged)
+}
+
+fn insert_memory(
+    conn: &Connection,
+    input: &MemoryCreateInput,
+    actor: &str,
+) -> Result<String, String> {
+    insert_memory_at(conn, input, now_ms(), actor)
+}
+
+fn insert_memory_at(
+    conn: &Connection,
+    input: &MemoryCreateInput,
+    created_at: i64,
+    actor: &str,
+) -> Result<String, String> {
+    let now = now_ms();
+    let id = input
+        .id
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map_or_else(generate_id, String::from);
+    let row_data = serde_json::to_string(&input.row_data).map_err(|e| e.to_string())?;
+    let is_active = bool_to_int(input.is_active.unwrap_or(true));
+    let is_pinned = bool_to_int(input.is_pinned.unwrap_or(false));
+    let priority = input.priority.unwrap_or(0);
+    let sort_order = input.sort_order.unwrap_or(0);
+    // 回收站里的同 id 旧行被新行取代
+    conn.execute(
+        "DELETE FROM memories WHERE id = ? AND deleted_at IS NOT NULL",
+        params![id],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO memories (id, template_id, table_id, contact_id, group_id, row_data, is_active, is_pinned, priority, sort_order, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            id,
+            input.template_id,
+            input.table_id,
+            input.contact_id,
+            input.group_id,
+            row_data,
+            is_active,
+            is_pinned,
+            priority,
+            sort_order,
+            created_at,
+            now
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    let created = load_memory(conn, &id)?;
+    record_history(conn, None, created.as_ref(), actor)?;
+    Ok(id)
+}
+
+/// 把一行移入回收站并记录历史；行不存在或已在回收站时返回 false
+fn delete_memory_row(
+    conn: &Connection,
+    id: &str,
+    deleted_at: i64,
+    actor: &str,
+) -> Result<bool, String> {
+    let Some(before) = load_memory(conn, id)? else {
+        return Ok(false);
+    };
+    conn.execute(
+        "UPDATE memories SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2",
+        params![deleted_at, id],
+    )
+    .map_err(|e| e.to_string())?;
+    record_history(conn, Some(&before), None, actor)?;
+    Ok(true)
+}
+
+/// 把回收站中的一行移回，并记录为一次创建
+fn restore_memory_row(conn: &Connection, id: &str, actor: &str) -> Result<(), String> {
+    conn.execute(
+        "UPDATE memories SET deleted_at = NULL, deleted_with_template = 0, updated_at = ? \
+         WHERE id = ?",
+        params![now_ms(), id],
+    )
+    .map_err(|e| e.to_string())?;
+    let restored = load_memory(conn, id)?;
+    record_history(conn, None, restored.as_ref(), actor)
+}
+
+/// 读取一行（回收站中的行视为不存在）
+fn load_memory(conn: &Connection, id: &str) -> Result<Option<MemoryRecord>, String> {
+    conn.query_row(
+        &format!("SELECT {MEMORY_COLUMNS} FROM memories WHERE id = ? AND deleted_at IS NULL"),
+        params![id],
+        memory_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+/// 写入一条变更历史；op 由前后状态推出（create / update / delete）
+fn record_history(
+    conn: &Connection,
+    before: Option<&MemoryRecord>,
+    after: Option<&MemoryRecord>,
+    actor: &str,
+) -> Result<(), String> {
+    let (op, identity) = match (before, after) {
+        (None, Some(after)) => ("create", after),
+        (Some(before), Some(_)) => ("update", before),
+        (Some(before), None) => ("delete", before),
+        (None, None) => return Ok(()),
+    };
+    let data = |record: Option<&MemoryRecord>| -> Result<Option<String>, String> {
+        record
+            .map(|r| serde_json::to_string(&r.row_data).map_err(|e| e.to_string()))
+            .transpose()
+    };
+    let state = |record: Option<&MemoryRecord>| -> Result<Option<String>, String> {
+        record
+            .map(|r| serde_json::to_string(&MemoryState::of(r)).map_err(|e| e.to_string()))
+            .transpose()
+    };
+    conn.execute(
+        "INSERT INTO memory_history (memory_id, op, template_id, table_id, contact_id, group_id, old_data, new_data, actor, created_at, old_state, new_state)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            identity.id,
+            op,
+            identity.template_id,
+            identity.table_id,
+            identity.contact_id,
+            identity.group_id,
+            data(before)?,
+            data(after)?,
+            actor,
+            now_ms(),
+            state(before)?,
+            state(after)?
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn load_history(conn: &Connection, id: i64) -> Result<Option<MemoryHistoryEntry>, String> {
+    conn.query_row(
+        &format!("SELECT {HISTORY_COLUMNS} FROM memory_history WHERE id = ?"),
+        params![id],
+        history_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+fn history_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<MemoryHistoryEntry> {
+    let old_data: Option<String> = row.get(7)?;
+    let new_data: Option<String> = row.get(8)?;
+    let state = |index: usize| -> rusqlite::Result<Option<MemoryState>> {
+        let raw: Option<String> = row.get(index)?;
+        Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+    };
+    Ok(MemoryHistoryEntry {
+        id: row.get(0)?,
+        memory_id: row.get(1)?,
+        op: row.get(2)?,
+        template_id: row.get(3)?,
+        table_id: row.get(4)?,
+        contact_id: row.get(5)?,
+        group_id: row.get(6)?,
+        old_data: old_data.map(parse_row_data),
+        new_data: new_data.map(parse_row_data),
+        actor: row.get(9)?,
+        created_at: row.get(10)?,
+        undone_at: row.get(11)?,
+        old_state: state(12)?,
+        new_state: state(13)?,
+    })
+}
+
+/// 把历史记录对应的行恢复为 target（None 表示该行不存在），并记录这次变更；
+/// state 为 None（v6 之前的记录）时保留行的当前标记
+fn restore_memory_state(
+    conn: &Connection,
+    entry: &MemoryHistoryEntry,
+    target: Option<&serde_json::Value>,
+    state: Option<&MemoryState>,
+    actor: &str,
+) -> Result<(), String> {
+    let current = load_memory(conn, &entry.memory_id)?;
+    match (current, target) {
+        (Some(_), None) => {
+            delete_memory_row(conn, &entry.memory_id, now_ms(), actor)?;
+        }
+        (Some(before), Some(row_data)) => {
+            write_memory_state(conn, &entry.memory_id, row_data, state, false)?;
+            let after = load_memory(conn, &entry.memory_id)?;
+            record_history(conn, Some(&before), after.as_ref(), actor)?;
+        }
+        (None, Some(row_data)) => {
+            // 软删除的行仍在回收站：原地恢复，保留关联的向量
+            if write_memory_state(conn, &entry.memory_id, row_data, state, true)? {
+                return restore_memory_row(conn, &entry.memory_id, actor);
+            }
+            let input = MemoryCreateInput {
+                id: Some(entry.memory_id.clone()),
+                template_id: entry.template_id.clone(),
+                table_id: entry.table_id.clone(),
+                contact_id: entry.contact_id.clone(),
+                group_id: entry.group_id.clone(),
+                row_data: row_data.clone(),
+                is_active: state.map(|s| s.is_active),
+                is_pinned: state.map(|s| s.is_pinned),
+                priority: state.map(|s| s.priority),
+                sort_order: state.map(|s| s.sort_order),
+            };
+            let created_at = state.map_or_else(now_ms, |s| s.created_at);
+            insert_memory_at(conn, &input, created_at, actor)?;
+        }
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// 覆写一行的 `row_data` 与状态字段；trashed 为 true 时只作用于回收站中的行。
+/// 返回是否有行被修改
+fn write_memory_state(
+    conn: &Connection,
+    id: &str,
+    row_data: &serde_json::Value,
+    state: Option<&MemoryState>,
+    trashed: bool,
+) -> Result<bool, String> {
+    let raw = serde_json::to_string(row_data).map_err(|e| e.to_string())?;
+    let deleted = if trashed { "IS NOT NULL" } else { "IS NULL" };
+    let affected = conn
+        .execute(
+            &format!(
+                "UPDATE memories SET row_data = ?, is_active = COALESCE(?, is_active), \
+                 is_pinned = COALESCE(?, is_pinned), priority = COALESCE(?, priority), \
+                 sort_order = COALESCE(?, sort_order), created_at = COALESCE(?, created_at), \
+                 updated_at = ? WHERE id = ? AND deleted_at {deleted}"
+            ),
+            params![
+                raw,
+                state.map(|s| bool_to_int(s.is_active)),
+                state.map(|s| bool_to_int(s.is_pinned)),
+                state.map(|s| s.priority),
+                state.map(|s| s.sort_order),
+                state.map(|s| s.created_at),
+                now_ms(),
+                id
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(affected > 0)
+}
+
+struct UpgradePlan {
+    existing: TemplateRecord,
+    diff: SchemaDiff,
+    rows: Vec<UpgradePlanRow>,
+}
+
+struct UpgradePlanRow {
+    before: MemoryRecord,
+    after: serde_json::Value,
+    changed: bool,
+    violations: Vec<SchemaViolation>,
+}
+
+/// 计算升级计划：校验映射，按新 schema 转换并校验该模板下的每一行
+fn plan_template_upgrade(
+    conn: &Connection,
+    input: &TemplateUpgradeInput,
+) -> Result<UpgradePlan, String> {
+    let template = &input.template;
+    let existing = conn
+        .query_row(
+            &format!(
+                "SELECT {TEMPLATE_COLUMNS} FROM templates WHERE id = ? AND deleted_at IS NULL"
+            ),
+            params![template.id],
+            template_from_row,
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "template not found".to_string())?;
+    let diff = memory_template_upgrade::diff_schemas(&existing.schema, &template.schema);
+    memory_template_upgrade::check_mappings(&diff, &template.schema, &input.mappings)?;
+
+    // 回收站中的行一并迁移，恢复后仍符合新 schema
+    let records = template_memories(conn, &template.id)?;
+    let stranded = records
+        .iter()
+        .filter(|r| diff.removed_tables.contains(&r.table_id))
+        .count();
+    if stranded > 0 {
+        return Err(format!(
+            "removed tables still have {stranded} memories (including trash); \
+             delete and purge them first"
+        ));
+    }
+
+    let mut rows = Vec::with_capacity(records.len());
+    for before in records {
+        let mapping = input
+            .mappings
+            .iter()
+            .find(|m| m.table_id == before.table_id);
+        let (after, overwritten) = memory_template_upgrade::map_row(&before.row_data, mapping);
+        let mut violations = row_violations(&template.schema, &before, &after);
+        violations.extend(overwritten.into_iter().map(|column| {
+            SchemaViolation::new(
+                ViolationCode::RenameConflict,
+                &before.table_id,
+                Some(&column),
+                format!("column {column} already has a value; renaming onto it would drop one"),
+            )
+        }));
+        rows.push(UpgradePlanRow {
+            changed: after != before.row_data,
+            before,
+            after,
+            violations,
+        });
+    }
+    Ok(UpgradePlan {
+        existing,
+        diff,
+        rows,
+    })
+}
+
+/// 按新 schema 校验一行（映射后的 `row_data` 与原有的联系人/群组范围）
+fn row_violations(
+    schema: &serde_json::Value,
+    record: &MemoryRecord,
+    row_data: &serde_json::Value,
+) -> Vec<SchemaViolation> {
+    let mut violations = Vec::new();
+    match memory_validation::table_rules(schema, &record.table_id) {
+        Ok(Some(rules)) => {
+            violations.extend(memory_validation::check_scope(
+                &rules,
+                record.contact_id.as_deref(),
+                record.group_id.as_deref(),
+            ));
+            violations.extend(memory_validation::check_row(&rules, row_data));
+        }
+        Ok(None) => {}
+        Err(violation) => violations.push(violation),
+    }
+    violations
+}
+
+/// 模板下的所有记忆，含回收站中的行
+fn template_memories(conn: &Connection, template_id: &str) -> Result<Vec<MemoryRecord>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {MEMORY_COLUMNS} FROM memories WHERE template_id = ? ORDER BY table_id, id"
+        ))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![template_id], memory_from_row)
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+/// 直接覆盖模板前的检查：模板下已有记忆（含回收站）且 schema 有变化时只允许新增表和列，
+/// 且现有行在新 schema 下仍然有效；其余改动需要 `upgrade_template` 迁移
+fn check_template_overwrite(
+    conn: &Connection,
+    existing: &TemplateRecord,
+    schema: &serde_json::Value,
+) -> Result<(), String> {
+    let records = template_memories(conn, &existing.id)?;
+    if records.is_empty() || existing.schema == *schema {
+        return Ok(());
+    }
+    let diff = memory_template_upgrade::diff_schemas(&existing.schema, schema);
+    if !diff.is_additive() {
+        return Err(format!(
+            "template {} has {} memories and the new schema removes tables or columns; \
+             use upgrade_template to migrate them",
+            existing.id,
+            records.len()
+        ));
+    }
+    let violations: Vec<SchemaViolation> = records
+        .iter()
+        .flat_map(|record| row_violations(schema, record, &record.row_data))
+        .collect();
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(MemoryValidationError::into_message(violations))
+    }
+}
+
+fn upsert_template(conn: &Connection, input: &TemplateInput) -> Result<(), String> {
+    let now = now_ms();
+    let schema = serde_json::to_string(&input.schema).map_err(|e| e.to_string())?;
+    let injection = match &input.injection {
+        Some(value) => Some(serde_json::to_string(value).map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let is_default = bool_to_int(input.is_default.unwrap_or(false));
+    let is_builtin = bool_to_int(input.is_builtin.unwrap_or(false));
+
+    conn.execute(
+        "INSERT INTO templates (id, name, author, version, description, schema, injection, created_at, updated_at, is_default, is_builtin)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+           name = excluded.name,
+           author = excluded.author,
+           version = excluded.version,
+           description = excluded.description,
+           schema = excluded.schema,
+           injection = excluded.injection,
+           updated_at = excluded.updated_at,
+           is_default = excluded.is_default,
+           is_builtin = excluded.is_builtin,
+           deleted_at = NULL",
+        params![
+            input.id,
+            input.name,
+            input.author,
+            input.version,
+            input.description,
+            schema,
+            injection,
+            now,
+            now,
+            is_default,
+            is_builtin
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn template_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<TemplateRecord> {
+    let schema_raw: String = row.get(5)?;
+    let injection_raw: Option<String> = row.get(6)?;
+    Ok(TemplateRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        author: row.get(2)?,
+        version: row.get(3)?,
+        description: row.get(4)?,
+        schema: parse_json_value(schema_raw),
+        injection: parse_json_optional(injection_raw),
+        created_at: row.get(7)?,
+        updated_at: row.get(8)?,
+        is_default: row.get::<_, i64>(9)? != 0,
+        is_builtin: row.get::<_, i64>(10)? != 0,
+        deleted_at: row.get(11)?,
+    })
+}
+
+/// 彻底删除 `deleted_at` 不晚于 cutoff 的回收站内容；仍被记忆引用的模板保留
+fn purge_trash(conn: &Connection, cutoff: i64) -> Result<TrashPurgeResult, String> {
+    let memories = conn
+        .execute(
+            "DELETE FROM memories WHERE deleted_at IS NOT NULL AND deleted_at <= ?",
+            params![cutoff],
+        )
+        .map_err(|e| e.to_string())?;
+    let templates = conn
+        .execute(
+            "DELETE FROM templates WHERE deleted_at IS NOT NULL AND deleted_at <= ? \
+             AND NOT EXISTS (SELECT 1 FROM memories WHERE memories.template_id = templates.id)",
+            params![cutoff],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(TrashPurgeResult {
+        memories,
+        templates,
+    })
+}
+
+fn memory_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<MemoryRecord> {
+    let row_data: String = row.get(5)?;
+    Ok(MemoryRecord {
+        id: row.get(0)?,
+        template_id: row.get(1)?,
+        table_id: row.get(2)?,
+        contact_id: row.get(3)?,
+        group_id: row.get(4)?,
+        row_data: parse_row_data(row_data),
+        is_active: row.get::<_, i64>(6)? != 0,
+        is_pinned: row.get::<_, i64>(7)? != 0,
+        priority: row.get(8)?,
+        sort_order: row.get(9)?,
+        created_at: row.get(10)?,
+        updated_at: row.get(11)?,
+        deleted_at: row.get(12)?,
+    })
+}
+
+/// `MemoryQuery` 对应的 WHERE 条件（列名不带表前缀）
+fn memory_query_clauses(
+    query: Option<MemoryQuery>,
+) -> Result<(Vec<String>, Vec<SqlValue>), String> {
+    // 回收站中的行不参与任何查询
+    let mut clauses: Vec<String> = vec!["deleted_at IS NULL".to_string()];
+    let mut values: Vec<SqlValue> = Vec::new();
+    let Some(query) = query else {
+        return Ok((clauses, values));
+    };
+
+    let scope = query.scope.unwrap_or_default().trim().to_lowercase();
+    if scope == "global" {
+        clauses.push("contact_id IS NULL AND group_id IS NULL".to_string());
+    } else if scope == "contact" {
+        let contact_id = query
+            .contact_id
+            .ok_or_else(|| "contact scope requires contact_id".to_string())?;
+        clauses.push("contact_id = ?".to_string());
+        values.push(SqlValue::Text(contact_id));
+    } else if scope == "group" {
+        let group_id = query
+            .group_id
+            .ok_or_else(|| "group scope requires group_id".to_string())?;
+        clauses.push("group_id = ?".to_string());
+        values.push(SqlValue::Text(group_id));
+    } else {
+        if let Some(contact_id) = query.contact_id {
+            clauses.push("contact_id = ?".to_string());
+            values.push(SqlValue::Text(contact_id));
+        }
+        if let Some(group_id) = query.group_id {
+            clauses.push("group_id = ?".to_string());
+            values.push(SqlValue::Text(group_id));
+        }
+    }
+    if let Some(template_id) = query.template_id {
+        clauses.push("template_id = ?".to_string());
+        values.push(SqlValue::Text(template_id));
+    }
+    if let Some(table_id) = query.table_id {
+        clauses.push("table_id = ?".to_string());
+        values.push(SqlValue::Text(table_id));
+    }
+    if let Some(is_active) = query.is_active {
+        clauses.push("is_active = ?".to_string());
+        values.push(SqlValue::Integer(bool_to_int(is_active)));
+    }
+    if let Some(is_pinned) = query.is_pinned {
+        clauses.push("is_pinned = ?".to_string());
+        values.push(SqlValue::Integer(bool_to_int(is_pinned)));
+    }
+    Ok((clauses, values))
+}
+
+/// 分页游标：排序键取值的 JSON 数组，base64url 编码
+fn encode_cursor(values: &[serde_json::Value]) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::Value::from(values.to_vec()).to_string())
+}
+
+fn decode_cursor(cursor: &str, len: usize) -> Result<Vec<SqlValue>, String> {
+    let invalid = || "invalid cursor".to_string();
+    let raw = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let values: Vec<serde_json::Value> = serde_json::from_slice(&raw).map_err(|_| invalid())?;
+    if values.len() != len {
+        return Err(invalid());
+    }
+    values
+        .into_iter()
+        .map(|value| match value {
+            serde_json::Value::Number(n) => n.as_i64().map(SqlValue::Integer).ok_or_else(invalid),
+            serde_json::Value::String(s) => Ok(SqlValue::Text(s)),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+fn search_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for term in query.split_whitespace() {
+        if !terms.iter().any(|t| t == term) {
+            terms.push(term.to_string());
+        }
+        if terms.len() >= SEARCH_MAX_TERMS {
+            break;
+        }
+    }
+    terms
+}
+
+/// 每个词作为短语匹配，避免用户输入被解析为 FTS5 语法
+fn fts_match_expression(terms: &[&String]) -> String {
+    terms
+        .iter()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn escape_like(term: &str) -> String {
+    let mut out = String::with_capacity(term.len());
+    for ch in term.chars() {
+        if matches!(ch, '%' | '_' | '\\') {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+    out
+}
+
+/// 不走索引时在 Rust 中截取命中位置附近的文本
+fn plain_snippet(content: &str, term: &str) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let lower = |c: char| c.to_lowercase().next().unwrap_or(c);
+    let needle: Vec<char> = term.chars().map(lower).collect();
+    let lowered: Vec<char> = chars.iter().copied().map(lower).collect();
+    let Some(pos) = (!needle.is_empty() && needle.len() <= lowered.len())
+        .then(|| {
+            lowered
+                .windows(needle.len())
+                .position(|window| window == needle.as_slice())
+        })
+        .flatten()
+    else {
+        return chars.iter().take(SNIPPET_CONTEXT_CHARS * 2).collect();
+    };
+    let start = pos.saturating_sub(SNIPPET_CONTEXT_CHARS);
+    let end = (pos + needle.len() + SNIPPET_CONTEXT_CHARS).min(chars.len());
+    let mut out = String::new();
+    if start > 0 {
+        out.push('…');
+    }
+    out.extend(&chars[start..pos]);
+    out.push_str(SNIPPET_OPEN);
+    out.extend(&chars[pos..pos + needle.len()]);
+    out.push_str(SNIPPET_CLOSE);
+    out.extend(&chars[pos + needle.len()..end]);
+    if end < chars.len() {
+        out.push('…');
+    }
+    out
+}
+
+fn generate_id() -> String {
+    let now = now_ms();
+    let seq = ID_COUNTER.fetch_add(1, Ordering::Relaxed) % 1000;
+    format!("mem_{}_{}", now, seq)
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+fn bool_to_int(value: bool) -> i64 {
+    if value {
+        1
+    } else {
+        0
+    }
+}
+
+fn parse_row_data(raw: String) -> serde_json::Value {
+    serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw))
+}
+
+fn parse_json_value(raw: String) -> serde_json::Value {
+    serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw))
+}
+
+fn parse_json_optional(raw: Option<String>) -> Option<serde_json::Value> {
     raw.and_then(|value| serde_json::from_str(&value).ok())
 }
 
@@ -680,8 +2630,10 @@ fn normalize_scope_id(scope_id: Option<String>) -> String {
     out.trim_matches('_').to_string()
 }
 
+/// 依次执行迁移；任一步骤或迁移后的完整性检查失败时，从迁移前的备份整体恢复
 fn run_migrations(
     conn: &mut Connection,
+    migrations: &[Migration],
     current_version: i64,
     target_version: i64,
     path: &Path,
@@ -690,44 +2642,91 @@ fn run_migrations(
     if current_version >= target_version {
         return Ok(());
     }
+    // 先确认迁移路径完整，避免执行到一半才发现缺失
+    let mut plan = Vec::new();
     let mut version = current_version;
-    let mut backed_up = false;
     while version < target_version {
         let next_version = version + 1;
-        let migration = MIGRATIONS
+        let migration = migrations
             .iter()
             .find(|m| m.from == version && m.to == next_version)
             .ok_or_else(|| format!("missing migration path: {} -> {}", version, next_version))?;
-        if existed && !backed_up {
-            let _ = backup_database(path, "pre_migrate")?;
-            backed_up = true;
-        }
-        let tx = conn.transaction().map_err(|e| e.to_string())?;
-        tx.execute_batch(migration.sql).map_err(|e| e.to_string())?;
-        tx.execute(
-            "INSERT OR REPLACE INTO schema_info (key, value) VALUES (?, ?)",
-            params![SCHEMA_KEY, next_version.to_string()],
-        )
-        .map_err(|e| e.to_string())?;
-        tx.commit().map_err(|e| e.to_string())?;
+        plan.push(migration);
         version = next_version;
     }
-    Ok(())
+
+    let backup = if existed {
+        Some(backup_database(conn, path, "pre_migrate")?)
+    } else {
+        None
+    };
+    let result = plan
+        .iter()
+        .try_for_each(|migration| apply_migration(conn, migration))
+        .and_then(|()| integrity_check(conn, "integrity_check"))
+        .and_then(|()| foreign_key_check(conn));
+    let Err(err) = result else {
+        prune_backups(path, "pre_migrate", MIGRATION_BACKUPS_KEPT);
+        return Ok(());
+    };
+    match backup {
+        Some(backup) => {
+            conn.restore(DatabaseName::Main, &backup, None::<fn(Progress)>)
+                .map_err(|e| format!("memory db migration failed: {err}; restore failed: {e}"))?;
+            Err(format!(
+                "memory db migration failed (restored from {}): {err}",
+                backup.display()
+            ))
+        }
+        None => Err(format!("memory db migration failed: {err}")),
+    }
+}
+
+fn apply_migration(conn: &mut Connection, migration: &Migration) -> Result<(), String> {
+    // foreign_keys 在事务内设置无效，必须在开启事务前切换
+    if migration.rebuilds_tables {
+        conn.pragma_update(None, "foreign_keys", "OFF")
+            .map_err(|e| e.to_string())?;
+    }
+    let result = apply_migration_tx(conn, migration);
+    if migration.rebuilds_tables {
+        conn.pragma_update(None, "foreign_keys", "ON")
+            .map_err(|e| e.to_string())?;
+    }
+    result.map_err(|e| format!("migration {} -> {}: {e}", migration.from, migration.to))
+}
+
+fn apply_migration_tx(conn: &mut Connection, migration: &Migration) -> Result<(), String> {
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    match migration.step {
+        MigrationStep::Sql(sql) => tx.execute_batch(sql).map_err(|e| e.to_string())?,
+        MigrationStep::Rust(apply) => apply(&tx)?,
+    }
+    if migration.rebuilds_tables {
+        foreign_key_check(&tx)?;
+    }
+    tx.execute(
+        "INSERT OR REPLACE INTO schema_info (key, value) VALUES (?, ?)",
+        params![SCHEMA_KEY, migration.to.to_string()],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
 }
 
 fn ensure_db_health(conn: &mut Connection, path: &Path) -> Result<(), String> {
-    match quick_check(conn) {
+    match integrity_check(conn, "quick_check") {
         Ok(()) => Ok(()),
         Err(err) => {
-            let _ = backup_database(path, "corrupt")?;
+            let _ = backup_database(conn, path, "corrupt")?;
             Err(format!("memory db integrity check failed: {}", err))
         }
     }
 }
 
-fn quick_check(conn: &mut Connection) -> Result<(), String> {
+/// 执行 `quick_check` / `integrity_check`，结果不是 ok 时返回第一条问题
+fn integrity_check(conn: &Connection, pragma: &str) -> Result<(), String> {
     let mut stmt = conn
-        .prepare("PRAGMA quick_check")
+        .prepare(&format!("PRAGMA {pragma}"))
         .map_err(|e| e.to_string())?;
     let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
     while let Some(row) = rows.next().map_err(|e| e.to_string())? {
@@ -739,7 +2738,22 @@ fn quick_check(conn: &mut Connection) -> Result<(), String> {
     Ok(())
 }
 
-fn backup_database(path: &Path, label: &str) -> Result<PathBuf, String> {
+fn foreign_key_check(conn: &Connection) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare("PRAGMA foreign_key_check")
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+    if let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let table: String = row.get(0).map_err(|e| e.to_string())?;
+        let parent: String = row.get(2).map_err(|e| e.to_string())?;
+        return Err(format!("foreign key violation: {table} -> {parent}"));
+    }
+    Ok(())
+}
+
+/// 用 `SQLite` 在线备份接口复制数据库（包含尚未 checkpoint 的 WAL 内容）；
+/// 库已损坏导致备份失败时退回直接复制文件
+fn backup_database(conn: &Connection, path: &Path, label: &str) -> Result<PathBuf, String> {
     let file_name = path
         .file_name()
         .and_then(|name| name.to_str())
@@ -747,10 +2761,40 @@ fn backup_database(path: &Path, label: &str) -> Result<PathBuf, String> {
     let stamp = now_ms();
     let backup_name = format!("{}.{}.{}.bak", file_name, label, stamp);
     let backup_path = path.with_file_name(backup_name);
-    fs::copy(path, &backup_path).map_err(|e| e.to_string())?;
+    if conn.backup(DatabaseName::Main, &backup_path, None).is_err() {
+        fs::copy(path, &backup_path).map_err(|e| e.to_string())?;
+    }
     Ok(backup_path)
 }
 
+/// 只保留最近 `keep` 份指定标签的备份，按文件名中的时间戳排序；清理失败不影响调用方
+fn prune_backups(path: &Path, label: &str, keep: usize) {
+    let (Some(dir), Some(file_name)) = (path.parent(), path.file_name().and_then(|n| n.to_str()))
+    else {
+        return;
+    };
+    let prefix = format!("{file_name}.{label}.");
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut backups: Vec<(u128, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            let stamp = name
+                .strip_prefix(&prefix)?
+                .strip_suffix(".bak")?
+                .parse()
+                .ok()?;
+            Some((stamp, entry.path()))
+        })
+        .collect();
+    backups.sort_by_key(|(stamp, _)| std::cmp::Reverse(*stamp));
+    for (_, old) in backups.into_iter().skip(keep) {
+        let _ = fs::remove_file(old);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -802,6 +2846,7 @@ mod tests {
                 is_default: Some(true),
                 is_builtin: Some(false),
             },
+            false,
         )
         .unwrap();
     }
@@ -837,6 +2882,7 @@ mod tests {
                     group_id: None,
                     template_id: Some("tpl_crud".to_string()),
                     scope: None,
+                    ..MemoryQuery::default()
                 },
             )
             .unwrap();
@@ -864,6 +2910,7 @@ mod tests {
                     group_id: None,
                     template_id: Some("tpl_crud".to_string()),
                     scope: None,
+                    ..MemoryQuery::default()
                 },
             )
             .unwrap();
@@ -882,6 +2929,7 @@ mod tests {
                     group_id: None,
                     template_id: Some("tpl_crud".to_string()),
                     scope: None,
+                    ..MemoryQuery::default()
                 },
             )
             .unwrap();
@@ -939,6 +2987,7 @@ mod tests {
                     group_id: None,
                     template_id: Some("tpl_scope".to_string()),
                     scope: None,
+                    ..MemoryQuery::default()
                 },
             )
             .unwrap();
@@ -950,6 +2999,7 @@ mod tests {
                     group_id: None,
                     template_id: Some("tpl_scope".to_string()),
                     scope: None,
+                    ..MemoryQuery::default()
                 },
             )
             .unwrap();
@@ -1004,6 +3054,7 @@ mod tests {
                     group_id: None,
                     template_id: Some("tpl_batch".to_string()),
                     scope: None,
+                    ..MemoryQuery::default()
                 },
             )
             .unwrap();
@@ -1016,4 +3067,1183 @@ mod tests {
         drop(db);
         fs::remove_dir_all(base_dir).ok();
     }
+
+    fn memory_input(
+        id: &str,
+        template_id: &str,
+        contact_id: &str,
+        row_data: serde_json::Value,
+    ) -> MemoryCreateInput {
+        MemoryCreateInput {
+            id: Some(id.to_string()),
+            template_id: template_id.to_string(),
+            table_id: "events".to_string(),
+            contact_id: Some(contact_id.to_string()),
+            group_id: None,
+            row_data,
+            is_active: None,
+            is_pinned: None,
+            priority: None,
+            sort_order: None,
+        }
+    }
+
+    #[test]
+    fn search_memories_ranks_and_tracks_changes() {
+        let (db, base_dir) = new_test_db("search");
+        seed_template(&db, None, "tpl_search");
+        db.batch_create_memories(
+            None,
+            vec![
+                memory_input(
+                    "m1",
+                    "tpl_search",
+                    "c1",
+                    json!({ "event": "went hiking with Alice", "place": "mountain" }),
+                ),
+                memory_input(
+                    "m2",
+                    "tpl_search",
+                    "c1",
+                    json!({ "event": "hiking hiking hiking trip", "mood": "tired" }),
+                ),
+                memory_input(
+                    "m3",
+                    "tpl_search",
+                    "c2",
+                    json!({ "event": "和小明一起去爬山", "note": "好朋友" }),
+                ),
+            ],
+        )
+        .unwrap();
+
+        let hits = db.search_memories(None, "hiking", None, None).unwrap();
+        let ids: Vec<&str> = hits.iter().map(|h| h.memory.id.as_str()).collect();
+        assert_eq!(ids, vec!["m2", "m1"]);
+        assert!(hits[0].score >= hits[1].score);
+        assert!(hits[1].snippet.contains("【hik"), "{}", hits[1].snippet);
+        // JSON 键名不参与索引
+        assert!(db
+            .search_memories(None, "event", None, None)
+            .unwrap()
+            .is_empty());
+
+        let filtered = db
+            .search_memories(
+                None,
+                "hiking alice",
+                Some(MemoryQuery {
+                    contact_id: Some("c1".to_string()),
+                    group_id: None,
+                    template_id: None,
+                    scope: Some("contact".to_string()),
+                    ..MemoryQuery::default()
+                }),
+                Some(5),
+            )
+            .unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].memory.id, "m1");
+
+        // 两个字的中文词走 LIKE 回退
+        let chinese = db.search_memories(None, "爬山", None, None).unwrap();
+        assert_eq!(chinese.len(), 1);
+        assert!(
+            chinese[0].snippet.contains("【爬山】"),
+            "{}",
+            chinese[0].snippet
+        );
+        assert_eq!(
+            db.search_memories(None, "一起去爬", None, None)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        db.update_memory(
+            None,
+            MemoryUpdateInput {
+                id: "m3".to_string(),
+                row_data: Some(json!({ "event": "went hiking again" })),
+                is_active: None,
+                is_pinned: None,
+                priority: None,
+                sort_order: None,
+            },
+        )
+        .unwrap();
+        db.delete_memory(None, "m2".to_string()).unwrap();
+        // FTS5 语法字符按字面匹配，不会报错
+        let literal = db
+            .search_memories(None, "\"hiking\" OR", None, None)
+            .unwrap();
+        assert!(literal.is_empty());
+        let mut ids: Vec<String> = db
+            .search_memories(None, "hiking", None, None)
+            .unwrap()
+            .into_iter()
+            .map(|h| h.memory.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["m1", "m3"]);
+        assert!(db
+            .search_memories(None, "爬山", None, None)
+            .unwrap()
+            .is_empty());
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn semantic_search_ranks_and_drops_stale_vectors() {
+        let (db, base_dir) = new_test_db("semantic");
+        seed_template(&db, None, "tpl_vec");
+        db.batch_create_memories(
+            None,
+            vec![
+                memory_input("a", "tpl_vec", "c1", json!({ "event": "cats" })),
+                memory_input("b", "tpl_vec", "c1", json!({ "event": "dogs" })),
+                memory_input("c", "tpl_vec", "c2", json!({ "event": "cars" })),
+            ],
+        )
+        .unwrap();
+        assert_eq!(db.pending_embeddings(None, "m", 10).unwrap().len(), 3);
+        let saved = db
+            .save_embeddings(
+                None,
+                "m",
+                &[
+                    ("a".to_string(), vec![1.0, 0.1, 0.0]),
+                    ("b".to_string(), vec![0.7, 0.7, 0.0]),
+                    ("c".to_string(), vec![0.0, 0.2, 1.0]),
+                    ("missing".to_string(), vec![1.0, 0.0, 0.0]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(saved, 3);
+        assert_eq!(db.count_pending_embeddings(None, "m").unwrap(), 0);
+        assert_eq!(db.count_pending_embeddings(None, "other").unwrap(), 3);
+
+        let query = [0.9, 0.3, 0.0];
+        let ids = |quantized: bool| -> Vec<String> {
+            db.semantic_search_memories(None, "m", &query, None, Some(2), quantized)
+                .unwrap()
+                .into_iter()
+                .map(|h| h.memory.id)
+                .collect()
+        };
+        assert_eq!(ids(false), vec!["a", "b"]);
+        assert_eq!(ids(true), ids(false));
+        let hits = db
+            .semantic_search_memories(None, "m", &query, None, None, true)
+            .unwrap();
+        assert_eq!(hits.len(), 3);
+        assert!(hits[0].score <= 1.0 + 1e-6 && hits[0].snippet == "cats");
+        // 维度不一致的向量不参与比较
+        assert!(db
+            .semantic_search_memories(None, "m", &[1.0, 0.0], None, None, false)
+            .unwrap()
+            .is_empty());
+
+        db.update_memory(
+            None,
+            MemoryUpdateInput {
+                id: "a".to_string(),
+                row_data: Some(json!({ "event": "birds" })),
+                is_active: None,
+                is_pinned: None,
+                priority: None,
+                sort_order: None,
+            },
+        )
+        .unwrap();
+        db.delete_memory(None, "c".to_string()).unwrap();
+        let pending = db.pending_embeddings(None, "m", 10).unwrap();
+        assert_eq!(pending, vec![("a".to_string(), "birds".to_string())]);
+        assert_eq!(ids(false), vec!["b"]);
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn history_supports_revert_and_undo_of_automated_changes() {
+        let (db, base_dir) = new_test_db("history");
+        seed_template(&db, None, "tpl_hist");
+        let update = |id: &str, row_data: serde_json::Value, actor: &str| {
+            db.update_memory_as(
+                None,
+                MemoryUpdateInput {
+                    id: id.to_string(),
+                    row_data: Some(row_data),
+                    is_active: None,
+                    is_pinned: None,
+                    priority: None,
+                    sort_order: None,
+                },
+                actor,
+            )
+            .unwrap();
+        };
+        let row = |id: &str| {
+            db.with_conn(None, |conn| load_memory(conn, id))
+                .unwrap()
+                .map(|m| m.row_data)
+        };
+
+        db.create_memory(
+            None,
+            memory_input("a", "tpl_hist", "c1", json!({ "mood": "happy" })),
+        )
+        .unwrap();
+        db.create_memory(
+            None,
+            memory_input("b", "tpl_hist", "c1", json!({ "mood": "calm" })),
+        )
+        .unwrap();
+        update("a", json!({ "mood": "sad" }), "llm");
+        update("a", json!({ "mood": "angry" }), "llm");
+        db.save_embeddings(None, "m", &[("b".to_string(), vec![1.0, 0.0])])
+            .unwrap();
+        db.delete_memory_as(None, "b", "llm").unwrap();
+        let created = db
+            .create_memory_as(
+                None,
+                memory_input("c", "tpl_hist", "c1", json!({ "mood": "noise" })),
+                "llm",
+            )
+            .unwrap();
+
+        let history = db.get_memory_history(None, "a", None).unwrap();
+        let ops: Vec<&str> = history.iter().map(|h| h.op.as_str()).collect();
+        assert_eq!(ops, vec!["update", "update", "create"]);
+        assert_eq!(history[0].old_data, Some(json!({ "mood": "sad" })));
+        assert_eq!(history[0].new_data, Some(json!({ "mood": "angry" })));
+        assert_eq!(history[2].old_data, None);
+
+        // 恢复到创建后的版本，本身也会留下记录
+        db.revert_memory(None, history[2].id, ACTOR_USER).unwrap();
+        assert_eq!(row("a"), Some(json!({ "mood": "happy" })));
+        assert_eq!(
+            db.get_memory_history(None, "a", Some(1)).unwrap()[0].actor,
+            "user"
+        );
+
+        // a 已被用户改回，两条自动修改都会跳过；b 的删除和 c 的创建被撤销
+        let result = db
+            .undo_automated_changes(None, Some("c1".to_string()), None, 10)
+            .unwrap();
+        assert_eq!(result.undone.len(), 2);
+        assert_eq!(result.skipped.len(), 2);
+        assert_eq!(row("b"), Some(json!({ "mood": "calm" })));
+        // 软删除的撤销是原地恢复，向量不会随行一起被清掉
+        let vectors: i64 = db
+            .with_conn(None, |conn| {
+                conn.query_row(
+                    "SELECT COUNT(*) FROM memory_embeddings WHERE memory_id = 'b'",
+                    [],
+                    |r| r.get(0),
+                )
+                .map_err(|e| e.to_string())
+            })
+            .unwrap();
+        assert_eq!(vectors, 1);
+        assert_eq!(row(&created), None);
+        assert_eq!(row("a"), Some(json!({ "mood": "happy" })));
+
+        // 撤销过的记录和撤销本身都不会再次被撤销
+        update("a", json!({ "mood": "bored" }), "llm");
+        let result = db
+            .undo_automated_changes(None, Some("c1".to_string()), None, 1)
+            .unwrap();
+        assert_eq!(result.undone.len(), 1);
+        assert_eq!(row("a"), Some(json!({ "mood": "happy" })));
+        assert!(db
+            .undo_automated_changes(None, Some("c1".to_string()), Some("g".to_string()), 1)
+            .is_err());
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn history_restores_flags_and_created_at() {
+        let (db, base_dir) = new_test_db("history_state");
+        seed_template(&db, None, "tpl_state");
+        let load = || {
+            db.with_conn(None, |conn| load_memory(conn, "a"))
+                .unwrap()
+                .unwrap()
+        };
+        let mut input = memory_input("a", "tpl_state", "c1", json!({ "mood": "happy" }));
+        input.priority = Some(1);
+        db.create_memory(None, input).unwrap();
+        let created_at = load().created_at;
+
+        db.update_memory_as(
+            None,
+            MemoryUpdateInput {
+                id: "a".to_string(),
+                row_data: Some(json!({ "mood": "sad" })),
+                is_active: Some(false),
+                is_pinned: Some(true),
+                priority: Some(9),
+                sort_order: Some(4),
+            },
+            "llm",
+        )
+        .unwrap();
+        let result = db
+            .undo_automated_changes(None, Some("c1".to_string()), None, 1)
+            .unwrap();
+        assert_eq!(result.undone.len(), 1);
+        let undone = load();
+        assert_eq!(undone.row_data, json!({ "mood": "happy" }));
+        assert!(undone.is_active && !undone.is_pinned);
+        assert_eq!((undone.priority, undone.sort_order), (1, 0));
+
+        // 行被彻底删除后，按创建记录重建时沿用原来的标记与创建时间
+        db.with_conn(None, |conn| {
+            conn.execute("DELETE FROM memories WHERE id = 'a'", [])
+                .map_err(|e| e.to_string())
+        })
+        .unwrap();
+        let history = db.get_memory_history(None, "a", None).unwrap();
+        let create = history.iter().find(|h| h.op == "create").unwrap();
+        db.revert_memory(None, create.id, ACTOR_USER).unwrap();
+        let rebuilt = load();
+        assert_eq!(rebuilt.priority, 1);
+        assert_eq!(rebuilt.created_at, created_at);
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn trash_blocks_or_cascades_template_delete_and_purges() {
+        let (db, base_dir) = new_test_db("trash");
+        seed_template(&db, None, "tpl_trash");
+        db.batch_create_memories(
+            None,
+            vec![
+                memory_input("a", "tpl_trash", "c1", json!({ "note": "apple pie" })),
+                memory_input("b", "tpl_trash", "c1", json!({ "note": "banana bread" })),
+            ],
+        )
+        .unwrap();
+        let live = |db: &MemoryDb| {
+            db.get_memories(
+                None,
+                MemoryQuery {
+                    contact_id: None,
+                    group_id: None,
+                    template_id: None,
+                    scope: None,
+                    ..MemoryQuery::default()
+                },
+            )
+            .unwrap()
+            .len()
+        };
+
+        db.delete_memory(None, "a".to_string()).unwrap();
+        assert_eq!(live(&db), 1);
+        assert!(db
+            .search_memories(None, "apple", None, None)
+            .unwrap()
+            .is_empty());
+        assert!(db.delete_memory(None, "a".to_string()).is_err());
+        let trash = db.list_trash(None).unwrap();
+        assert_eq!(trash.memories.len(), 1);
+        assert!(trash.memories[0].deleted_at.is_some());
+        db.restore_memory(None, "a", ACTOR_USER).unwrap();
+        assert_eq!(live(&db), 2);
+
+        // 仍被引用时默认拒绝，显式 cascade 才一起移入回收站
+        let err = db
+            .delete_template(None, "tpl_trash", false, ACTOR_USER)
+            .unwrap_err();
+        assert!(err.contains("used by 2 memories"), "{err}");
+        db.delete_memory(None, "b".to_string()).unwrap();
+        db.delete_template(None, "tpl_trash", true, ACTOR_USER)
+            .unwrap();
+        assert_eq!(live(&db), 0);
+        assert!(db
+            .get_templates(
+                None,
+                TemplateQuery {
+                    id: None,
+                    is_default: None,
+                    is_builtin: None,
+                },
+            )
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            db.restore_memory(None, "a", ACTOR_USER).unwrap_err(),
+            "template is in trash"
+        );
+
+        // 恢复模板只带回级联删除的 a，单独删除的 b 仍在回收站
+        assert_eq!(
+            db.restore_template(None, "tpl_trash", ACTOR_USER).unwrap(),
+            1
+        );
+        assert_eq!(live(&db), 1);
+        assert_eq!(db.list_trash(None).unwrap().memories[0].id, "b");
+
+        // 默认保留期内不会清理，清空时仍被引用的模板保留
+        let kept = db.purge_trash(None, None).unwrap();
+        assert_eq!((kept.memories, kept.templates), (0, 0));
+        db.delete_memory(None, "a".to_string()).unwrap();
+        db.delete_template(None, "tpl_trash", false, ACTOR_USER)
+            .unwrap();
+        let purged = db.purge_trash(None, Some(0)).unwrap();
+        assert_eq!((purged.memories, purged.templates), (2, 1));
+        let trash = db.list_trash(None).unwrap();
+        assert!(trash.memories.is_empty() && trash.templates.is_empty());
+        // 历史仍保留，可以在模板重建后恢复
+        assert_eq!(
+            db.get_memory_history(None, "a", None).unwrap()[0].op,
+            "delete"
+        );
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn writes_are_validated_against_template_schema() {
+        let (db, base_dir) = new_test_db("validate");
+        db.save_template(
+            None,
+            TemplateInput {
+                id: "tpl_rules".to_string(),
+                name: "Rules".to_string(),
+                author: None,
+                version: None,
+                description: None,
+                schema: json!({
+                    "tables": [{
+                        "id": "profile",
+                        "scope": "contact",
+                        "maxRows": 1,
+                        "columns": [{ "id": "name", "type": "text", "required": true }]
+                    }]
+                }),
+                injection: None,
+                is_default: None,
+                is_builtin: None,
+            },
+            false,
+        )
+        .unwrap();
+        let input = |id: &str, contact_id: &str, row_data: serde_json::Value| MemoryCreateInput {
+            table_id: "profile".to_string(),
+            ..memory_input(id, "tpl_rules", contact_id, row_data)
+        };
+        let violations = |err: String| -> serde_json::Value {
+            serde_json::from_str::<serde_json::Value>(&err).unwrap()["violations"].clone()
+        };
+
+        // 第二行超出 maxRows，第三行缺少必填列；整批回滚
+        let err = db
+            .batch_create_memories(
+                None,
+                vec![
+                    input("p1", "c1", json!({ "name": "A" })),
+                    input("p2", "c1", json!({ "name": "B" })),
+                    input("p3", "c2", json!({})),
+                ],
+            )
+            .unwrap_err();
+        let list = violations(err);
+        assert_eq!(list[0]["code"], "max_rows_exceeded");
+        assert_eq!(list[0]["index"], 1);
+        assert_eq!(list[1]["code"], "missing_column");
+        assert_eq!(list[1]["index"], 2);
+        assert!(db.get_memory_history(None, "p1", None).unwrap().is_empty());
+
+        db.create_memory(None, input("p1", "c1", json!({ "name": "A" })))
+            .unwrap();
+        let err = db
+            .create_memory(
+                None,
+                MemoryCreateInput {
+                    table_id: "events".to_string(),
+                    ..input("p9", "c1", json!({}))
+                },
+            )
+            .unwrap_err();
+        assert_eq!(violations(err)[0]["code"], "unknown_table");
+        let err = db
+            .update_memory(
+                None,
+                MemoryUpdateInput {
+                    id: "p1".to_string(),
+                    row_data: Some(json!({ "name": 3 })),
+                    is_active: None,
+                    is_pinned: None,
+                    priority: None,
+                    sort_order: None,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(violations(err)[0]["code"], "invalid_type");
+
+        // 行进入回收站后不再占用 maxRows
+        db.delete_memory(None, "p1".to_string()).unwrap();
+        db.create_memory(None, input("p2", "c1", json!({ "name": "B" })))
+            .unwrap();
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn get_memories_filters_sorts_and_pages_by_cursor() {
+        let (db, base_dir) = new_test_db("paging");
+        seed_template(&db, None, "tpl_page");
+        let rows = (0..7)
+            .map(|i| MemoryCreateInput {
+                is_active: Some(i != 3),
+                is_pinned: Some(i == 5),
+                priority: Some(i % 3),
+                sort_order: Some(10 - i),
+                ..memory_input(&format!("m{i}"), "tpl_page", "c1", json!({ "n": i }))
+            })
+            .collect();
+        db.batch_create_memories(None, rows).unwrap();
+
+        let page_through = |sort: MemorySort, descending: Option<bool>| {
+            let mut ids = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page = db
+                    .get_memories_page(
+                        None,
+                        MemoryPageQuery {
+                            filters: MemoryQuery {
+                                is_active: Some(true),
+                                ..MemoryQuery::default()
+                            },
+                            limit: Some(2),
+                            cursor: cursor.take(),
+                            sort: Some(sort),
+                            descending,
+                        },
+                    )
+                    .unwrap();
+                assert_eq!(page.total, 6);
+                assert!(page.items.len() <= 2);
+                ids.extend(page.items.into_iter().map(|m| m.id));
+                match page.next_cursor {
+                    Some(next) => cursor = Some(next),
+                    None => break ids,
+                }
+            }
+        };
+
+        assert_eq!(
+            page_through(MemorySort::SortOrder, None),
+            vec!["m6", "m5", "m4", "m2", "m1", "m0"]
+        );
+        assert_eq!(
+            page_through(MemorySort::Priority, Some(false)),
+            vec!["m0", "m6", "m1", "m4", "m2", "m5"]
+        );
+        // 默认排序置顶优先，再按优先级降序
+        assert_eq!(
+            page_through(MemorySort::Default, None),
+            vec!["m5", "m2", "m4", "m1", "m6", "m0"]
+        );
+
+        let pinned = db
+            .get_memories_page(
+                None,
+                MemoryPageQuery {
+                    filters: MemoryQuery {
+                        is_pinned: Some(true),
+                        table_id: Some("events".to_string()),
+                        ..MemoryQuery::default()
+                    },
+                    ..MemoryPageQuery::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(pinned.total, 1);
+        assert!(pinned.next_cursor.is_none());
+        // 不分页时返回全部行，顺序与分页的默认排序一致
+        let all = db.get_memories(None, MemoryQuery::default()).unwrap();
+        let all: Vec<&str> = all.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(all, vec!["m5", "m2", "m4", "m1", "m6", "m3", "m0"]);
+        assert!(db
+            .get_memories_page(
+                None,
+                MemoryPageQuery {
+                    limit: Some(2),
+                    cursor: Some("bm90LWpzb24".to_string()),
+                    ..MemoryPageQuery::default()
+                },
+            )
+            .is_err());
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn apply_memory_ops_reports_per_op_and_honours_atomic() {
+        let (db, base_dir) = new_test_db("ops");
+        seed_template(&db, None, "tpl_ops");
+        db.create_memory(
+            None,
+            memory_input("keep", "tpl_ops", "c1", json!({ "a": 1, "b": 2 })),
+        )
+        .unwrap();
+        let ops =
+            |raw: serde_json::Value| -> Vec<MemoryOp> { serde_json::from_value(raw).unwrap() };
+        let row = |id: &str| {
+            db.with_conn(None, |conn| load_memory(conn, id))
+                .unwrap()
+                .map(|m| m.row_data)
+        };
+        let base = json!({ "template_id": "tpl_ops", "table_id": "events", "contact_id": "c1" });
+        let with = |extra: serde_json::Value| {
+            let mut value = base.clone();
+            value
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            value
+        };
+
+        let result = db
+            .apply_memory_ops(
+                None,
+                ops(json!([
+                    with(json!({ "op": "insert", "id": "n1", "row_data": { "x": 1 } })),
+                    with(json!({ "op": "insert", "id": "keep", "row_data": {} })),
+                    { "op": "update", "id": "keep", "row_data": { "b": null, "c": 3 }, "is_pinned": true },
+                    with(json!({ "op": "upsert", "id": "n2", "row_data": { "y": 1 } })),
+                    with(json!({ "op": "upsert", "id": "n1", "row_data": { "x": 2 } })),
+                    { "op": "delete", "id": "missing" }
+                ])),
+                false,
+                "llm",
+            )
+            .unwrap();
+        assert!(result.applied);
+        let statuses: Vec<MemoryOpStatus> = result.results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                MemoryOpStatus::Inserted,
+                MemoryOpStatus::Failed,
+                MemoryOpStatus::Updated,
+                MemoryOpStatus::Inserted,
+                MemoryOpStatus::Updated,
+                MemoryOpStatus::Failed,
+            ]
+        );
+        assert_eq!(row("keep"), Some(json!({ "a": 1, "c": 3 })));
+        assert_eq!(row("n1"), Some(json!({ "x": 2 })));
+        assert_eq!(row("n2"), Some(json!({ "y": 1 })));
+        assert_eq!(
+            db.get_memory_history(None, "n1", None).unwrap()[0].actor,
+            "llm"
+        );
+
+        // atomic 时一条失败整批回滚
+        let result = db
+            .apply_memory_ops(
+                None,
+                ops(json!([
+                    { "op": "delete", "id": "n1" },
+                    { "op": "update", "id": "nope", "row_data": {} }
+                ])),
+                true,
+                "llm",
+            )
+            .unwrap();
+        assert!(!result.applied);
+        assert_eq!(result.results[0].status, MemoryOpStatus::Deleted);
+        assert_eq!(result.results[1].error.as_deref(), Some("memory not found"));
+        assert_eq!(row("n1"), Some(json!({ "x": 2 })));
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    /// v1 夹具库：一个模板和两条引用它的记忆
+    fn v1_fixture(path: &Path) -> Connection {
+        let conn = open_connection(path).unwrap();
+        conn.execute_batch(SCHEMA_SQL).unwrap();
+        conn.execute_batch(
+            "INSERT INTO schema_info (key, value) VALUES ('schema_version', '1');
+             INSERT INTO templates (id, name, schema) VALUES ('tpl', 'T', '{}');
+             INSERT INTO memories (id, template_id, table_id, row_data, created_at, updated_at)
+             VALUES ('m1', 'tpl', 'events', '{\"event\":\"picnic\"}', 1, 1),
+                    ('m2', 'tpl', 'events', '{\"event\":\"concert\"}', 2, 2);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn schema_version(conn: &Connection) -> String {
+        conn.query_row(
+            "SELECT value FROM schema_info WHERE key = ?",
+            params![SCHEMA_KEY],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    const FIXTURE_MIGRATIONS: &[Migration] = &[
+        // v1 -> v2：Rust 数据转换，把 event 字段改名为 summary
+        Migration {
+            from: 1,
+            to: 2,
+            step: MigrationStep::Rust(|tx| {
+                let rows: Vec<(String, String)> = {
+                    let mut stmt = tx
+                        .prepare("SELECT id, row_data FROM memories")
+                        .map_err(|e| e.to_string())?;
+                    let rows = stmt
+                        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                        .map_err(|e| e.to_string())?;
+                    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+                };
+                for (id, raw) in rows {
+                    let mut value = parse_row_data(raw);
+                    if let Some(obj) = value.as_object_mut() {
+                        if let Some(event) = obj.remove("event") {
+                            obj.insert("summary".to_string(), event);
+                        }
+                    }
+                    tx.execute(
+                        "UPDATE memories SET row_data = ? WHERE id = ?",
+                        params![value.to_string(), id],
+                    )
+                    .map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            }),
+            rebuilds_tables: false,
+        },
+        // v2 -> v3：重建被 memories 外键引用的 templates 表
+        Migration {
+            from: 2,
+            to: 3,
+            step: MigrationStep::Sql(
+                "CREATE TABLE templates_new (
+                     id TEXT PRIMARY KEY,
+                     name TEXT NOT NULL,
+                     author TEXT,
+                     version TEXT,
+                     description TEXT,
+                     schema TEXT NOT NULL,
+                     injection TEXT,
+                     created_at INTEGER,
+                     updated_at INTEGER,
+                     is_default BOOLEAN DEFAULT 0,
+                     is_builtin BOOLEAN DEFAULT 0,
+                     kind TEXT NOT NULL DEFAULT 'table'
+                 );
+                 INSERT INTO templates_new (id, name, author, version, description, schema,
+                     injection, created_at, updated_at, is_default, is_builtin)
+                 SELECT id, name, author, version, description, schema,
+                     injection, created_at, updated_at, is_default, is_builtin FROM templates;
+                 DROP TABLE templates;
+                 ALTER TABLE templates_new RENAME TO templates;",
+            ),
+            rebuilds_tables: true,
+        },
+        // v3 -> v4：外键关闭期间删掉仍被引用的模板，foreign_key_check 应当拦下
+        Migration {
+            from: 3,
+            to: 4,
+            step: MigrationStep::Sql("DELETE FROM templates WHERE id = 'tpl';"),
+            rebuilds_tables: true,
+        },
+    ];
+
+    #[test]
+    fn migrations_run_rust_steps_and_rebuild_referenced_tables() {
+        let base_dir = make_temp_dir("migrate_chain");
+        let path = base_dir.join("memories.db");
+        let mut conn = v1_fixture(&path);
+
+        run_migrations(&mut conn, FIXTURE_MIGRATIONS, 1, 3, &path, true).unwrap();
+        assert_eq!(schema_version(&conn), "3");
+        let row: String = conn
+            .query_row("SELECT row_data FROM memories WHERE id = 'm1'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(parse_row_data(row), json!({ "summary": "picnic" }));
+        let kind: String = conn
+            .query_row("SELECT kind FROM templates WHERE id = 'tpl'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(kind, "table");
+        // 重建后外键仍然生效
+        assert!(conn
+            .execute(
+                "INSERT INTO memories (id, template_id, table_id, row_data, created_at, updated_at) \
+                 VALUES ('m3', 'nope', 'events', '{}', 3, 3)",
+                [],
+            )
+            .is_err());
+
+        // 破坏外键的迁移失败后，整库回到迁移前的备份
+        let err = run_migrations(&mut conn, FIXTURE_MIGRATIONS, 3, 4, &path, true).unwrap_err();
+        assert!(err.contains("foreign key violation"), "{err}");
+        assert_eq!(schema_version(&conn), "3");
+        let templates: i64 = conn
+            .query_row("SELECT COUNT(*) FROM templates", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(templates, 1);
+        let fk: i64 = conn
+            .query_row("PRAGMA foreign_keys", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(fk, 1);
+
+        // 缺少迁移路径时不做任何改动
+        let err = run_migrations(&mut conn, FIXTURE_MIGRATIONS, 3, 5, &path, true).unwrap_err();
+        assert!(err.contains("missing migration path: 4 -> 5"), "{err}");
+        assert_eq!(schema_version(&conn), "3");
+
+        drop(conn);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn migrations_upgrade_v1_fixture_to_current_schema() {
+        let base_dir = make_temp_dir("migrate_current");
+        let path = base_dir.join("memories.db");
+        drop(v1_fixture(&path));
+
+        let mut conn = open_connection(&path).unwrap();
+        ensure_schema(&mut conn, &path, true).unwrap();
+        assert_eq!(schema_version(&conn), SCHEMA_VERSION.to_string());
+        for table in ["memories_fts", "memory_embeddings"] {
+            let exists: i64 = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM sqlite_master WHERE name = ?",
+                    params![table],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(exists, 1, "{table}");
+        }
+        let indexed: i64 = conn
+            .query_row("SELECT COUNT(*) FROM memories_fts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(indexed, 2);
+        // 触发器按 rowid 映射维护索引
+        conn.execute_batch(
+            "UPDATE memories SET row_data = '{\"event\":\"festival\"}' WHERE id = 'm1';
+             DELETE FROM memories WHERE id = 'm2';",
+        )
+        .unwrap();
+        let entries: Vec<String> = conn
+            .prepare(
+                "SELECT memory_fts_keys.memory_id || ':' || memories_fts.content FROM memories_fts \
+                 JOIN memory_fts_keys ON memory_fts_keys.fts_rowid = memories_fts.rowid",
+            )
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries, vec!["m1:festival"]);
+        // 再次打开不会重复迁移或备份
+        ensure_schema(&mut conn, &path, true).unwrap();
+        let backups = fs::read_dir(&base_dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .is_ok_and(|e| e.file_name().to_string_lossy().contains("pre_migrate"))
+            })
+            .count();
+        assert_eq!(backups, 1);
+
+        drop(conn);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn prune_backups_keeps_latest() {
+        let base_dir = make_temp_dir("prune_backups");
+        let path = base_dir.join("memories.db");
+        for name in [
+            "memories.db.pre_migrate.100.bak",
+            "memories.db.pre_migrate.300.bak",
+            "memories.db.pre_migrate.200.bak",
+            "memories.db.corrupt.50.bak",
+            "memories_other.db.pre_migrate.10.bak",
+        ] {
+            fs::write(base_dir.join(name), b"").unwrap();
+        }
+        prune_backups(&path, "pre_migrate", 1);
+        let mut left: Vec<String> = fs::read_dir(&base_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            vec![
+                "memories.db.corrupt.50.bak",
+                "memories.db.pre_migrate.300.bak",
+                "memories_other.db.pre_migrate.10.bak",
+            ]
+        );
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn migration_builds_index_for_existing_rows() {
+        let base_dir = make_temp_dir("fts_migrate");
+        let path = base_dir.join("memories.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(SCHEMA_SQL).unwrap();
+            conn.execute(
+                "INSERT INTO schema_info (key, value) VALUES (?, '1')",
+                params![SCHEMA_KEY],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO templates (id, name, schema) VALUES ('tpl', 'T', '{}')",
+                [],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO memories (id, template_id, table_id, row_data, created_at, updated_at) \
+                 VALUES ('old', 'tpl', 'events', '{\"event\":\"legacy picnic\"}', 1, 1)",
+                [],
+            )
+            .unwrap();
+        }
+        let db = MemoryDb {
+            base_dir: base_dir.clone(),
+            connections: Mutex::new(HashMap::new()),
+        };
+        let hits = db.search_memories(None, "picnic", None, None).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].memory.id, "old");
+        let backups = fs::read_dir(&base_dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .is_ok_and(|e| e.file_name().to_string_lossy().contains("pre_migrate"))
+            })
+            .count();
+        assert_eq!(backups, 1);
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn templates_export_and_import_with_conflicts() {
+        let (db, base_dir) = new_test_db("tpl_file");
+        seed_template(&db, None, "tpl_share");
+        let rows = (0..5)
+            .map(|i| memory_input(&format!("m{i}"), "tpl_share", "c1", json!({ "n": i })))
+            .collect();
+        db.batch_create_memories(None, rows).unwrap();
+
+        let document = db.export_template(None, "tpl_share", true).unwrap();
+        assert_eq!(document.template.version.as_deref(), Some("1"));
+        assert_eq!(
+            document.samples.len(),
+            memory_template_file::SAMPLE_ROWS_PER_TABLE
+        );
+        let path = base_dir.join("share.zip");
+        memory_template_file::write_document(&path, &document).unwrap();
+
+        let read = || memory_template_file::read_document(&path).unwrap();
+        let renamed = db
+            .import_template(None, read(), TemplateConflict::Rename)
+            .unwrap();
+        assert_eq!(renamed.id, "tpl_share_imported");
+        assert!(renamed.renamed);
+        assert_eq!(renamed.samples.len(), 3);
+        let again = db
+            .import_template(None, read(), TemplateConflict::Rename)
+            .unwrap();
+        assert_eq!(again.id, "tpl_share_imported_2");
+
+        let bumped = db
+            .import_template(None, read(), TemplateConflict::BumpVersion)
+            .unwrap();
+        assert_eq!(bumped.id, "tpl_share");
+        assert!(bumped.replaced);
+        assert_eq!(bumped.version.as_deref(), Some("2"));
+        let stored = db
+            .get_templates(
+                None,
+                TemplateQuery {
+                    id: Some("tpl_share".to_string()),
+                    is_default: None,
+                    is_builtin: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(stored[0].version.as_deref(), Some("2"));
+        // 已有记忆时覆盖只接受新增表或列，删除需走 upgrade_template
+        let mut extended = read();
+        extended.template.schema = json!({ "tables": [
+            { "id": "events", "columns": [{ "id": "n", "type": "number" }] },
+            { "id": "extra", "columns": [{ "id": "a" }] }
+        ] });
+        db.import_template(None, extended, TemplateConflict::BumpVersion)
+            .unwrap();
+        let err = db
+            .import_template(None, read(), TemplateConflict::BumpVersion)
+            .unwrap_err();
+        assert!(err.contains("upgrade_template"), "{err}");
+        // 导入不会写入样例行
+        assert_eq!(
+            db.get_memories(None, MemoryQuery::default()).unwrap().len(),
+            5
+        );
+
+        let mut broken = read();
+        broken.template.schema = json!({ "tables": "nope" });
+        assert!(db
+            .import_template(None, broken, TemplateConflict::Rename)
+            .is_err());
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
+
+    #[test]
+    fn template_upgrade_previews_and_migrates_rows() {
+        let (db, base_dir) = new_test_db("tpl_upgrade");
+        let template = |version: &str, columns: serde_json::Value| TemplateInput {
+            id: "tpl_up".to_string(),
+            name: "Upgrade".to_string(),
+            author: None,
+            version: Some(version.to_string()),
+            description: None,
+            schema: json!({ "tables": [{ "id": "events", "columns": columns }] }),
+            injection: None,
+            is_default: None,
+            is_builtin: None,
+        };
+        db.save_template(
+            None,
+            template(
+                "1",
+                json!([{ "id": "time" }, { "id": "note" }, { "id": "old" }]),
+            ),
+            false,
+        )
+        .unwrap();
+        let rows = vec![
+            memory_input(
+                "m1",
+                "tpl_up",
+                "c1",
+                json!({ "time": "t1", "note": "a", "old": "x" }),
+            ),
+            memory_input("m2", "tpl_up", "c1", json!({ "time": "t2" })),
+            memory_input("m3", "tpl_up", "c1", json!({ "time": "t3", "note": "c" })),
+        ];
+        db.batch_create_memories(None, rows).unwrap();
+        db.delete_memory(None, "m3".to_string()).unwrap();
+        // 已有记忆时直接保存不能删除列
+        let err = db
+            .save_template(
+                None,
+                template("1.1", json!([{ "id": "time" }, { "id": "note" }])),
+                true,
+            )
+            .unwrap_err();
+        assert!(err.contains("upgrade_template"), "{err}");
+
+        let new_columns = json!([
+            { "id": "time" },
+            { "id": "text" },
+            { "id": "level", "type": "number", "required": true }
+        ]);
+        let upgrade = |mappings: serde_json::Value| TemplateUpgradeInput {
+            template: template("2", new_columns.clone()),
+            mappings: serde_json::from_value(mappings).unwrap(),
+        };
+        let err = db
+            .preview_template_upgrade(None, &upgrade(json!([])))
+            .unwrap_err();
+        assert!(err.contains("events.note was removed"), "{err}");
+
+        let partial =
+            json!([{ "table_id": "events", "rename": { "note": "text" }, "drop": ["old"] }]);
+        let preview = db
+            .preview_template_upgrade(None, &upgrade(partial.clone()))
+            .unwrap();
+        assert_eq!(preview.diff.tables[0].removed, vec!["note", "old"]);
+        assert_eq!(preview.affected, 2);
+        assert_eq!(preview.invalid, 3);
+        let err = db
+            .upgrade_template(None, upgrade(partial), ACTOR_USER)
+            .unwrap_err();
+        assert!(err.contains("memory_validation"), "{err}");
+        let unchanged = db.get_memories(None, MemoryQuery::default()).unwrap();
+        assert!(unchanged.iter().any(|m| m.row_data["note"] == "a"));
+
+        let full = json!([{
+            "table_id": "events",
+            "rename": { "note": "text" },
+            "drop": ["old"],
+            "defaults": { "level": 1 }
+        }]);
+        let preview = db
+            .preview_template_upgrade(None, &upgrade(full.clone()))
+            .unwrap();
+        assert_eq!((preview.affected, preview.invalid), (3, 0));
+        let result = db.upgrade_template(None, upgrade(full), "agent").unwrap();
+        assert_eq!(result.updated, 3);
+        // 回收站中的行也已迁移，恢复后符合新 schema
+        db.restore_memory(None, "m3", ACTOR_USER).unwrap();
+
+        let migrated = db.get_memories(None, MemoryQuery::default()).unwrap();
+        let m1 = migrated.iter().find(|m| m.id == "m1").unwrap();
+        assert_eq!(
+            m1.row_data,
+            json!({ "time": "t1", "text": "a", "level": 1 })
+        );
+        let m3 = migrated.iter().find(|m| m.id == "m3").unwrap();
+        assert_eq!(
+            m3.row_data,
+            json!({ "time": "t3", "text": "c", "level": 1 })
+        );
+        let stored = db
+            .get_templates(
+                None,
+                TemplateQuery {
+                    id: Some("tpl_up".to_string()),
+                    is_default: None,
+                    is_builtin: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(stored[0].version.as_deref(), Some("2"));
+        let history = db.get_memory_history(None, "m1", None).unwrap();
+        assert_eq!(history[0].actor, "agent");
+        // 未开启检查时（前端编辑器）仍可直接覆盖
+        db.save_template(None, template("3", json!([{ "id": "time" }])), false)
+            .unwrap();
+
+        drop(db);
+        fs::remove_dir_all(base_dir).ok();
+    }
 }
@@ -0,0 +1,19 @@
+-- Memory embeddings side table (v2 -> v3)
+-- vector 为归一化后的 f32 小端序列，quantized 为 int8 量化副本（scale 为量化前的最大绝对值）
+CREATE TABLE IF NOT EXISTS memory_embeddings (
+    memory_id TEXT PRIMARY KEY REFERENCES memories(id) ON DELETE CASCADE,
+    model TEXT NOT NULL,
+    dim INTEGER NOT NULL,
+    vector BLOB NOT NULL,
+    quantized BLOB NOT NULL,
+    scale REAL NOT NULL,
+    embedded_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_memory_embeddings_model ON memory_embeddings(model, dim);
+
+-- 行内容变化后旧向量失效，等待重新生成
+CREATE TRIGGER IF NOT EXISTS memory_embeddings_stale AFTER UPDATE OF row_data ON memories
+WHEN old.row_data IS NOT new.row_data BEGIN
+    DELETE FROM memory_embeddings WHERE memory_id = old.id;
+END;
@@ -0,0 +1,56 @@
+-- Memory full-text index keyed by rowid (v6 -> v7)
+-- v2 的 memory_id 为 UNINDEXED 列，触发器按它删除会扫描整张 FTS 表；
+-- 改为由 memory_fts_keys 记录 memory id -> FTS rowid（INTEGER PRIMARY KEY，VACUUM 后不变）
+DROP TRIGGER IF EXISTS memories_fts_ai;
+DROP TRIGGER IF EXISTS memories_fts_ad;
+DROP TRIGGER IF EXISTS memories_fts_au;
+DROP TABLE IF EXISTS memories_fts;
+
+CREATE TABLE IF NOT EXISTS memory_fts_keys (
+    fts_rowid INTEGER PRIMARY KEY,
+    memory_id TEXT NOT NULL UNIQUE
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS memories_fts USING fts5(
+    content,
+    tokenize = 'trigram'
+);
+
+CREATE TRIGGER IF NOT EXISTS memories_fts_ai AFTER INSERT ON memories BEGIN
+    INSERT INTO memory_fts_keys (memory_id) VALUES (new.id);
+    INSERT INTO memories_fts (rowid, content)
+    VALUES (
+        (SELECT fts_rowid FROM memory_fts_keys WHERE memory_id = new.id),
+        CASE WHEN json_valid(new.row_data)
+            THEN (SELECT group_concat(value, ' ') FROM json_tree(new.row_data) WHERE type = 'text')
+            ELSE new.row_data
+        END
+    );
+END;
+
+CREATE TRIGGER IF NOT EXISTS memories_fts_ad AFTER DELETE ON memories BEGIN
+    DELETE FROM memories_fts
+    WHERE rowid = (SELECT fts_rowid FROM memory_fts_keys WHERE memory_id = old.id);
+    DELETE FROM memory_fts_keys WHERE memory_id = old.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS memories_fts_au AFTER UPDATE OF id, row_data ON memories BEGIN
+    UPDATE memory_fts_keys SET memory_id = new.id WHERE memory_id = old.id;
+    UPDATE memories_fts
+    SET content = CASE WHEN json_valid(new.row_data)
+            THEN (SELECT group_concat(value, ' ') FROM json_tree(new.row_data) WHERE type = 'text')
+            ELSE new.row_data
+        END
+    WHERE rowid = (SELECT fts_rowid FROM memory_fts_keys WHERE memory_id = new.id);
+END;
+
+DELETE FROM memory_fts_keys;
+INSERT INTO memory_fts_keys (memory_id) SELECT id FROM memories;
+INSERT INTO memories_fts (rowid, content)
+SELECT
+    memory_fts_keys.fts_rowid,
+    CASE WHEN json_valid(memories.row_data)
+        THEN (SELECT group_concat(value, ' ') FROM json_tree(memories.row_data) WHERE type = 'text')
+        ELSE memories.row_data
+    END
+FROM memories JOIN memory_fts_keys ON memory_fts_keys.memory_id = memories.id;
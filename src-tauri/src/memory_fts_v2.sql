@@ -0,0 +1,44 @@
+-- Memory full-text index (v1 -> v2)
+-- 索引 row_data 中的文本值；trigram 分词支持中文子串匹配
+CREATE VIRTUAL TABLE IF NOT EXISTS memories_fts USING fts5(
+    memory_id UNINDEXED,
+    content,
+    tokenize = 'trigram'
+);
+
+CREATE TRIGGER IF NOT EXISTS memories_fts_ai AFTER INSERT ON memories BEGIN
+    INSERT INTO memories_fts (memory_id, content)
+    VALUES (
+        new.id,
+        CASE WHEN json_valid(new.row_data)
+            THEN (SELECT group_concat(value, ' ') FROM json_tree(new.row_data) WHERE type = 'text')
+            ELSE new.row_data
+        END
+    );
+END;
+
+CREATE TRIGGER IF NOT EXISTS memories_fts_ad AFTER DELETE ON memories BEGIN
+    DELETE FROM memories_fts WHERE memory_id = old.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS memories_fts_au AFTER UPDATE OF id, row_data ON memories BEGIN
+    DELETE FROM memories_fts WHERE memory_id = old.id;
+    INSERT INTO memories_fts (memory_id, content)
+    VALUES (
+        new.id,
+        CASE WHEN json_valid(new.row_data)
+            THEN (SELECT group_concat(value, ' ') FROM json_tree(new.row_data) WHERE type = 'text')
+            ELSE new.row_data
+        END
+    );
+END;
+
+DELETE FROM memories_fts;
+INSERT INTO memories_fts (memory_id, content)
+SELECT
+    id,
+    CASE WHEN json_valid(row_data)
+        THEN (SELECT group_concat(value, ' ') FROM json_tree(memories.row_data) WHERE type = 'text')
+        ELSE row_data
+    END
+FROM memories;
@@ -0,0 +1,5 @@
+-- Memory history row state (v5 -> v6)
+-- row_data 之外的字段（is_active / is_pinned / priority / sort_order / created_at）
+-- 以 JSON 记录，回退时一并恢复；旧记录为 NULL，回退时保留当前值
+ALTER TABLE memory_history ADD COLUMN old_state TEXT;
+ALTER TABLE memory_history ADD COLUMN new_state TEXT;
@@ -0,0 +1,20 @@
+-- Memory change history (v3 -> v4)
+-- 不对 memories 建外键：行被删除后历史仍需保留，用于恢复
+CREATE TABLE IF NOT EXISTS memory_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    memory_id TEXT NOT NULL,
+    op TEXT NOT NULL,
+    template_id TEXT NOT NULL,
+    table_id TEXT NOT NULL,
+    contact_id TEXT,
+    group_id TEXT,
+    old_data TEXT,
+    new_data TEXT,
+    actor TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    undone_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_memory_history_memory ON memory_history(memory_id, id);
+CREATE INDEX IF NOT EXISTS idx_memory_history_contact ON memory_history(contact_id, id);
+CREATE INDEX IF NOT EXISTS idx_memory_history_group ON memory_history(group_id, id);
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub const FORMAT: &str = "chatapp-memory-template";
+pub const FORMAT_VERSION: u64 = 1;
+/// 导出样例时每张表最多保留的行数
+pub const SAMPLE_ROWS_PER_TABLE: usize = 3;
+const ZIP_ENTRY: &str = "template.json";
+const MAX_FILE_BYTES: u64 = 4 * 1024 * 1024;
+const MAX_TEMPLATE_ID_LEN: usize = 64;
+const IMPORTED_SUFFIX: &str = "_imported";
+const MAX_RENAME_ATTEMPTS: usize = 1000;
+const SCOPES: &[&str] = &["global", "contact", "group"];
+
+/// 模板分享文件（JSON，或包含 `template.json` 的 zip）
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateDocument {
+    pub format: String,
+    pub format_version: u64,
+    #[serde(default)]
+    pub exported_at: i64,
+    pub template: TemplatePayload,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<TemplateSample>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplatePayload {
+    pub id: String,
+    pub name: String,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub schema: Value,
+    pub injection: Option<Value>,
+}
+
+/// 样例行只保留表与内容，不带联系人/群组等本机信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateSample {
+    pub table_id: String,
+    pub row_data: Value,
+}
+
+/// 导入时 id 已存在的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateConflict {
+    /// 以 `<id>_imported` 另存一份
+    #[default]
+    Rename,
+    /// 覆盖原模板，版本号取两者较新者并递增
+    BumpVersion,
+}
+
+/// 导入结果；样例行不写入记忆库，原样返回供前端预览
+#[derive(Debug, Serialize)]
+pub struct TemplateImportResult {
+    pub id: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub renamed: bool,
+    pub replaced: bool,
+    pub samples: Vec<TemplateSample>,
+}
+
+pub fn write_document(path: &Path, document: &TemplateDocument) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(document).map_err(|e| e.to_string())?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let is_zip = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    if !is_zip {
+        return fs::write(path, json).map_err(|e| e.to_string());
+    }
+    let file = fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut writer = ZipWriter::new(file);
+    writer
+        .start_file(
+            ZIP_ENTRY,
+            FileOptions::default().compression_method(CompressionMethod::Deflated),
+        )
+        .map_err(|e| e.to_string())?;
+    writer.write_all(&json).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// 读取并校验模板文件；按内容而非扩展名识别 zip
+pub fn read_document(path: &Path) -> Result<TemplateDocument, String> {
+    let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    if size > MAX_FILE_BYTES {
+        return Err("template file is too large".to_string());
+    }
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let json = if bytes.starts_with(b"PK\x03\x04") {
+        let mut archive = ZipArchive::new(Cursor::new(bytes))
+            .map_err(|e| format!("invalid template archive: {e}"))?;
+        let entry = archive
+            .by_name(ZIP_ENTRY)
+            .map_err(|_| format!("template archive missing {ZIP_ENTRY}"))?;
+        let mut out = Vec::new();
+        entry
+            .take(MAX_FILE_BYTES)
+            .read_to_end(&mut out)
+            .map_err(|e| e.to_string())?;
+        out
+    } else {
+        bytes
+    };
+    let document: TemplateDocument =
+        serde_json::from_slice(&json).map_err(|e| format!("invalid template file: {e}"))?;
+    validate_document(&document)?;
+    Ok(document)
+}
+
+pub fn validate_document(document: &TemplateDocument) -> Result<(), String> {
+    if document.format != FORMAT {
+        return Err(format!("unsupported template format: {}", document.format));
+    }
+    if document.format_version == 0 || document.format_version > FORMAT_VERSION {
+        return Err(format!(
+            "unsupported template format version: {}",
+            document.format_version
+        ));
+    }
+    let template = &document.template;
+    validate_template_id(&template.id)?;
+    if template.name.trim().is_empty() {
+        return Err("template name is required".to_string());
+    }
+    let errors = schema_errors(&template.schema);
+    if !errors.is_empty() {
+        return Err(format!("invalid template schema: {}", errors.join("; ")));
+    }
+    Ok(())
+}
+
+pub fn validate_template_id(id: &str) -> Result<(), String> {
+    let valid = !id.is_empty()
+        && id.len() <= MAX_TEMPLATE_ID_LEN
+        && id
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid template id: {id}"))
+    }
+}
+
+/// schema 结构检查：表与列的 id 必须存在且不重复，scope / maxRows 取值合法
+fn schema_errors(schema: &Value) -> Vec<String> {
+    let Some(tables) = schema.get("tables").and_then(Value::as_array) else {
+        return vec!["tables must be an array".to_string()];
+    };
+    let mut errors = Vec::new();
+    let mut table_ids = HashSet::new();
+    for (index, table) in tables.iter().enumerate() {
+        let Some(table_id) = table.get("id").and_then(Value::as_str) else {
+            errors.push(format!("table #{index} is missing id"));
+            continue;
+        };
+        if !table_ids.insert(table_id) {
+            errors.push(format!("duplicate table {table_id}"));
+        }
+        if let Some(scope) = table.get("scope") {
+            if !scope.as_str().is_some_and(|s| SCOPES.contains(&s)) {
+                errors.push(format!("table {table_id} has invalid scope"));
+            }
+        }
+        if let Some(max_rows) = table.get("maxRows") {
+            if !max_rows.is_u64() {
+                errors.push(format!("table {table_id} has invalid maxRows"));
+            }
+        }
+        let Some(columns) = table.get("columns").and_then(Value::as_array) else {
+            errors.push(format!("table {table_id} is missing columns"));
+            continue;
+        };
+        let mut column_ids = HashSet::new();
+        for column in columns {
+            match column.get("id").and_then(Value::as_str) {
+                Some(id) if column_ids.insert(id) => {}
+                Some(id) => errors.push(format!("table {table_id} has duplicate column {id}")),
+                None => errors.push(format!("table {table_id} has a column without id")),
+            }
+        }
+    }
+    errors
+}
+
+/// 为冲突的模板找一个未占用的新 id；过长的 id 先截短，保证结果仍是合法 id
+pub fn renamed_id(id: &str, mut taken: impl FnMut(&str) -> bool) -> Result<String, String> {
+    let room =
+        MAX_TEMPLATE_ID_LEN - IMPORTED_SUFFIX.len() - format!("_{MAX_RENAME_ATTEMPTS}").len();
+    let stem = id.get(..room.min(id.len())).unwrap_or(id);
+    let base = format!("{stem}{IMPORTED_SUFFIX}");
+    if !taken(&base) {
+        return Ok(base);
+    }
+    (2..=MAX_RENAME_ATTEMPTS)
+        .map(|n| format!("{base}_{n}"))
+        .find(|candidate| !taken(candidate))
+        .ok_or_else(|| format!("no free template id for {id}"))
+}
+
+/// 覆盖导入时的新版本号：导入版本更新则沿用，否则在现有版本上递增末位
+pub fn bumped_version(existing: Option<&str>, incoming: Option<&str>) -> String {
+    match (existing, incoming) {
+        (Some(existing), Some(incoming)) if compare_versions(incoming, existing).is_gt() => {
+            incoming.to_string()
+        }
+        (Some(existing), _) => increment_version(existing),
+        (None, Some(incoming)) => incoming.to_string(),
+        (None, None) => "1".to_string(),
+    }
+}
+
+fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .trim()
+        .trim_start_matches(['v', 'V'])
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a, b) = (version_parts(a), version_parts(b));
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| {
+            a.get(i)
+                .copied()
+                .unwrap_or(0)
+                .cmp(&b.get(i).copied().unwrap_or(0))
+        })
+        .find(|ord| ord.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+fn increment_version(version: &str) -> String {
+    let trimmed = version.trim();
+    match trimmed.rsplit_once('.') {
+        Some((head, last)) if last.parse::<u64>().is_ok() => {
+            format!("{head}.{}", last.parse::<u64>().unwrap_or(0) + 1)
+        }
+        None if trimmed.parse::<u64>().is_ok() => {
+            (trimmed.parse::<u64>().unwrap_or(0) + 1).to_string()
+        }
+        _ => format!("{trimmed}.1"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::make_temp_dir;
+    use serde_json::json;
+
+    fn document(schema: Value) -> TemplateDocument {
+        TemplateDocument {
+            format: FORMAT.to_string(),
+            format_version: FORMAT_VERSION,
+            exported_at: 1,
+            template: TemplatePayload {
+                id: "default-v1".to_string(),
+                name: "通用记忆模板".to_string(),
+                author: Some("官方".to_string()),
+                version: Some("1.0.2".to_string()),
+                description: Some("desc".to_string()),
+                schema,
+                injection: None,
+            },
+            samples: vec![TemplateSample {
+                table_id: "events".to_string(),
+                row_data: json!({ "time": "today" }),
+            }],
+        }
+    }
+
+    #[test]
+    fn documents_roundtrip_and_are_validated() {
+        let dir = make_temp_dir("roundtrip");
+        let schema = json!({ "tables": [{ "id": "events", "scope": "contact", "columns": [{ "id": "time" }] }] });
+        for name in ["t.json", "t.ZIP"] {
+            let path = dir.join(name);
+            write_document(&path, &document(schema.clone())).unwrap();
+            let read = read_document(&path).unwrap();
+            assert_eq!(read.template.author.as_deref(), Some("官方"));
+            assert_eq!(read.template.version.as_deref(), Some("1.0.2"));
+            assert_eq!(read.samples.len(), 1);
+        }
+        // 文件格式统一使用 camelCase 字段名
+        let raw: Value = serde_json::from_slice(&fs::read(dir.join("t.json")).unwrap()).unwrap();
+        assert_eq!(raw["formatVersion"], FORMAT_VERSION);
+        assert_eq!(raw["samples"][0]["tableId"], "events");
+        assert_eq!(raw["samples"][0]["rowData"]["time"], "today");
+
+        let bad = json!({ "tables": [
+            { "id": "a", "scope": "world", "columns": [{ "id": "x" }, { "id": "x" }] },
+            { "id": "a", "maxRows": -1, "columns": [] }
+        ] });
+        let err = validate_document(&document(bad)).unwrap_err();
+        assert!(err.contains("invalid scope"), "{err}");
+        assert!(err.contains("duplicate column x"), "{err}");
+        assert!(err.contains("duplicate table a"), "{err}");
+        assert!(err.contains("invalid maxRows"), "{err}");
+        let mut wrong = document(schema);
+        wrong.template.id = "../evil".to_string();
+        assert!(validate_document(&wrong).is_err());
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn collisions_rename_or_bump_version() {
+        let taken = ["t_imported", "t_imported_2"];
+        assert_eq!(
+            renamed_id("t", |id| taken.contains(&id)).unwrap(),
+            "t_imported_3"
+        );
+        let long = "x".repeat(MAX_TEMPLATE_ID_LEN);
+        let renamed = renamed_id(&long, |id| id.ends_with(IMPORTED_SUFFIX)).unwrap();
+        assert!(renamed.ends_with("_imported_2"), "{renamed}");
+        validate_template_id(&renamed).unwrap();
+        assert_eq!(bumped_version(Some("1.0.2"), Some("1.0.2")), "1.0.3");
+        assert_eq!(bumped_version(Some("1.0.2"), Some("1.1")), "1.1");
+        assert_eq!(bumped_version(Some("2"), Some("1.9")), "3");
+        assert_eq!(bumped_version(Some("beta"), None), "beta.1");
+        assert_eq!(bumped_version(None, Some("0.1")), "0.1");
+    }
+}
@@ -0,0 +1,255 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// 同一张表在新旧 schema 间的列差异
+#[derive(Debug, Clone, Serialize)]
+pub struct TableDiff {
+    pub table_id: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// 模板升级的 schema 差异；tables 只列出列有变化的表
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SchemaDiff {
+    pub added_tables: Vec<String>,
+    pub removed_tables: Vec<String>,
+    pub tables: Vec<TableDiff>,
+}
+
+impl SchemaDiff {
+    /// 只新增表或列，已有记忆无需迁移
+    pub fn is_additive(&self) -> bool {
+        self.removed_tables.is_empty() && self.tables.iter().all(|t| t.removed.is_empty())
+    }
+
+    fn table(&self, table_id: &str) -> Option<&TableDiff> {
+        self.tables.iter().find(|t| t.table_id == table_id)
+    }
+}
+
+/// 一张表的列映射：rename 为旧列 -> 新列，drop 为放弃的旧列，
+/// defaults 在列缺失或为 null 时填入
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TableMapping {
+    pub table_id: String,
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
+    #[serde(default)]
+    pub drop: Vec<String>,
+    #[serde(default)]
+    pub defaults: Map<String, Value>,
+}
+
+fn table_columns(schema: &Value) -> Vec<(String, Vec<String>)> {
+    schema
+        .get("tables")
+        .and_then(Value::as_array)
+        .map(|tables| {
+            tables
+                .iter()
+                .filter_map(|table| {
+                    let id = table.get("id")?.as_str()?.to_string();
+                    let columns = table
+                        .get("columns")
+                        .and_then(Value::as_array)
+                        .map(|columns| {
+                            columns
+                                .iter()
+                                .filter_map(|c| c.get("id")?.as_str().map(String::from))
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    Some((id, columns))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn diff_schemas(old: &Value, new: &Value) -> SchemaDiff {
+    let old_tables = table_columns(old);
+    let new_tables = table_columns(new);
+    let mut diff = SchemaDiff::default();
+    for (table_id, old_columns) in &old_tables {
+        let Some((_, new_columns)) = new_tables.iter().find(|(id, _)| id == table_id) else {
+            diff.removed_tables.push(table_id.clone());
+            continue;
+        };
+        let added: Vec<String> = new_columns
+            .iter()
+            .filter(|c| !old_columns.contains(c))
+            .cloned()
+            .collect();
+        let removed: Vec<String> = old_columns
+            .iter()
+            .filter(|c| !new_columns.contains(c))
+            .cloned()
+            .collect();
+        if !added.is_empty() || !removed.is_empty() {
+            diff.tables.push(TableDiff {
+                table_id: table_id.clone(),
+                added,
+                removed,
+            });
+        }
+    }
+    diff.added_tables = new_tables
+        .into_iter()
+        .map(|(id, _)| id)
+        .filter(|id| !old_tables.iter().any(|(old_id, _)| old_id == id))
+        .collect();
+    diff
+}
+
+/// 检查映射是否完整：每个被移除的列都必须改名或显式丢弃，
+/// 改名目标与默认值只能指向新 schema 中的列
+pub fn check_mappings(
+    diff: &SchemaDiff,
+    new_schema: &Value,
+    mappings: &[TableMapping],
+) -> Result<(), String> {
+    let new_tables = table_columns(new_schema);
+    let mut errors = Vec::new();
+    for mapping in mappings {
+        let table_id = &mapping.table_id;
+        let Some((_, columns)) = new_tables.iter().find(|(id, _)| id == table_id) else {
+            errors.push(format!("table {table_id} is not in the new template"));
+            continue;
+        };
+        let removed = diff
+            .table(table_id)
+            .map(|t| t.removed.as_slice())
+            .unwrap_or_default();
+        for (from, to) in &mapping.rename {
+            if !removed.contains(from) {
+                errors.push(format!("column {table_id}.{from} was not removed"));
+            }
+            if !columns.contains(to) {
+                errors.push(format!("column {table_id}.{to} is not in the new template"));
+            }
+        }
+        for column in &mapping.drop {
+            if !removed.contains(column) {
+                errors.push(format!("column {table_id}.{column} was not removed"));
+            }
+        }
+        for column in mapping.defaults.keys() {
+            if !columns.contains(column) {
+                errors.push(format!(
+                    "column {table_id}.{column} is not in the new template"
+                ));
+            }
+        }
+    }
+    for table in &diff.tables {
+        let mapping = mappings.iter().find(|m| m.table_id == table.table_id);
+        for column in &table.removed {
+            let handled =
+                mapping.is_some_and(|m| m.rename.contains_key(column) || m.drop.contains(column));
+            if !handled {
+                errors.push(format!(
+                    "column {}.{column} was removed; rename or drop it",
+                    table.table_id
+                ));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("invalid template upgrade: {}", errors.join("; ")))
+    }
+}
+
+/// 按映射转换一行 `row_data`；非对象的行原样返回，交给校验处理。
+/// 同时返回改名时目标列已有不同值的列（保留目标列原值，调用方应视为冲突）
+pub fn map_row(row_data: &Value, mapping: Option<&TableMapping>) -> (Value, Vec<String>) {
+    let (Some(row), Some(mapping)) = (row_data.as_object(), mapping) else {
+        return (row_data.clone(), Vec::new());
+    };
+    let mut out = row.clone();
+    let mut overwritten = Vec::new();
+    for (from, to) in &mapping.rename {
+        let Some(value) = out.remove(from) else {
+            continue;
+        };
+        match out.get(to) {
+            Some(existing) if !existing.is_null() && !value.is_null() && *existing != value => {
+                overwritten.push(to.clone());
+            }
+            Some(existing) if !existing.is_null() => {}
+            _ => {
+                out.insert(to.clone(), value);
+            }
+        }
+    }
+    for column in &mapping.drop {
+        out.remove(column);
+    }
+    for (column, value) in &mapping.defaults {
+        if out.get(column).is_none_or(Value::is_null) {
+            out.insert(column.clone(), value.clone());
+        }
+    }
+    overwritten.sort();
+    (Value::Object(out), overwritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diff_and_mapping_rename_drop_and_default() {
+        let old = json!({ "tables": [
+            { "id": "profile", "columns": [{ "id": "name" }, { "id": "nick" }, { "id": "age" }] },
+            { "id": "legacy", "columns": [] }
+        ] });
+        let new = json!({ "tables": [
+            { "id": "profile", "columns": [{ "id": "name" }, { "id": "alias" }, { "id": "mood" }] },
+            { "id": "events", "columns": [] }
+        ] });
+        let diff = diff_schemas(&old, &new);
+        assert_eq!(diff.added_tables, vec!["events"]);
+        assert_eq!(diff.removed_tables, vec!["legacy"]);
+        assert_eq!(diff.tables[0].added, vec!["alias", "mood"]);
+        assert_eq!(diff.tables[0].removed, vec!["nick", "age"]);
+
+        let err = check_mappings(&diff, &new, &[]).unwrap_err();
+        assert!(err.contains("profile.nick was removed"), "{err}");
+        let mapping = TableMapping {
+            table_id: "profile".to_string(),
+            rename: HashMap::from([("nick".to_string(), "alias".to_string())]),
+            drop: vec!["age".to_string()],
+            defaults: Map::from_iter([("mood".to_string(), json!("平静"))]),
+        };
+        check_mappings(&diff, &new, std::slice::from_ref(&mapping)).unwrap();
+        let mut wrong = mapping.clone();
+        wrong.rename.insert("name".to_string(), "nope".to_string());
+        let err = check_mappings(&diff, &new, &[wrong]).unwrap_err();
+        assert!(err.contains("profile.name was not removed"), "{err}");
+        assert!(
+            err.contains("profile.nope is not in the new template"),
+            "{err}"
+        );
+
+        let row = json!({ "name": "A", "nick": "a", "age": 3, "mood": null });
+        assert_eq!(
+            map_row(&row, Some(&mapping)),
+            (json!({ "name": "A", "alias": "a", "mood": "平静" }), vec![])
+        );
+        assert_eq!(map_row(&row, None), (row, vec![]));
+        // 目标列已有不同的值时保留原值并报告冲突
+        let clash = json!({ "nick": "a", "alias": "b" });
+        assert_eq!(
+            map_row(&clash, Some(&mapping)),
+            (
+                json!({ "alias": "b", "mood": "平静" }),
+                vec!["alias".to_string()]
+            )
+        );
+    }
+}
@@ -0,0 +1,9 @@
+-- Soft delete (v4 -> v5)
+-- deleted_at 非空表示已移入回收站，超过保留期后由 purge 真正删除
+ALTER TABLE memories ADD COLUMN deleted_at INTEGER;
+ALTER TABLE templates ADD COLUMN deleted_at INTEGER;
+-- 随模板级联移入回收站的记忆，恢复模板时一并恢复
+ALTER TABLE memories ADD COLUMN deleted_with_template BOOLEAN DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_memories_deleted ON memories(deleted_at);
+CREATE INDEX IF NOT EXISTS idx_templates_deleted ON templates(deleted_at);
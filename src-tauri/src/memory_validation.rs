@@ -0,0 +1,334 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// 写入校验的错误码
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationCode {
+    UnknownTemplate,
+    UnknownTable,
+    InvalidRowData,
+    MissingColumn,
+    UnknownColumn,
+    InvalidType,
+    InvalidOption,
+    ScopeMismatch,
+    MaxRowsExceeded,
+    /// 模板升级时改名的目标列已有值
+    RenameConflict,
+}
+
+/// 一条校验失败；index 为批量写入时的行下标
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaViolation {
+    pub code: ViolationCode,
+    pub table_id: String,
+    pub column: Option<String>,
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+impl SchemaViolation {
+    pub fn new(code: ViolationCode, table_id: &str, column: Option<&str>, message: String) -> Self {
+        Self {
+            code,
+            table_id: table_id.to_string(),
+            column: column.map(String::from),
+            index: None,
+            message,
+        }
+    }
+}
+
+/// 校验失败时命令返回的错误体（序列化为 JSON 字符串，前端可 `JSON.parse`）
+#[derive(Debug, Serialize)]
+pub struct MemoryValidationError {
+    pub code: &'static str,
+    pub violations: Vec<SchemaViolation>,
+}
+
+impl MemoryValidationError {
+    pub fn into_message(violations: Vec<SchemaViolation>) -> String {
+        let error = Self {
+            code: "memory_validation",
+            violations,
+        };
+        serde_json::to_string(&error).unwrap_or_else(|_| "memory validation failed".to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnRule {
+    pub id: String,
+    pub kind: String,
+    pub required: bool,
+    pub options: Vec<String>,
+}
+
+/// 模板 schema 中一张表的约束
+#[derive(Debug, Clone)]
+pub struct TableRules {
+    pub id: String,
+    pub scope: Option<String>,
+    pub max_rows: Option<usize>,
+    /// 严格模式下拒绝未声明的列；表上的 `strict` 优先于模板 schema 顶层的设置
+    pub strict: bool,
+    pub columns: Vec<ColumnRule>,
+}
+
+/// 从模板 schema 中取出表定义。schema 没有声明任何表时视为自由格式，返回 None
+pub fn table_rules(schema: &Value, table_id: &str) -> Result<Option<TableRules>, SchemaViolation> {
+    let tables = match schema.get("tables").and_then(Value::as_array) {
+        Some(tables) if !tables.is_empty() => tables,
+        _ => return Ok(None),
+    };
+    let table = tables
+        .iter()
+        .find(|t| t.get("id").and_then(Value::as_str) == Some(table_id))
+        .ok_or_else(|| {
+            SchemaViolation::new(
+                ViolationCode::UnknownTable,
+                table_id,
+                None,
+                format!("table {table_id} is not defined by the template"),
+            )
+        })?;
+    let columns = table
+        .get("columns")
+        .and_then(Value::as_array)
+        .map(|columns| columns.iter().filter_map(column_rule).collect())
+        .unwrap_or_default();
+    Ok(Some(TableRules {
+        id: table_id.to_string(),
+        scope: table
+            .get("scope")
+            .and_then(Value::as_str)
+            .map(str::to_lowercase),
+        max_rows: table
+            .get("maxRows")
+            .and_then(Value::as_u64)
+            .and_then(|n| usize::try_from(n).ok())
+            .filter(|n| *n > 0),
+        strict: table
+            .get("strict")
+            .or_else(|| schema.get("strict"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        columns,
+    }))
+}
+
+fn column_rule(column: &Value) -> Option<ColumnRule> {
+    Some(ColumnRule {
+        id: column.get("id")?.as_str()?.to_string(),
+        kind: column
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("text")
+            .to_lowercase(),
+        required: column
+            .get("required")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        options: column
+            .get("options")
+            .and_then(Value::as_array)
+            .map(|options| {
+                options
+                    .iter()
+                    .filter_map(|o| o.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+/// scope 为 global / contact / group 时，`contact_id` 与 `group_id` 必须与之对应
+pub fn check_scope(
+    rules: &TableRules,
+    contact_id: Option<&str>,
+    group_id: Option<&str>,
+) -> Option<SchemaViolation> {
+    let ok = match rules.scope.as_deref() {
+        Some("global") => contact_id.is_none() && group_id.is_none(),
+        Some("contact") => contact_id.is_some() && group_id.is_none(),
+        Some("group") => group_id.is_some() && contact_id.is_none(),
+        _ => true,
+    };
+    (!ok).then(|| {
+        SchemaViolation::new(
+            ViolationCode::ScopeMismatch,
+            &rules.id,
+            None,
+            format!(
+                "table {} has scope {}",
+                rules.id,
+                rules.scope.as_deref().unwrap_or_default()
+            ),
+        )
+    })
+}
+
+/// 校验 `row_data` 的列：必填、类型、可选项；严格模式下还检查未声明的列
+pub fn check_row(rules: &TableRules, row_data: &Value) -> Vec<SchemaViolation> {
+    let Some(row) = row_data.as_object() else {
+        return vec![SchemaViolation::new(
+            ViolationCode::InvalidRowData,
+            &rules.id,
+            None,
+            "row_data must be an object".to_string(),
+        )];
+    };
+    let mut violations = Vec::new();
+    for key in row.keys() {
+        if rules.strict && !rules.columns.iter().any(|c| &c.id == key) {
+            violations.push(SchemaViolation::new(
+                ViolationCode::UnknownColumn,
+                &rules.id,
+                Some(key),
+                format!("column {key} is not defined by table {}", rules.id),
+            ));
+        }
+    }
+    for column in &rules.columns {
+        let value = row.get(&column.id).filter(|v| !v.is_null());
+        let Some(value) = value else {
+            if column.required {
+                violations.push(missing(rules, column));
+            }
+            continue;
+        };
+        if let Some(violation) = check_value(rules, column, value) {
+            violations.push(violation);
+        }
+    }
+    violations
+}
+
+fn missing(rules: &TableRules, column: &ColumnRule) -> SchemaViolation {
+    SchemaViolation::new(
+        ViolationCode::MissingColumn,
+        &rules.id,
+        Some(&column.id),
+        format!("column {} is required", column.id),
+    )
+}
+
+fn check_value(rules: &TableRules, column: &ColumnRule, value: &Value) -> Option<SchemaViolation> {
+    let type_ok = match column.kind.as_str() {
+        "text" | "multiline" | "select" => value.is_string(),
+        "number" => value.is_number(),
+        "boolean" | "checkbox" => value.is_boolean(),
+        // 未知类型不做限制，便于模板扩展
+        _ => true,
+    };
+    if !type_ok {
+        return Some(SchemaViolation::new(
+            ViolationCode::InvalidType,
+            &rules.id,
+            Some(&column.id),
+            format!("column {} expects {}", column.id, column.kind),
+        ));
+    }
+    let text = value.as_str().unwrap_or_default();
+    if column.required && value.is_string() && text.trim().is_empty() {
+        return Some(missing(rules, column));
+    }
+    if column.kind == "select"
+        && !column.options.is_empty()
+        && !text.is_empty()
+        && !column.options.iter().any(|o| o == text)
+    {
+        return Some(SchemaViolation::new(
+            ViolationCode::InvalidOption,
+            &rules.id,
+            Some(&column.id),
+            format!("column {} does not allow {text}", column.id),
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Value {
+        json!({
+            "meta": { "id": "tpl" },
+            "tables": [
+                {
+                    "id": "profile",
+                    "scope": "contact",
+                    "maxRows": 1,
+                    "columns": [
+                        { "id": "name", "type": "text", "required": true },
+                        { "id": "gender", "type": "select", "options": ["男", "女"] },
+                        { "id": "age", "type": "number" },
+                        { "id": "extra", "type": "custom" }
+                    ]
+                }
+            ]
+        })
+    }
+
+    fn codes(violations: &[SchemaViolation]) -> Vec<(ViolationCode, Option<&str>)> {
+        violations
+            .iter()
+            .map(|v| (v.code, v.column.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn rules_check_columns_types_and_scope() {
+        let rules = table_rules(&schema(), "profile").unwrap().unwrap();
+        assert_eq!(rules.max_rows, Some(1));
+        assert!(check_row(&rules, &json!({ "name": "A", "gender": "男", "age": 3 })).is_empty());
+        assert!(check_row(&rules, &json!({ "name": "A", "extra": [1, 2] })).is_empty());
+
+        let violations = check_row(
+            &rules,
+            &json!({ "name": " ", "gender": "猫", "age": "3", "mood": "ok" }),
+        );
+        assert_eq!(
+            codes(&violations),
+            vec![
+                (ViolationCode::MissingColumn, Some("name")),
+                (ViolationCode::InvalidOption, Some("gender")),
+                (ViolationCode::InvalidType, Some("age")),
+            ]
+        );
+        // 只有声明 strict 的模板才拒绝未声明的列
+        let mut strict_schema = schema();
+        strict_schema["strict"] = json!(true);
+        let strict = table_rules(&strict_schema, "profile").unwrap().unwrap();
+        assert_eq!(
+            codes(&check_row(&strict, &json!({ "name": "A", "mood": "ok" }))),
+            vec![(ViolationCode::UnknownColumn, Some("mood"))]
+        );
+        strict_schema["tables"][0]["strict"] = json!(false);
+        let relaxed = table_rules(&strict_schema, "profile").unwrap().unwrap();
+        assert!(check_row(&relaxed, &json!({ "name": "A", "mood": "ok" })).is_empty());
+        assert_eq!(
+            codes(&check_row(&rules, &json!("text"))),
+            vec![(ViolationCode::InvalidRowData, None)]
+        );
+
+        assert!(check_scope(&rules, Some("c1"), None).is_none());
+        assert!(check_scope(&rules, None, Some("g1")).is_some());
+        assert!(check_scope(&rules, None, None).is_some());
+
+        let unknown = table_rules(&schema(), "nope").unwrap_err();
+        assert_eq!(unknown.code, ViolationCode::UnknownTable);
+        // 未声明表的模板不做约束
+        assert!(table_rules(&json!({ "tables": [] }), "any")
+            .unwrap()
+            .is_none());
+
+        let message = MemoryValidationError::into_message(vec![unknown]);
+        let parsed: Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(parsed["code"], "memory_validation");
+        assert_eq!(parsed["violations"][0]["code"], "unknown_table");
+    }
+}
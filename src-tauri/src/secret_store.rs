@@ -0,0 +1,120 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use base64::Engine;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+// 凭据加密（WebDAV / S3 等同步配置中的密码与密钥）。
+// 主密钥以明文保存在数据目录的 `MASTER_KEY_FILE` 中，这只是混淆而非保护：
+// - 能防止凭据随资料包导出、同步或被直接从配置文件中读出
+// - 不能防御可以读取数据目录的人或程序，拿到主密钥文件即可解密全部凭据
+// 系统钥匙串（macOS Keychain / Windows Credential Manager / Secret Service）可提供真正的
+// 保护，接入前文件方案是唯一实现；Unix 上主密钥文件创建时即为 0600
+
+/// 本机主密钥文件：只保留在本机，不参与资料包导出与同步
+pub const MASTER_KEY_FILE: &str = "secret_keyring_master_v1.json";
+const SEALED_PREFIX: &str = "sealed:v1:";
+const NONCE_LEN: usize = 12;
+
+static MASTER_KEY_LOCK: Mutex<()> = Mutex::new(());
+
+fn load_or_create_key(data_dir: &Path) -> Result<[u8; 32], String> {
+    let _guard = MASTER_KEY_LOCK
+        .lock()
+        .map_err(|_| "secret key lock poisoned".to_string())?;
+    let path = data_dir.join(MASTER_KEY_FILE);
+    if let Ok(raw) = fs::read_to_string(&path) {
+        let value: serde_json::Value =
+            serde_json::from_str(&raw).map_err(|e| format!("invalid secret key file: {e}"))?;
+        let encoded = value
+            .get("key")
+            .and_then(serde_json::Value::as_str)
+            .ok_or("secret key missing")?;
+        let bytes = BASE64_ENGINE.decode(encoded).map_err(|e| e.to_string())?;
+        return bytes
+            .try_into()
+            .map_err(|_| "invalid secret key length".to_string());
+    }
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    fs::create_dir_all(data_dir).map_err(|e| e.to_string())?;
+    let json = serde_json::json!({ "version": 1, "key": BASE64_ENGINE.encode(key) });
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&path)
+        .and_then(|mut file| file.write_all(json.to_string().as_bytes()))
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+/// 用本机主密钥加密凭据（AES-256-GCM）；安全性取决于主密钥文件，见文件开头说明
+pub fn seal(data_dir: &Path, plaintext: &str) -> Result<String, String> {
+    let key = load_or_create_key(data_dir)?;
+    let cipher = Aes256Gcm::new((&key).into());
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| "encrypt secret failed".to_string())?;
+    let mut payload = nonce.to_vec();
+    payload.extend_from_slice(&ciphertext);
+    Ok(format!("{SEALED_PREFIX}{}", BASE64_ENGINE.encode(payload)))
+}
+
+pub fn open(data_dir: &Path, sealed: &str) -> Result<String, String> {
+    let encoded = sealed
+        .strip_prefix(SEALED_PREFIX)
+        .ok_or("unsupported secret format")?;
+    let payload = BASE64_ENGINE.decode(encoded).map_err(|e| e.to_string())?;
+    if payload.len() <= NONCE_LEN {
+        return Err("secret payload too short".to_string());
+    }
+    let key = load_or_create_key(data_dir)?;
+    let cipher = Aes256Gcm::new((&key).into());
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "secret cannot be decrypted on this device".to_string())?;
+    String::from_utf8(plaintext).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::make_temp_dir;
+
+    #[test]
+    fn seal_roundtrip_is_device_bound() {
+        let dir = make_temp_dir("seal");
+        let sealed = seal(&dir, "hunter2").unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert!(!sealed.contains("hunter2"));
+        assert_ne!(sealed, seal(&dir, "hunter2").unwrap());
+        assert_eq!(open(&dir, &sealed).unwrap(), "hunter2");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join(MASTER_KEY_FILE))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let other = make_temp_dir("seal_other");
+        assert!(open(&other, &sealed).is_err());
+        assert!(open(&dir, "hunter2").is_err());
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&other);
+    }
+}
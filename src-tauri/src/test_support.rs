@@ -0,0 +1,22 @@
+//! 测试共用的辅助函数
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static TEMP_DIR_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 在系统临时目录下创建唯一的测试目录（时间戳 + 进程号 + 序号）
+pub fn make_temp_dir(tag: &str) -> PathBuf {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let seq = TEMP_DIR_SEQ.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!(
+        "chatapp_test_{tag}_{stamp}_{}_{seq}",
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
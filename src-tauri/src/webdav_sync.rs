@@ -0,0 +1,1022 @@
+use crate::backup_chain::{self, DeltaPlan, FileState, LocalFile};
+use crate::bundle::{self, BundleFileIndex, BundleSelection};
+use crate::secret_store;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use reqwest::header::{HeaderMap, ETAG, IF_MATCH, IF_NONE_MATCH};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 本机同步配置与状态（不参与资料包导出）
+pub const CONFIG_FILE: &str = "sync_webdav_v1.json";
+pub const STATE_FILE: &str = "sync_state_v1.json";
+pub const CONFLICT_ERROR: &str = "remote changed during sync";
+
+const MANIFEST_NAME: &str = "sync.json";
+const SYNC_FORMAT: &str = "tauri-chat-app-sync-v1";
+const DEFAULT_REMOTE_DIR: &str = "tauri-chat-app";
+
+/// 落盘的 `WebDAV` 配置；密码用本机主密钥加密
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct StoredConfig {
+    url: String,
+    username: String,
+    password: Option<String>,
+    remote_dir: String,
+}
+
+/// 前端提交的配置；password 为 None 时保留原密码，空字符串表示清除
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WebDavConfigInput {
+    pub url: String,
+    pub username: String,
+    pub password: Option<String>,
+    pub remote_dir: Option<String>,
+}
+
+/// 返回给前端的配置（不含密码）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebDavConfigView {
+    pub url: String,
+    pub username: String,
+    pub remote_dir: String,
+    pub has_password: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebDavConfig {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub remote_dir: String,
+}
+
+fn read_stored(data_dir: &Path) -> StoredConfig {
+    fs::read_to_string(data_dir.join(CONFIG_FILE))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn view(stored: &StoredConfig) -> WebDavConfigView {
+    WebDavConfigView {
+        url: stored.url.clone(),
+        username: stored.username.clone(),
+        remote_dir: stored.remote_dir.clone(),
+        has_password: stored.password.is_some(),
+    }
+}
+
+pub fn load_config_view(data_dir: &Path) -> WebDavConfigView {
+    view(&read_stored(data_dir))
+}
+
+pub fn save_config(data_dir: &Path, input: WebDavConfigInput) -> Result<WebDavConfigView, String> {
+    let url = input.url.trim().to_string();
+    if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err("webdav url must start with http:// or https://".to_string());
+    }
+    let remote_dir = input
+        .remote_dir
+        .map(|dir| dir.trim().trim_matches('/').to_string())
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or_else(|| DEFAULT_REMOTE_DIR.to_string());
+    if remote_dir.split('/').any(|seg| seg == ".." || seg == ".") {
+        return Err("invalid webdav remote dir".to_string());
+    }
+    let password = match input.password {
+        None => read_stored(data_dir).password,
+        Some(password) if password.is_empty() => None,
+        Some(password) => Some(secret_store::seal(data_dir, &password)?),
+    };
+    let stored = StoredConfig {
+        url,
+        username: input.username.trim().to_string(),
+        password,
+        remote_dir,
+    };
+    let json = serde_json::to_string_pretty(&stored).map_err(|e| e.to_string())?;
+    fs::write(data_dir.join(CONFIG_FILE), json).map_err(|e| e.to_string())?;
+    Ok(view(&stored))
+}
+
+/// 读取可用的配置；未配置 URL 时返回 None
+pub fn load_config(data_dir: &Path) -> Result<Option<WebDavConfig>, String> {
+    let stored = read_stored(data_dir);
+    if stored.url.is_empty() {
+        return Ok(None);
+    }
+    let password = match stored.password.as_deref() {
+        Some(sealed) => secret_store::open(data_dir, sealed)?,
+        None => String::new(),
+    };
+    Ok(Some(WebDavConfig {
+        url: stored.url,
+        username: stored.username,
+        password,
+        remote_dir: stored.remote_dir,
+    }))
+}
+
+/// 上一次同步后的本地状态
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SyncState {
+    pub device_id: String,
+    pub last_revision: Option<u64>,
+    pub last_synced_at: Option<String>,
+    /// 上次同步时与远端一致的文件清单
+    pub files: BTreeMap<String, FileState>,
+}
+
+pub fn load_state(data_dir: &Path) -> SyncState {
+    let mut state: SyncState = fs::read_to_string(data_dir.join(STATE_FILE))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default();
+    if state.device_id.is_empty() {
+        let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        state.device_id = format!("dev_{nanos:x}");
+    }
+    state
+}
+
+/// 每次推送使用唯一的资料包对象名，两台设备抢同一 revision 时不会互相覆盖或误删
+pub fn bundle_object_name(revision: u64, device_id: &str) -> String {
+    let device: String = device_id
+        .chars()
+        .filter(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_'))
+        .collect();
+    format!("bundle_{revision}_{device}_{:016x}.zip", OsRng.next_u64())
+}
+
+pub fn save_state(data_dir: &Path, state: &SyncState) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+    fs::write(data_dir.join(STATE_FILE), json).map_err(|e| e.to_string())
+}
+
+/// 远端 sync.json：当前版本号与资料包内逐文件摘要
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteManifest {
+    pub format: String,
+    pub revision: u64,
+    pub device_id: String,
+    pub updated_at: String,
+    pub bundle: String,
+    pub bundle_sha256: String,
+    pub files: BTreeMap<String, String>,
+}
+
+impl RemoteManifest {
+    pub fn new(
+        revision: u64,
+        device_id: &str,
+        bundle: &str,
+        bundle_sha256: String,
+        files: &BTreeMap<String, FileState>,
+    ) -> Self {
+        Self {
+            format: SYNC_FORMAT.to_string(),
+            revision,
+            device_id: device_id.to_string(),
+            updated_at: chrono::Utc::now().to_rfc3339(),
+            bundle: bundle.to_string(),
+            bundle_sha256,
+            files: files
+                .iter()
+                .map(|(rel, state)| (rel.clone(), state.sha256.clone()))
+                .collect(),
+        }
+    }
+
+    fn parse(raw: &[u8]) -> Result<Self, String> {
+        let manifest: Self =
+            serde_json::from_slice(raw).map_err(|e| format!("invalid remote sync.json: {e}"))?;
+        if manifest.format != SYNC_FORMAT {
+            return Err(format!(
+                "unsupported remote sync format: {}",
+                manifest.format
+            ));
+        }
+        if manifest.bundle.contains('/') || manifest.bundle.contains("..") {
+            return Err("invalid remote bundle name".to_string());
+        }
+        Ok(manifest)
+    }
+}
+
+/// 以远端清单为基线；本地内容与远端一致的文件沿用本地大小/修改时间
+pub fn baseline_from_remote(
+    remote: &RemoteManifest,
+    local: &BTreeMap<String, FileState>,
+) -> BTreeMap<String, FileState> {
+    remote
+        .files
+        .iter()
+        .map(|(rel, sha)| {
+            let state = match local.get(rel) {
+                Some(state) if &state.sha256 == sha => state.clone(),
+                _ => FileState {
+                    sha256: sha.clone(),
+                    size: 0,
+                    modified: None,
+                    stored_in: remote.revision.to_string(),
+                },
+            };
+            (rel.clone(), state)
+        })
+        .collect()
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncPhase {
+    NotConfigured,
+    InSync,
+    LocalAhead,
+    RemoteAhead,
+    /// 双方都有修改但不涉及同一文件
+    Diverged,
+    /// 同一文件在两端都被修改
+    Conflict,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    pub state: SyncPhase,
+    pub remote_revision: Option<u64>,
+    pub remote_device_id: Option<String>,
+    pub last_revision: Option<u64>,
+    pub last_synced_at: Option<String>,
+    pub local_changed: Vec<String>,
+    pub remote_changed: Vec<String>,
+    pub conflicts: Vec<String>,
+}
+
+impl SyncStatus {
+    pub fn not_configured(state: &SyncState) -> Self {
+        Self {
+            state: SyncPhase::NotConfigured,
+            remote_revision: None,
+            remote_device_id: None,
+            last_revision: state.last_revision,
+            last_synced_at: state.last_synced_at.clone(),
+            local_changed: Vec::new(),
+            remote_changed: Vec::new(),
+            conflicts: Vec::new(),
+        }
+    }
+
+    /// 远端有未拉取的修改时拒绝推送（除非强制）
+    pub fn blocks_push(&self) -> bool {
+        matches!(
+            self.state,
+            SyncPhase::RemoteAhead | SyncPhase::Diverged | SyncPhase::Conflict
+        )
+    }
+
+    pub fn push_blocked_error(&self) -> String {
+        format!(
+            "remote has newer changes (revision {}), pull first; conflicts: {}",
+            self.remote_revision.unwrap_or_default(),
+            self.conflicts.len()
+        )
+    }
+}
+
+/// 对比上次同步基线、当前本地快照与远端清单
+pub fn compute_status(
+    state: &SyncState,
+    local: &DeltaPlan,
+    remote: Option<&RemoteManifest>,
+) -> SyncStatus {
+    let local_changed: BTreeSet<String> = local
+        .changed
+        .iter()
+        .chain(local.removed.iter())
+        .cloned()
+        .collect();
+    let remote_newer = remote.filter(|r| Some(r.revision) != state.last_revision);
+    let mut remote_changed = BTreeSet::new();
+    if let Some(remote) = remote_newer {
+        for (rel, sha) in &remote.files {
+            if state.files.get(rel).map(|s| &s.sha256) != Some(sha) {
+                remote_changed.insert(rel.clone());
+            }
+        }
+        for rel in state.files.keys() {
+            if !remote.files.contains_key(rel) {
+                remote_changed.insert(rel.clone());
+            }
+        }
+    }
+    let conflicts: Vec<String> = local_changed
+        .intersection(&remote_changed)
+        .filter(|rel| {
+            let local_sha = local.files.get(*rel).map(|s| &s.sha256);
+            let remote_sha = remote_newer.and_then(|r| r.files.get(*rel));
+            local_sha != remote_sha
+        })
+        .cloned()
+        .collect();
+    let phase = if conflicts.is_empty() {
+        match (!local_changed.is_empty(), remote_newer.is_some()) {
+            (false, false) => SyncPhase::InSync,
+            (true, false) => SyncPhase::LocalAhead,
+            (false, true) => SyncPhase::RemoteAhead,
+            (true, true) => SyncPhase::Diverged,
+        }
+    } else {
+        SyncPhase::Conflict
+    };
+    SyncStatus {
+        state: phase,
+        remote_revision: remote.map(|r| r.revision),
+        remote_device_id: remote.map(|r| r.device_id.clone()),
+        last_revision: state.last_revision,
+        last_synced_at: state.last_synced_at.clone(),
+        local_changed: local_changed.into_iter().collect(),
+        remote_changed: remote_changed.into_iter().collect(),
+        conflicts,
+    }
+}
+
+/// 写入远端文件时的前置条件（用于并发冲突检测）
+pub enum Precondition {
+    IfMatch(String),
+    IfNoneMatch,
+}
+
+fn encode_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            out.push(char::from(byte));
+        } else {
+            let _ = write!(out, "%{byte:02X}");
+        }
+    }
+    out
+}
+
+fn etag_of(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+pub struct WebDavClient {
+    client: reqwest::Client,
+    root: String,
+    segments: Vec<String>,
+    username: String,
+    password: String,
+}
+
+impl WebDavClient {
+    pub fn new(config: &WebDavConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(std::time::Duration::from_secs(15))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            client,
+            root: format!("{}/", config.url.trim_end_matches('/')),
+            segments: config
+                .remote_dir
+                .split('/')
+                .filter(|seg| !seg.is_empty())
+                .map(encode_segment)
+                .collect(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+        })
+    }
+
+    fn dir_url(&self, depth: usize) -> String {
+        let mut url = self.root.clone();
+        for seg in &self.segments[..depth] {
+            url.push_str(seg);
+            url.push('/');
+        }
+        url
+    }
+
+    fn file_url(&self, name: &str) -> String {
+        format!(
+            "{}{}",
+            self.dir_url(self.segments.len()),
+            encode_segment(name)
+        )
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let builder = self.client.request(method, url);
+        if self.username.is_empty() {
+            builder
+        } else {
+            builder.basic_auth(&self.username, Some(&self.password))
+        }
+    }
+
+    fn check_status(status: StatusCode, action: &str) -> Result<(), String> {
+        match status {
+            s if s.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err("webdav authentication failed".to_string())
+            }
+            StatusCode::PRECONDITION_FAILED => Err(CONFLICT_ERROR.to_string()),
+            s => Err(format!("webdav {action} failed: {s}")),
+        }
+    }
+
+    /// 逐级创建远端目录（已存在时服务器返回 405）
+    pub async fn ensure_dir(&self) -> Result<(), String> {
+        let mkcol = Method::from_bytes(b"MKCOL").map_err(|e| e.to_string())?;
+        for depth in 1..=self.segments.len() {
+            let resp = self
+                .request(mkcol.clone(), &self.dir_url(depth))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if resp.status() == StatusCode::METHOD_NOT_ALLOWED {
+                continue;
+            }
+            Self::check_status(resp.status(), "MKCOL")?;
+        }
+        Ok(())
+    }
+
+    /// 下载远端文件；不存在时返回 None
+    pub async fn get(&self, name: &str) -> Result<Option<(Vec<u8>, Option<String>)>, String> {
+        let resp = self
+            .request(Method::GET, &self.file_url(name))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Self::check_status(resp.status(), &format!("GET {name}"))?;
+        let etag = etag_of(resp.headers());
+        let bytes = resp.bytes().await.map_err(|e| e.to_string())?;
+        Ok(Some((bytes.to_vec(), etag)))
+    }
+
+    pub async fn put(
+        &self,
+        name: &str,
+        body: Vec<u8>,
+        precondition: Precondition,
+    ) -> Result<(), String> {
+        let mut req = self.request(Method::PUT, &self.file_url(name)).body(body);
+        req = match precondition {
+            Precondition::IfMatch(etag) => req.header(IF_MATCH, etag),
+            Precondition::IfNoneMatch => req.header(IF_NONE_MATCH, "*"),
+        };
+        let resp = req.send().await.map_err(|e| e.to_string())?;
+        Self::check_status(resp.status(), &format!("PUT {name}"))
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<(), String> {
+        let resp = self
+            .request(Method::DELETE, &self.file_url(name))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        Self::check_status(resp.status(), &format!("DELETE {name}"))
+    }
+
+    pub async fn fetch_manifest(&self) -> Result<Option<(RemoteManifest, Option<String>)>, String> {
+        let Some((raw, etag)) = self.get(MANIFEST_NAME).await? else {
+            return Ok(None);
+        };
+        Ok(Some((RemoteManifest::parse(&raw)?, etag)))
+    }
+
+    /// 写入 sync.json；以读取时的 `ETag` 作为前置条件，期间被其它设备修改则返回冲突
+    pub async fn put_manifest(
+        &self,
+        manifest: &RemoteManifest,
+        previous: Option<&(RemoteManifest, Option<String>)>,
+    ) -> Result<(), String> {
+        let precondition = match previous {
+            None => Precondition::IfNoneMatch,
+            Some((_, Some(etag))) => Precondition::IfMatch(etag.clone()),
+            // 服务器不返回 ETag 时无法检测并发写入，宁可失败也不盲目覆盖
+            Some((_, None)) => {
+                return Err("remote sync.json has no ETag; refusing to overwrite".to_string())
+            }
+        };
+        let body = serde_json::to_vec_pretty(manifest).map_err(|e| e.to_string())?;
+        self.put(MANIFEST_NAME, body, precondition).await
+    }
+}
+
+/// 远端 sync.json 及读取时的 `ETag`
+pub type RemoteSnapshot = (RemoteManifest, Option<String>);
+
+/// 按本机配置创建客户端
+pub fn client(data_dir: &Path) -> Result<WebDavClient, String> {
+    let config = load_config(data_dir)?.ok_or("webdav sync is not configured")?;
+    WebDavClient::new(&config)
+}
+
+/// 本地文件相对上次同步基线的变化
+pub fn local_snapshot(
+    data_dir: &Path,
+    state: &SyncState,
+    label: &str,
+) -> Result<DeltaPlan, String> {
+    let selection = BundleSelection::default();
+    let local: Vec<LocalFile> = crate::commands::list_local_files(data_dir, data_dir, &selection)?
+        .iter()
+        .map(|rel| LocalFile::stat(data_dir, rel))
+        .collect();
+    backup_chain::plan_delta(Some(&state.files), &local, label, |rel| {
+        bundle::sha256_file(&data_dir.join(rel))
+    })
+}
+
+/// 同步状态：本地与远端相对上次同步的变化及冲突文件
+pub async fn status(data_dir: PathBuf) -> Result<SyncStatus, String> {
+    let state = load_state(&data_dir);
+    if load_config(&data_dir)?.is_none() {
+        return Ok(SyncStatus::not_configured(&state));
+    }
+    let remote = client(&data_dir)?.fetch_manifest().await?;
+    let snapshot_state = state.clone();
+    let local = tauri::async_runtime::spawn_blocking(move || {
+        local_snapshot(&data_dir, &snapshot_state, "local")
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    Ok(compute_status(
+        &state,
+        &local,
+        remote.as_ref().map(|(manifest, _)| manifest),
+    ))
+}
+
+/// 推送前检查本地变化（阻塞调用）；无需推送时返回 None，
+/// 否则由 `write_bundle` 写出完整资料包，返回待上传的快照与包路径
+pub fn prepare_push<F>(
+    data_dir: &Path,
+    state: &SyncState,
+    remote: Option<&RemoteManifest>,
+    revision: u64,
+    force: bool,
+    write_bundle: F,
+) -> Result<Option<(DeltaPlan, PathBuf)>, String>
+where
+    F: FnOnce() -> Result<(PathBuf, BundleFileIndex), String>,
+{
+    let mut local = local_snapshot(data_dir, state, &revision.to_string())?;
+    let status = compute_status(state, &local, remote);
+    if !force && status.blocks_push() {
+        return Err(status.push_blocked_error());
+    }
+    if !force && status.state == SyncPhase::InSync {
+        return Ok(None);
+    }
+    let (path, index) = write_bundle()?;
+    local.apply_index(
+        &index,
+        data_dir,
+        &revision.to_string(),
+        Some(&state.files),
+        true,
+    );
+    Ok(Some((local, path)))
+}
+
+/// 上传资料包并写入新的 sync.json，成功后保存同步基线，返回上传字节数。
+/// sync.json 写入失败时删除刚上传的包；本地包文件总会被删除
+pub async fn upload_push(
+    client: &WebDavClient,
+    data_dir: &Path,
+    state: &mut SyncState,
+    remote: Option<&RemoteSnapshot>,
+    revision: u64,
+    local: DeltaPlan,
+    bundle_path: &Path,
+) -> Result<u64, String> {
+    let bundle_name = bundle_object_name(revision, &state.device_id);
+    let uploaded = async {
+        let bytes = fs::read(bundle_path).map_err(|e| e.to_string())?;
+        let size = bytes.len() as u64;
+        let sha = sha256_hex(&bytes);
+        client
+            .put(&bundle_name, bytes, Precondition::IfNoneMatch)
+            .await?;
+        let manifest =
+            RemoteManifest::new(revision, &state.device_id, &bundle_name, sha, &local.files);
+        if let Err(err) = client.put_manifest(&manifest, remote).await {
+            let _ = client.delete(&bundle_name).await;
+            return Err(err);
+        }
+        if let Some((previous, _)) = remote {
+            if previous.bundle != bundle_name {
+                let _ = client.delete(&previous.bundle).await;
+            }
+        }
+        Ok(size)
+    }
+    .await;
+    let _ = fs::remove_file(bundle_path);
+    let bytes = uploaded?;
+
+    state.last_revision = Some(revision);
+    state.last_synced_at = Some(chrono::Utc::now().to_rfc3339());
+    state.files = local.files;
+    save_state(data_dir, state)?;
+    Ok(bytes)
+}
+
+/// 读取远端清单并下载、校验资料包；已是上次同步的版本且未强制时不下载
+pub async fn fetch_pull(
+    client: &WebDavClient,
+    state: &SyncState,
+    force: bool,
+) -> Result<(RemoteManifest, Option<Vec<u8>>), String> {
+    let (remote, _) = client
+        .fetch_manifest()
+        .await?
+        .ok_or("remote has no synced data yet")?;
+    if !force && state.last_revision == Some(remote.revision) {
+        return Ok((remote, None));
+    }
+    let (bytes, _) = client
+        .get(&remote.bundle)
+        .await?
+        .ok_or("remote bundle is missing")?;
+    if sha256_hex(&bytes) != remote.bundle_sha256 {
+        return Err("remote bundle checksum mismatch".to_string());
+    }
+    Ok((remote, Some(bytes)))
+}
+
+/// 导入拉取的资料包并保存同步基线（阻塞调用）。
+/// 未指定 mode 时，本地有未推送的修改按 merge 导入，否则 replace
+pub fn apply_pull<T, F>(
+    data_dir: &Path,
+    mut state: SyncState,
+    remote: &RemoteManifest,
+    mode: Option<String>,
+    import: F,
+) -> Result<T, String>
+where
+    F: FnOnce(String) -> Result<T, String>,
+{
+    let before = local_snapshot(data_dir, &state, "local")?;
+    let dirty = !before.changed.is_empty() || !before.removed.is_empty();
+    let mode = mode.unwrap_or_else(|| if dirty { "merge" } else { "replace" }.to_string());
+    let result = import(mode)?;
+    // 同步基线取远端清单，merge 后本地多出的修改会在下次状态中显示为待推送
+    let after = local_snapshot(data_dir, &state, "local")?;
+    state.files = baseline_from_remote(remote, &after.files);
+    state.last_revision = Some(remote.revision);
+    state.last_synced_at = Some(chrono::Utc::now().to_rfc3339());
+    save_state(data_dir, &state)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+
+    /// 最小 `WebDAV` 替身：内存存储，支持 MKCOL/PUT/GET/DELETE、`ETag` 与 Basic 认证
+    #[derive(Default)]
+    struct DavStore {
+        dirs: BTreeSet<String>,
+        files: HashMap<String, (Vec<u8>, u64)>,
+        next_etag: u64,
+    }
+
+    fn respond(stream: &mut TcpStream, code: u16, etag: Option<u64>, body: &[u8]) {
+        let etag = etag
+            .map(|e| format!("ETag: \"{e}\"\r\n"))
+            .unwrap_or_default();
+        let head = format!(
+            "HTTP/1.1 {code} X\r\nContent-Length: {}\r\nConnection: close\r\n{etag}\r\n",
+            body.len()
+        );
+        let _ = stream.write_all(head.as_bytes());
+        let _ = stream.write_all(body);
+    }
+
+    fn handle(stream: &mut TcpStream, store: &Mutex<DavStore>, auth: &str) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string();
+        let mut headers = HashMap::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((k, v)) = header.split_once(':') {
+                headers.insert(k.trim().to_ascii_lowercase(), v.trim().to_string());
+            }
+        }
+        let len: usize = headers
+            .get("content-length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0u8; len];
+        reader.read_exact(&mut body).unwrap();
+        if headers.get("authorization").map(String::as_str) != Some(auth) {
+            return respond(stream, 401, None, b"");
+        }
+        let mut store = store.lock().unwrap();
+        let parent = path
+            .rsplit_once('/')
+            .map(|(p, _)| p.to_string())
+            .unwrap_or_default();
+        let parent_ok = parent.is_empty() || store.dirs.contains(&parent);
+        let current = store.files.get(&path).map(|(_, etag)| *etag);
+        match method.as_str() {
+            "MKCOL" if store.dirs.contains(&path) => respond(stream, 405, None, b""),
+            "MKCOL" if !parent_ok => respond(stream, 409, None, b""),
+            "MKCOL" => {
+                store.dirs.insert(path);
+                respond(stream, 201, None, b"");
+            }
+            "PUT" => {
+                let if_match = headers
+                    .get("if-match")
+                    .map(|v| v.trim_matches('"').to_string());
+                let stale = if_match.is_some_and(|m| current.map(|e| e.to_string()) != Some(m));
+                let exists = headers.contains_key("if-none-match") && current.is_some();
+                if !parent_ok {
+                    respond(stream, 409, None, b"");
+                } else if stale || exists {
+                    respond(stream, 412, None, b"");
+                } else {
+                    store.next_etag += 1;
+                    let etag = store.next_etag;
+                    store.files.insert(path, (body, etag));
+                    respond(stream, 201, Some(etag), b"");
+                }
+            }
+            "GET" => match store.files.get(&path) {
+                Some((data, etag)) => respond(stream, 200, Some(*etag), data),
+                None => respond(stream, 404, None, b""),
+            },
+            "DELETE" => {
+                let code = if store.files.remove(&path).is_some() {
+                    204
+                } else {
+                    404
+                };
+                respond(stream, code, None, b"");
+            }
+            _ => respond(stream, 405, None, b""),
+        }
+    }
+
+    fn start_dav(user: &str, pass: &str) -> (String, Arc<Mutex<DavStore>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let store = Arc::new(Mutex::new(DavStore::default()));
+        let auth = format!(
+            "Basic {}",
+            base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                format!("{user}:{pass}")
+            )
+        );
+        let shared = store.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                handle(&mut stream, &shared, &auth);
+            }
+        });
+        (format!("http://{addr}/dav"), store)
+    }
+
+    fn config(url: &str, password: &str) -> WebDavConfig {
+        WebDavConfig {
+            url: url.to_string(),
+            username: "alice".to_string(),
+            password: password.to_string(),
+            remote_dir: "apps/chat sync".to_string(),
+        }
+    }
+
+    fn file_state(sha: &str) -> FileState {
+        FileState {
+            sha256: sha.to_string(),
+            size: 1,
+            modified: Some(1),
+            stored_in: "1".to_string(),
+        }
+    }
+
+    #[test]
+    fn client_roundtrip_and_manifest_conflicts() {
+        let (url, store) = start_dav("alice", "secret");
+        store.lock().unwrap().dirs.insert("/dav".to_string());
+        let client = WebDavClient::new(&config(&url, "secret")).unwrap();
+        tauri::async_runtime::block_on(async {
+            client.ensure_dir().await.unwrap();
+            client.ensure_dir().await.unwrap();
+            assert!(client.fetch_manifest().await.unwrap().is_none());
+
+            client
+                .put("bundle_1.zip", b"zip".to_vec(), Precondition::IfNoneMatch)
+                .await
+                .unwrap();
+            let (data, etag) = client.get("bundle_1.zip").await.unwrap().unwrap();
+            assert_eq!(data, b"zip");
+            assert!(etag.is_some());
+
+            let files = BTreeMap::from([("a.json".to_string(), file_state("aa"))]);
+            let first = RemoteManifest::new(1, "dev_a", "bundle_1.zip", sha256_hex(b"zip"), &files);
+            client.put_manifest(&first, None).await.unwrap();
+            // 另一设备在我们读取之后抢先写入
+            let seen = client.fetch_manifest().await.unwrap().unwrap();
+            assert_eq!(seen.0.revision, 1);
+            assert_eq!(
+                client.put_manifest(&first, None).await,
+                Err(CONFLICT_ERROR.to_string())
+            );
+            let second = RemoteManifest::new(2, "dev_b", "bundle_2.zip", String::new(), &files);
+            client.put_manifest(&second, Some(&seen)).await.unwrap();
+            assert_eq!(
+                client.put_manifest(&second, Some(&seen)).await,
+                Err(CONFLICT_ERROR.to_string())
+            );
+
+            let untagged = (seen.0.clone(), None);
+            assert!(client.put_manifest(&second, Some(&untagged)).await.is_err());
+            let name = bundle_object_name(2, "dev_b");
+            assert!(name.starts_with("bundle_2_dev_b_"), "{name}");
+            assert_ne!(name, bundle_object_name(2, "dev_b"));
+            client
+                .put(&name, b"zip".to_vec(), Precondition::IfNoneMatch)
+                .await
+                .unwrap();
+            assert!(client
+                .put(&name, b"zip".to_vec(), Precondition::IfNoneMatch)
+                .await
+                .is_err());
+
+            client.delete("bundle_1.zip").await.unwrap();
+            client.delete("bundle_1.zip").await.unwrap();
+            assert!(client.get("bundle_1.zip").await.unwrap().is_none());
+
+            let denied = WebDavClient::new(&config(&url, "wrong")).unwrap();
+            assert_eq!(
+                denied.get("sync.json").await,
+                Err("webdav authentication failed".to_string())
+            );
+        });
+        assert!(store
+            .lock()
+            .unwrap()
+            .files
+            .contains_key("/dav/apps/chat%20sync/sync.json"));
+    }
+
+    #[test]
+    fn push_then_pull_updates_sync_baselines() {
+        let base = crate::test_support::make_temp_dir("webdav_flow");
+        let (dir_a, dir_b) = (base.join("a"), base.join("b"));
+        fs::create_dir_all(&dir_a).unwrap();
+        fs::create_dir_all(&dir_b).unwrap();
+        fs::write(dir_a.join("a.json"), b"{}").unwrap();
+        let (url, store) = start_dav("alice", "secret");
+        store.lock().unwrap().dirs.insert("/dav".to_string());
+        let client = WebDavClient::new(&config(&url, "secret")).unwrap();
+        tauri::async_runtime::block_on(async {
+            client.ensure_dir().await.unwrap();
+            let mut state = load_state(&dir_a);
+            let bundle_path = base.join("bundle.zip");
+            let (local, path) = prepare_push(&dir_a, &state, None, 1, false, || {
+                fs::write(&bundle_path, b"zip").unwrap();
+                let mut index = BundleFileIndex::default();
+                index.record("a.json", sha256_hex(b"{}"), None);
+                Ok((bundle_path.clone(), index))
+            })
+            .unwrap()
+            .unwrap();
+            let bytes = upload_push(&client, &dir_a, &mut state, None, 1, local, &path)
+                .await
+                .unwrap();
+            assert_eq!(bytes, 3);
+            assert!(!bundle_path.exists());
+            assert_eq!(load_state(&dir_a).last_revision, Some(1));
+
+            let remote = client.fetch_manifest().await.unwrap().unwrap();
+            let unchanged = prepare_push(&dir_a, &state, Some(&remote.0), 2, false, || {
+                Err("nothing to push".to_string())
+            });
+            assert!(unchanged.unwrap().is_none());
+            assert!(fetch_pull(&client, &state, false)
+                .await
+                .unwrap()
+                .1
+                .is_none());
+
+            let state_b = load_state(&dir_b);
+            let (remote, bytes) = fetch_pull(&client, &state_b, false).await.unwrap();
+            assert_eq!(bytes.as_deref(), Some(&b"zip"[..]));
+            let mode = apply_pull(&dir_b, state_b, &remote, None, |mode| {
+                fs::write(dir_b.join("a.json"), b"{}").unwrap();
+                Ok(mode)
+            })
+            .unwrap();
+            assert_eq!(mode, "replace");
+        });
+        let pulled = load_state(&dir_b);
+        assert_eq!(pulled.last_revision, Some(1));
+        assert!(pulled.files.contains_key("a.json"));
+        fs::remove_dir_all(base).ok();
+    }
+
+    #[test]
+    fn status_detects_divergence_and_conflicts() {
+        let state = SyncState {
+            last_revision: Some(1),
+            files: BTreeMap::from([
+                ("a.json".to_string(), file_state("a1")),
+                ("b.json".to_string(), file_state("b1")),
+            ]),
+            ..SyncState::default()
+        };
+        let unchanged = DeltaPlan {
+            files: state.files.clone(),
+            ..DeltaPlan::default()
+        };
+        let same_remote =
+            RemoteManifest::new(1, "dev_a", "bundle_1.zip", String::new(), &state.files);
+        let status = compute_status(&state, &unchanged, Some(&same_remote));
+        assert_eq!(status.state, SyncPhase::InSync);
+        assert!(!status.blocks_push());
+
+        let mut local_edit = unchanged.clone();
+        local_edit
+            .files
+            .insert("a.json".to_string(), file_state("a2"));
+        local_edit.changed.push("a.json".to_string());
+        assert_eq!(
+            compute_status(&state, &local_edit, Some(&same_remote)).state,
+            SyncPhase::LocalAhead
+        );
+
+        let mut remote_files = state.files.clone();
+        remote_files.insert("b.json".to_string(), file_state("b2"));
+        let newer = RemoteManifest::new(2, "dev_b", "bundle_2.zip", String::new(), &remote_files);
+        let status = compute_status(&state, &unchanged, Some(&newer));
+        assert_eq!(status.state, SyncPhase::RemoteAhead);
+        assert_eq!(status.remote_changed, vec!["b.json"]);
+        assert_eq!(
+            compute_status(&state, &local_edit, Some(&newer)).state,
+            SyncPhase::Diverged
+        );
+
+        remote_files.insert("a.json".to_string(), file_state("a3"));
+        let clash = RemoteManifest::new(3, "dev_b", "bundle_3.zip", String::new(), &remote_files);
+        let status = compute_status(&state, &local_edit, Some(&clash));
+        assert_eq!(status.state, SyncPhase::Conflict);
+        assert_eq!(status.conflicts, vec!["a.json"]);
+        assert!(status.blocks_push());
+
+        let baseline = baseline_from_remote(&clash, &local_edit.files);
+        assert_eq!(baseline["a.json"].modified, None);
+        assert_eq!(baseline["b.json"].sha256, "b2");
+    }
+}
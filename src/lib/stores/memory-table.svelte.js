@@ -68,6 +68,11 @@ export class MemoryTableStore {
     return safeInvoke('get_memories', { scopeId: this.scopeId, query });
   }
 
+  async getMemoriesPage(query = {}) {
+    await this.ensureReady();
+    return safeInvoke('get_memories_page', { scopeId: this.scopeId, query });
+  }
+
   async batchCreateMemories(memories = []) {
     return this.queueWrite(async () => {
       await this.ensureReady();